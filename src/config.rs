@@ -5,6 +5,10 @@ pub const PROCESS_USER_STACK_BASE: usize = KERNEL_SPACE_BASE;
 pub const PROCESS_KERNEL_STACK_SIZE: usize = 512; // in pages. 4K * 128 = 512KB
 pub const PROCESS_MAX_USER_STACK_SIZE: usize = 0x2000_0000; // Max stack size is 512M
 pub const PROCESS_MMAP_BASE: usize = (PROCESS_USER_STACK_BASE - PROCESS_MAX_USER_STACK_SIZE);
+pub const PROCESS_MAX_ARG_SIZE: usize = 0x20000; // Max total size of argv and env strings, 128K
+pub const PROCESS_PIE_BASE: usize = 0x1000_0000; // ET_DYN program is loaded here
+pub const PROCESS_INTERP_BASE: usize = 0x4000_0000; // Dynamic linker is loaded here, below mmap area
+pub const PROCESS_SIGNAL_TRAMPOLINE: usize = PROCESS_MMAP_BASE; // One page holding rt_sigreturn call
 pub const CLOCK_FREQ: usize = 10000000; // Got from https://github.com/qemu/qemu/blob/master/include/hw/intc/riscv_aclint.h#L78
 pub const TICKS_PER_SECOND: usize = 10;
 pub const MS_PER_SECOND: usize = 1000;
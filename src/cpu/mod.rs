@@ -17,18 +17,20 @@ use riscv::register::{mhartid, sstatus};
 use log::info;
 use crate::interrupt::{disable_trap, enable_trap};
 use crate::startup;
-use crate::process::{Process, TaskContext};
+use crate::process::{Process, TaskContext, Thread};
+use crate::process::scheduler::RunQueue;
 use crate::interrupt::TrapContext;
 use crate::core::{Spinlock, SpinlockGuard};
 use spin::RwLock;
 pub use vendor::{CpuId, VendorId, ArchId, ImplId, CPUID};
 
 pub(super) struct CPU {
-    proc: Spinlock<Option<Arc<Process>>>,
+    thread: Spinlock<Option<Arc<Thread>>>,
     // trap_off_depth: usize,
     // trap_enabled: bool,
     trap_info: Spinlock<(usize, bool)>,
     cpu_context: Spinlock<TaskContext>,
+    run_queue: RunQueue,
 }
 
 lazy_static! {
@@ -50,9 +52,10 @@ pub fn init() {
 impl CPU {
     pub fn new() -> Self {
         Self {
-            proc: Spinlock::new(None),
+            thread: Spinlock::new(None),
             trap_info: Spinlock::new((0, false)),
             cpu_context: Spinlock::new(TaskContext::new()),
+            run_queue: RunQueue::new(),
         }
     }
 
@@ -72,11 +75,27 @@ impl CPU {
         CPUS.len()
     }
 
+    pub fn get(id: usize) -> Option<&'static CPU> {
+        CPUS.get(id)
+    }
+
+    pub fn run_queue(&self) -> &RunQueue {
+        &self.run_queue
+    }
+
+    pub fn get_thread(&self) -> Option<Arc<Thread>> {
+        let thread_lock = self.thread.lock();
+        let thread = thread_lock.clone();
+        drop(thread_lock);
+        thread
+    }
+
     pub fn get_process(&self) -> Option<Arc<Process>> {
-        let proc_lock = self.proc.lock();
-        let proc = proc_lock.clone();
-        drop(proc_lock);
-        proc
+        self.get_thread().map(|thread| thread.process.clone())
+    }
+
+    pub fn get_current_thread() -> Option<Arc<Thread>> {
+        Self::get_current().unwrap().get_thread()
     }
 
     pub fn get_current_process() -> Option<Arc<Process>> {
@@ -109,8 +128,8 @@ impl CPU {
         }
     }
 
-    pub fn set_process(&self, proc: Option<Arc<Process>>) {
-        *self.proc.lock() = proc
+    pub fn set_thread(&self, thread: Option<Arc<Thread>>) {
+        *self.thread.lock() = thread
     }
 
     pub fn get_context_mut(&self) -> *mut TaskContext {
@@ -126,6 +145,11 @@ impl CPU {
         drop(cpu_context);
         ctx
     }
+    /// Nesting of `push_interrupt`, non-zero while holding an interrupt lock.
+    pub fn get_trap_depth(&self) -> usize {
+        self.trap_info.lock().0
+    }
+
     pub fn get_trap_enabled(&self) -> bool {
         self.trap_info.lock().1
     }
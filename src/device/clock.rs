@@ -0,0 +1,49 @@
+//! # Clock
+//!
+//! Timekeeping on the `time` CSR. Monotonic and boot time count from boot, realtime is boot time
+//! plus the epoch time at boot, seeded from RTC and counting from epoch 0 if there is none.
+//! System never suspends, so boot time and monotonic time are the same.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use log::info;
+use crate::device::{rtc, timer};
+
+// Nanoseconds since epoch at boot.
+static BOOT_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+pub fn init() {
+    if let Some(now) = rtc::read() {
+        BOOT_EPOCH.store(now.saturating_sub(timer::current_time()).as_nanos() as u64, Ordering::Relaxed);
+    } else {
+        info!("No RTC found, realtime starts from epoch.");
+    }
+}
+
+/// Time since boot, never goes backwards.
+pub fn monotonic() -> Duration {
+    timer::current_time()
+}
+
+/// Time since boot including suspended time.
+pub fn boottime() -> Duration {
+    timer::current_time()
+}
+
+/// Time since epoch.
+pub fn realtime() -> Duration {
+    boot_epoch() + timer::current_time()
+}
+
+/// Time since epoch at boot, realtime of a time since boot is this plus it.
+pub fn boot_epoch() -> Duration {
+    Duration::from_nanos(BOOT_EPOCH.load(Ordering::Relaxed))
+}
+
+/// Smallest step of the clocks above.
+pub fn resolution() -> Duration {
+    timer::resolution()
+}
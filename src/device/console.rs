@@ -4,6 +4,7 @@
 //! ---
 //! Change log:
 //!   - 2024/03/14: File created.
+//!   - 2026/10/16: Exposed as /dev/tty and /dev/console through devfs.
 
 pub struct Console;
 
@@ -11,8 +12,7 @@ use alloc::sync::Arc;
 use alloc::vec::Vec;
 pub use core::fmt::{self, Write};
 use sbi::legacy::{console_getchar, console_putchar};
-use crate::filesystem::{DirEntry, File, SeekPosition};
-use crate::print;
+use crate::filesystem::{DirEntry, Device, File, FileOpenFlags, register_char_device, SeekPosition};
 use crate::process::do_yield;
 use crate::utils::error::{Result, EmptyResult};
 
@@ -25,12 +25,23 @@ impl Write for Console {
     }
 }
 
-pub struct Stdin;
+// Linux major of tty devices, minor 0 is /dev/tty and 1 is /dev/console.
+const TTY_MAJOR: usize = 5;
 
-pub struct Stdout;
-// struct Stderr;
+/// The sbi console as a char device.
+struct Tty;
 
-impl File for Stdin {
+impl Device for Tty {
+    fn open(self: Arc<Self>, dentry: Arc<DirEntry>, flags: FileOpenFlags) -> Result<Arc<dyn File>> {
+        Ok(Arc::new(TtyFile { dentry }))
+    }
+}
+
+struct TtyFile {
+    dentry: Arc<DirEntry>,
+}
+
+impl File for TtyFile {
     fn seek(&self, offset: isize, whence: SeekPosition) -> Result<usize> {
         Err("You cannot seek a stream.".into())
     }
@@ -51,38 +62,22 @@ impl File for Stdin {
     }
 
     fn write(&self, buf: &[u8]) -> Result<usize> {
-        // Err("You cannot write to stdin.".into())
-        // FIXME: For whatever newlib bug
-        print!("{}", core::str::from_utf8(buf).unwrap());
-        Ok(buf.len())
-    }
-
-    fn close(&self) -> EmptyResult { Ok(())}
-
-    fn get_dentry(&self) -> Result<Arc<DirEntry>> {
-        Err("Invalid get dentry for stdin/stdout".into())
-    }
-}
-
-impl File for Stdout {
-    fn seek(&self, offset: isize, whence: SeekPosition) -> Result<usize> {
-        Err("You cannot seek a stream.".into())
-    }
-
-    fn read(&self, buf: &mut [u8]) -> Result<usize> {
-        Err("You cannot read from stdout.".into())
-    }
-
-    fn write(&self, buf: &[u8]) -> Result<usize> {
-        print!("{}", core::str::from_utf8(buf).unwrap());
+        // Bytes go out as they are, a UTF-8 character may be split across writes.
+        for &byte in buf {
+            console_putchar(byte.into());
+        }
         Ok(buf.len())
     }
 
     fn close(&self) -> EmptyResult { Ok(()) }
 
     fn get_dentry(&self) -> Result<Arc<DirEntry>> {
-        Err("Invalid get dentry for stdin/stdout".into())
+        Ok(self.dentry.clone())
     }
 }
 
-pub fn init() {}
\ No newline at end of file
+pub fn init() {
+    let tty = Arc::new(Tty);
+    register_char_device("tty", TTY_MAJOR, 0, tty.clone()).expect("Failed to register /dev/tty.");
+    register_char_device("console", TTY_MAJOR, 1, tty).expect("Failed to register /dev/console.");
+}
@@ -10,6 +10,9 @@ pub mod console;
 pub mod timer;
 pub mod virtio;
 pub mod pipe;
+pub mod timerfd;
+pub mod rtc;
+pub mod clock;
 
 pub use console::{Console, Write as ConsoleWrite};
 use crate::do_init;
@@ -19,6 +22,7 @@ pub fn init() {
     do_init!(
         console,
         timer,
+        clock,
         virtio
     );
 }
\ No newline at end of file
@@ -1,10 +1,11 @@
 use alloc::format;
+use core::time::Duration;
 use alloc::sync::{Arc, Weak};
 use bitflags::*;
 use alloc::vec::Vec;
 use crate::core::Spinlock;
 use crate::filesystem::{DirEntry, DirEntryType, File, FileModes, FileOpenFlags, Inode, InodeStat, SeekPosition};
-use crate::process::{Condvar, do_yield};
+use crate::process::{WaitQueue, WakeReason};
 use crate::utils::error::{EmptyResult,Result};
 
 const PIPE_SIZE: usize = 512;
@@ -15,8 +16,13 @@ struct PipeBuffer {
     n_write: usize,
     pub read_open: bool,
     pub write_open: bool,
-    pub wait_read: Condvar,
-    pub wait_write: Condvar,
+}
+
+struct Pipe {
+    buffer: Spinlock<PipeBuffer>,
+    // Writers wait here for readers to make room, and readers for writers to fill.
+    wait_read: WaitQueue,
+    wait_write: WaitQueue,
 }
 
 impl PipeBuffer {
@@ -27,8 +33,6 @@ impl PipeBuffer {
             n_write: 0,
             read_open: false,
             write_open: false,
-            wait_read: Condvar::new(),
-            wait_write: Condvar::new(),
         }
     }
 
@@ -46,9 +50,6 @@ impl PipeBuffer {
                 i += 1;
                 self.n_read += 1;
             }
-            if i != 0 {
-                self.wait_write.wakeup();
-            }
             Some(i)
         }
     }
@@ -64,9 +65,6 @@ impl PipeBuffer {
             self.n_write += 1;
             wrote_bytes += 1;
         }
-        if wrote_bytes != 0 {
-            self.wait_read.wakeup();
-        }
         if self.read_open {
             // Someone is reading
             Some(wrote_bytes)
@@ -84,7 +82,7 @@ enum PipeFileType {
 
 pub struct PipeFile {
     type_: PipeFileType,
-    buffer: Arc<Spinlock<PipeBuffer>>,
+    pipe: Arc<Pipe>,
 }
 
 impl File for PipeFile {
@@ -96,15 +94,17 @@ impl File for PipeFile {
         match self.type_ {
             PipeFileType::Reader => {
                 loop {
-                    let mut buffer = self.buffer.lock();
+                    let mut buffer = self.pipe.buffer.lock();
                     let result = buffer.read(buf);
                     if let Some(read_bytes) = result {
                         if read_bytes == 0 && buffer.write_open {
                             // Read nothing but writer is open
-                            buffer.wait_write.wait();
-                            drop(buffer);
-                            do_yield();
+                            if self.pipe.wait_write.wait_interruptible(buffer) == WakeReason::Interrupted {
+                                return Err("Pipe read interrupted.".into());
+                            }
                         } else {
+                            drop(buffer);
+                            self.pipe.wait_read.wake_all();
                             return Ok(read_bytes);
                         }
                     } else {
@@ -121,18 +121,22 @@ impl File for PipeFile {
             PipeFileType::Writer => {
                 let mut total_wrote = 0;
                 loop {
-                    let mut buffer = self.buffer.lock();
+                    let mut buffer = self.pipe.buffer.lock();
                     let result = buffer.write(&buf[total_wrote..]);
                     if let Some(wrote_bytes) = result {
                         total_wrote += wrote_bytes;
+                        if wrote_bytes != 0 {
+                            self.pipe.wait_write.wake_all();
+                        }
                     } else {
                         return Err("Write to a pipe no one could read.".into());
                     }
                     if total_wrote != buf.len() {
                         // Write is not complete
-                        buffer.wait_read.wait();
-                        drop(buffer);
-                        do_yield();
+                        if self.pipe.wait_read.wait_interruptible(buffer) == WakeReason::Interrupted {
+                            // Part written is not lost.
+                            return if total_wrote != 0 { Ok(total_wrote) } else { Err("Pipe write interrupted.".into()) };
+                        }
                     } else {
                         // Write complete
                         break;
@@ -145,15 +149,15 @@ impl File for PipeFile {
     }
 
     fn close(&self) -> EmptyResult {
-        let mut buffer = self.buffer.lock();
+        let mut buffer = self.pipe.buffer.lock();
         match self.type_ {
             PipeFileType::Reader => {
                 buffer.read_open = false;
-                buffer.wait_write.wakeup();
+                self.pipe.wait_read.wake_all();
             }
             PipeFileType::Writer => {
                 buffer.write_open = false;
-                buffer.wait_read.wakeup();
+                self.pipe.wait_write.wake_all();
             }
         }
         Ok(())
@@ -181,14 +185,18 @@ impl PipeFile {
         let mut buffer = PipeBuffer::new();
         buffer.write_open = true;
         buffer.read_open = true;
-        let buffer = Arc::new(Spinlock::new(buffer));
+        let pipe = Arc::new(Pipe {
+            buffer: Spinlock::new(buffer),
+            wait_read: WaitQueue::new(),
+            wait_write: WaitQueue::new(),
+        });
         let reader = Self {
             type_: PipeFileType::Reader,
-            buffer: buffer.clone(),
+            pipe: pipe.clone(),
         };
         let writer = Self {
             type_: PipeFileType::Writer,
-            buffer,
+            pipe,
         };
         (reader, writer)
     }
@@ -238,8 +246,12 @@ impl Inode for PipeDummyInode {
             ino: 0,
             mode: self.mode,
             nlink: 1,
+            rdev: 0,
             size: PIPE_SIZE,
             block_size: 1,
+            atime: Duration::ZERO,
+            mtime: Duration::ZERO,
+            ctime: Duration::ZERO,
         }
     }
 }
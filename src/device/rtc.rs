@@ -0,0 +1,42 @@
+//! # RTC
+//!
+//! Goldfish RTC of QEMU virt machine, only read once at boot to seed the realtime clock.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use core::time::Duration;
+use log::info;
+use crate::config::HARDWARE_BASE_ADDR;
+use crate::memory::{Addr, flush_page_table, get_kernel_page_table, PAGE_SIZE, PhyAddr, PTEFlags, VirtAddr};
+use crate::startup::get_boot_fdt;
+use crate::utils;
+
+// Nanoseconds since epoch. Reading low half latches high half.
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
+
+/// Time since epoch read from the RTC, None if there is no RTC.
+pub fn read() -> Option<Duration> {
+    let fdt = get_boot_fdt();
+    let node = fdt.find_compatible(&["google,goldfish-rtc"])?;
+    let reg = node.reg()?.next()?;
+    let start = reg.starting_address as usize;
+    if start % PAGE_SIZE != 0 {
+        info!("RTC with unaligned addr is not supported.");
+        return None;
+    }
+    let size = utils::round_up_to(reg.size.unwrap_or(PAGE_SIZE), PAGE_SIZE);
+    let vaddr = VirtAddr::from(start + HARDWARE_BASE_ADDR);
+    let paddr = PhyAddr::from(start);
+    get_kernel_page_table().lock().map_many(vaddr, paddr, size, PTEFlags::W | PTEFlags::R);
+    flush_page_table(None);
+    let (low, high) = unsafe {
+        let low = (vaddr.get_addr() as *const u32).byte_add(TIME_LOW).read_volatile();
+        let high = (vaddr.get_addr() as *const u32).byte_add(TIME_HIGH).read_volatile();
+        (low, high)
+    };
+    let time = Duration::from_nanos((high as u64) << 32 | low as u64);
+    info!("RTC @ {} reads {}s since epoch.", paddr, time.as_secs());
+    Some(time)
+}
@@ -1,33 +1,172 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
 use lazy_static::lazy_static;
 use riscv::register::scause::set;
-use riscv::register::time;
-use crate::config::{CLOCK_FREQ, TICKS_PER_SECOND};
+use riscv::register::{sstatus, time};
+use riscv::register::sstatus::SPP;
+use crate::config::{CLOCK_FREQ, TICKS_PER_SECOND, TIMER_INTERVAL};
+use crate::core::Intrlock;
 use crate::cpu::CPU;
 use crate::process;
-use crate::process::Condvar;
+use crate::process::{scheduler, Thread, WaitQueue, WakeReason};
+
+enum TimerEvent {
+    Wake(Weak<Thread>),
+    // Runs in interrupt handler with no timer locked, may add timers again.
+    Callback(Arc<dyn Fn() + Send + Sync>),
+}
 
 lazy_static! {
-    static ref TIMER_CONDVAR: Condvar = Condvar::new();
+    // Pending timers of all CPUs, any CPU fires the due ones.
+    static ref TIMERS: Intrlock<BTreeMap<TimerId, TimerEvent>> = Intrlock::new(BTreeMap::new());
+    // Raw time of next scheduler tick of each CPU, u64::MAX while it is idle.
+    static ref NEXT_TICK: Vec<AtomicU64> = (0..CPU::get_count()).map(|_| AtomicU64::new(u64::MAX)).collect();
+}
+
+static NEXT_TIMER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Handle to cancel a timer, ordered by deadline.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimerId(Duration, usize);
+
+// Raw time of `time` since boot, rounded up to never fire early.
+fn raw_time(time: Duration) -> u64 {
+    let raw = (time.as_nanos() * CLOCK_FREQ as u128).div_ceil(1_000_000_000);
+    raw.min(u64::MAX as u128) as u64
 }
 
-#[inline]
-fn set_next_trigger() {
-    sbi::timer::set_timer(time::read64() + (CLOCK_FREQ / TICKS_PER_SECOND) as u64).expect("Set timer failed");
+// One-shot timer of current CPU fires at its next tick or the earliest timer, whichever first.
+// Every CPU programs the earliest one, so none is left behind when a CPU goes idle.
+fn program() {
+    let tick = NEXT_TICK[CPU::get_current_id()].load(Ordering::Relaxed);
+    let earliest = TIMERS.lock().first_key_value().map_or(u64::MAX, |(id, _)| raw_time(id.0));
+    sbi::timer::set_timer(tick.min(earliest)).expect("Set timer failed");
 }
 
 pub fn init() {
-    set_next_trigger();
+    start_tick();
+}
+
+/// Start scheduler tick of current CPU, called before it runs a thread.
+pub fn start_tick() {
+    let tick = &NEXT_TICK[CPU::get_current_id()];
+    if tick.load(Ordering::Relaxed) == u64::MAX {
+        tick.store(time::read64() + TIMER_INTERVAL as u64, Ordering::Relaxed);
+        program();
+    }
+}
+
+/// Stop scheduler tick of idle CPU, only timers and IPIs wake it up.
+pub fn stop_tick() {
+    let tick = &NEXT_TICK[CPU::get_current_id()];
+    if tick.load(Ordering::Relaxed) != u64::MAX {
+        tick.store(u64::MAX, Ordering::Relaxed);
+        program();
+    }
 }
 
 pub fn handler() {
-    set_next_trigger();
-    TIMER_CONDVAR.wakeup();
-    if CPU::get_current_process().is_some() {
-        process::try_yield();
+    let tick = &NEXT_TICK[CPU::get_current_id()];
+    let now = time::read64();
+    let ticked = tick.load(Ordering::Relaxed) <= now;
+    if ticked {
+        tick.store(now + TIMER_INTERVAL as u64, Ordering::Relaxed);
+    }
+    expire_timers();
+    program();
+    if !ticked {
+        return;
+    }
+    scheduler::balance();
+    if let Some(thread) = CPU::get_current_thread() {
+        let preempt = thread.data.try_lock().is_some_and(|mut data| scheduler::tick(&mut data));
+        // Interrupted user space if trapped from there.
+        let user = sstatus::read().spp() == SPP::User;
+        process::itimer::tick(&thread.process, Duration::from_secs(1) / TICKS_PER_SECOND as u32, user);
+        drop(thread);
+        if preempt {
+            process::try_yield();
+        }
     }
 }
 
-pub fn sleep_on_timer() {
-    TIMER_CONDVAR.wait();
-    process::do_yield();
-}
\ No newline at end of file
+/// Time since boot.
+pub fn current_time() -> Duration {
+    let time = time::read64();
+    let freq = CLOCK_FREQ as u64;
+    Duration::new(time / freq, ((time % freq) * 1_000_000_000 / freq) as u32)
+}
+
+/// Smallest step of `current_time`.
+pub fn resolution() -> Duration {
+    Duration::from_nanos((1_000_000_000 / CLOCK_FREQ).max(1) as u64)
+}
+
+pub fn current_ticks() -> usize {
+    time::read64() as usize / (CLOCK_FREQ / TICKS_PER_SECOND)
+}
+
+/// Time since boot when tick `ticks` begins.
+pub fn tick_time(ticks: usize) -> Duration {
+    Duration::from_secs(ticks as u64) / TICKS_PER_SECOND as u32
+}
+
+/// Period of periodic timers, non-zero one is at least a scheduler tick. Shorter ones would keep
+/// the CPU in timer interrupts.
+pub fn clamp_period(interval: Duration) -> Duration {
+    if interval.is_zero() {
+        interval
+    } else {
+        interval.max(tick_time(1))
+    }
+}
+
+fn add(deadline: Duration, event: TimerEvent) -> TimerId {
+    let id = TimerId(deadline, NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed));
+    TIMERS.lock().insert(id, event);
+    program();
+    id
+}
+
+/// Wake `thread` up once `deadline` since boot passes.
+pub fn add_timeout(deadline: Duration, thread: &Arc<Thread>) -> TimerId {
+    add(deadline, TimerEvent::Wake(Arc::downgrade(thread)))
+}
+
+/// Run `callback` in interrupt handler once `deadline` since boot passes.
+pub fn add_timer(deadline: Duration, callback: Arc<dyn Fn() + Send + Sync>) -> TimerId {
+    add(deadline, TimerEvent::Callback(callback))
+}
+
+/// Return false if it has fired already.
+pub fn cancel_timer(id: TimerId) -> bool {
+    TIMERS.lock().remove(&id).is_some()
+}
+
+fn expire_timers() {
+    let now = current_time();
+    loop {
+        let mut timers = TIMERS.lock();
+        let Some(entry) = timers.first_entry() else { break };
+        if entry.key().0 > now {
+            break;
+        }
+        let event = entry.remove();
+        drop(timers);
+        match event {
+            TimerEvent::Wake(thread) => if let Some(thread) = thread.upgrade() {
+                scheduler::wake_up(&thread, &mut thread.data.lock());
+            },
+            TimerEvent::Callback(callback) => callback(),
+        }
+    }
+}
+
+/// Sleep until `deadline` since boot, or a signal comes if `interruptible`.
+pub fn sleep_until(deadline: Duration, interruptible: bool) -> WakeReason {
+    // Nobody else wakes it up.
+    WaitQueue::new().wait_until((), deadline, interruptible)
+}
@@ -0,0 +1,162 @@
+//! # Timer fd
+//!
+//! File counting expirations of a timer. Read gives the count since last read as u64, and blocks
+//! until there is one.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use alloc::sync::{Arc, Weak};
+use core::any::Any;
+use core::time::Duration;
+use crate::core::Intrlock;
+use crate::device::timer::{self, TimerId};
+use crate::filesystem::{DirEntry, File, SeekPosition};
+use crate::process::{WaitQueue, WakeReason};
+use crate::utils::error::{EmptyResult, KernelError, Result};
+
+struct TimerFdState {
+    expirations: u64,
+    // Next expiration since boot, None if disarmed.
+    deadline: Option<Duration>,
+    interval: Duration,
+    timer: Option<TimerId>,
+    // Changed on every setting, so a timer fired meanwhile does nothing.
+    generation: usize,
+}
+
+struct TimerFdInner {
+    // Timer interrupt handler takes it too.
+    state: Intrlock<TimerFdState>,
+    readers: WaitQueue,
+}
+
+pub struct TimerFd {
+    clock: usize,
+    nonblock: bool,
+    inner: Arc<TimerFdInner>,
+}
+
+fn arm(inner: &Arc<TimerFdInner>, state: &mut TimerFdState) {
+    if let Some(deadline) = state.deadline {
+        let weak = Arc::downgrade(inner);
+        let generation = state.generation;
+        state.timer = Some(timer::add_timer(deadline, Arc::new(move || expire(&weak, generation))));
+    }
+}
+
+fn expire(inner: &Weak<TimerFdInner>, generation: usize) {
+    let Some(inner) = inner.upgrade() else { return };
+    let mut state = inner.state.lock();
+    if state.generation != generation {
+        return;
+    }
+    let Some(deadline) = state.deadline else { return };
+    state.timer = None;
+    if state.interval.is_zero() {
+        state.expirations += 1;
+        state.deadline = None;
+    } else {
+        // Periods missed are counted too.
+        let interval = state.interval.as_nanos();
+        let periods = (timer::current_time().saturating_sub(deadline).as_nanos() / interval + 1) as u64;
+        state.expirations += periods;
+        state.deadline = Some(deadline + Duration::from_nanos((interval * periods as u128) as u64));
+        arm(&inner, &mut state);
+    }
+    drop(state);
+    inner.readers.wake_all();
+}
+
+fn setting(state: &TimerFdState) -> (Duration, Duration) {
+    let left = state.deadline.map_or(Duration::ZERO, |deadline| deadline.saturating_sub(timer::current_time()));
+    (state.interval, left)
+}
+
+impl TimerFd {
+    pub fn new(clock: usize, nonblock: bool) -> Self {
+        Self {
+            clock,
+            nonblock,
+            inner: Arc::new(TimerFdInner {
+                state: Intrlock::new(TimerFdState {
+                    expirations: 0,
+                    deadline: None,
+                    interval: Duration::ZERO,
+                    timer: None,
+                    generation: 0,
+                }),
+                readers: WaitQueue::new(),
+            }),
+        }
+    }
+
+    /// Clock given at creation, absolute time of setting is on it.
+    pub fn clock(&self) -> usize {
+        self.clock
+    }
+
+    /// (interval, time left), zero time left if disarmed.
+    pub fn get(&self) -> (Duration, Duration) {
+        setting(&self.inner.state.lock())
+    }
+
+    /// Expire first at `deadline` since boot and then every `interval`, or disarm if None.
+    /// Expirations not read are dropped. Return the old (interval, time left).
+    pub fn set(&self, deadline: Option<Duration>, interval: Duration) -> (Duration, Duration) {
+        let mut state = self.inner.state.lock();
+        let old = setting(&state);
+        if let Some(id) = state.timer.take() {
+            timer::cancel_timer(id);
+        }
+        state.generation += 1;
+        state.expirations = 0;
+        state.deadline = deadline;
+        state.interval = timer::clamp_period(interval);
+        arm(&self.inner, &mut state);
+        old
+    }
+}
+
+impl File for TimerFd {
+    fn seek(&self, offset: isize, whence: SeekPosition) -> Result<usize> {
+        Err("Cannot seek timerfd.".into())
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        if buf.len() < 8 {
+            return Err("Timerfd read needs 8 bytes.".into());
+        }
+        loop {
+            let mut state = self.inner.state.lock();
+            if state.expirations != 0 {
+                buf[..8].copy_from_slice(&state.expirations.to_ne_bytes());
+                state.expirations = 0;
+                return Ok(8);
+            }
+            if self.nonblock {
+                return Err(KernelError::WOULD_BLOCK);
+            }
+            if self.inner.readers.wait_interruptible(state) == WakeReason::Interrupted {
+                return Err("Timerfd read interrupted.".into());
+            }
+        }
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        Err("Cannot write timerfd.".into())
+    }
+
+    fn close(&self) -> EmptyResult {
+        self.set(None, Duration::ZERO);
+        Ok(())
+    }
+
+    fn get_dentry(&self) -> Result<Arc<DirEntry>> {
+        Err("Timerfd has no dentry.".into())
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
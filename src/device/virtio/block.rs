@@ -1,24 +1,26 @@
 use alloc::collections::BTreeMap;
 use alloc::sync::{Arc, Weak};
-use alloc::vec;
+use alloc::{format, vec};
+use alloc::string::String;
 use alloc::vec::Vec;
 use core::cmp::{max, min};
 use core::ops::DerefMut;
 use bitflags::Flags;
 use fatfs::Dir;
 use lazy_static::lazy_static;
-use log::info;
+use log::{info, warn};
 use virtio_drivers::device::blk::{BlkReq, BlkResp, RespStatus, SECTOR_SIZE, VirtIOBlk};
 use virtio_drivers::transport::mmio::MmioTransport;
 use virtio_drivers::transport::Transport;
 use crate::device::virtio::VirtioHal;
-use crate::filesystem::{DirEntry, DirEntryType, File, FileModes, FileOpenFlags, Inode, SeekPosition, DirFile, InodeStat};
+use crate::filesystem::{BlockDevice, BlockDeviceFile, DirEntry, Device, File, FileOpenFlags, register_block_device, register_partitions};
 use crate::utils::error::EmptyResult;
-use crate::core::Spinlock;
+use crate::core::{Intrlock, Spinlock};
+use crate::cpu::CPU;
 use crate::interrupt::{plic, register_interrupt_handler};
 use crate::memory::{Addr, PAGE_SIZE, PhyAddr, PhyPage, VirtAddr};
 use crate::{process, utils};
-use crate::process::Condvar;
+use crate::process::WaitQueue;
 use crate::utils::error::Result;
 
 #[derive(Copy, Clone, PartialEq)]
@@ -27,234 +29,210 @@ pub enum VirtIOBlockRequestType {
     Read,
 }
 
-struct VirtIOBlockFile {
-    device: Arc<VirtIOBlock>,
-    dentry: Arc<DirEntry>,
-    cur: Spinlock<usize>,
+// Request in flight, everything pointed to lives on the stack of the waiting thread.
+struct PendingRequest {
+    req: *mut BlkReq,
+    resp: *mut BlkResp,
+    buf: *mut [u8],
+    type_: VirtIOBlockRequestType,
+    // Set by interrupt handler once the request is completed.
+    result: Option<EmptyResult>,
 }
 
-impl File for VirtIOBlockFile {
-    fn seek(&self, offset: isize, whence: SeekPosition) -> crate::utils::error::Result<usize> {
-        let mut cur = self.cur.lock();
-        match whence {
-            SeekPosition::Set => { *cur = offset as usize }
-            SeekPosition::Cur => { *cur = if offset.is_positive() { *cur + offset.unsigned_abs() } else { *cur - offset.unsigned_abs() } }
-            SeekPosition::End => { *cur = if offset.is_positive() { self.device.size } else { self.device.size - offset.unsigned_abs() } }
-        }
-        if *cur > self.device.size {
-            *cur = self.device.size
-        }
-        Ok(*cur)
-    }
-
-    fn read(&self, buf: &mut [u8]) -> crate::utils::error::Result<usize> {
-        let mut offset = self.cur.lock();
-        let read_bytes = self.device.read(*offset, buf);
-        *offset += read_bytes;
-        Ok(read_bytes)
-    }
-
-    fn write(&self, buf: &[u8]) -> crate::utils::error::Result<usize> {
-        let mut offset = self.cur.lock();
-        let write_bytes = self.device.write(*offset, buf);
-        *offset += write_bytes;
-        Ok(write_bytes)
-    }
-
-    fn close(&self) -> EmptyResult {
-        // Self is dropped.
-        Ok(())
-    }
-
-    fn get_dentry(&self) -> Result<Arc<DirEntry>> {
-        Ok(self.dentry.clone())
-    }
+struct VirtIOBlock {
+    // Interrupt handler takes it too, keep interrupts off while holding.
+    device: Intrlock<VirtIOBlk<VirtioHal, MmioTransport>>,
+    // Waiting thread and request of each descriptor token.
+    waiters: BTreeMap<u16, (WaitQueue, Spinlock<Option<PendingRequest>>)>,
+    // Threads waiting for free descriptors.
+    queue_free: WaitQueue,
+    size: usize, // in bytes
 }
 
-impl VirtIOBlockFile {
-    pub fn new(device: Arc<VirtIOBlock>, dentry: Arc<DirEntry>) -> Self {
+impl VirtIOBlock {
+    pub fn new(device: VirtIOBlk<VirtioHal, MmioTransport>) -> Self {
+        let size = device.capacity() as usize * SECTOR_SIZE;
+        let max_idx = device.virt_queue_size();
+        let mut waiters = BTreeMap::new();
+        for i in 0..max_idx {
+            waiters.insert(i, (WaitQueue::new(), Spinlock::new(None)));
+        }
         Self {
-            device,
-            dentry,
-            cur: Spinlock::new(0),
+            device: Intrlock::new(device),
+            waiters,
+            queue_free: WaitQueue::new(),
+            size,
         }
     }
-}
-
-struct VirtIOBlockInode {
-    device: Arc<VirtIOBlock>,
-}
-
-impl Inode for VirtIOBlockInode {
-    fn lookup(&self, name: &str, this_dentry: Weak<DirEntry>) -> Option<DirEntry> {
-        None
-    }
-
-    fn link(&self, inode: Arc<dyn Inode>, name: &str) -> EmptyResult {
-        Err("Cannot perform link on block device.".into())
-    }
 
-    fn unlink(&self, name: &str) -> EmptyResult {
-        Err("Cannot perform unlink on block device.".into())
-    }
-
-    fn mkdir(&self, name: &str) -> crate::utils::error::Result<Arc<dyn Inode>> {
-        Err("Cannot perform mkdir on block device.".into())
+    pub fn read_block(&self, block_id: usize, buf: &mut [u8]) -> EmptyResult {
+        assert_eq!(buf.len() % SECTOR_SIZE, 0, "Read block only accepts buf aligned with SECTOR_SIZE");
+        self.submit(block_id, buf as *mut [u8], VirtIOBlockRequestType::Read)
+            .map_err(|_| "Failed to read virtio-block.".into())
     }
 
-    fn rmdir(&self, name: &str) -> EmptyResult {
-        Err("Cannot perform rmdir on block device.".into())
+    pub fn write_block(&self, block_id: usize, buf: &[u8]) -> EmptyResult {
+        assert_eq!(buf.len() % SECTOR_SIZE, 0, "Write block only accepts buf aligned with SECTOR_SIZE");
+        self.submit(block_id, buf as *const [u8] as *mut [u8], VirtIOBlockRequestType::Write)
+            .map_err(|_| "Failed to write virtio-block.".into())
     }
 
-    fn read_dir(&self, this_dentry: Weak<DirEntry>) -> crate::utils::error::Result<Vec<DirEntry>> {
-        Err("Cannot perform read_dir on block device.".into())
+    // Nobody to put to sleep before scheduler runs, and yielding with an interrupt lock held (e.g.
+    // process data on page fault) breaks interrupt depth of CPU. Requests are polled then.
+    fn can_sleep() -> bool {
+        CPU::get_current_thread().is_some() && CPU::get_current().unwrap().get_trap_depth() == 0
     }
 
-    fn open(&self, dentry: Arc<DirEntry>, flags: FileOpenFlags, mode: FileModes) -> crate::utils::error::Result<Arc<dyn File>> {
-        // TODO: respect open flags.
-        Ok(Arc::new(VirtIOBlockFile::new(
-            self.device.clone(),
-            dentry,
-        )))
-    }
+    // Queue the request and sleep until interrupt handler completes it, or poll for it.
+    fn submit(&self, block_id: usize, buf: *mut [u8], type_: VirtIOBlockRequestType) -> EmptyResult {
+        let poll = !Self::can_sleep();
+        let mut req = BlkReq::default();
+        let mut resp = BlkResp::default();
+        let token = loop {
+            let mut device = self.device.lock();
+            let result = unsafe {
+                match type_ {
+                    VirtIOBlockRequestType::Read => device.read_blocks_nb(block_id, &mut req, &mut *buf, &mut resp),
+                    VirtIOBlockRequestType::Write => device.write_blocks_nb(block_id, &mut req, &*buf, &mut resp),
+                }
+            };
+            match result {
+                Ok(token) => {
+                    // Filled before the device is unlocked, so interrupt handler always finds it.
+                    *self.waiters.get(&token).unwrap().1.lock() = Some(PendingRequest {
+                        req: &mut req,
+                        resp: &mut resp,
+                        buf,
+                        type_,
+                        result: None,
+                    });
+                    break token;
+                }
+                Err(virtio_drivers::Error::QueueFull) => {
+                    // Wait for others to finish.
+                    if poll {
+                        drop(device);
+                        self.handle_irq();
+                    } else {
+                        self.queue_free.wait(device);
+                    }
+                }
+                Err(_) => return Err("Failed to queue virtio-block request.".into())
+            }
+        };
 
-    fn get_dentry_type(&self) -> DirEntryType {
-        DirEntryType::File
+        let (queue, slot) = self.waiters.get(&token).unwrap();
+        loop {
+            let mut slot = slot.lock();
+            if let Some(result) = slot.as_mut().unwrap().result.take() {
+                *slot = None;
+                return result;
+            }
+            // Buffers on stack are in use by device, could not be interrupted.
+            if poll {
+                drop(slot);
+                self.handle_irq();
+            } else {
+                queue.wait(slot);
+            }
+        }
     }
 
-    fn get_stat(&self) -> InodeStat {
-        InodeStat {
-            ino: 0,
-            mode: (FileModes::BLK | FileModes::Read | FileModes::Write).bits() as usize,
-            nlink: 1,
-            size: self.device.size,
-            block_size: SECTOR_SIZE,
+    pub fn handle_irq(&self) {
+        let mut device = self.device.lock();
+        device.ack_interrupt();
+        while let Some(idx) = device.peek_used() {
+            let Some((queue, slot)) = self.waiters.get(&idx) else { break };
+            let mut slot = slot.lock();
+            let Some(request) = slot.as_mut() else {
+                // Not ours, left for the one polling it.
+                break;
+            };
+            let result = unsafe {
+                let req = request.req.as_ref().unwrap();
+                let resp = request.resp.as_mut().unwrap();
+                let buf = request.buf.as_mut().unwrap();
+                match request.type_ {
+                    VirtIOBlockRequestType::Write => device.complete_write_blocks(idx, req, buf, resp),
+                    VirtIOBlockRequestType::Read => device.complete_read_blocks(idx, req, buf, resp),
+                }
+            };
+            request.result = Some(result.map_err(|_| "Virtio-block request failed.".into()));
+            queue.wake_one();
         }
+        self.queue_free.wake_all();
     }
 }
 
-impl Drop for VirtIOBlockInode {
-    fn drop(&mut self) {
-        todo!()
+impl BlockDevice for VirtIOBlock {
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
     }
-}
 
-impl VirtIOBlockInode {
-    pub fn new(device: Arc<VirtIOBlock>) -> Self {
-        Self {
-            device,
-        }
+    fn sector_count(&self) -> usize {
+        self.size / SECTOR_SIZE
     }
-}
 
-struct VirtIOBlock {
-    device: Spinlock<VirtIOBlk<VirtioHal, MmioTransport>>,
-    condvars: BTreeMap<u16, (Condvar, Spinlock<Option<(*mut BlkReq, *mut BlkResp, *mut [u8], VirtIOBlockRequestType)>>)>,
-    size: usize, // in bytes
-}
-
-impl VirtIOBlock {
-    pub fn new(device: VirtIOBlk<VirtioHal, MmioTransport>) -> Self {
-        let size = device.capacity() as usize * SECTOR_SIZE;
-        let max_idx = device.virt_queue_size();
-        let mut condvars = BTreeMap::new();
-        for i in 0..max_idx {
-            condvars.insert(i, (Condvar::new(), Spinlock::new(None)));
-        }
-        Self {
-            device: Spinlock::new(device),
-            condvars,
-            size,
-        }
+    fn read_sectors(&self, sector: usize, buf: &mut [u8]) -> EmptyResult {
+        self.read_block(sector, buf)
     }
 
-    pub fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        assert_eq!(buf.len() % SECTOR_SIZE, 0, "Read block only accepts buf aligned with SECTOR_SIZE");
-        /*
-        let mut resp = BlkResp::default();
-        let mut req = BlkReq::default();
-        let idx = unsafe { self.device.lock().read_blocks_nb(block_id, &mut req, buf, &mut resp) }
-            .expect("Failed to send read request");
-        let (condvar, data) = self.condvars.get(&idx).unwrap();
-        condvar.wait();
-        *data.lock() = Some((&mut req as *mut BlkReq, &mut resp as *mut BlkResp, buf as *mut [u8], VirtIOBlockRequestType::Read));
-        process::do_yield();
-        assert_eq!(resp.status(), RespStatus::OK, "Failed to read result.");
-        */
-
-        self.device.lock().read_blocks(block_id, buf).unwrap();
+    fn write_sectors(&self, sector: usize, buf: &[u8]) -> EmptyResult {
+        self.write_block(sector, buf)
     }
+}
 
-    pub fn read(&self, offset: usize, buf: &mut [u8]) -> usize {
-        assert!(offset <= self.size, "Read go beyond size.");
-        let block_id = offset / SECTOR_SIZE;
-        let in_block_offset = offset % SECTOR_SIZE;
-        let mut must_read_size = in_block_offset + buf.len();
-        if must_read_size + block_id * SECTOR_SIZE > self.size {
-            must_read_size = self.size - block_id * SECTOR_SIZE;
-        }
-        let rounded_size = utils::round_up_to(must_read_size, SECTOR_SIZE);
-        let pgs = utils::round_up_to(rounded_size, PAGE_SIZE) / PAGE_SIZE;
-        let pgs = PhyPage::alloc_many(pgs);
-        let kbuf = PhyAddr::from(pgs.first().unwrap().id).get_slice_mut(rounded_size);
-        self.read_block(block_id, kbuf);
-        let read_size = if buf.len() + in_block_offset + block_id * SECTOR_SIZE > self.size {
-            self.size - block_id * SECTOR_SIZE - in_block_offset
-        } else {
-            buf.len()
-        };
-        buf.copy_from_slice(&kbuf[in_block_offset..in_block_offset + read_size]);
-        read_size
+impl Device for VirtIOBlock {
+    fn open(self: Arc<Self>, dentry: Arc<DirEntry>, flags: FileOpenFlags) -> Result<Arc<dyn File>> {
+        Ok(Arc::new(BlockDeviceFile::new(self, dentry, flags)))
     }
 
-    pub fn write(&self, offset: usize, buf: &[u8]) -> usize {
-        0
+    fn size(&self) -> usize {
+        self.size
     }
 
-    pub fn handle_irq(&self) {
-        // info!("int.");
-        let mut device = self.device.lock();
-        while let Some(idx) = device.peek_used() {
-            if let Some(condvar) = self.condvars.get(&idx) {
-                // self.device.lock().complete_read_blocks();
-                // condvar.wakeup();
-                let (condvar, data) = condvar;
-                if let Some((req, resp, buf, type_)) = *data.lock() {
-                    unsafe {
-                        let req = req.as_mut().unwrap();
-                        let resp = resp.as_mut().unwrap();
-                        let buf = buf.as_mut().unwrap();
-                        match type_ {
-                            VirtIOBlockRequestType::Write => {
-                                device.complete_write_blocks(idx, req, buf, resp).expect("Failed to complete write");
-                            }
-                            VirtIOBlockRequestType::Read => {
-                                device.complete_read_blocks(idx, req, buf, resp).expect("Failed to complete read");
-                            }
-                        }
-                    }
-                    condvar.wakeup();
-                } else {
-                    panic!("");
-                }
-            }
-        }
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
     }
 }
 
+// Linux has no fixed major for virtio-blk, use the one it usually gets.
+const VIRTIO_BLOCK_MAJOR: usize = 254;
+// Minors of a disk, the disk itself and its partitions.
+const VIRTIO_BLOCK_MINORS: usize = 16;
+
 lazy_static! {
     static ref VIRTIO_BLOCKS: Spinlock<Vec<Arc<VirtIOBlock>>> = Spinlock::new(Vec::new());
 }
 
+// vda, vdb, ..., vdz, vdaa, ...
+fn disk_name(index: usize) -> String {
+    let mut name = String::new();
+    let mut n = index + 1;
+    while n > 0 {
+        n -= 1;
+        name.insert(0, (b'a' + (n % 26) as u8) as char);
+        n /= 26;
+    }
+    format!("vd{}", name)
+}
+
 pub fn init(device: VirtIOBlk<VirtioHal, MmioTransport>, irq: usize) {
     let device = Arc::new(VirtIOBlock::new(device));
-    VIRTIO_BLOCKS.lock().push(device.clone());
-    let capacity = device.device.lock().capacity() as usize * SECTOR_SIZE;
-    info!("Detected {} Bytes virtio-block device.", capacity);
-
-    let dev = DirEntry::from_path("/dev", None).expect("Failed to get /dev on vfs.");
-    // TODO: blk0 not hard coded.
-    dev.link(Arc::new(VirtIOBlockInode::new(device.clone())), "blk0").expect("Failed to link /dev/blk0 on vfs");
+    let index = {
+        let mut blocks = VIRTIO_BLOCKS.lock();
+        blocks.push(device.clone());
+        blocks.len() - 1
+    };
+    let name = disk_name(index);
+    info!("Detected {} Bytes virtio-block device {}.", device.size, name);
+
+    let minor = index * VIRTIO_BLOCK_MINORS;
+    register_block_device(&name, VIRTIO_BLOCK_MAJOR, minor, device.clone())
+        .expect("Failed to register virtio-block device.");
+    if let Err(err) = register_partitions(&name, VIRTIO_BLOCK_MAJOR, minor, VIRTIO_BLOCK_MINORS - 1, device) {
+        warn!("Failed to read partition table of {}: {:?}", name, err);
+    }
 
     plic::enable_irq(irq);
     register_interrupt_handler(irq, interrupt_handler).expect("Failed to register interrupt");
@@ -0,0 +1,281 @@
+//! # Buffer cache
+//!
+//! Shared cache of block device contents in page sized buffers. Reads and writes of block device
+//! files go through here, dirty buffers reach the device when evicted or synced.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::min;
+use lazy_static::lazy_static;
+use crate::core::Spinlock;
+use crate::process::SleepLock;
+use crate::filesystem::{DirEntry, File, FileOpenFlags, SeekPosition};
+use crate::memory::{Addr, PAGE_SIZE, PhyAddr, PhyPage};
+use crate::utils::error::{EmptyResult, KernelError, Result};
+use crate::utils::lru::LruCache;
+
+const BUFFER_SIZE: usize = PAGE_SIZE;
+// 4MiB
+const MAX_BUFFERS: usize = 1024;
+
+/// Sector addressed device below the buffer cache.
+pub trait BlockDevice {
+    fn sector_size(&self) -> usize;
+    fn sector_count(&self) -> usize;
+    // buf的长度是扇区大小的整数倍
+    fn read_sectors(&self, sector: usize, buf: &mut [u8]) -> EmptyResult;
+    fn write_sectors(&self, sector: usize, buf: &[u8]) -> EmptyResult;
+}
+
+fn device_size(device: &Arc<dyn BlockDevice>) -> usize {
+    device.sector_size() * device.sector_count()
+}
+
+// Buffers keep their device alive, so the address is never reused while cached.
+fn device_key(device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(device) as *const () as usize
+}
+
+struct Buffer {
+    device: Arc<dyn BlockDevice>,
+    index: usize,
+    data: SleepLock<BufferData>,
+}
+
+struct BufferData {
+    page: PhyPage,
+    // Shorter than BUFFER_SIZE only at the end of device.
+    len: usize,
+    loaded: bool,
+    dirty: bool,
+}
+
+impl BufferData {
+    fn bytes(&mut self) -> &mut [u8] {
+        PhyAddr::from(self.page.id).get_slice_mut(self.len)
+    }
+}
+
+impl Buffer {
+    fn first_sector(&self) -> usize {
+        self.index * BUFFER_SIZE / self.device.sector_size()
+    }
+
+    fn load(&self, data: &mut BufferData) -> EmptyResult {
+        if !data.loaded {
+            self.device.read_sectors(self.first_sector(), data.bytes())?;
+            data.loaded = true;
+        }
+        Ok(())
+    }
+
+    fn write_back(&self, data: &mut BufferData) -> EmptyResult {
+        if data.dirty {
+            self.device.write_sectors(self.first_sector(), data.bytes())?;
+            data.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+lazy_static! {
+    static ref BUFFERS: Spinlock<LruCache<(usize, usize), Arc<Buffer>>> = Spinlock::new(LruCache::new());
+}
+
+fn get_buffer(device: &Arc<dyn BlockDevice>, index: usize) -> Result<Arc<Buffer>> {
+    let key = (device_key(device), index);
+    loop {
+        let mut buffers = BUFFERS.lock();
+        if let Some(buffer) = buffers.get(&key) {
+            return Ok(buffer.clone());
+        }
+        // Buffers being used by others stay, cache may go over the limit for a while.
+        if buffers.len() >= MAX_BUFFERS {
+            let idle = |buffer: &Arc<Buffer>| Arc::strong_count(buffer) == 1;
+            if buffers.evict(|buffer| idle(buffer) && buffer.data.try_lock().is_some_and(|data| !data.dirty)).is_some() {
+                continue;
+            }
+            // Writing back may sleep, do it without cache locked and look again.
+            if let Some(victim) = buffers.find_lru(idle).cloned() {
+                drop(buffers);
+                victim.write_back(&mut victim.data.lock())?;
+                continue;
+            }
+        }
+        let buffer = Arc::new(Buffer {
+            device: device.clone(),
+            index,
+            data: SleepLock::new(BufferData {
+                page: PhyPage::alloc(),
+                len: min(BUFFER_SIZE, device_size(device) - index * BUFFER_SIZE),
+                loaded: false,
+                dirty: false,
+            }),
+        });
+        buffers.insert(key, buffer.clone());
+        return Ok(buffer);
+    }
+}
+
+pub fn read(device: &Arc<dyn BlockDevice>, offset: usize, buf: &mut [u8]) -> Result<usize> {
+    let size = device_size(device);
+    if offset >= size {
+        return Ok(0);
+    }
+    let len = min(buf.len(), size - offset);
+    let mut done = 0;
+    while done < len {
+        let pos = offset + done;
+        let in_buffer = pos % BUFFER_SIZE;
+        let chunk = min(len - done, BUFFER_SIZE - in_buffer);
+        let buffer = get_buffer(device, pos / BUFFER_SIZE)?;
+        let mut data = buffer.data.lock();
+        buffer.load(&mut data)?;
+        buf[done..done + chunk].copy_from_slice(&data.bytes()[in_buffer..in_buffer + chunk]);
+        done += chunk;
+    }
+    Ok(len)
+}
+
+pub fn write(device: &Arc<dyn BlockDevice>, offset: usize, buf: &[u8]) -> Result<usize> {
+    let size = device_size(device);
+    if offset >= size {
+        return Ok(0);
+    }
+    let len = min(buf.len(), size - offset);
+    let mut done = 0;
+    while done < len {
+        let pos = offset + done;
+        let in_buffer = pos % BUFFER_SIZE;
+        let chunk = min(len - done, BUFFER_SIZE - in_buffer);
+        let buffer = get_buffer(device, pos / BUFFER_SIZE)?;
+        let mut data = buffer.data.lock();
+        if in_buffer == 0 && chunk == data.len {
+            // Overwritten as a whole, no need to read it.
+            data.loaded = true;
+        } else {
+            buffer.load(&mut data)?;
+        }
+        data.bytes()[in_buffer..in_buffer + chunk].copy_from_slice(&buf[done..done + chunk]);
+        data.dirty = true;
+        done += chunk;
+    }
+    Ok(len)
+}
+
+fn write_back_all(buffers: Vec<Arc<Buffer>>) -> EmptyResult {
+    for buffer in buffers {
+        buffer.write_back(&mut buffer.data.lock())?;
+    }
+    Ok(())
+}
+
+/// Write dirty buffers of `device` back.
+pub fn sync_device(device: &Arc<dyn BlockDevice>) -> EmptyResult {
+    let key = device_key(device);
+    let buffers = BUFFERS.lock().range((key, 0)..=(key, usize::MAX))
+        .map(|(_, buffer)| buffer.clone())
+        .collect();
+    write_back_all(buffers)
+}
+
+/// Write dirty buffers of all devices back.
+pub fn sync_all() -> EmptyResult {
+    let buffers = BUFFERS.lock().values().cloned().collect();
+    write_back_all(buffers)
+}
+
+pub fn cached_bytes() -> usize {
+    BUFFERS.lock().len() * BUFFER_SIZE
+}
+
+/// Device file of block device, goes through the buffer cache. Partition file sees its own range
+/// of the disk only, and shares buffers with the whole disk.
+pub struct BlockDeviceFile {
+    device: Arc<dyn BlockDevice>,
+    dentry: Arc<DirEntry>,
+    // Range on device in bytes.
+    start: usize,
+    size: usize,
+    cur: SleepLock<usize>,
+    // Opened for writing, read-only mounts open their devices without it.
+    writable: bool,
+}
+
+impl BlockDeviceFile {
+    pub fn new(device: Arc<dyn BlockDevice>, dentry: Arc<DirEntry>, flags: FileOpenFlags) -> Self {
+        let size = device_size(&device);
+        Self::with_range(device, dentry, 0, size, flags)
+    }
+
+    pub fn with_range(device: Arc<dyn BlockDevice>, dentry: Arc<DirEntry>, start: usize, size: usize, flags: FileOpenFlags) -> Self {
+        assert!(start + size <= device_size(&device), "Range beyond block device.");
+        Self {
+            device,
+            dentry,
+            start,
+            size,
+            cur: SleepLock::new(0),
+            writable: flags.is_write(),
+        }
+    }
+}
+
+impl File for BlockDeviceFile {
+    fn seek(&self, offset: isize, whence: SeekPosition) -> Result<usize> {
+        let mut cur = self.cur.lock();
+        let base = match whence {
+            SeekPosition::Set => 0,
+            SeekPosition::Cur => *cur as isize,
+            SeekPosition::End => self.size as isize,
+        };
+        let new_offset = base + offset;
+        if new_offset < 0 {
+            return Err("Seek before start of device.".into());
+        }
+        *cur = min(new_offset as usize, self.size);
+        Ok(*cur)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut cur = self.cur.lock();
+        let read_bytes = self.read_at(*cur, buf)?;
+        *cur += read_bytes;
+        Ok(read_bytes)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        let mut cur = self.cur.lock();
+        let write_bytes = self.write_at(*cur, buf)?;
+        *cur += write_bytes;
+        Ok(write_bytes)
+    }
+
+    fn close(&self) -> EmptyResult {
+        Ok(())
+    }
+
+    fn get_dentry(&self) -> Result<Arc<DirEntry>> {
+        Ok(self.dentry.clone())
+    }
+
+    fn sync(&self) -> EmptyResult {
+        sync_device(&self.device)
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let len = min(buf.len(), self.size.saturating_sub(offset));
+        read(&self.device, self.start + offset, &mut buf[..len])
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        if !self.writable {
+            return Err(KernelError::READ_ONLY);
+        }
+        let len = min(buf.len(), self.size.saturating_sub(offset));
+        write(&self.device, self.start + offset, &buf[..len])
+    }
+}
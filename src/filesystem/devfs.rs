@@ -0,0 +1,322 @@
+//! # Devfs
+//!
+//! Filesystem holding device nodes. Drivers register their char or block devices here with
+//! major/minor numbers, and nodes show up under `/dev` as soon as they are registered.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use lazy_static::lazy_static;
+use crate::core::Spinlock;
+use crate::device::{clock, timer};
+use crate::filesystem::{DirEntry, DirEntryType, File, FileModes, FileOpenFlags, Filesystem, Inode, InodeStat, MountFlags, register_filesystem, SeekPosition};
+use crate::memory::PAGE_SIZE;
+use crate::utils::error::{EmptyResult, Result};
+
+// Linux major numbers of memory devices.
+const MEM_MAJOR: usize = 1;
+
+/// Driver side of a device node, devfs calls into it when the node is opened.
+pub trait Device {
+    fn open(self: Arc<Self>, dentry: Arc<DirEntry>, flags: FileOpenFlags) -> Result<Arc<dyn File>>;
+    // 块设备的大小（字节），字符设备为0
+    fn size(&self) -> usize {
+        0
+    }
+    // 首选的IO大小
+    fn block_size(&self) -> usize {
+        PAGE_SIZE
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum DeviceType {
+    Char,
+    Block,
+}
+
+struct DeviceNode {
+    ino: usize,
+    type_: DeviceType,
+    major: usize,
+    minor: usize,
+    device: Arc<dyn Device>,
+    ctime: Duration,
+}
+
+lazy_static! {
+    static ref DEVICES: Spinlock<BTreeMap<String, Arc<DeviceNode>>> = Spinlock::new(BTreeMap::new());
+}
+
+// ino 1 is the root of devfs.
+static NEXT_INO: AtomicUsize = AtomicUsize::new(2);
+
+/// Encode device number the way glibc and musl `makedev` do.
+pub fn makedev(major: usize, minor: usize) -> usize {
+    ((major & 0xfffff000) << 32) | ((major & 0xfff) << 8)
+        | ((minor & 0xffffff00) << 12) | (minor & 0xff)
+}
+
+fn register_device(name: &str, type_: DeviceType, major: usize, minor: usize, device: Arc<dyn Device>) -> EmptyResult {
+    let mut devices = DEVICES.lock();
+    if devices.contains_key(name) {
+        return Err("Device name already registered.".into());
+    }
+    if devices.values().any(|node| node.type_ == type_ && node.major == major && node.minor == minor) {
+        return Err("Device number already registered.".into());
+    }
+    devices.insert(name.to_string(), Arc::new(DeviceNode {
+        ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+        type_,
+        major,
+        minor,
+        device,
+        ctime: clock::realtime(),
+    }));
+    Ok(())
+}
+
+pub fn register_char_device(name: &str, major: usize, minor: usize, device: Arc<dyn Device>) -> EmptyResult {
+    register_device(name, DeviceType::Char, major, minor, device)
+}
+
+pub fn register_block_device(name: &str, major: usize, minor: usize, device: Arc<dyn Device>) -> EmptyResult {
+    register_device(name, DeviceType::Block, major, minor, device)
+}
+
+/// Remove node from `/dev`, files already opened keep working.
+pub fn unregister_device(name: &str) -> EmptyResult {
+    DEVICES.lock().remove(name).map(|_| ()).ok_or("Device not registered.".into())
+}
+
+enum DevfsInode {
+    Root,
+    Node(Arc<DeviceNode>),
+}
+
+impl DevfsInode {
+    fn make_dentry(name: &str, node: Arc<DeviceNode>, this_dentry: Weak<DirEntry>) -> DirEntry {
+        DirEntry::new(Some(this_dentry), name.to_string(), Some(Arc::new(DevfsInode::Node(node))), DirEntryType::File)
+    }
+}
+
+impl Inode for DevfsInode {
+    fn lookup(&self, name: &str, this_dentry: Weak<DirEntry>) -> Option<DirEntry> {
+        match self {
+            DevfsInode::Root => {
+                let node = DEVICES.lock().get(name).cloned()?;
+                Some(Self::make_dentry(name, node, this_dentry))
+            }
+            DevfsInode::Node(_) => None
+        }
+    }
+
+    fn link(&self, inode: Arc<dyn Inode>, name: &str) -> EmptyResult {
+        Err("Devfs nodes are created by registering devices.".into())
+    }
+
+    fn unlink(&self, name: &str) -> EmptyResult {
+        Err("Devfs nodes are removed by unregistering devices.".into())
+    }
+
+    fn mkdir(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        Err("Cannot mkdir in devfs.".into())
+    }
+
+    fn rmdir(&self, name: &str) -> EmptyResult {
+        Err("Cannot rmdir in devfs.".into())
+    }
+
+    fn read_dir(&self, this_dentry: Weak<DirEntry>) -> Result<Vec<DirEntry>> {
+        match self {
+            DevfsInode::Root => Ok(DEVICES.lock().iter()
+                .map(|(name, node)| Self::make_dentry(name, node.clone(), this_dentry.clone()))
+                .collect()),
+            DevfsInode::Node(_) => Err("Not a directory.".into())
+        }
+    }
+
+    fn open(&self, dentry: Arc<DirEntry>, flags: FileOpenFlags, mode: FileModes) -> Result<Arc<dyn File>> {
+        match self {
+            DevfsInode::Root => Err("Cannot open devfs root as file.".into()),
+            DevfsInode::Node(node) => node.device.clone().open(dentry, flags)
+        }
+    }
+
+    fn get_dentry_type(&self) -> DirEntryType {
+        match self {
+            DevfsInode::Root => DirEntryType::Dir,
+            DevfsInode::Node(_) => DirEntryType::File
+        }
+    }
+
+    fn get_stat(&self) -> InodeStat {
+        match self {
+            DevfsInode::Root => InodeStat {
+                ino: 1,
+                mode: (FileModes::DIRECTORY | FileModes::Read | FileModes::Exec | FileModes::OwnerWrite).bits() as usize,
+                nlink: 2,
+                rdev: 0,
+                size: 0,
+                block_size: PAGE_SIZE,
+                atime: Duration::ZERO,
+                mtime: Duration::ZERO,
+                ctime: Duration::ZERO,
+            },
+            DevfsInode::Node(node) => {
+                let mode = match node.type_ {
+                    DeviceType::Char => FileModes::CHAR | FileModes::Read | FileModes::Write,
+                    DeviceType::Block => FileModes::BLK | FileModes::OwnerRead | FileModes::OwnerWrite
+                        | FileModes::GroupRead | FileModes::GroupWrite,
+                };
+                InodeStat {
+                    ino: node.ino,
+                    mode: mode.bits() as usize,
+                    nlink: 1,
+                    rdev: makedev(node.major, node.minor),
+                    size: node.device.size(),
+                    block_size: node.device.block_size(),
+                    atime: node.ctime,
+                    mtime: node.ctime,
+                    ctime: node.ctime,
+                }
+            }
+        }
+    }
+
+    fn is_dynamic(&self) -> bool {
+        // Devices come and go with their drivers.
+        matches!(self, DevfsInode::Root)
+    }
+}
+
+/// Built-in memory char devices.
+#[derive(Copy, Clone, PartialEq)]
+enum MemDevice {
+    Null,
+    Zero,
+    Full,
+    Random,
+}
+
+impl Device for MemDevice {
+    fn open(self: Arc<Self>, dentry: Arc<DirEntry>, flags: FileOpenFlags) -> Result<Arc<dyn File>> {
+        Ok(Arc::new(MemDeviceFile {
+            device: *self,
+            dentry,
+        }))
+    }
+}
+
+lazy_static! {
+    // xorshift64* state, seeded by the time since boot at first use.
+    static ref RANDOM_STATE: Spinlock<u64> = Spinlock::new(timer::current_time().as_nanos() as u64 | 1);
+}
+
+// 伪随机数，不具备密码学安全性
+fn fill_random(buf: &mut [u8]) {
+    let mut state = RANDOM_STATE.lock();
+    for chunk in buf.chunks_mut(8) {
+        *state ^= *state >> 12;
+        *state ^= *state << 25;
+        *state ^= *state >> 27;
+        let value = state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        chunk.copy_from_slice(&value.to_le_bytes()[..chunk.len()]);
+    }
+}
+
+// Written bytes are mixed into the state, like writing to /dev/random on Linux.
+fn mix_random(buf: &[u8]) {
+    let mut state = RANDOM_STATE.lock();
+    for chunk in buf.chunks(8) {
+        let mut bytes = [0u8; 8];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        *state = (*state ^ u64::from_le_bytes(bytes)).rotate_left(17) | 1;
+    }
+}
+
+struct MemDeviceFile {
+    device: MemDevice,
+    dentry: Arc<DirEntry>,
+}
+
+impl File for MemDeviceFile {
+    fn seek(&self, offset: isize, whence: SeekPosition) -> Result<usize> {
+        // Position means nothing to these devices.
+        Ok(0)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        match self.device {
+            MemDevice::Null => Ok(0),
+            MemDevice::Zero | MemDevice::Full => {
+                buf.fill(0);
+                Ok(buf.len())
+            }
+            MemDevice::Random => {
+                fill_random(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        match self.device {
+            MemDevice::Null | MemDevice::Zero => Ok(buf.len()),
+            MemDevice::Full => Err("No space left on device.".into()),
+            MemDevice::Random => {
+                mix_random(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn close(&self) -> EmptyResult {
+        Ok(())
+    }
+
+    fn get_dentry(&self) -> Result<Arc<DirEntry>> {
+        Ok(self.dentry.clone())
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        self.read(buf)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        self.write(buf)
+    }
+}
+
+struct Devfs {}
+
+impl Filesystem for Devfs {
+    fn new() -> Self {
+        Self {}
+    }
+
+    fn mount(&self, device: Option<Arc<dyn File>>, mount_point: Arc<DirEntry>, flags: MountFlags) -> Result<Arc<dyn Inode>> {
+        Ok(Arc::new(DevfsInode::Root))
+    }
+}
+
+pub fn init() {
+    register_filesystem("devfs", Box::new(Devfs::new()));
+    let random = Arc::new(MemDevice::Random);
+    [
+        ("null", 3, Arc::new(MemDevice::Null)),
+        ("zero", 5, Arc::new(MemDevice::Zero)),
+        ("full", 7, Arc::new(MemDevice::Full)),
+        ("random", 8, random.clone()),
+        ("urandom", 9, random),
+    ].into_iter().for_each(|(name, minor, device)| {
+        register_char_device(name, MEM_MAJOR, minor, device).expect("Failed to register memory device.");
+    });
+}
@@ -0,0 +1,1462 @@
+//! # Ext2
+//!
+//! Read-write ext2 filesystem on top of a block device file.
+//! Supports revision 0/1 images with only `filetype` incompatible feature, as built by `mke2fs -t ext2`.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::mem::size_of;
+use core::time::Duration;
+use crate::core::Spinlock;
+use crate::process::SleepLock;
+use crate::device::clock;
+use crate::filesystem::{DirEntry, DirEntryType, File, FileModes, FileOpenFlags, Filesystem, Inode, InodeStat, MountFlags, makedev, register_filesystem, RenameFlags, SeekPosition};
+use crate::filesystem::page_cache::PageCache;
+use crate::memory::PAGE_SIZE;
+use crate::utils::error::{EmptyResult, KernelError, Result};
+
+const EXT2_MAGIC: u16 = 0xEF53;
+const SUPERBLOCK_OFFSET: usize = 1024;
+const ROOT_INO: u32 = 2;
+
+const GOOD_OLD_REV: u32 = 0;
+const GOOD_OLD_INODE_SIZE: usize = 128;
+const GOOD_OLD_FIRST_INO: u32 = 11;
+
+const FEATURE_INCOMPAT_FILETYPE: u32 = 0x0002;
+const FEATURE_RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+const FEATURE_RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+const FEATURE_RO_COMPAT_BTREE_DIR: u32 = 0x0004;
+
+// Inode flag of hashed directory, cleared once we modify the directory linearly.
+const INDEX_FL: u32 = 0x1000;
+
+const DIRECT_BLOCKS: usize = 12;
+// Target shorter than this is stored inside i_block.
+const FAST_SYMLINK_MAX: usize = 60;
+// Most links an inode can have, EXT2_LINK_MAX.
+const LINK_MAX: u16 = 32000;
+const NAME_MAX: usize = 255;
+
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+const FT_SYMLINK: u8 = 7;
+
+const S_IFMT: u16 = 0o170_000;
+
+/* On-disk structures, all little endian which matches our target. */
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Ext2SuperBlock {
+    inodes_count: u32,
+    blocks_count: u32,
+    r_blocks_count: u32,
+    free_blocks_count: u32,
+    free_inodes_count: u32,
+    first_data_block: u32,
+    log_block_size: u32,
+    log_frag_size: u32,
+    blocks_per_group: u32,
+    frags_per_group: u32,
+    inodes_per_group: u32,
+    mtime: u32,
+    wtime: u32,
+    mnt_count: u16,
+    max_mnt_count: u16,
+    magic: u16,
+    state: u16,
+    errors: u16,
+    minor_rev_level: u16,
+    lastcheck: u32,
+    checkinterval: u32,
+    creator_os: u32,
+    rev_level: u32,
+    def_resuid: u16,
+    def_resgid: u16,
+    // Following fields are valid only for dynamic revision.
+    first_ino: u32,
+    inode_size: u16,
+    block_group_nr: u16,
+    feature_compat: u32,
+    feature_incompat: u32,
+    feature_ro_compat: u32,
+    rest: [u8; 920],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Ext2GroupDesc {
+    block_bitmap: u32,
+    inode_bitmap: u32,
+    inode_table: u32,
+    free_blocks_count: u16,
+    free_inodes_count: u16,
+    used_dirs_count: u16,
+    pad: u16,
+    reserved: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Ext2DiskInode {
+    mode: u16,
+    uid: u16,
+    size: u32,
+    atime: u32,
+    ctime: u32,
+    mtime: u32,
+    dtime: u32,
+    gid: u16,
+    links_count: u16,
+    // In 512 bytes sectors
+    blocks: u32,
+    flags: u32,
+    osd1: u32,
+    block: [u32; 15],
+    generation: u32,
+    file_acl: u32,
+    size_high: u32,
+    faddr: u32,
+    osd2: [u8; 12],
+}
+
+// Header of directory entry, name follows.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Ext2DirEntryHead {
+    inode: u32,
+    rec_len: u16,
+    name_len: u8,
+    file_type: u8,
+}
+
+fn from_bytes<T: Copy>(buf: &[u8]) -> T {
+    assert!(buf.len() >= size_of::<T>());
+    unsafe { core::ptr::read_unaligned(buf.as_ptr() as *const T) }
+}
+
+fn to_bytes<T: Copy>(value: &T) -> &[u8] {
+    unsafe {
+        core::slice::from_raw_parts(
+            value as *const T as *const u8,
+            size_of::<T>(),
+        )
+    }
+}
+
+fn now() -> u32 {
+    clock::realtime().as_secs() as u32
+}
+
+// Size of a directory entry holding `name_len` bytes of name.
+fn dir_entry_size(name_len: usize) -> usize {
+    (size_of::<Ext2DirEntryHead>() + name_len + 3) & !3
+}
+
+impl Ext2DiskInode {
+    fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == FileModes::DIRECTORY.bits() as u16
+    }
+
+    fn is_regular(&self) -> bool {
+        self.mode & S_IFMT == FileModes::REGULAR.bits() as u16
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.mode & S_IFMT == FileModes::LINK.bits() as u16
+    }
+
+    /// Type recorded in directory entries pointing to this inode.
+    fn file_type(&self) -> u8 {
+        if self.is_dir() {
+            FT_DIR
+        } else if self.is_symlink() {
+            FT_SYMLINK
+        } else {
+            FT_REG_FILE
+        }
+    }
+
+    fn get_size(&self) -> usize {
+        if self.is_regular() {
+            self.size as usize | (self.size_high as usize) << 32
+        } else {
+            self.size as usize
+        }
+    }
+
+    fn set_size(&mut self, size: usize) {
+        self.size = size as u32;
+        if self.is_regular() {
+            self.size_high = (size >> 32) as u32;
+        }
+    }
+
+    /// Fast symlink keeps its target in i_block, and owns no data block except for xattr one.
+    fn is_fast_symlink(&self, block_size: usize) -> bool {
+        let xattr_sectors = if self.file_acl != 0 { block_size / 512 } else { 0 };
+        self.is_symlink() && self.blocks as usize == xattr_sectors
+    }
+
+    /// Device number of char and block device inodes, kept in i_block like Linux does.
+    /// block[0] holds the old 8:8 encoding, block[1] the new 12:20 one when block[0] is zero.
+    fn get_rdev(&self) -> usize {
+        let type_ = self.mode & S_IFMT;
+        if type_ != FileModes::CHAR.bits() as u16 && type_ != FileModes::BLK.bits() as u16 {
+            return 0;
+        }
+        let (major, minor) = if self.block[0] != 0 {
+            let dev = self.block[0] as usize;
+            ((dev >> 8) & 0xff, dev & 0xff)
+        } else {
+            let dev = self.block[1] as usize;
+            ((dev & 0xfff00) >> 8, (dev & 0xff) | ((dev >> 12) & 0xfff00))
+        };
+        makedev(major, minor)
+    }
+
+    fn block_bytes(&self) -> &[u8] {
+        to_bytes(&self.block)
+    }
+}
+
+struct DirRecord {
+    name: String,
+    ino: u32,
+}
+
+struct Ext2Inner {
+    device: Arc<dyn File>,
+    block_size: usize,
+    inode_size: usize,
+    first_ino: u32,
+    has_filetype: bool,
+    sb: Ext2SuperBlock,
+    groups: Vec<Ext2GroupDesc>,
+    // Mounted read-only, nothing is written back to the device.
+    readonly: bool,
+}
+
+// Device file is only used under the lock of Ext2Fs.
+unsafe impl Send for Ext2Inner {}
+
+impl Ext2Inner {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> EmptyResult {
+        self.device.seek(offset as isize, SeekPosition::Set)?;
+        let mut done = 0;
+        while done < buf.len() {
+            let read_bytes = self.device.read(&mut buf[done..])?;
+            if read_bytes == 0 {
+                return Err("Ext2 read beyond device.".into());
+            }
+            done += read_bytes;
+        }
+        Ok(())
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> EmptyResult {
+        self.device.seek(offset as isize, SeekPosition::Set)?;
+        let mut done = 0;
+        while done < buf.len() {
+            let write_bytes = self.device.write(&buf[done..])?;
+            if write_bytes == 0 {
+                return Err("Ext2 write beyond device.".into());
+            }
+            done += write_bytes;
+        }
+        Ok(())
+    }
+
+    fn read_block(&self, block: u32) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; self.block_size];
+        self.read_at(block as usize * self.block_size, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn write_block(&self, block: u32, buf: &[u8]) -> EmptyResult {
+        assert_eq!(buf.len(), self.block_size);
+        self.write_at(block as usize * self.block_size, buf)
+    }
+
+    fn read_ptr(&self, block: u32, index: usize) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        self.read_at(block as usize * self.block_size + index * 4, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn write_ptr(&self, block: u32, index: usize, ptr: u32) -> EmptyResult {
+        self.write_at(block as usize * self.block_size + index * 4, &ptr.to_le_bytes())
+    }
+
+    fn write_super(&self) -> EmptyResult {
+        self.write_at(SUPERBLOCK_OFFSET, to_bytes(&self.sb))
+    }
+
+    fn group_desc_offset(&self, group: usize) -> usize {
+        // Descriptor table is in the block right after superblock.
+        (self.sb.first_data_block as usize + 1) * self.block_size + group * size_of::<Ext2GroupDesc>()
+    }
+
+    fn write_group(&self, group: usize) -> EmptyResult {
+        self.write_at(self.group_desc_offset(group), to_bytes(&self.groups[group]))
+    }
+
+    fn inode_offset(&self, ino: u32) -> Result<usize> {
+        if ino == 0 || ino > self.sb.inodes_count {
+            return Err("Ext2 inode number out of range.".into());
+        }
+        let index = (ino - 1) as usize;
+        let ipg = self.sb.inodes_per_group as usize;
+        let table = self.groups[index / ipg].inode_table as usize;
+        Ok(table * self.block_size + (index % ipg) * self.inode_size)
+    }
+
+    fn read_inode(&self, ino: u32) -> Result<Ext2DiskInode> {
+        let mut buf = [0u8; GOOD_OLD_INODE_SIZE];
+        self.read_at(self.inode_offset(ino)?, &mut buf)?;
+        Ok(from_bytes(&buf))
+    }
+
+    fn write_inode(&self, ino: u32, inode: &Ext2DiskInode) -> EmptyResult {
+        // Only the old 128 bytes are touched, extra fields of large inode are kept.
+        self.write_at(self.inode_offset(ino)?, to_bytes(inode))
+    }
+
+    fn inode_group(&self, ino: u32) -> usize {
+        (ino - 1) as usize / self.sb.inodes_per_group as usize
+    }
+
+    /// Find and set a clear bit in bitmap block, searching groups from `goal`.
+    /// `avail` returns free count and number of valid bits of a group.
+    fn alloc_bit(&mut self, goal: usize, bitmap_of: fn(&Ext2GroupDesc) -> u32, avail: impl Fn(&Self, usize) -> (usize, usize)) -> Result<(usize, usize)> {
+        let count = self.groups.len();
+        for i in 0..count {
+            let group = (goal + i) % count;
+            let (free, bits) = avail(self, group);
+            if free == 0 {
+                continue;
+            }
+            let bitmap_block = bitmap_of(&self.groups[group]);
+            let mut bitmap = self.read_block(bitmap_block)?;
+            if let Some(bit) = (0..bits).find(|bit| bitmap[bit / 8] & (1 << (bit % 8)) == 0) {
+                bitmap[bit / 8] |= 1 << (bit % 8);
+                self.write_block(bitmap_block, &bitmap)?;
+                return Ok((group, bit));
+            }
+        }
+        Err(KernelError::NO_SPACE)
+    }
+
+    fn clear_bit(&self, bitmap_block: u32, bit: usize) -> Result<bool> {
+        let mut bitmap = self.read_block(bitmap_block)?;
+        let was_set = bitmap[bit / 8] & (1 << (bit % 8)) != 0;
+        bitmap[bit / 8] &= !(1 << (bit % 8));
+        self.write_block(bitmap_block, &bitmap)?;
+        Ok(was_set)
+    }
+
+    fn group_blocks(&self, group: usize) -> usize {
+        let bpg = self.sb.blocks_per_group as usize;
+        let total = (self.sb.blocks_count - self.sb.first_data_block) as usize;
+        min(bpg, total - group * bpg)
+    }
+
+    /// Allocate a zeroed block, preferring group `goal`.
+    fn alloc_block(&mut self, goal: usize) -> Result<u32> {
+        let (group, bit) = self.alloc_bit(goal, |g| g.block_bitmap, |fs, group| {
+            (fs.groups[group].free_blocks_count as usize, fs.group_blocks(group))
+        })?;
+        // Counts come from disk.
+        self.groups[group].free_blocks_count = self.groups[group].free_blocks_count.checked_sub(1).ok_or(KernelError::CORRUPTED)?;
+        self.sb.free_blocks_count = self.sb.free_blocks_count.checked_sub(1).ok_or(KernelError::CORRUPTED)?;
+        self.write_group(group)?;
+        self.write_super()?;
+        let block = (self.sb.first_data_block as usize + group * self.sb.blocks_per_group as usize + bit) as u32;
+        self.write_block(block, &vec![0u8; self.block_size])?;
+        Ok(block)
+    }
+
+    fn free_block(&mut self, block: u32) -> EmptyResult {
+        // Block pointer comes from disk.
+        if block < self.sb.first_data_block || block >= self.sb.blocks_count {
+            return Err(KernelError::CORRUPTED);
+        }
+        let index = (block - self.sb.first_data_block) as usize;
+        let bpg = self.sb.blocks_per_group as usize;
+        let group = index / bpg;
+        if group >= self.groups.len() {
+            return Err(KernelError::CORRUPTED);
+        }
+        if self.clear_bit(self.groups[group].block_bitmap, index % bpg)? {
+            self.groups[group].free_blocks_count += 1;
+            self.sb.free_blocks_count += 1;
+            self.write_group(group)?;
+            self.write_super()?;
+        }
+        Ok(())
+    }
+
+    /// Allocate an inode number, preferring group `goal`. The inode itself is not initialized.
+    fn alloc_inode(&mut self, goal: usize, is_dir: bool) -> Result<u32> {
+        let (group, bit) = self.alloc_bit(goal, |g| g.inode_bitmap, |fs, group| {
+            (fs.groups[group].free_inodes_count as usize, fs.sb.inodes_per_group as usize)
+        })?;
+        let ino = (group * self.sb.inodes_per_group as usize + bit + 1) as u32;
+        if ino < self.first_ino {
+            // Reserved inodes are marked in bitmap by mke2fs, so this means broken image.
+            return Err("Ext2 allocated a reserved inode.".into());
+        }
+        self.groups[group].free_inodes_count = self.groups[group].free_inodes_count.checked_sub(1).ok_or(KernelError::CORRUPTED)?;
+        self.sb.free_inodes_count = self.sb.free_inodes_count.checked_sub(1).ok_or(KernelError::CORRUPTED)?;
+        if is_dir {
+            self.groups[group].used_dirs_count += 1;
+        }
+        self.write_group(group)?;
+        self.write_super()?;
+        Ok(ino)
+    }
+
+    /// Free inode and its blocks if nothing links to it anymore.
+    fn free_inode_if_dead(&mut self, ino: u32) -> EmptyResult {
+        let mut inode = self.read_inode(ino)?;
+        if inode.links_count != 0 || inode.dtime != 0 {
+            return Ok(());
+        }
+        // Left for fsck, like an orphan of a crash.
+        self.check_writable()?;
+        if !inode.is_fast_symlink(self.block_size) {
+            self.truncate_blocks(&mut inode, 0)?;
+        }
+        if inode.file_acl != 0 {
+            // Shared xattr block is not reference counted here, just leave it.
+            inode.file_acl = 0;
+        }
+        inode.dtime = now();
+        self.write_inode(ino, &inode)?;
+
+        let group = self.inode_group(ino);
+        let bit = (ino - 1) as usize % self.sb.inodes_per_group as usize;
+        if self.clear_bit(self.groups[group].inode_bitmap, bit)? {
+            self.groups[group].free_inodes_count += 1;
+            if inode.is_dir() {
+                self.groups[group].used_dirs_count = self.groups[group].used_dirs_count.checked_sub(1).ok_or(KernelError::CORRUPTED)?;
+            }
+            self.sb.free_inodes_count += 1;
+            self.write_group(group)?;
+            self.write_super()?;
+        }
+        Ok(())
+    }
+
+    /// Every change to the filesystem checks this first, nothing is written on a read-only mount.
+    fn check_writable(&self) -> EmptyResult {
+        if self.readonly {
+            Err(KernelError::READ_ONLY)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn ptrs_per_block(&self) -> usize {
+        self.block_size / 4
+    }
+
+    /// Map file block index to disk block. 0 means a hole unless `create` is set.
+    fn bmap(&mut self, inode: &mut Ext2DiskInode, ino: u32, index: usize, create: bool) -> Result<u32> {
+        let goal = self.inode_group(ino);
+        let sectors = (self.block_size / 512) as u32;
+        if index < DIRECT_BLOCKS {
+            if inode.block[index] == 0 && create {
+                inode.block[index] = self.alloc_block(goal)?;
+                inode.blocks += sectors;
+            }
+            return Ok(inode.block[index]);
+        }
+
+        let ptrs = self.ptrs_per_block();
+        let mut index = index - DIRECT_BLOCKS;
+        let mut depth = 1;
+        let mut span = ptrs;
+        while index >= span {
+            index -= span;
+            depth += 1;
+            span *= ptrs;
+            if depth > 3 {
+                return Err(KernelError::FILE_TOO_LARGE);
+            }
+        }
+
+        let slot = DIRECT_BLOCKS + depth - 1;
+        if inode.block[slot] == 0 {
+            if !create {
+                return Ok(0);
+            }
+            inode.block[slot] = self.alloc_block(goal)?;
+            inode.blocks += sectors;
+        }
+        let mut block = inode.block[slot];
+        for level in (0..depth).rev() {
+            let per = ptrs.pow(level as u32);
+            let i = index / per;
+            index %= per;
+            let mut next = self.read_ptr(block, i)?;
+            if next == 0 {
+                if !create {
+                    return Ok(0);
+                }
+                next = self.alloc_block(goal)?;
+                inode.blocks += sectors;
+                self.write_ptr(block, i, next)?;
+            }
+            block = next;
+        }
+        Ok(block)
+    }
+
+    /// Free the tree under `block` except for its first `keep` data blocks.
+    /// `depth` 0 is a data block, others are indirect blocks.
+    fn free_tree(&mut self, inode: &mut Ext2DiskInode, block: u32, depth: usize, keep: usize) -> EmptyResult {
+        let sectors = (self.block_size / 512) as u32;
+        if depth > 0 {
+            let ptrs = self.ptrs_per_block();
+            let span = ptrs.pow(depth as u32 - 1);
+            let mut table = self.read_block(block)?;
+            let mut modified = false;
+            for i in 0..ptrs {
+                let child = u32::from_le_bytes(table[i * 4..i * 4 + 4].try_into().unwrap());
+                let child_start = i * span;
+                if child == 0 || keep >= child_start + span {
+                    continue;
+                }
+                if keep <= child_start {
+                    self.free_tree(inode, child, depth - 1, 0)?;
+                    table[i * 4..i * 4 + 4].copy_from_slice(&0u32.to_le_bytes());
+                    modified = true;
+                } else {
+                    self.free_tree(inode, child, depth - 1, keep - child_start)?;
+                }
+            }
+            if keep != 0 && modified {
+                self.write_block(block, &table)?;
+            }
+        }
+        if keep == 0 {
+            self.free_block(block)?;
+            inode.blocks = inode.blocks.checked_sub(sectors).ok_or(KernelError::CORRUPTED)?;
+        }
+        Ok(())
+    }
+
+    /// Free every data block after the first `keep` ones.
+    fn truncate_blocks(&mut self, inode: &mut Ext2DiskInode, keep: usize) -> EmptyResult {
+        for i in keep..DIRECT_BLOCKS {
+            let block = inode.block[i];
+            if block != 0 {
+                self.free_tree(inode, block, 0, 0)?;
+                inode.block[i] = 0;
+            }
+        }
+        let ptrs = self.ptrs_per_block();
+        let mut base = DIRECT_BLOCKS;
+        let mut span = ptrs;
+        for depth in 1..=3 {
+            let slot = DIRECT_BLOCKS + depth - 1;
+            let block = inode.block[slot];
+            if block != 0 && keep < base + span {
+                let keep_here = keep.saturating_sub(base);
+                self.free_tree(inode, block, depth, keep_here)?;
+                if keep_here == 0 {
+                    inode.block[slot] = 0;
+                }
+            }
+            base += span;
+            span *= ptrs;
+        }
+        Ok(())
+    }
+
+    /// Change size of file. Shrinking frees blocks and zeroes the tail of the last block.
+    fn truncate(&mut self, inode: &mut Ext2DiskInode, ino: u32, size: usize) -> EmptyResult {
+        let old_size = inode.get_size();
+        if size < old_size {
+            let keep = (size + self.block_size - 1) / self.block_size;
+            self.truncate_blocks(inode, keep)?;
+            let tail = size % self.block_size;
+            if tail != 0 {
+                let block = self.bmap(inode, ino, size / self.block_size, false)?;
+                if block != 0 {
+                    let zeros = vec![0u8; self.block_size - tail];
+                    self.write_at(block as usize * self.block_size + tail, &zeros)?;
+                }
+            }
+        }
+        inode.set_size(size);
+        Ok(())
+    }
+
+    fn read_data(&mut self, inode: &mut Ext2DiskInode, ino: u32, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let size = inode.get_size();
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = min(buf.len(), size - offset);
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let in_block = pos % self.block_size;
+            let chunk = min(len - done, self.block_size - in_block);
+            let block = self.bmap(inode, ino, pos / self.block_size, false)?;
+            if block == 0 {
+                // Hole reads as zero.
+                buf[done..done + chunk].fill(0);
+            } else {
+                self.read_at(block as usize * self.block_size + in_block, &mut buf[done..done + chunk])?;
+            }
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    fn write_data(&mut self, inode: &mut Ext2DiskInode, ino: u32, offset: usize, buf: &[u8]) -> Result<usize> {
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let in_block = pos % self.block_size;
+            let chunk = min(buf.len() - done, self.block_size - in_block);
+            let block = self.bmap(inode, ino, pos / self.block_size, true)?;
+            self.write_at(block as usize * self.block_size + in_block, &buf[done..done + chunk])?;
+            done += chunk;
+        }
+        if offset + done > inode.get_size() {
+            inode.set_size(offset + done);
+        }
+        Ok(done)
+    }
+
+    /// Call `f` with every directory block, stop once it returns true.
+    /// Block is written back if `f` modified it.
+    fn walk_dir(&mut self, dir: &mut Ext2DiskInode, ino: u32, mut f: impl FnMut(&mut [u8], &mut bool) -> bool) -> Result<bool> {
+        let blocks = dir.get_size() / self.block_size;
+        for index in 0..blocks {
+            let block = self.bmap(dir, ino, index, false)?;
+            if block == 0 {
+                continue;
+            }
+            let mut data = self.read_block(block)?;
+            let mut modified = false;
+            let stop = f(&mut data, &mut modified);
+            if modified {
+                self.write_block(block, &data)?;
+            }
+            if stop {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn read_dir(&mut self, dir: &mut Ext2DiskInode, ino: u32) -> Result<Vec<DirRecord>> {
+        let mut records = Vec::new();
+        self.walk_dir(dir, ino, |data, _| {
+            let mut offset = 0;
+            while offset + size_of::<Ext2DirEntryHead>() <= data.len() {
+                let head: Ext2DirEntryHead = from_bytes(&data[offset..]);
+                if head.rec_len == 0 {
+                    break;
+                }
+                let name_start = offset + size_of::<Ext2DirEntryHead>();
+                let name_end = min(name_start + head.name_len as usize, data.len());
+                if head.inode != 0 {
+                    let name = String::from_utf8_lossy(&data[name_start..name_end]).to_string();
+                    records.push(DirRecord { name, ino: head.inode });
+                }
+                offset += head.rec_len as usize;
+            }
+            false
+        })?;
+        Ok(records)
+    }
+
+    fn find_entry(&mut self, dir: &mut Ext2DiskInode, ino: u32, name: &str) -> Result<Option<u32>> {
+        Ok(self.read_dir(dir, ino)?.into_iter().find(|r| r.name == name).map(|r| r.ino))
+    }
+
+    fn add_entry(&mut self, dir: &mut Ext2DiskInode, dir_ino: u32, name: &str, ino: u32, file_type: u8) -> EmptyResult {
+        if name.len() > NAME_MAX {
+            return Err("File name too long.".into());
+        }
+        let needed = dir_entry_size(name.len());
+        let file_type = if self.has_filetype { file_type } else { 0 };
+        let write_entry = |data: &mut [u8], offset: usize, rec_len: usize| {
+            let head = Ext2DirEntryHead {
+                inode: ino,
+                rec_len: rec_len as u16,
+                name_len: name.len() as u8,
+                file_type,
+            };
+            let name_start = offset + size_of::<Ext2DirEntryHead>();
+            data[offset..name_start].copy_from_slice(to_bytes(&head));
+            data[name_start..name_start + name.len()].copy_from_slice(name.as_bytes());
+        };
+
+        let found = self.walk_dir(dir, dir_ino, |data, modified| {
+            let mut offset = 0;
+            while offset + size_of::<Ext2DirEntryHead>() <= data.len() {
+                let mut head: Ext2DirEntryHead = from_bytes(&data[offset..]);
+                if head.rec_len == 0 {
+                    break;
+                }
+                let rec_len = head.rec_len as usize;
+                let used = if head.inode == 0 { 0 } else { dir_entry_size(head.name_len as usize) };
+                if rec_len >= used + needed {
+                    if used == 0 {
+                        write_entry(data, offset, rec_len);
+                    } else {
+                        head.rec_len = used as u16;
+                        data[offset..offset + size_of::<Ext2DirEntryHead>()].copy_from_slice(to_bytes(&head));
+                        write_entry(data, offset + used, rec_len - used);
+                    }
+                    *modified = true;
+                    return true;
+                }
+                offset += rec_len;
+            }
+            false
+        })?;
+
+        if !found {
+            // Append a new block holding only this entry.
+            let index = dir.get_size() / self.block_size;
+            let block = self.bmap(dir, dir_ino, index, true)?;
+            let mut data = vec![0u8; self.block_size];
+            write_entry(&mut data, 0, self.block_size);
+            self.write_block(block, &data)?;
+            dir.set_size((index + 1) * self.block_size);
+        }
+        let time = now();
+        dir.mtime = time;
+        dir.ctime = time;
+        dir.flags &= !INDEX_FL;
+        Ok(())
+    }
+
+    /// Remove entry `name` from directory, return its inode number.
+    fn remove_entry(&mut self, dir: &mut Ext2DiskInode, dir_ino: u32, name: &str) -> Result<u32> {
+        let mut removed = 0;
+        self.walk_dir(dir, dir_ino, |data, modified| {
+            let mut offset = 0;
+            let mut prev: Option<usize> = None;
+            while offset + size_of::<Ext2DirEntryHead>() <= data.len() {
+                let mut head: Ext2DirEntryHead = from_bytes(&data[offset..]);
+                if head.rec_len == 0 {
+                    break;
+                }
+                let name_start = offset + size_of::<Ext2DirEntryHead>();
+                if head.inode != 0 && data.get(name_start..name_start + head.name_len as usize) == Some(name.as_bytes()) {
+                    removed = head.inode;
+                    if let Some(prev) = prev {
+                        // Merge into previous entry.
+                        let mut prev_head: Ext2DirEntryHead = from_bytes(&data[prev..]);
+                        prev_head.rec_len += head.rec_len;
+                        data[prev..prev + size_of::<Ext2DirEntryHead>()].copy_from_slice(to_bytes(&prev_head));
+                    } else {
+                        head.inode = 0;
+                        data[offset..name_start].copy_from_slice(to_bytes(&head));
+                    }
+                    *modified = true;
+                    return true;
+                }
+                prev = Some(offset);
+                offset += head.rec_len as usize;
+            }
+            false
+        })?;
+        if removed == 0 {
+            return Err("No such file.".into());
+        }
+        let time = now();
+        dir.mtime = time;
+        dir.ctime = time;
+        dir.flags &= !INDEX_FL;
+        Ok(removed)
+    }
+
+    /// Point ".." of directory `ino` to `parent`.
+    fn set_parent(&mut self, dir: &mut Ext2DiskInode, ino: u32, parent: u32) -> EmptyResult {
+        let found = self.walk_dir(dir, ino, |data, modified| {
+            let mut offset = 0;
+            while offset + size_of::<Ext2DirEntryHead>() <= data.len() {
+                let mut head: Ext2DirEntryHead = from_bytes(&data[offset..]);
+                if head.rec_len == 0 {
+                    break;
+                }
+                let name_start = offset + size_of::<Ext2DirEntryHead>();
+                if head.inode != 0 && data.get(name_start..name_start + head.name_len as usize) == Some(b"..".as_slice()) {
+                    head.inode = parent;
+                    data[offset..name_start].copy_from_slice(to_bytes(&head));
+                    *modified = true;
+                    return true;
+                }
+                offset += head.rec_len as usize;
+            }
+            false
+        })?;
+        if found {
+            Ok(())
+        } else {
+            Err("Directory has no \"..\" entry.".into())
+        }
+    }
+
+    fn new_inode(&mut self, parent_ino: u32, mode: u16) -> Result<(u32, Ext2DiskInode)> {
+        let is_dir = mode & S_IFMT == FileModes::DIRECTORY.bits() as u16;
+        let ino = self.alloc_inode(self.inode_group(parent_ino), is_dir)?;
+        let old = self.read_inode(ino)?;
+        let time = now();
+        let inode = Ext2DiskInode {
+            mode,
+            uid: 0,
+            size: 0,
+            atime: time,
+            ctime: time,
+            mtime: time,
+            dtime: 0,
+            gid: 0,
+            links_count: 1,
+            blocks: 0,
+            flags: 0,
+            osd1: 0,
+            block: [0; 15],
+            generation: old.generation.wrapping_add(1),
+            file_acl: 0,
+            size_high: 0,
+            faddr: 0,
+            osd2: [0; 12],
+        };
+        self.write_inode(ino, &inode)?;
+        Ok((ino, inode))
+    }
+}
+
+struct Ext2Fs {
+    inner: SleepLock<Ext2Inner>,
+    // Live inode objects, one per inode number. Lock order: inner -> inodes.
+    inodes: Spinlock<BTreeMap<u32, Weak<Ext2Inode>>>,
+}
+
+impl Ext2Fs {
+    fn get_inode(self: &Arc<Self>, ino: u32) -> Arc<Ext2Inode> {
+        let mut inodes = self.inodes.lock();
+        if let Some(inode) = inodes.get(&ino).and_then(|w| w.upgrade()) {
+            return inode;
+        }
+        let inode = Arc::new_cyclic(|this| Ext2Inode {
+            ino,
+            this: this.clone(),
+            fs: self.clone(),
+            cache: PageCache::new(),
+        });
+        inodes.insert(ino, Arc::downgrade(&inode));
+        inode
+    }
+
+    /// Find our inode behind a trait object. None if it belongs to other filesystem.
+    fn find(&self, inode: &Arc<dyn Inode>) -> Option<Arc<Ext2Inode>> {
+        let ino = inode.get_stat().ino as u32;
+        let found = self.inodes.lock().get(&ino)?.upgrade()?;
+        if Arc::as_ptr(&found) as *const () == Arc::as_ptr(inode) as *const () {
+            Some(found)
+        } else {
+            None
+        }
+    }
+
+    /// Free unlinked inode now, or leave it to drop of the inode object still in use.
+    fn release(&self, inner: &mut Ext2Inner, ino: u32) -> EmptyResult {
+        if self.inodes.lock().contains_key(&ino) {
+            return Ok(());
+        }
+        inner.free_inode_if_dead(ino)
+    }
+}
+
+struct Ext2Inode {
+    ino: u32,
+    this: Weak<Ext2Inode>,
+    fs: Arc<Ext2Fs>,
+    // Data of regular file.
+    cache: PageCache,
+}
+
+impl Drop for Ext2Inode {
+    fn drop(&mut self) {
+        let mut inner = self.fs.inner.lock();
+        {
+            let mut inodes = self.fs.inodes.lock();
+            if inodes.get(&self.ino).is_some_and(|w| w.as_ptr() == self as *const Self) {
+                inodes.remove(&self.ino);
+            }
+        }
+        // Open but unlinked file goes away now.
+        let _ = inner.free_inode_if_dead(self.ino);
+    }
+}
+
+impl Ext2Inode {
+    fn make_dentry(&self, record: DirRecord, this_dentry: Weak<DirEntry>) -> DirEntry {
+        let inode = self.fs.get_inode(record.ino);
+        let type_ = inode.get_dentry_type();
+        DirEntry::new(Some(this_dentry), record.name, Some(inode), type_)
+    }
+
+    /// Create a new inode of `mode` and link it as `name` into this directory.
+    fn create_child(&self, name: &str, mode: u16, file_type: u8, init: impl FnOnce(&mut Ext2Inner, u32, &mut Ext2DiskInode) -> EmptyResult) -> Result<Arc<dyn Inode>> {
+        let ino = {
+            let mut inner = self.fs.inner.lock();
+            inner.check_writable()?;
+            let mut dir = inner.read_inode(self.ino)?;
+            if !dir.is_dir() {
+                return Err("Not a directory.".into());
+            }
+            if inner.find_entry(&mut dir, self.ino, name)?.is_some() {
+                return Err(KernelError::ALREADY_EXISTS);
+            }
+            if file_type == FT_DIR && dir.links_count >= LINK_MAX {
+                return Err(KernelError::TOO_MANY_LINKS);
+            }
+            let (ino, mut inode) = inner.new_inode(self.ino, mode)?;
+            let result = init(&mut *inner, ino, &mut inode)
+                .and_then(|_| inner.write_inode(ino, &inode))
+                .and_then(|_| inner.add_entry(&mut dir, self.ino, name, ino, file_type));
+            if let Err(e) = result {
+                inode.links_count = 0;
+                let _ = inner.write_inode(ino, &inode);
+                let _ = inner.free_inode_if_dead(ino);
+                return Err(e);
+            }
+            if file_type == FT_DIR {
+                // ".." of the new directory
+                dir.links_count += 1;
+            }
+            inner.write_inode(self.ino, &dir)?;
+            ino
+        };
+        Ok(self.fs.get_inode(ino))
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn lookup(&self, name: &str, this_dentry: Weak<DirEntry>) -> Option<DirEntry> {
+        let ino = {
+            let mut inner = self.fs.inner.lock();
+            let mut dir = inner.read_inode(self.ino).ok()?;
+            if !dir.is_dir() {
+                return None;
+            }
+            inner.find_entry(&mut dir, self.ino, name).ok()??
+        };
+        Some(self.make_dentry(DirRecord { name: name.to_string(), ino }, this_dentry))
+    }
+
+    fn link(&self, inode: Arc<dyn Inode>, name: &str) -> EmptyResult {
+        let target = self.fs.find(&inode).ok_or("Cross-device link.")?;
+        let mut inner = self.fs.inner.lock();
+        inner.check_writable()?;
+        let mut dir = inner.read_inode(self.ino)?;
+        if !dir.is_dir() {
+            return Err("Not a directory.".into());
+        }
+        if inner.find_entry(&mut dir, self.ino, name)?.is_some() {
+            return Err(KernelError::ALREADY_EXISTS);
+        }
+        let mut target_inode = inner.read_inode(target.ino)?;
+        if target_inode.is_dir() {
+            return Err("Cannot hard link a directory.".into());
+        }
+        if target_inode.links_count >= LINK_MAX {
+            return Err(KernelError::TOO_MANY_LINKS);
+        }
+        inner.add_entry(&mut dir, self.ino, name, target.ino, target_inode.file_type())?;
+        inner.write_inode(self.ino, &dir)?;
+        target_inode.links_count += 1;
+        target_inode.ctime = now();
+        inner.write_inode(target.ino, &target_inode)
+    }
+
+    fn unlink(&self, name: &str) -> EmptyResult {
+        let mut inner = self.fs.inner.lock();
+        inner.check_writable()?;
+        let mut dir = inner.read_inode(self.ino)?;
+        let ino = inner.find_entry(&mut dir, self.ino, name)?.ok_or("No such file.")?;
+        let mut inode = inner.read_inode(ino)?;
+        if inode.is_dir() {
+            return Err("Is a directory.".into());
+        }
+        if inode.links_count == 0 {
+            return Err(KernelError::CORRUPTED);
+        }
+        inner.remove_entry(&mut dir, self.ino, name)?;
+        inner.write_inode(self.ino, &dir)?;
+        inode.links_count -= 1;
+        inode.ctime = now();
+        inner.write_inode(ino, &inode)?;
+        self.fs.release(&mut *inner, ino)
+    }
+
+    fn mkdir(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let mode = (FileModes::DIRECTORY | FileModes::Read | FileModes::Exec | FileModes::OwnerWrite).bits() as u16;
+        let parent_ino = self.ino;
+        let inode = self.create_child(name, mode, FT_DIR, |inner, ino, inode| {
+            // "." and the entry in parent
+            inode.links_count = 2;
+            inner.add_entry(inode, ino, ".", ino, FT_DIR)?;
+            inner.add_entry(inode, ino, "..", parent_ino, FT_DIR)
+        })?;
+        Ok(inode)
+    }
+
+    fn rmdir(&self, name: &str) -> EmptyResult {
+        if name == "." || name == ".." {
+            return Err("Cannot remove . or ..".into());
+        }
+        let mut inner = self.fs.inner.lock();
+        inner.check_writable()?;
+        let mut dir = inner.read_inode(self.ino)?;
+        let ino = inner.find_entry(&mut dir, self.ino, name)?.ok_or("No such file.")?;
+        let mut inode = inner.read_inode(ino)?;
+        if !inode.is_dir() {
+            return Err("Not a directory.".into());
+        }
+        if inner.read_dir(&mut inode, ino)?.iter().any(|r| r.name != "." && r.name != "..") {
+            return Err(KernelError::NOT_EMPTY);
+        }
+        if dir.links_count == 0 {
+            return Err(KernelError::CORRUPTED);
+        }
+        inner.remove_entry(&mut dir, self.ino, name)?;
+        // ".." of child
+        dir.links_count -= 1;
+        inner.write_inode(self.ino, &dir)?;
+        inode.links_count = 0;
+        inode.ctime = now();
+        inner.write_inode(ino, &inode)?;
+        self.fs.release(&mut *inner, ino)
+    }
+
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str, flags: RenameFlags) -> EmptyResult {
+        let new_dir = self.fs.find(new_dir).ok_or("Cross-device rename.")?;
+        let mut inner = self.fs.inner.lock();
+        inner.check_writable()?;
+        let same_dir = new_dir.ino == self.ino;
+        // dirs[src] and dirs[dst] are the same one when renaming inside a directory.
+        let (src, dst) = (0, if same_dir { 0 } else { 1 });
+        let dir_inos = [self.ino, new_dir.ino];
+        let mut dirs = [inner.read_inode(self.ino)?, inner.read_inode(new_dir.ino)?];
+        if !dirs[src].is_dir() || !dirs[dst].is_dir() {
+            return Err("Not a directory.".into());
+        }
+        let source_ino = inner.find_entry(&mut dirs[src], dir_inos[src], old_name)?.ok_or("No such file.")?;
+        let target_ino = inner.find_entry(&mut dirs[dst], dir_inos[dst], new_name)?;
+        if target_ino == Some(source_ino) {
+            // Both names are links to the same inode, nothing to do.
+            return Ok(());
+        }
+        let time = now();
+        let mut source = inner.read_inode(source_ino)?;
+        let mut target = match target_ino {
+            Some(ino) => Some((ino, inner.read_inode(ino)?)),
+            None => None
+        };
+        let mut released = None;
+        // Every count below drops by one at most, linked inode never has zero of it.
+        // It rises by one at most too, never beyond LINK_MAX + 1 meanwhile.
+        if dirs.iter().chain(target.as_ref().map(|(_, target)| target))
+            .any(|inode| inode.links_count == 0 || inode.links_count > LINK_MAX) {
+            return Err(KernelError::CORRUPTED);
+        }
+        // Directory moved to another parent takes a ".." link of it, the one it replaces gives one back.
+        let exchange = flags.contains(RenameFlags::RENAME_EXCHANGE);
+        let target_is_dir = target.as_ref().is_some_and(|(_, target)| target.is_dir());
+        let src_gains = !same_dir && exchange && target_is_dir && !source.is_dir();
+        let dst_gains = !same_dir && source.is_dir() && !target_is_dir;
+        if (src_gains && dirs[src].links_count >= LINK_MAX) || (dst_gains && dirs[dst].links_count >= LINK_MAX) {
+            return Err(KernelError::TOO_MANY_LINKS);
+        }
+
+        if exchange {
+            let (target_ino, target) = target.as_mut().ok_or("No such file.")?;
+            inner.remove_entry(&mut dirs[src], dir_inos[src], old_name)?;
+            inner.remove_entry(&mut dirs[dst], dir_inos[dst], new_name)?;
+            inner.add_entry(&mut dirs[src], dir_inos[src], old_name, *target_ino, target.file_type())?;
+            if !same_dir && target.is_dir() {
+                inner.set_parent(target, *target_ino, dir_inos[src])?;
+                dirs[src].links_count += 1;
+                dirs[dst].links_count -= 1;
+            }
+            target.ctime = time;
+            inner.write_inode(*target_ino, target)?;
+        } else {
+            if let Some((target_ino, target)) = target.as_mut() {
+                if flags.contains(RenameFlags::RENAME_NOREPLACE) {
+                    return Err(KernelError::ALREADY_EXISTS);
+                }
+                match (target.is_dir(), source.is_dir()) {
+                    (true, true) => {
+                        if inner.read_dir(target, *target_ino)?.iter().any(|r| r.name != "." && r.name != "..") {
+                            return Err(KernelError::NOT_EMPTY);
+                        }
+                        target.links_count = 0;
+                        // ".." of the replaced directory
+                        dirs[dst].links_count -= 1;
+                    }
+                    (true, false) => return Err("Is a directory.".into()),
+                    (false, true) => return Err("Not a directory.".into()),
+                    (false, false) => target.links_count -= 1,
+                }
+                inner.remove_entry(&mut dirs[dst], dir_inos[dst], new_name)?;
+                target.ctime = time;
+                inner.write_inode(*target_ino, target)?;
+                released = Some(*target_ino);
+            }
+            inner.remove_entry(&mut dirs[src], dir_inos[src], old_name)?;
+        }
+        inner.add_entry(&mut dirs[dst], dir_inos[dst], new_name, source_ino, source.file_type())?;
+        if !same_dir && source.is_dir() {
+            inner.set_parent(&mut source, source_ino, dir_inos[dst])?;
+            dirs[src].links_count -= 1;
+            dirs[dst].links_count += 1;
+        }
+        source.ctime = time;
+        inner.write_inode(source_ino, &source)?;
+        inner.write_inode(dir_inos[src], &dirs[src])?;
+        if !same_dir {
+            inner.write_inode(dir_inos[dst], &dirs[dst])?;
+        }
+        match released {
+            Some(ino) => self.fs.release(&mut *inner, ino),
+            None => Ok(())
+        }
+    }
+
+    fn read_dir(&self, this_dentry: Weak<DirEntry>) -> Result<Vec<DirEntry>> {
+        let records = {
+            let mut inner = self.fs.inner.lock();
+            let mut dir = inner.read_inode(self.ino)?;
+            if !dir.is_dir() {
+                return Err("Cannot read dir on a file inode.".into());
+            }
+            inner.read_dir(&mut dir, self.ino)?
+        };
+        Ok(records.into_iter()
+            .filter(|r| r.name != "." && r.name != "..")
+            .map(|r| self.make_dentry(r, this_dentry.clone()))
+            .collect())
+    }
+
+    fn open(&self, dentry: Arc<DirEntry>, flags: FileOpenFlags, mode: FileModes) -> Result<Arc<dyn File>> {
+        {
+            let mut inner = self.fs.inner.lock();
+            let mut inode = inner.read_inode(self.ino)?;
+            if inode.is_symlink() {
+                return Err("Cannot open symlink.".into());
+            }
+            if inode.is_dir() {
+                return Err("Cannot open directory as file.".into());
+            }
+            if flags.contains(FileOpenFlags::O_TRUNC) && inode.get_size() != 0 {
+                inner.check_writable()?;
+                inner.truncate(&mut inode, self.ino, 0)?;
+                self.cache.invalidate_all();
+                let time = now();
+                inode.mtime = time;
+                inode.ctime = time;
+                inner.write_inode(self.ino, &inode)?;
+            }
+        }
+        Ok(Arc::new(Ext2File {
+            inode: self.this.upgrade().unwrap(),
+            dentry,
+            offset: SleepLock::new(0),
+            append: flags.contains(FileOpenFlags::O_APPEND),
+        }))
+    }
+
+    fn get_dentry_type(&self) -> DirEntryType {
+        match self.fs.inner.lock().read_inode(self.ino) {
+            Ok(inode) if inode.is_dir() => DirEntryType::Dir,
+            Ok(inode) if inode.is_symlink() => DirEntryType::Link,
+            _ => DirEntryType::File,
+        }
+    }
+
+    fn get_stat(&self) -> InodeStat {
+        let inner = self.fs.inner.lock();
+        let block_size = inner.block_size;
+        match inner.read_inode(self.ino) {
+            Ok(inode) => InodeStat {
+                ino: self.ino as usize,
+                mode: inode.mode as usize,
+                nlink: inode.links_count as usize,
+                rdev: inode.get_rdev(),
+                size: inode.get_size(),
+                block_size,
+                atime: Duration::from_secs(inode.atime as u64),
+                mtime: Duration::from_secs(inode.mtime as u64),
+                ctime: Duration::from_secs(inode.ctime as u64),
+            },
+            Err(_) => InodeStat {
+                ino: self.ino as usize,
+                mode: 0,
+                nlink: 0,
+                rdev: 0,
+                size: 0,
+                block_size,
+                atime: Duration::ZERO,
+                mtime: Duration::ZERO,
+                ctime: Duration::ZERO,
+            }
+        }
+    }
+
+    fn create(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let mode = (FileModes::REGULAR | FileModes::Read | FileModes::OwnerWrite).bits() as u16;
+        self.create_child(name, mode, FT_REG_FILE, |_, _, _| Ok(()))
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>> {
+        let mode = (FileModes::LINK | FileModes::RWX).bits() as u16;
+        self.create_child(name, mode, FT_SYMLINK, |inner, ino, inode| {
+            if target.len() < FAST_SYMLINK_MAX {
+                let mut block = [0u8; FAST_SYMLINK_MAX];
+                block[..target.len()].copy_from_slice(target.as_bytes());
+                inode.block = from_bytes(&block);
+                inode.set_size(target.len());
+                Ok(())
+            } else if target.len() < inner.block_size {
+                inner.write_data(inode, ino, 0, target.as_bytes()).map(|_| ())
+            } else {
+                Err("Symlink target too long.".into())
+            }
+        })
+    }
+
+    fn readlink(&self) -> Result<String> {
+        let mut inner = self.fs.inner.lock();
+        let mut inode = inner.read_inode(self.ino)?;
+        if !inode.is_symlink() {
+            return Err("Not a symlink.".into());
+        }
+        let size = inode.get_size();
+        let target = if inode.is_fast_symlink(inner.block_size) {
+            inode.block_bytes()[..min(size, FAST_SYMLINK_MAX)].to_vec()
+        } else {
+            let mut buf = vec![0u8; size];
+            let read_bytes = inner.read_data(&mut inode, self.ino, 0, &mut buf)?;
+            buf.truncate(read_bytes);
+            buf
+        };
+        Ok(String::from_utf8_lossy(&target).to_string())
+    }
+
+    fn sync(&self) -> EmptyResult {
+        // Metadata is written to the buffer cache as it changes, only the write time is left.
+        let mut inner = self.fs.inner.lock();
+        if inner.readonly {
+            return Ok(());
+        }
+        inner.sb.wtime = now();
+        inner.write_super()?;
+        inner.device.sync()
+    }
+
+    fn remount(&self, flags: MountFlags) -> EmptyResult {
+        let mut inner = self.fs.inner.lock();
+        let readonly = flags.contains(MountFlags::MS_RDONLY);
+        if inner.readonly && !readonly {
+            // Device was opened read-only for the read-only mount.
+            let dentry = inner.device.get_dentry()?;
+            inner.device = dentry.open(FileOpenFlags::O_RDWR, FileModes::from_bits(0).unwrap())?;
+        }
+        inner.readonly = readonly;
+        Ok(())
+    }
+}
+
+struct Ext2File {
+    inode: Arc<Ext2Inode>,
+    dentry: Arc<DirEntry>,
+    offset: SleepLock<usize>,
+    append: bool,
+}
+
+impl Ext2File {
+    /// Write at `offset`, or at end of file if None. Return offset written at and bytes written.
+    fn write_from(&self, offset: Option<usize>, buf: &[u8]) -> Result<(usize, usize)> {
+        let mut inner = self.inode.fs.inner.lock();
+        inner.check_writable()?;
+        let ino = self.inode.ino;
+        let mut inode = inner.read_inode(ino)?;
+        let offset = offset.unwrap_or(inode.get_size());
+        let result = inner.write_data(&mut inode, ino, offset, buf);
+        self.inode.cache.invalidate(offset, buf.len());
+        // Blocks allocated before a failure still belong to the inode.
+        let time = now();
+        inode.mtime = time;
+        inode.ctime = time;
+        inner.write_inode(ino, &inode)?;
+        Ok((offset, result?))
+    }
+}
+
+impl File for Ext2File {
+    fn seek(&self, offset: isize, whence: SeekPosition) -> Result<usize> {
+        let mut cur = self.offset.lock();
+        let base = match whence {
+            SeekPosition::Set => 0,
+            SeekPosition::Cur => *cur as isize,
+            SeekPosition::End => self.inode.get_stat().size as isize,
+        };
+        let new_offset = base + offset;
+        if new_offset < 0 {
+            return Err("Seek before start of file.".into());
+        }
+        *cur = new_offset as usize;
+        Ok(*cur)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut offset = self.offset.lock();
+        let read_bytes = self.read_at(*offset, buf)?;
+        *offset += read_bytes;
+        Ok(read_bytes)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        let mut offset = self.offset.lock();
+        let (begin, write_bytes) = self.write_from(if self.append { None } else { Some(*offset) }, buf)?;
+        *offset = begin + write_bytes;
+        Ok(write_bytes)
+    }
+
+    fn close(&self) -> EmptyResult {
+        Ok(())
+    }
+
+    fn get_dentry(&self) -> Result<Arc<DirEntry>> {
+        Ok(self.dentry.clone())
+    }
+
+    fn sync(&self) -> EmptyResult {
+        Inode::sync(&*self.inode)
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let ino = self.inode.ino;
+        let size = self.inode.fs.inner.lock().read_inode(ino)?.get_size();
+        self.inode.cache.read(offset, buf, size, |index, page| {
+            let mut inner = self.inode.fs.inner.lock();
+            let mut inode = inner.read_inode(ino)?;
+            inner.read_data(&mut inode, ino, index * PAGE_SIZE, page)
+        })
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        self.write_from(Some(offset), buf).map(|(_, write_bytes)| write_bytes)
+    }
+}
+
+struct Ext2 {}
+
+impl Filesystem for Ext2 {
+    fn new() -> Self {
+        Self {}
+    }
+
+    fn mount(&self, device: Option<Arc<dyn File>>, mount_point: Arc<DirEntry>, flags: MountFlags) -> Result<Arc<dyn Inode>> {
+        let device = device.ok_or("Must provided device file for ext2")?;
+        let mut inner = Ext2Inner {
+            device,
+            block_size: 1024,
+            inode_size: GOOD_OLD_INODE_SIZE,
+            first_ino: GOOD_OLD_FIRST_INO,
+            has_filetype: false,
+            sb: from_bytes(&[0u8; size_of::<Ext2SuperBlock>()]),
+            groups: Vec::new(),
+            readonly: flags.contains(MountFlags::MS_RDONLY),
+        };
+        let mut buf = [0u8; size_of::<Ext2SuperBlock>()];
+        inner.read_at(SUPERBLOCK_OFFSET, &mut buf)?;
+        let sb: Ext2SuperBlock = from_bytes(&buf);
+        if sb.magic != EXT2_MAGIC {
+            return Err("Not an ext2 filesystem.".into());
+        }
+        // rec_len of directory entry is u16, which a block of 64 KiB overflows.
+        if sb.log_block_size > 5 || sb.blocks_per_group == 0 || sb.inodes_per_group == 0 {
+            return Err(KernelError::CORRUPTED);
+        }
+        inner.block_size = 1024 << sb.log_block_size;
+        // Bitmaps of a group are one block each.
+        let bits_per_block = inner.block_size * 8;
+        if sb.blocks_per_group as usize > bits_per_block || sb.inodes_per_group as usize > bits_per_block {
+            return Err(KernelError::CORRUPTED);
+        }
+        if sb.rev_level != GOOD_OLD_REV {
+            let supported_ro = FEATURE_RO_COMPAT_SPARSE_SUPER | FEATURE_RO_COMPAT_LARGE_FILE | FEATURE_RO_COMPAT_BTREE_DIR;
+            if sb.feature_incompat & !FEATURE_INCOMPAT_FILETYPE != 0 {
+                return Err("Unsupported ext2 incompatible features.".into());
+            }
+            if sb.feature_ro_compat & !supported_ro != 0 {
+                return Err("Unsupported ext2 read-only compatible features.".into());
+            }
+            if (sb.inode_size as usize) < GOOD_OLD_INODE_SIZE || sb.inode_size as usize > inner.block_size {
+                return Err("Unsupported ext2 inode size.".into());
+            }
+            inner.inode_size = sb.inode_size as usize;
+            inner.first_ino = sb.first_ino;
+            inner.has_filetype = sb.feature_incompat & FEATURE_INCOMPAT_FILETYPE != 0;
+        }
+        inner.sb = sb;
+
+        if sb.first_data_block >= sb.blocks_count {
+            return Err(KernelError::CORRUPTED);
+        }
+        let data_blocks = (sb.blocks_count - sb.first_data_block) as usize;
+        let group_count = (data_blocks + sb.blocks_per_group as usize - 1) / sb.blocks_per_group as usize;
+        // Inode numbers up to inodes_count index into the groups.
+        if sb.inodes_count as usize > group_count * sb.inodes_per_group as usize {
+            return Err(KernelError::CORRUPTED);
+        }
+        let mut table = vec![0u8; group_count * size_of::<Ext2GroupDesc>()];
+        inner.read_at(inner.group_desc_offset(0), &mut table)?;
+        inner.groups = table.chunks(size_of::<Ext2GroupDesc>()).map(|desc| from_bytes(desc)).collect();
+
+        if !inner.readonly {
+            inner.sb.mnt_count = inner.sb.mnt_count.wrapping_add(1);
+            inner.sb.mtime = now();
+            inner.write_super()?;
+        }
+
+        let fs = Arc::new(Ext2Fs {
+            inner: SleepLock::new(inner),
+            inodes: Spinlock::new(BTreeMap::new()),
+        });
+        let root = fs.get_inode(ROOT_INO);
+        if root.get_dentry_type() != DirEntryType::Dir {
+            return Err("Ext2 root is not a directory.".into());
+        }
+        Ok(root)
+    }
+}
+
+pub fn init() {
+    register_filesystem("ext2", Box::new(Ext2::new()));
+}
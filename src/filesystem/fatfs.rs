@@ -1,16 +1,19 @@
 use alloc::boxed::Box;
 use alloc::{format, vec};
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::string::{String, ToString};
 use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
 use core::borrow;
-use core::cell::OnceCell;
+use core::mem::ManuallyDrop;
 use core::ops::Deref;
+use core::time::Duration;
 use fatfs::{DefaultTimeProvider, Dir, FileSystem, IoBase, LossyOemCpConverter, Read, Seek, SeekFrom, Write};
+use lazy_static::lazy_static;
 use log::info;
 use crate::core::Spinlock;
-use crate::filesystem::{DirEntry, DirEntryType, File, FileModes, FileOpenFlags, Filesystem, Inode, InodeStat, register_filesystem, SeekPosition};
+use crate::process::SleepLock;
+use crate::filesystem::{DirEntry, DirEntryType, File, FileModes, FileOpenFlags, Filesystem, Inode, InodeStat, MountFlags, register_filesystem, RenameFlags, SeekPosition};
 use crate::utils::error::{EmptyResult, KernelError, Result as KernelResult};
 
 struct FatFSDeviceWrapper {
@@ -42,8 +45,8 @@ impl Write for FatFSDeviceWrapper {
     }
 
     fn flush(&mut self) -> Result<(), Self::Error> {
-        // TODO: implement flush
-        Ok(())
+        // fatfs flushes when file is flushed or filesystem unmounted, push the buffers to disk then.
+        self.file.sync().map_err(|_| ())
     }
 }
 
@@ -58,6 +61,30 @@ impl Seek for FatFSDeviceWrapper {
     }
 }
 
+type FatFileSystem = FileSystem<FatFSDeviceWrapper, DefaultTimeProvider, LossyOemCpConverter>;
+type FatDir<'a> = Dir<'a, FatFSDeviceWrapper, DefaultTimeProvider, LossyOemCpConverter>;
+
+lazy_static! {
+    // Live inodes by address, to find our own inode from `Arc<dyn Inode>` when renaming.
+    static ref INODES: Spinlock<BTreeMap<usize, Weak<FatFSInode>>> = Spinlock::new(BTreeMap::new());
+    // Open files by (filesystem, path). fatfs frees clusters on remove right away,
+    // so removing an open file is deferred until it is closed, those are kept in UNLINKED.
+    // Lock order: OPEN_FILES -> UNLINKED.
+    static ref OPEN_FILES: Spinlock<BTreeMap<(usize, String), OpenFile>> = Spinlock::new(BTreeMap::new());
+    static ref UNLINKED: Spinlock<BTreeSet<(usize, String)>> = Spinlock::new(BTreeSet::new());
+}
+
+// fatfs keeps its state in RefCell, which panics rather than waits when the thread inside sleeps
+// on disk I/O. Every call into fatfs is made under this lock.
+static FATFS_LOCK: SleepLock<()> = SleepLock::new(());
+
+#[derive(Default)]
+struct OpenFile {
+    count: usize,
+    // Size is in directory entry only after the file is flushed, stat uses this before that.
+    unflushed_size: Option<usize>,
+}
+
 #[derive(Copy, Clone, PartialEq)]
 enum FatFSInodeType {
     Dir,
@@ -73,60 +100,100 @@ struct FatFSInode {
 }
 
 impl Drop for FatFSInode {
-    fn drop(&mut self) {}
+    fn drop(&mut self) {
+        INODES.lock().remove(&(self as *const Self as usize));
+        if self.mountpoint.is_some() {
+            // Filesystem flushes itself on drop.
+            let _guard = FATFS_LOCK.lock();
+            self.mountpoint = None;
+        }
+    }
 }
 
-impl Inode for FatFSInode {
-    fn lookup(&self, name: &str, this_dentry: Weak<DirEntry>) -> Option<DirEntry> {
-        let search_through_iter = |name: &str, path, dir: Dir<FatFSDeviceWrapper, DefaultTimeProvider, LossyOemCpConverter>, fs| -> Option<DirEntry> {
-            if let Some(dir) = dir.iter().find(
-                |v| if let Ok(v) = v { v.file_name() == name } else { false }) {
-                let dir = dir.unwrap();
-                let inode_n = dir.first_cluster().unwrap() as usize; // THIS IS OUR MODIFICATION TO FATFS
-                let inode = FatFSInode {
-                    inode_n,
-                    path,
-                    type_: if dir.is_dir() { FatFSInodeType::Dir } else { FatFSInodeType::File },
-                    mountpoint: None,
-                    fs,
-                };
-                let dentry = DirEntry {
-                    parent: Some(this_dentry),
-                    name: name.to_string(),
-                    inode: Some(Arc::new(inode)),
-                    type_: if dir.is_dir() { DirEntryType::Dir } else { DirEntryType::File },
-                    children: Spinlock::new(BTreeMap::new()),
-                    children_fully_loaded: OnceCell::new(),
-                };
-                Some(dentry)
-            } else {
-                None
-            }
-        };
+impl FatFSInode {
+    fn new(inode_n: usize, path: String, type_: FatFSInodeType, mountpoint: Option<Arc<FatFileSystem>>, fs: &'static FatFileSystem) -> Arc<Self> {
+        let inode = Arc::new(Self {
+            inode_n,
+            path,
+            type_,
+            mountpoint,
+            fs,
+        });
+        INODES.lock().insert(Arc::as_ptr(&inode) as usize, Arc::downgrade(&inode));
+        inode
+    }
 
-        if let Some(fs) = &self.mountpoint {
-            let root_dir = fs.root_dir();
-            let fs = Arc::as_ptr(fs);
-            let fs = unsafe { fs.as_ref::<'static>().unwrap() };
-            search_through_iter(name, format!("/{}", name), root_dir, fs)
+    fn find(inode: &Arc<dyn Inode>) -> Option<Arc<Self>> {
+        INODES.lock().get(&(Arc::as_ptr(inode) as *const () as usize))?.upgrade()
+    }
+
+    fn fs_key(&self) -> usize {
+        self.fs as *const FatFileSystem as usize
+    }
+
+    fn child_path(&self, name: &str) -> String {
+        if self.path == "/" {
+            format!("/{}", name)
         } else {
-            match &self.type_ {
-                FatFSInodeType::Dir => {
-                    let fs = self.fs;
-                    let dir = fs.root_dir().open_dir(self.path.as_str()).unwrap();
-                    search_through_iter(name, format!("{}/{}", self.path, name), dir, fs)
-                }
-                FatFSInodeType::File => { None }
-            }
+            format!("{}/{}", self.path, name)
         }
     }
 
+    fn is_unlinked(&self, path: &str) -> bool {
+        UNLINKED.lock().contains(&(self.fs_key(), path.to_string()))
+    }
+
+    fn dir(&self) -> KernelResult<FatDir<'static>> {
+        if self.type_ != FatFSInodeType::Dir {
+            return Err("Not a directory.".into());
+        }
+        if self.path == "/" {
+            Ok(self.fs.root_dir())
+        } else {
+            self.fs.root_dir().open_dir(self.path.as_str()).map_err(|_| "Failed to open fatfs directory.".into())
+        }
+    }
+
+    fn find_entry(&self, dir: &FatDir<'static>, name: &str) -> Option<fatfs::DirEntry<'static, FatFSDeviceWrapper, DefaultTimeProvider, LossyOemCpConverter>> {
+        if self.is_unlinked(&self.child_path(name)) {
+            return None;
+        }
+        dir.iter().filter_map(|dirent| dirent.ok()).find(|dirent| dirent.file_name() == name)
+    }
+}
+
+impl Inode for FatFSInode {
+    fn lookup(&self, name: &str, this_dentry: Weak<DirEntry>) -> Option<DirEntry> {
+        let _guard = FATFS_LOCK.lock();
+        let dir = self.dir().ok()?;
+        let dirent = self.find_entry(&dir, name)?;
+        let inode_n = dirent.first_cluster().unwrap() as usize; // THIS IS OUR MODIFICATION TO FATFS
+        let type_ = if dirent.is_dir() { FatFSInodeType::Dir } else { FatFSInodeType::File };
+        let inode = FatFSInode::new(inode_n, self.child_path(name), type_, None, self.fs);
+        let dentry_type = if dirent.is_dir() { DirEntryType::Dir } else { DirEntryType::File };
+        let dentry = DirEntry::new(Some(this_dentry), name.to_string(), Some(inode), dentry_type);
+        Some(dentry)
+    }
+
     fn link(&self, inode: Arc<dyn Inode>, name: &str) -> EmptyResult {
         todo!()
     }
 
     fn unlink(&self, name: &str) -> EmptyResult {
-        todo!()
+        let _guard = FATFS_LOCK.lock();
+        let dir = self.dir()?;
+        let dirent = self.find_entry(&dir, name).ok_or("No such file.")?;
+        if dirent.is_dir() {
+            return Err("Is a directory.".into());
+        }
+        let key = (self.fs_key(), self.child_path(name));
+        let open_files = OPEN_FILES.lock();
+        if open_files.contains_key(&key) {
+            // Removed once the last one is closed, hidden from now on.
+            UNLINKED.lock().insert(key);
+            return Ok(());
+        }
+        dir.remove(name).map_err(|_| "Failed to remove fatfs file.".into())
     }
 
     fn mkdir(&self, name: &str) -> crate::utils::error::Result<Arc<dyn Inode>> {
@@ -134,39 +201,70 @@ impl Inode for FatFSInode {
     }
 
     fn rmdir(&self, name: &str) -> EmptyResult {
-        todo!()
+        let _guard = FATFS_LOCK.lock();
+        let dir = self.dir()?;
+        let dirent = self.find_entry(&dir, name).ok_or("No such directory.")?;
+        if !dirent.is_dir() {
+            return Err("Not a directory.".into());
+        }
+        // fatfs refuses to remove non-empty directory.
+        dir.remove(name).map_err(|_| "Failed to remove fatfs directory.".into())
+    }
+
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str, flags: RenameFlags) -> EmptyResult {
+        if flags.contains(RenameFlags::RENAME_EXCHANGE) {
+            return Err("Exchange is not supported by fatfs.".into());
+        }
+        let new_dir = FatFSInode::find(new_dir).ok_or("Cross-device rename.")?;
+        if new_dir.fs_key() != self.fs_key() {
+            return Err("Cross-device rename.".into());
+        }
+        let _guard = FATFS_LOCK.lock();
+        let src_dir = self.dir()?;
+        let dst_dir = new_dir.dir()?;
+        let old_path = self.child_path(old_name);
+        let new_path = new_dir.child_path(new_name);
+        let open_files = OPEN_FILES.lock();
+        let unlinked = UNLINKED.lock();
+        // Open fatfs file writes its entry back to where it was opened, so neither the entries
+        // nor anything under them may be open.
+        let in_use = |path: &str| {
+            let prefix = format!("{}/", path);
+            open_files.keys().chain(unlinked.iter())
+                .any(|(fs, open_path)| *fs == self.fs_key() && (open_path == path || open_path.starts_with(&prefix)))
+        };
+        if in_use(&old_path) || in_use(&new_path) {
+            return Err("File is in use.".into());
+        }
+        if dst_dir.iter().filter_map(|dirent| dirent.ok()).any(|dirent| dirent.file_name() == new_name) {
+            if flags.contains(RenameFlags::RENAME_NOREPLACE) {
+                return Err(KernelError::ALREADY_EXISTS);
+            }
+            dst_dir.remove(new_name).map_err(|_| "Failed to remove rename target.")?;
+        }
+        src_dir.rename(old_name, &dst_dir, new_name).map_err(|_| "Failed to rename on fatfs.".into())
     }
 
     fn read_dir(&self, this_dentry: Weak<DirEntry>) -> crate::utils::error::Result<Vec<DirEntry>> {
+        let _guard = FATFS_LOCK.lock();
         if self.type_ == FatFSInodeType::File {
             return Err("Cannot read dir on a file inode.".into());
         }
-        let dir =
-            if self.path == "/" {
-                self.fs.root_dir()
-            } else {
-                self.fs.root_dir().open_dir(self.path.as_str()).unwrap()
-            };
+        let dir = self.dir()?;
         let result = dir.iter()
             .filter_map(|possible_dirent| possible_dirent.ok())
+            .filter(|dirent| !self.is_unlinked(&self.child_path(&dirent.file_name())))
             .map(|dirent| {
                 let inode_n = dirent.first_cluster().unwrap_or(0) as usize; // THIS IS OUR MODIFICATION TO FATFS
-                let inode = FatFSInode {
+                let inode = FatFSInode::new(
                     inode_n,
-                    path: format!("{}/{}", self.path, dirent.file_name()),
-                    type_: if dirent.is_dir() { FatFSInodeType::Dir } else { FatFSInodeType::File },
-                    mountpoint: None,
-                    fs: self.fs,
-                };
-                let dentry = DirEntry {
-                    parent: Some(this_dentry.clone()),
-                    name: dirent.file_name(),
-                    inode: Some(Arc::new(inode)),
-                    type_: if dirent.is_dir() { DirEntryType::Dir } else { DirEntryType::File },
-                    children: Spinlock::new(BTreeMap::new()),
-                    children_fully_loaded: OnceCell::new(),
-                };
-                dentry
+                    self.child_path(&dirent.file_name()),
+                    if dirent.is_dir() { FatFSInodeType::Dir } else { FatFSInodeType::File },
+                    None,
+                    self.fs,
+                );
+                let dentry_type = if dirent.is_dir() { DirEntryType::Dir } else { DirEntryType::File };
+                DirEntry::new(Some(this_dentry.clone()), dirent.file_name(), Some(inode), dentry_type)
             })
             .collect::<Vec<_>>();
 
@@ -174,12 +272,16 @@ impl Inode for FatFSInode {
     }
 
     fn open(&self, dentry: Arc<DirEntry>, flags: FileOpenFlags, mode: FileModes) -> crate::utils::error::Result<Arc<dyn File>> {
+        let _guard = FATFS_LOCK.lock();
         let fs = self.fs;
         let dir = fs.root_dir();
-        let file = dir.open_file(self.path.as_str()).unwrap();
+        let file = dir.open_file(self.path.as_str()).map_err(|_| "Failed to open fatfs file.")?;
+        OPEN_FILES.lock().entry((self.fs_key(), self.path.clone())).or_default().count += 1;
         Ok(Arc::new(FatFSFile {
             dentry,
-            file: Spinlock::new(file),
+            file: Spinlock::new(ManuallyDrop::new(file)),
+            fs,
+            path: self.path.clone(),
         }))
     }
 
@@ -195,26 +297,63 @@ impl Inode for FatFSInode {
             FatFSInodeType::Dir => { FileModes::DIRECTORY }
             FatFSInodeType::File => { FileModes::REGULAR }
         };
+        let _guard = FATFS_LOCK.lock();
         let fs = self.fs;
         let dir = fs.root_dir();
         let possible_entry = dir.get_dentry(&self.path);
+        let unflushed_size = OPEN_FILES.lock().get(&(self.fs_key(), self.path.clone()))
+            .and_then(|open_file| open_file.unflushed_size);
         InodeStat {
             ino: self.inode_n,
             mode: (type_bits | FileModes::RWX).bits() as usize,
             nlink: 1,
-            size: possible_entry.map(|v| v.len() as usize).unwrap_or(0),
+            rdev: 0,
+            size: unflushed_size.unwrap_or(possible_entry.map(|v| v.len() as usize).unwrap_or(0)),
             block_size: self.fs.stats().unwrap().cluster_size() as usize,
+            atime: Duration::ZERO,
+            mtime: Duration::ZERO,
+            ctime: Duration::ZERO,
         }
     }
 }
 
 struct FatFSFile<'a> {
     dentry: Arc<DirEntry>,
-    file: Spinlock<fatfs::File<'a, FatFSDeviceWrapper, DefaultTimeProvider, LossyOemCpConverter>>,
+    // Dropped by hand, it must be flushed before a deferred remove.
+    file: Spinlock<ManuallyDrop<fatfs::File<'a, FatFSDeviceWrapper, DefaultTimeProvider, LossyOemCpConverter>>>,
+    fs: &'static FatFileSystem,
+    path: String,
+}
+
+impl<'a> FatFSFile<'a> {
+    fn key(&self) -> (usize, String) {
+        (self.fs as *const FatFileSystem as usize, self.path.clone())
+    }
+}
+
+impl<'a> Drop for FatFSFile<'a> {
+    fn drop(&mut self) {
+        let _guard = FATFS_LOCK.lock();
+        // Safety: never used again.
+        unsafe { ManuallyDrop::drop(&mut *self.file.lock()) };
+        let key = self.key();
+        let mut open_files = OPEN_FILES.lock();
+        let open_file = open_files.get_mut(&key).expect("Open fatfs file not counted.");
+        open_file.count -= 1;
+        // Flushed by the drop above.
+        open_file.unflushed_size = None;
+        if open_file.count == 0 {
+            open_files.remove(&key);
+            if UNLINKED.lock().remove(&key) {
+                let _ = self.fs.root_dir().remove(self.path.as_str());
+            }
+        }
+    }
 }
 
 impl<'a> File for FatFSFile<'a> {
     fn seek(&self, offset: isize, whence: SeekPosition) -> KernelResult<usize> {
+        let _guard = FATFS_LOCK.lock();
         let mut file = self.file.lock();
         file.seek(match whence {
             SeekPosition::Set => SeekFrom::Start(offset as u64),
@@ -224,6 +363,7 @@ impl<'a> File for FatFSFile<'a> {
     }
 
     fn read(&self, buf: &mut [u8]) -> KernelResult<usize> {
+        let _guard = FATFS_LOCK.lock();
         let mut file = self.file.lock();
         let mut read_bytes = 0;
         loop {
@@ -235,7 +375,47 @@ impl<'a> File for FatFSFile<'a> {
     }
 
     fn write(&self, buf: &[u8]) -> KernelResult<usize> {
-        todo!()
+        let _guard = FATFS_LOCK.lock();
+        let mut file = self.file.lock();
+        file.write_all(buf).map_err(|e| "write failed for fatfs.")?;
+        let pos = file.seek(SeekFrom::Current(0)).map_err(|e| "seek failed for fatfs.")?;
+        let size = file.seek(SeekFrom::End(0)).map_err(|e| "seek failed for fatfs.")?;
+        file.seek(SeekFrom::Start(pos)).map_err(|e| "seek failed for fatfs.")?;
+        if let Some(open_file) = OPEN_FILES.lock().get_mut(&self.key()) {
+            open_file.unflushed_size = Some(size as usize);
+        }
+        Ok(buf.len())
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> KernelResult<usize> {
+        let _guard = FATFS_LOCK.lock();
+        let mut file = self.file.lock();
+        // Position is put back before the file lock is released, nobody sees it moved.
+        let pos = file.seek(SeekFrom::Current(0)).map_err(|e| "seek failed for fatfs.")?;
+        file.seek(SeekFrom::Start(offset as u64)).map_err(|e| "seek failed for fatfs.")?;
+        let mut read_bytes = 0;
+        loop {
+            let this_read_bytes = file.read(&mut buf[read_bytes..]).unwrap();
+            if this_read_bytes == 0 { break; }
+            read_bytes += this_read_bytes;
+        }
+        file.seek(SeekFrom::Start(pos)).map_err(|e| "seek failed for fatfs.")?;
+        Ok(read_bytes)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> KernelResult<usize> {
+        let _guard = FATFS_LOCK.lock();
+        let mut file = self.file.lock();
+        let pos = file.seek(SeekFrom::Current(0)).map_err(|e| "seek failed for fatfs.")?;
+        file.seek(SeekFrom::Start(offset as u64)).map_err(|e| "seek failed for fatfs.")?;
+        let result = file.write_all(buf).map_err(|e| "write failed for fatfs.");
+        let size = file.seek(SeekFrom::End(0)).map_err(|e| "seek failed for fatfs.")?;
+        file.seek(SeekFrom::Start(pos)).map_err(|e| "seek failed for fatfs.")?;
+        result?;
+        if let Some(open_file) = OPEN_FILES.lock().get_mut(&self.key()) {
+            open_file.unflushed_size = Some(size as usize);
+        }
+        Ok(buf.len())
     }
 
     fn close(&self) -> EmptyResult {
@@ -246,6 +426,16 @@ impl<'a> File for FatFSFile<'a> {
     fn get_dentry(&self) -> KernelResult<Arc<DirEntry>> {
         Ok(self.dentry.clone())
     }
+
+    fn sync(&self) -> EmptyResult {
+        let _guard = FATFS_LOCK.lock();
+        // Directory entry first, then fatfs flushes the device.
+        self.file.lock().flush().map_err(|e| "flush failed for fatfs.")?;
+        if let Some(open_file) = OPEN_FILES.lock().get_mut(&self.key()) {
+            open_file.unflushed_size = None;
+        }
+        Ok(())
+    }
 }
 
 struct FatFS {}
@@ -255,19 +445,14 @@ impl Filesystem for FatFS {
         Self {}
     }
 
-    fn mount(&self, device: Option<Arc<dyn File>>, mount_point: Arc<DirEntry>) -> KernelResult<Arc<dyn Inode>> {
+    fn mount(&self, device: Option<Arc<dyn File>>, mount_point: Arc<DirEntry>, flags: MountFlags) -> KernelResult<Arc<dyn Inode>> {
         let device = device.ok_or("Must provided device file for fatfs")?;
+        let _guard = FATFS_LOCK.lock();
         let fs = fatfs::FileSystem::new(FatFSDeviceWrapper::new(device), fatfs::FsOptions::new()).unwrap();
         let fs = Arc::new(fs);
         let fs_ref = unsafe { Arc::as_ptr(&fs).as_ref::<'static>().unwrap() };
         let root_inode_n = (fs.stats().unwrap().total_clusters() + 1) as usize;
-        Ok(Arc::new(FatFSInode {
-            inode_n: root_inode_n,
-            path: "/".to_string(),
-            type_: FatFSInodeType::Dir,
-            mountpoint: Some(fs),
-            fs: fs_ref,
-        }))
+        Ok(FatFSInode::new(root_inode_n, "/".to_string(), FatFSInodeType::Dir, Some(fs), fs_ref))
     }
 }
 
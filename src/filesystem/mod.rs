@@ -1,8 +1,17 @@
 /* Structs */
 
 mod fatfs;
+mod tmpfs;
+mod ext2;
+mod procfs;
+mod devfs;
+mod buffer_cache;
+mod page_cache;
+mod partition;
 
 use crate::core::Spinlock;
+use crate::process::SleepLock;
+use core::any::Any;
 use core::iter::Peekable;
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
@@ -10,9 +19,10 @@ use alloc::string::{String, ToString};
 use alloc::sync::{Weak, Arc};
 use alloc::{format, vec};
 use alloc::vec::Vec;
-use core::cell::OnceCell;
 use core::fmt::{Debug, Display, Formatter};
 use core::ops::Deref;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
 use ::fatfs::Dir;
 use bitflags::{bitflags, Flags};
 use log::info;
@@ -20,33 +30,64 @@ use lazy_static::lazy_static;
 use sbi::pmu::configure_matching_counters;
 use virtio_drivers::device::socket::SocketError;
 use crate::{do_init, println};
-use crate::utils::error::{Result, EmptyResult};
+use crate::utils::error::{Result, EmptyResult, KernelError};
+
+pub use devfs::{Device, makedev, register_block_device, register_char_device, unregister_device};
+pub use buffer_cache::{BlockDevice, BlockDeviceFile};
+pub use partition::register_partitions;
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum DirEntryType {
     File,
     Dir,
+    Link,
+}
+
+/// Why a path walk failed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LookupError {
+    NotFound,
+    NotDir,
+    // Too many symlinks followed, likely a loop.
+    Loop,
 }
 
+/// Why unmounting failed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UnmountError {
+    // Not the root of a mount.
+    NotMounted,
+    // Files opened, cwd or other mounts inside.
+    Busy,
+    // Writing back to device failed.
+    Sync,
+}
+
+// Same as MAXSYMLINKS of Linux.
+const MAX_SYMLINK_FOLLOWS: usize = 40;
+
 pub struct DirEntry {
     parent: Option<Weak<DirEntry>>,
     pub name: String,
-    inode: Option<Arc<dyn Inode>>,
+    // Swapped by mount and unmount, under the lock of children.
+    inode: Spinlock<Option<Arc<dyn Inode>>>,
     type_: DirEntryType,
-    children: Spinlock<BTreeMap<String, Arc<DirEntry>>>,
-    children_fully_loaded: OnceCell<()>,
+    children: SleepLock<BTreeMap<String, Arc<DirEntry>>>,
+    children_fully_loaded: AtomicBool,
 }
 
 impl Debug for DirEntry {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "[DirEntry({}) {}]", match &self.type_ {
             &DirEntryType::File => "File",
-            &DirEntryType::Dir => "Dir"
+            &DirEntryType::Dir => "Dir",
+            &DirEntryType::Link => "Link",
         }, self.fullpath())
     }
 }
 
 bitflags! {
+    #[derive(Copy, Clone, PartialEq)]
     pub struct FileOpenFlags: u32 {
         const O_RDONLY = 0x00;
         const O_WRONLY = 0x01;
@@ -57,6 +98,7 @@ bitflags! {
         const O_EXCL = 0x80;
         const O_TRUNC = 0x200;
         const O_DIRECTORY = 0x10000;
+        const O_NOFOLLOW = 0x20000;
         const O_CLOEXEC = 0x80000;
 
         // file status flags
@@ -98,6 +140,26 @@ bitflags! {
     }
 }
 
+bitflags! {
+    #[derive(Copy, Clone, PartialEq)]
+    pub struct RenameFlags: u32 {
+        const RENAME_NOREPLACE = 1;
+        const RENAME_EXCHANGE = 2;
+        const RENAME_WHITEOUT = 4;
+    }
+}
+
+bitflags! {
+    #[derive(Copy, Clone, PartialEq)]
+    pub struct MountFlags: u32 {
+        const MS_RDONLY = 1;
+        const MS_NOSUID = 2;
+        const MS_NODEV = 4;
+        const MS_NOEXEC = 8;
+        const MS_REMOUNT = 32;
+    }
+}
+
 impl From<usize> for FileOpenFlags {
     fn from(value: usize) -> Self {
         FileOpenFlags::from_bits(value as u32).unwrap()
@@ -116,7 +178,8 @@ impl FileOpenFlags {
     }
 
     pub fn is_write(&self) -> bool {
-        !self.contains(FileOpenFlags::O_RDONLY)
+        // O_RDONLY is 0, contained by anything.
+        self.intersects(FileOpenFlags::O_WRONLY | FileOpenFlags::O_RDWR)
     }
 
     pub fn is_directory(&self) -> bool {
@@ -130,6 +193,10 @@ impl FileOpenFlags {
     pub fn must_create(&self) -> bool {
         self.contains(FileOpenFlags::O_CREAT) && self.contains(FileOpenFlags::O_EXCL)
     }
+
+    pub fn is_nofollow(&self) -> bool {
+        self.contains(FileOpenFlags::O_NOFOLLOW)
+    }
 }
 
 impl FileModes {
@@ -174,8 +241,13 @@ pub struct InodeStat {
     pub ino: usize,
     pub mode: usize,
     pub nlink: usize,
+    // 设备号，仅对字符/块设备有意义
+    pub rdev: usize,
     pub size: usize,
     pub block_size: usize,
+    pub atime: Duration,
+    pub mtime: Duration,
+    pub ctime: Duration,
 }
 
 impl InodeStat {
@@ -184,14 +256,18 @@ impl InodeStat {
             ino: 0,
             mode: (FileModes::DIRECTORY | FileModes::Read | FileModes::Write | FileModes::Exec).bits() as usize,
             nlink: 1,
+            rdev: 0,
             size: 0,
             block_size: 0,
+            atime: Duration::ZERO,
+            mtime: Duration::ZERO,
+            ctime: Duration::ZERO,
         }
     }
 }
 
 /* Traits */
-pub trait Inode: Drop {
+pub trait Inode {
     // Inode must be droppable
     // 在目录项中寻找名字为name的。
     fn lookup(&self, name: &str, this_dentry: Weak<DirEntry>) -> Option<DirEntry>;
@@ -209,6 +285,35 @@ pub trait Inode: Drop {
     fn get_dentry_type(&self) -> DirEntryType;
     // 获取统计信息
     fn get_stat(&self) -> InodeStat;
+    // 在本目录中创建普通文件
+    fn create(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        Err("create is not supported.".into())
+    }
+    // 将本目录中的old_name移动为new_dir中的new_name，两者属于同一文件系统。
+    // 调用者保证两项都不是对方目录的祖先
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str, flags: RenameFlags) -> EmptyResult {
+        Err("rename is not supported.".into())
+    }
+    // 在本目录中创建符号链接
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>> {
+        Err("symlink is not supported.".into())
+    }
+    // 目录内容由内核状态动态生成（如procfs），缓存的子目录项每次访问都要重新校验
+    fn is_dynamic(&self) -> bool {
+        false
+    }
+    // 读取符号链接的目标
+    fn readlink(&self) -> Result<String> {
+        Err("Not a symlink.".into())
+    }
+    // 将本Inode所在文件系统的数据写回设备
+    fn sync(&self) -> EmptyResult {
+        Ok(())
+    }
+    // 本Inode所在文件系统的挂载标志改变，只读挂载之后不得再写设备
+    fn remount(&self, flags: MountFlags) -> EmptyResult {
+        Ok(())
+    }
 }
 
 pub trait Superblock {
@@ -216,9 +321,10 @@ pub trait Superblock {
 }
 
 // 1 FS has ONE FS
-pub trait Filesystem {
+pub trait Filesystem: Send {
     fn new() -> Self where Self: Sized;
-    fn mount(&self, device: Option<Arc<dyn File>>, mount_point: Arc<DirEntry>) -> Result<Arc<dyn Inode>>;
+    // Read-only mounts (MS_RDONLY in `flags`) must not write the device.
+    fn mount(&self, device: Option<Arc<dyn File>>, mount_point: Arc<DirEntry>, flags: MountFlags) -> Result<Arc<dyn Inode>>;
 }
 
 pub trait File {
@@ -227,6 +333,26 @@ pub trait File {
     fn write(&self, buf: &[u8]) -> Result<usize>;
     fn close(&self) -> EmptyResult;
     fn get_dentry(&self) -> Result<Arc<DirEntry>>;
+    // 将文件数据写回设备
+    fn sync(&self) -> EmptyResult {
+        Ok(())
+    }
+    /// Concrete file for syscalls working on a special kind of file only, like timerfd.
+    fn as_any(&self) -> Option<&dyn Any> {
+        None
+    }
+    /// Flags it was opened with by path, None for files not opened so.
+    fn open_flags(&self) -> Option<FileOpenFlags> {
+        None
+    }
+    /// Read at `offset` without using or moving the file position.
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        Err("Positioned read is not supported.".into())
+    }
+    /// Write at `offset` without using or moving the file position, O_APPEND is ignored.
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        Err("Positioned write is not supported.".into())
+    }
 }
 
 pub struct DirFile {
@@ -275,10 +401,124 @@ impl File for DirFile {
     }
 }
 
+// Counted in `open_files` of the mount while alive.
+struct OpenRef(Arc<AtomicUsize>);
+
+impl OpenRef {
+    fn new(open_files: &Arc<AtomicUsize>) -> Self {
+        open_files.fetch_add(1, Ordering::Relaxed);
+        Self(open_files.clone())
+    }
+}
+
+impl Drop for OpenRef {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// File opened on a mounted filesystem, keeps the mount busy until dropped.
+struct MountedFile {
+    file: Arc<dyn File>,
+    flags: FileOpenFlags,
+    _open: OpenRef,
+}
+
+impl File for MountedFile {
+    fn seek(&self, offset: isize, whence: SeekPosition) -> Result<usize> {
+        self.file.seek(offset, whence)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        self.file.read(buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn close(&self) -> EmptyResult {
+        self.file.close()
+    }
+
+    fn get_dentry(&self) -> Result<Arc<DirEntry>> {
+        self.file.get_dentry()
+    }
+
+    fn sync(&self) -> EmptyResult {
+        self.file.sync()
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        self.file.as_any()
+    }
+
+    fn open_flags(&self) -> Option<FileOpenFlags> {
+        Some(self.flags)
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        self.file.read_at(offset, buf)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        self.file.write_at(offset, buf)
+    }
+}
+
+#[derive(Clone)]
+pub struct MountRecord {
+    pub id: usize,
+    // Mount the mount point lives in, 0 if on vfs.
+    pub parent_id: usize,
+    pub device: String,
+    pub mount_point: String,
+    pub filesystem: &'static str,
+    pub flags: MountFlags,
+    // Dentry the filesystem is grafted on.
+    dentry: Weak<DirEntry>,
+    // Inode of mount point before mounting, put back on unmount.
+    covered: Option<Arc<dyn Inode>>,
+    // Files opened on it, wherever their dentries are.
+    open_files: Arc<AtomicUsize>,
+}
+
+// Inodes are used from every CPU, filesystems lock their own state.
+unsafe impl Send for MountRecord {}
+
+impl MountRecord {
+    /// Options field of `/proc/mounts`.
+    pub fn options(&self) -> String {
+        let mut options = if self.flags.contains(MountFlags::MS_RDONLY) { "ro" } else { "rw" }.to_string();
+        for (flag, name) in [(MountFlags::MS_NOSUID, ",nosuid"), (MountFlags::MS_NODEV, ",nodev"), (MountFlags::MS_NOEXEC, ",noexec")] {
+            if self.flags.contains(flag) {
+                options.push_str(name);
+            }
+        }
+        options
+    }
+
+    fn is_on(&self, dentry: &Arc<DirEntry>) -> bool {
+        self.dentry.upgrade().is_some_and(|mounted| Arc::ptr_eq(&mounted, dentry))
+    }
+}
+
+// Lazily unmounted filesystem, dropped once nothing inside is in use.
+struct DetachedMount {
+    // Declared first, entries go away before the filesystem they live in.
+    children: BTreeMap<String, Arc<DirEntry>>,
+    root: Arc<dyn Inode>,
+    open_files: Arc<AtomicUsize>,
+}
+
 lazy_static! {
-    static ref FILESYSTEMS: Spinlock<BTreeMap<&'static str, Box<dyn Filesystem>>> = Spinlock::new(BTreeMap::new());
+    static ref FILESYSTEMS: SleepLock<BTreeMap<&'static str, Box<dyn Filesystem>>> = SleepLock::new(BTreeMap::new());
+    static ref MOUNTS: SleepLock<Vec<MountRecord>> = SleepLock::new(Vec::new());
+    static ref DETACHED: Spinlock<Vec<DetachedMount>> = Spinlock::new(Vec::new());
 }
 
+static NEXT_MOUNT_ID: AtomicUsize = AtomicUsize::new(1);
+
 static mut ROOT_DENTRY: Option<Arc<DirEntry>> = None;
 
 pub fn register_filesystem(name: &'static str, filesystem: Box<dyn Filesystem>) {
@@ -290,29 +530,69 @@ pub fn init() {
     let root_dentry = Arc::new(DirEntry {
         parent: None,
         name: "/".to_string(),
-        inode: None,
-        children: Spinlock::new(BTreeMap::new()),
+        inode: Spinlock::new(None),
+        children: SleepLock::new(BTreeMap::new()),
         type_: DirEntryType::Dir,
-        children_fully_loaded: OnceCell::new(),
+        children_fully_loaded: AtomicBool::new(false),
     });
     // Safety: Only write here once
     unsafe { ROOT_DENTRY = Some(root_dentry.clone()) };
+    do_init!(
+        fatfs,
+        tmpfs,
+        ext2,
+        procfs,
+        devfs
+    );
+
     // Create /dev
     root_dentry.mkdir("dev").expect("Failed to create /dev on vfs.");
+    mount(None, "", "/dev", "devfs", MountFlags::MS_NOSUID).expect("Failed to mount devfs.");
 
-    do_init!(
-        fatfs
-    );
+    // Create /proc
+    root_dentry.mkdir("proc").expect("Failed to create /proc on vfs.");
+    mount(None, "", "/proc", "proc", MountFlags::MS_NOSUID | MountFlags::MS_NODEV | MountFlags::MS_NOEXEC)
+        .expect("Failed to mount procfs.");
 }
 
-pub fn mount(cwd: Option<Arc<DirEntry>>, dev: &str, mount_point: &str, filesystem: &str) -> EmptyResult {
+/// Write everything of mounted filesystems back to their devices.
+pub fn sync() -> EmptyResult {
+    reap_detached();
+    let roots = MOUNTS.lock().iter()
+        .filter_map(|record| record.dentry.upgrade().and_then(|dentry| dentry.get_inode()))
+        .collect::<Vec<_>>();
+    for root in roots {
+        root.sync()?;
+    }
+    buffer_cache::sync_all()
+}
+
+/// Bytes held by buffer cache and page cache.
+pub fn cache_stats() -> (usize, usize) {
+    (buffer_cache::cached_bytes(), page_cache::cached_bytes())
+}
+
+/// Snapshot of mounted filesystems in mount order.
+pub fn mounts() -> Vec<MountRecord> {
+    MOUNTS.lock().clone()
+}
+
+pub fn mount(cwd: Option<Arc<DirEntry>>, dev: &str, mount_point: &str, filesystem: &str, flags: MountFlags) -> EmptyResult {
+    reap_detached();
+    if flags.contains(MountFlags::MS_REMOUNT) {
+        return remount(cwd, mount_point, flags);
+    }
     // get filesystem
     let fss = FILESYSTEMS.lock();
-    let fs = fss.get(filesystem).ok_or("Filesystem Not Found")?;
-    // get dev
-    let dev = DirEntry::from_path(dev, cwd.clone()).ok_or("Device Not Found")?;
+    let (fs_name, fs) = fss.get_key_value(filesystem).ok_or("Filesystem Not Found")?;
+    // get dev, filesystem like tmpfs needs no device
+    let dev_dentry = if dev.is_empty() {
+        None
+    } else {
+        DirEntry::from_path(dev, cwd.clone())
+    };
     // get mount_point
-    let mut mount_point = DirEntry::from_path(mount_point, cwd.clone()).ok_or("Mount Point Not Found")?;
+    let mount_point = DirEntry::from_path(mount_point, cwd.clone()).ok_or("Mount Point Not Found")?;
     // check if mount_point is a dir
     if mount_point.type_ != DirEntryType::Dir {
         return Err("Mount Point is not a directory.".into());
@@ -321,19 +601,112 @@ pub fn mount(cwd: Option<Arc<DirEntry>>, dev: &str, mount_point: &str, filesyste
     if mount_point.children.lock().len() != 0 {
         return Err("Mount Point is not empty.".into());
     }
-    // FIXME: Check if already mounted.
+    if mount_point.is_mount_point() {
+        return Err("Mount Point is already mounted.".into());
+    }
 
     // Open device file
-    let dev = dev.open(FileOpenFlags::O_RDWR, FileModes::from_bits(0).unwrap())?;
+    let dev_flags = if flags.contains(MountFlags::MS_RDONLY) { FileOpenFlags::O_RDONLY } else { FileOpenFlags::O_RDWR };
+    let dev_file = match dev_dentry {
+        Some(dev) => Some(dev.open(dev_flags, FileModes::from_bits(0).unwrap())?),
+        None => None
+    };
     // mount filesystem
-    let root_inode = fs.mount(Some(dev), mount_point.clone())?;
+    let root_inode = fs.mount(dev_file, mount_point.clone(), flags)?;
+    let parent_id = mount_point.parent.as_ref().and_then(|p| p.upgrade())
+        .and_then(|parent| parent.mount_record())
+        .map(|record| record.id)
+        .unwrap_or(0);
     // mount to dentry
-    unsafe {
-        Arc::get_mut_unchecked(&mut mount_point).inode = Some(root_inode);
+    let covered = {
+        // Walkers look at inode and children under the lock of children.
+        let _children = mount_point.children.lock();
+        // Listing of the covered directory says nothing about the mounted one.
+        mount_point.children_fully_loaded.store(false, Ordering::Relaxed);
+        mount_point.inode.lock().replace(root_inode)
+    };
+    MOUNTS.lock().push(MountRecord {
+        id: NEXT_MOUNT_ID.fetch_add(1, Ordering::Relaxed),
+        parent_id,
+        device: if dev.is_empty() { "none".to_string() } else { dev.to_string() },
+        mount_point: mount_point.fullpath(),
+        filesystem: fs_name,
+        flags: flags.difference(MountFlags::MS_REMOUNT),
+        dentry: Arc::downgrade(&mount_point),
+        covered,
+        open_files: Arc::new(AtomicUsize::new(0)),
+    });
+    Ok(())
+}
+
+/// Change flags of an existing mount.
+fn remount(cwd: Option<Arc<DirEntry>>, mount_point: &str, flags: MountFlags) -> EmptyResult {
+    let mount_point = DirEntry::from_path(mount_point, cwd).ok_or("Mount Point Not Found")?;
+    let mut mounts = MOUNTS.lock();
+    let record = mounts.iter_mut().find(|record| record.is_on(&mount_point)).ok_or("Not a mount point.")?;
+    let root = mount_point.get_inode().unwrap();
+    if flags.contains(MountFlags::MS_RDONLY) && !record.flags.contains(MountFlags::MS_RDONLY) {
+        root.sync()?;
     }
+    root.remount(flags)?;
+    record.flags = flags.difference(MountFlags::MS_REMOUNT);
     Ok(())
 }
 
+// Whether entries below are referenced from outside of the dentry cache, i.e. by opened
+// files, cwd of processes or whoever is walking in there.
+fn subtree_in_use(children: &BTreeMap<String, Arc<DirEntry>>) -> bool {
+    children.values().any(|child| Arc::strong_count(child) > 1 || subtree_in_use(&child.children.lock()))
+}
+
+/// Unmount filesystem mounted on `mount_point`, the caller must hold no other reference to it.
+/// With `detach`, the filesystem leaves the tree right away and is dropped when no longer used.
+pub fn unmount(mount_point: Arc<DirEntry>, detach: bool) -> core::result::Result<(), UnmountError> {
+    reap_detached();
+    let mut mounts = MOUNTS.lock();
+    let index = mounts.iter().position(|record| record.is_on(&mount_point)).ok_or(UnmountError::NotMounted)?;
+    // Mounts inside have to go first, even for detaching.
+    if mounts.iter().any(|record| record.dentry.upgrade()
+        .is_some_and(|dentry| !Arc::ptr_eq(&dentry, &mount_point) && mount_point.is_ancestor_of(&dentry))) {
+        return Err(UnmountError::Busy);
+    }
+    // Ours and the one in dentry cache of parent (or ROOT_DENTRY).
+    let busy = mounts[index].open_files.load(Ordering::Relaxed) != 0
+        || Arc::strong_count(&mount_point) > 2
+        || subtree_in_use(&mount_point.children.lock());
+    if busy && !detach {
+        return Err(UnmountError::Busy);
+    }
+    let root = mount_point.get_inode().unwrap();
+    root.sync().map_err(|_| UnmountError::Sync)?;
+    let record = mounts.remove(index);
+    drop(mounts);
+    let children = {
+        let mut children = mount_point.children.lock();
+        mount_point.children_fully_loaded.store(false, Ordering::Relaxed);
+        *mount_point.inode.lock() = record.covered;
+        core::mem::take(&mut *children)
+    };
+    if busy {
+        DETACHED.lock().push(DetachedMount { children, root, open_files: record.open_files });
+    }
+    Ok(())
+}
+
+// Drop lazily unmounted filesystems nobody uses anymore.
+fn reap_detached() {
+    let idle = {
+        let mut detached = DETACHED.lock();
+        let (idle, busy): (Vec<_>, Vec<_>) = core::mem::take(&mut *detached).into_iter()
+            .partition(|detached| detached.open_files.load(Ordering::Relaxed) == 0 && !subtree_in_use(&detached.children));
+        *detached = busy;
+        idle
+    };
+    for detached in idle {
+        let _ = detached.root.sync();
+    }
+}
+
 /*
     Filesystem子系统负责管理DirEntry。其他部分交由具体的FS实现Inode和File部分。
  */
@@ -342,10 +715,10 @@ impl DirEntry {
         Self {
             parent,
             name,
-            inode,
+            inode: Spinlock::new(inode),
             type_,
-            children: Spinlock::new(BTreeMap::new()),
-            children_fully_loaded: OnceCell::new(),
+            children: SleepLock::new(BTreeMap::new()),
+            children_fully_loaded: AtomicBool::new(false),
         }
     }
 
@@ -354,16 +727,39 @@ impl DirEntry {
         return unsafe { ROOT_DENTRY.as_ref().unwrap() }.clone();
     }
 
+    /// Walk to the parent of last component of `path`, symlinks in the middle are followed.
     pub fn get_parent(path: &str, cwd: Option<Arc<DirEntry>>) -> Option<(Arc<DirEntry>, &str)> {
-        let root_dentry_arc = Self::root();
+        Self::resolve_parent(path, cwd).ok()
+    }
+
+    /// Walk to the entry of `path`, following symlinks all the way.
+    pub fn from_path(path: &str, cwd: Option<Arc<DirEntry>>) -> Option<Arc<DirEntry>> {
+        Self::resolve(path, cwd, true).ok()
+    }
+
+    pub fn resolve_parent(path: &str, cwd: Option<Arc<DirEntry>>) -> core::result::Result<(Arc<DirEntry>, &str), LookupError> {
+        let mut budget = MAX_SYMLINK_FOLLOWS;
+        Self::walk_parent(path, cwd, &mut budget)
+    }
+
+    /// Walk to the entry of `path`. With `follow` unset, a symlink at the last component is
+    /// returned as is, like lstat and O_NOFOLLOW want.
+    pub fn resolve(path: &str, cwd: Option<Arc<DirEntry>>, follow: bool) -> core::result::Result<Arc<DirEntry>, LookupError> {
+        let mut budget = MAX_SYMLINK_FOLLOWS;
+        Self::walk(path, cwd, follow, &mut budget)
+    }
+
+    // budget 是整个路径解析过程中还能跟随的符号链接数，嵌套的链接共享同一个预算
+    fn walk(path: &str, cwd: Option<Arc<DirEntry>>, follow: bool, budget: &mut usize) -> core::result::Result<Arc<DirEntry>, LookupError> {
+        let (parent, name) = Self::walk_parent(path, cwd, budget)?;
+        parent.step(name, follow, budget)
+    }
+
+    fn walk_parent<'a>(path: &'a str, cwd: Option<Arc<DirEntry>>, budget: &mut usize) -> core::result::Result<(Arc<DirEntry>, &'a str), LookupError> {
         let (cwd, path) = if let Some(cwd) = cwd && !path.starts_with("/") {
             (cwd, path)
         } else {
-            (root_dentry_arc.clone(), if path.starts_with("/") {
-                &path[1..]
-            } else {
-                path
-            })
+            (Self::root(), path.trim_start_matches('/'))
         };
 
         let mut paths = path.split("/").peekable();
@@ -371,85 +767,64 @@ impl DirEntry {
         while let Some(name) = paths.next() {
             if paths.peek().is_none() {
                 // last name
-                return Some((parent, name));
-            }
-            if name == ".." {
-                parent = parent.parent.as_ref().map(|p| p.upgrade().expect("Parent not found."))
-                    .unwrap_or(root_dentry_arc.clone());
-            } else if name == "." || name.len() == 0 {
-                // do nothing
-            } else {
-                // do search deep
-                let mut new_parent = None;
-                'found: loop {
-                    if DirEntryType::Dir != parent.type_ {
-                        return None;
-                    }
-                    let mut children = parent.children.lock();
-                    if let Some(child) = children.get(name) {
-                        new_parent = Some(child.clone());
-                        break 'found;
-                    }
-
-                    // not found in loaded children
-                    if let Some(dir_inode) = &parent.inode {
-                        let dir_inode = dir_inode.clone();
-                        let lookup_result = dir_inode.lookup(name, Arc::downgrade(&parent));
-                        if let Some(mut lookup_result) = lookup_result {
-                            let dentry = Arc::new(lookup_result);
-                            children.insert(dentry.name.clone(), dentry.clone());
-                            new_parent = Some(dentry);
-                        } else {
-                            return None;
-                        }
-                    } else {
-                        return None;
-                    }
-                }
-                if let Some(new_parent) = new_parent {
-                    parent = new_parent
-                }
+                return Ok((parent, name));
             }
+            parent = parent.step(name, true, budget)?;
         }
         // If path is empty
-        Some((parent, ""))
+        Ok((parent, ""))
     }
 
-    pub fn from_path(path: &str, cwd: Option<Arc<DirEntry>>) -> Option<Arc<DirEntry>> {
-        if path == "/" {
-            return Some(Self::root());
+    /// Go one component down from this directory.
+    fn step(self: &Arc<Self>, name: &str, follow: bool, budget: &mut usize) -> core::result::Result<Arc<DirEntry>, LookupError> {
+        if self.type_ != DirEntryType::Dir {
+            return Err(LookupError::NotDir);
         }
-        let parent = Self::get_parent(path, cwd);
-        if let Some((parent, target_name)) = parent {
-            if target_name == ".." {
-                Some(parent.parent.as_ref().map(|p| p.upgrade().unwrap()).unwrap_or(Self::root()))
-            } else if target_name == "." || target_name == "" {
-                Some(parent)
-            } else {
-                loop {
-                    let mut children = parent.children.lock();
-
-                    if let Some(child) = children.get(target_name) {
-                        return Some(child.clone());
-                    }
-
-                    // not found in loaded children
-                    if let Some(dir_inode) = &parent.inode {
-                        let dir_inode = dir_inode.clone();
-                        let lookup_result = dir_inode.lookup(target_name, Arc::downgrade(&parent));
-                        if let Some(lookup_result) = lookup_result {
-                            let dentry = Arc::new(lookup_result);
-                            children.insert(dentry.name.clone(), dentry.clone());
-                            return Some(dentry);
-                        } else {
-                            return None;
-                        }
-                    } else {
-                        return None;
-                    }
+        match name {
+            ".." => Ok(self.parent.as_ref().map(|p| p.upgrade().expect("Parent not found."))
+                .unwrap_or(Self::root())),
+            "." | "" => Ok(self.clone()),
+            _ => {
+                let child = self.lookup_child(name).ok_or(LookupError::NotFound)?;
+                if follow && child.type_ == DirEntryType::Link {
+                    self.follow_link(&child, budget)
+                } else {
+                    Ok(child)
                 }
             }
+        }
+    }
+
+    /// Resolve symlink `link` living in this directory, relative target starts from here.
+    fn follow_link(self: &Arc<Self>, link: &Arc<DirEntry>, budget: &mut usize) -> core::result::Result<Arc<DirEntry>, LookupError> {
+        if *budget == 0 {
+            return Err(LookupError::Loop);
+        }
+        *budget -= 1;
+        let target = link.readlink().map_err(|_| LookupError::NotFound)?;
+        if target.is_empty() {
+            return Err(LookupError::NotFound);
+        }
+        Self::walk(&target, Some(self.clone()), true, budget)
+    }
+
+    /// Find child in loaded children, or look it up from inode.
+    /// Children of dynamic directory are looked up every time, and dropped once gone.
+    fn lookup_child(self: &Arc<Self>, name: &str) -> Option<Arc<DirEntry>> {
+        let mut children = self.children.lock();
+        let inode = self.get_inode();
+        let dynamic = inode.as_ref().is_some_and(|inode| inode.is_dynamic());
+        if !dynamic && let Some(child) = children.get(name) {
+            return Some(child.clone());
+        }
+
+        // not found in loaded children
+        let lookup_result = inode?.lookup(name, Arc::downgrade(self));
+        if let Some(lookup_result) = lookup_result {
+            // Keep the loaded one, so that its users still see the same entry.
+            Some(children.entry(name.to_string()).or_insert_with(|| Arc::new(lookup_result)).clone())
         } else {
+            children.remove(name);
             None
         }
     }
@@ -473,21 +848,21 @@ impl DirEntry {
     pub fn link(self: Arc<Self>, inode: Arc<dyn Inode>, name: &str) -> Result<Arc<DirEntry>> {
         let mut children = self.children.lock();
         if children.contains_key(name) {
-            return Err("Already existed.".into());
+            return Err(KernelError::ALREADY_EXISTS);
         }
 
-        if let Some(inode) = &self.inode {
-            inode.link(inode.clone(), name).expect("Cannot link to parent inode")
+        if let Some(parent_inode) = self.get_inode() {
+            parent_inode.link(inode.clone(), name)?;
         }
 
 
         let dentry = Arc::new(DirEntry {
             parent: Some(Arc::downgrade(&self)),
             name: name.to_string(),
-            inode: Some(inode.clone()),
+            inode: Spinlock::new(Some(inode.clone())),
             type_: inode.get_dentry_type(),
-            children: Spinlock::new(BTreeMap::new()),
-            children_fully_loaded: OnceCell::new(),
+            children: SleepLock::new(BTreeMap::new()),
+            children_fully_loaded: AtomicBool::new(false),
         });
         children.insert(name.to_string(), dentry.clone());
 
@@ -495,15 +870,26 @@ impl DirEntry {
     }
 
     pub fn open(self: Arc<DirEntry>, flags: FileOpenFlags, mode: FileModes) -> Result<Arc<dyn File>> {
-        match self.type_ {
+        // Counted before opening, so that unmount never misses it.
+        let root = self.mount_root();
+        let open = MOUNTS.lock().iter().find(|record| record.is_on(&root))
+            .map(|record| OpenRef::new(&record.open_files));
+        let file: Result<Arc<dyn File>> = match self.type_ {
             DirEntryType::File => {
-                self.inode.as_ref().ok_or("No inode to open")?.open(self.clone(), flags, mode)
+                self.get_inode().ok_or("No inode to open")?.open(self.clone(), flags, mode)
             }
             DirEntryType::Dir => Ok(Arc::new(DirFile {
                 dentry: self,
                 iterator: Spinlock::new(0),
-            }))
-        }
+            })),
+            // Links are resolved by path walk, only reachable here with O_NOFOLLOW.
+            DirEntryType::Link => Err("Cannot open symlink.".into())
+        };
+        let file = file?;
+        Ok(match open {
+            Some(open) => Arc::new(MountedFile { file, flags, _open: open }),
+            None => file
+        })
     }
 
     pub fn mkdir(self: Arc<DirEntry>, name: &str) -> Result<Arc<DirEntry>> {
@@ -512,10 +898,11 @@ impl DirEntry {
         }
         let mut children = self.children.lock();
         if children.contains_key(name) {
-            return Err("Already existed.".into());
+            return Err(KernelError::ALREADY_EXISTS);
         }
-        let mut dir_inode = if let Some(inode) = &self.inode {
-            Some(inode.mkdir(name).expect("Failed to mkdir inode."))
+        // Name may exist on disk though not cached, filesystem tells.
+        let mut dir_inode = if let Some(inode) = self.get_inode() {
+            Some(inode.mkdir(name)?)
         } else {
             None
         };
@@ -523,32 +910,195 @@ impl DirEntry {
         let dentry = Arc::new(DirEntry {
             parent: Some(Arc::downgrade(&self)),
             name: name.to_string(),
-            inode: dir_inode,
+            inode: Spinlock::new(dir_inode),
             type_: DirEntryType::Dir,
-            children: Spinlock::new(BTreeMap::new()),
-            children_fully_loaded: OnceCell::new(),
+            children: SleepLock::new(BTreeMap::new()),
+            children_fully_loaded: AtomicBool::new(false),
+        });
+        children.insert(name.to_string(), dentry.clone());
+        Ok(dentry)
+    }
+
+    pub fn create(self: Arc<DirEntry>, name: &str) -> Result<Arc<DirEntry>> {
+        if name == "." || name == ".." || name.len() == 0 {
+            return Err("Invalid file name.".into());
+        }
+        let mut children = self.children.lock();
+        if children.contains_key(name) {
+            return Err(KernelError::ALREADY_EXISTS);
+        }
+        // Pure VFS directory holds no file content.
+        let inode = self.get_inode().ok_or("Cannot create file on vfs.")?.create(name)?;
+
+        let dentry = Arc::new(DirEntry {
+            parent: Some(Arc::downgrade(&self)),
+            name: name.to_string(),
+            inode: Spinlock::new(Some(inode)),
+            type_: DirEntryType::File,
+            children: SleepLock::new(BTreeMap::new()),
+            children_fully_loaded: AtomicBool::new(false),
+        });
+        children.insert(name.to_string(), dentry.clone());
+        Ok(dentry)
+    }
+
+    /// Remove a non-directory entry. Open files keep their dentry and inode alive.
+    pub fn unlink(self: Arc<DirEntry>, name: &str) -> EmptyResult {
+        let mut children = self.children.lock();
+        self.get_inode().ok_or("Cannot unlink on vfs.")?.unlink(name)?;
+        children.remove(name);
+        Ok(())
+    }
+
+    pub fn rmdir(self: Arc<DirEntry>, name: &str) -> EmptyResult {
+        let child = self.lookup_child(name).ok_or("No such directory.")?;
+        if child.type_ != DirEntryType::Dir {
+            return Err("Not a directory.".into());
+        }
+        if child.is_mount_point() {
+            return Err("Mount point is busy.".into());
+        }
+        let mut children = self.children.lock();
+        if child.get_inode().is_some() {
+            self.get_inode().ok_or("Cannot rmdir on vfs.")?.rmdir(name)?;
+        } else if !child.children.lock().is_empty() {
+            // Directory created on vfs only.
+            return Err(KernelError::NOT_EMPTY);
+        }
+        children.remove(name);
+        Ok(())
+    }
+
+    /// Move `old_name` of this directory to `new_name` of `new_parent` on the same filesystem.
+    /// Entries held by others (open files, cwd) keep pointing to their inodes under the old name.
+    pub fn rename(self: Arc<DirEntry>, old_name: &str, new_parent: Arc<DirEntry>, new_name: &str, flags: RenameFlags) -> EmptyResult {
+        let inode = self.get_inode().ok_or("Cannot rename on vfs.")?;
+        let new_inode = new_parent.get_inode().ok_or("Cannot rename on vfs.")?;
+        inode.rename(old_name, &new_inode, new_name, flags)?;
+        // Both names are looked up again, fully loaded directory won't do it by itself when iterating.
+        for (parent, name) in [(&self, old_name), (&new_parent, new_name)] {
+            parent.children.lock().remove(name);
+            let _ = parent.lookup_child(name);
+        }
+        Ok(())
+    }
+
+    pub fn is_mount_point(self: &Arc<Self>) -> bool {
+        MOUNTS.lock().iter().any(|record| record.is_on(self))
+    }
+
+    /// Record of the mount this entry lives in, None if on vfs.
+    pub fn mount_record(self: &Arc<Self>) -> Option<MountRecord> {
+        let root = self.mount_root();
+        MOUNTS.lock().iter().find(|record| record.is_on(&root)).cloned()
+    }
+
+    pub fn mount_flags(self: &Arc<Self>) -> MountFlags {
+        self.mount_record().map(|record| record.flags).unwrap_or(MountFlags::empty())
+    }
+
+    pub fn is_readonly(self: &Arc<Self>) -> bool {
+        self.mount_flags().contains(MountFlags::MS_RDONLY)
+    }
+
+    /// Mount point of the filesystem this entry lives in.
+    fn mount_root(self: &Arc<Self>) -> Arc<DirEntry> {
+        let mut cur = self.clone();
+        while !cur.is_mount_point() {
+            cur = match cur.parent.as_ref().and_then(|p| p.upgrade()) {
+                Some(parent) => parent,
+                None => return cur
+            };
+        }
+        cur
+    }
+
+    pub fn same_filesystem(self: &Arc<Self>, other: &Arc<DirEntry>) -> bool {
+        Arc::ptr_eq(&self.mount_root(), &other.mount_root())
+    }
+
+    /// Whether `other` is this entry or lives under it.
+    pub fn is_ancestor_of(self: &Arc<Self>, other: &Arc<DirEntry>) -> bool {
+        let mut cur = Some(other.clone());
+        while let Some(dentry) = cur {
+            if Arc::ptr_eq(&dentry, self) {
+                return true;
+            }
+            cur = dentry.parent.as_ref().and_then(|p| p.upgrade());
+        }
+        false
+    }
+
+    /// Whether directory holds nothing except "." and "..".
+    pub fn is_empty_dir(self: &Arc<Self>) -> Result<bool> {
+        let mut i = 0;
+        while let Some(child) = self.get_child(i)? {
+            if child.name != "." && child.name != ".." {
+                return Ok(false);
+            }
+            i += 1;
+        }
+        Ok(true)
+    }
+
+    pub fn symlink(self: Arc<DirEntry>, name: &str, target: &str) -> Result<Arc<DirEntry>> {
+        if name == "." || name == ".." || name.len() == 0 {
+            return Err("Invalid file name.".into());
+        }
+        let mut children = self.children.lock();
+        if children.contains_key(name) {
+            return Err(KernelError::ALREADY_EXISTS);
+        }
+        let inode = self.get_inode().ok_or("Cannot create symlink on vfs.")?.symlink(name, target)?;
+
+        let dentry = Arc::new(DirEntry {
+            parent: Some(Arc::downgrade(&self)),
+            name: name.to_string(),
+            inode: Spinlock::new(Some(inode)),
+            type_: DirEntryType::Link,
+            children: SleepLock::new(BTreeMap::new()),
+            children_fully_loaded: AtomicBool::new(false),
         });
         children.insert(name.to_string(), dentry.clone());
         Ok(dentry)
     }
 
+    pub fn readlink(&self) -> Result<String> {
+        if self.type_ != DirEntryType::Link {
+            return Err("Not a symlink.".into());
+        }
+        self.get_inode().ok_or("No inode to read link")?.readlink()
+    }
+
+    pub fn get_type(&self) -> DirEntryType {
+        self.type_
+    }
+
     pub fn get_inode(&self) -> Option<Arc<dyn Inode>> {
-        self.inode.clone()
+        self.inode.lock().clone()
     }
 
     pub fn get_child(self: &Arc<Self>, i: usize) -> Result<Option<Arc<DirEntry>>> {
-        if self.children_fully_loaded.get().is_none() {
+        let mut loaded = self.children.lock();
+        let inode = self.get_inode();
+        let dynamic = inode.as_ref().is_some_and(|inode| inode.is_dynamic());
+        // Dynamic directory is reloaded whenever iterating from start.
+        if !self.children_fully_loaded.load(Ordering::Relaxed) || (dynamic && i == 0) {
             // Not FULLY loaded yet
-            if let Some(inode) = &self.inode {
+            if let Some(inode) = &inode {
                 let children = inode.read_dir(Arc::downgrade(self))?;
-                // dedup
-                self.children.lock().extend(children.into_iter().map(|v| (v.name.clone(), Arc::new(v))));
+                if dynamic {
+                    loaded.retain(|name, _| children.iter().any(|child| &child.name == name));
+                }
+                // dedup, loaded ones are kept
+                for child in children {
+                    loaded.entry(child.name.clone()).or_insert_with(|| Arc::new(child));
+                }
             }
             // VFS always FULLY loaded.
-            self.children_fully_loaded.set(()).unwrap();
+            self.children_fully_loaded.store(true, Ordering::Relaxed);
         }
-        let children = self.children.lock();
-        let mut iter = children.iter();
+        let mut iter = loaded.iter();
         for i in 0..i {
             iter.next();
         }
@@ -0,0 +1,108 @@
+//! # Page cache
+//!
+//! Cache of file data in pages, keyed by inode. Filesystems keep a `PageCache` in their inode
+//! objects and fill it from disk on miss. Writes go to the filesystem and drop cached pages in
+//! the range, so dirty data only lives in the buffer cache below.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use alloc::sync::Arc;
+use core::cmp::min;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use crate::core::Spinlock;
+use crate::memory::{Addr, PAGE_SIZE, PhyAddr, PhyPage};
+use crate::utils::error::Result;
+use crate::utils::lru::LruCache;
+
+// 8MiB
+const MAX_PAGES: usize = 2048;
+
+lazy_static! {
+    // Keyed by (cache id, page index).
+    static ref PAGES: Spinlock<LruCache<(usize, usize), Arc<PhyPage>>> = Spinlock::new(LruCache::new());
+}
+
+static NEXT_CACHE_ID: AtomicUsize = AtomicUsize::new(1);
+
+pub struct PageCache {
+    id: usize,
+    // Bumped on invalidation, page loaded before that must not get in.
+    generation: AtomicUsize,
+}
+
+impl PageCache {
+    pub fn new() -> Self {
+        Self {
+            id: NEXT_CACHE_ID.fetch_add(1, Ordering::Relaxed),
+            generation: AtomicUsize::new(0),
+        }
+    }
+
+    /// Read file data of `size` bytes long, page missed is filled by `load(page_index, page)`.
+    /// Bytes of page beyond what `load` gives are zero.
+    pub fn read(&self, offset: usize, buf: &mut [u8], size: usize, mut load: impl FnMut(usize, &mut [u8]) -> Result<usize>) -> Result<usize> {
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = min(buf.len(), size - offset);
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let in_page = pos % PAGE_SIZE;
+            let chunk = min(len - done, PAGE_SIZE - in_page);
+            let page = self.get_page(pos / PAGE_SIZE, &mut load)?;
+            let bytes: &[u8] = PhyAddr::from(page.id).get_slice(PAGE_SIZE);
+            buf[done..done + chunk].copy_from_slice(&bytes[in_page..in_page + chunk]);
+            done += chunk;
+        }
+        Ok(len)
+    }
+
+    fn get_page(&self, index: usize, load: &mut impl FnMut(usize, &mut [u8]) -> Result<usize>) -> Result<Arc<PhyPage>> {
+        let key = (self.id, index);
+        let generation = {
+            let mut pages = PAGES.lock();
+            if let Some(page) = pages.get(&key) {
+                return Ok(page.clone());
+            }
+            self.generation.load(Ordering::Acquire)
+        };
+        // Loaded without the cache locked, filesystem takes its own locks.
+        let page = Arc::new(PhyPage::alloc());
+        load(index, PhyAddr::from(page.id).get_slice_mut(PAGE_SIZE))?;
+        let mut pages = PAGES.lock();
+        if self.generation.load(Ordering::Acquire) == generation {
+            while pages.len() >= MAX_PAGES && pages.evict(|_| true).is_some() {}
+            pages.insert(key, page.clone());
+        }
+        Ok(page)
+    }
+
+    /// Drop cached pages overlapping `offset..offset + len` after the data is changed.
+    pub fn invalidate(&self, offset: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let mut pages = PAGES.lock();
+        self.generation.fetch_add(1, Ordering::Release);
+        pages.remove_range((self.id, offset / PAGE_SIZE)..=(self.id, (offset + len - 1) / PAGE_SIZE));
+    }
+
+    pub fn invalidate_all(&self) {
+        let mut pages = PAGES.lock();
+        self.generation.fetch_add(1, Ordering::Release);
+        pages.remove_range((self.id, 0)..=(self.id, usize::MAX));
+    }
+}
+
+impl Drop for PageCache {
+    fn drop(&mut self) {
+        self.invalidate_all();
+    }
+}
+
+pub fn cached_bytes() -> usize {
+    PAGES.lock().len() * PAGE_SIZE
+}
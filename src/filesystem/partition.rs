@@ -0,0 +1,202 @@
+//! # Partition
+//!
+//! MBR and GPT partition tables. Partitions of a disk are registered to devfs next to the disk,
+//! like `vda1` of `vda`, each one opened as a block device file bounded to its range.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use log::{info, warn};
+use crate::filesystem::{BlockDevice, BlockDeviceFile, Device, DirEntry, File, FileOpenFlags, register_block_device};
+use crate::filesystem::buffer_cache;
+use crate::utils::error::{EmptyResult, Result};
+
+const MBR_SIGNATURE: u16 = 0xaa55;
+const MBR_ENTRIES_OFFSET: usize = 446;
+const MBR_TYPE_PROTECTIVE: u8 = 0xee;
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+// Logical partitions are numbered after the four primary ones.
+const MBR_FIRST_LOGICAL: usize = 5;
+// Broken EBR chain may loop.
+const MBR_MAX_LOGICAL: usize = 64;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_MAX_ENTRIES: usize = 128;
+
+struct PartitionEntry {
+    // Partition number, starts from 1.
+    number: usize,
+    // In sectors of the disk.
+    start: usize,
+    count: usize,
+}
+
+/// Partition of a disk, opened as a block device file limited to its range.
+pub struct Partition {
+    device: Arc<dyn BlockDevice>,
+    // In bytes.
+    start: usize,
+    size: usize,
+}
+
+impl Device for Partition {
+    fn open(self: Arc<Self>, dentry: Arc<DirEntry>, flags: FileOpenFlags) -> Result<Arc<dyn File>> {
+        Ok(Arc::new(BlockDeviceFile::with_range(self.device.clone(), dentry, self.start, self.size, flags)))
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn block_size(&self) -> usize {
+        self.device.sector_size()
+    }
+}
+
+fn read_sector(device: &Arc<dyn BlockDevice>, sector: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; device.sector_size()];
+    if buffer_cache::read(device, sector * device.sector_size(), &mut buf)? != buf.len() {
+        return Err("Sector beyond device.".into());
+    }
+    Ok(buf)
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+// (type, start, count) of the four entries in MBR or EBR, empty ones included.
+fn mbr_entries(sector: &[u8]) -> [(u8, usize, usize); 4] {
+    core::array::from_fn(|i| {
+        let entry = &sector[MBR_ENTRIES_OFFSET + i * 16..MBR_ENTRIES_OFFSET + (i + 1) * 16];
+        (entry[4], u32_at(entry, 8) as usize, u32_at(entry, 12) as usize)
+    })
+}
+
+fn parse_gpt(device: &Arc<dyn BlockDevice>) -> Result<Vec<PartitionEntry>> {
+    // CRC of header and entries is not checked, neither is the backup table.
+    let header = read_sector(device, 1)?;
+    if &header[0..8] != GPT_SIGNATURE {
+        return Err("Bad GPT signature.".into());
+    }
+    let entries_lba = u64_at(&header, 72) as usize;
+    let entries = u32_at(&header, 80) as usize;
+    let entry_size = u32_at(&header, 84) as usize;
+    if entry_size < 128 || entry_size > device.sector_size() || device.sector_size() % entry_size != 0 {
+        return Err("Bad GPT entry size.".into());
+    }
+    let per_sector = device.sector_size() / entry_size;
+    let mut partitions = Vec::new();
+    for i in 0..entries.min(GPT_MAX_ENTRIES) {
+        let lba = entries_lba.checked_add(i / per_sector).ok_or("Bad GPT entry table location.")?;
+        let sector = read_sector(device, lba)?;
+        let entry = &sector[(i % per_sector) * entry_size..(i % per_sector + 1) * entry_size];
+        // Zero type GUID is unused entry.
+        if entry[0..16].iter().all(|b| *b == 0) {
+            continue;
+        }
+        let first = u64_at(entry, 32) as usize;
+        let last = u64_at(entry, 40) as usize;
+        if last < first {
+            continue;
+        }
+        partitions.push(PartitionEntry { number: i + 1, start: first, count: last - first + 1 });
+    }
+    Ok(partitions)
+}
+
+fn parse_mbr(device: &Arc<dyn BlockDevice>, mbr: &[u8]) -> Result<Vec<PartitionEntry>> {
+    let mut partitions = Vec::new();
+    let mut extended = None;
+    for (i, (type_, start, count)) in mbr_entries(mbr).into_iter().enumerate() {
+        if type_ == 0 || count == 0 {
+            continue;
+        }
+        if MBR_TYPES_EXTENDED.contains(&type_) {
+            extended = Some(start);
+        } else {
+            partitions.push(PartitionEntry { number: i + 1, start, count });
+        }
+    }
+    // Logical partitions, each EBR is relative to itself and links the next one relative to
+    // the extended partition.
+    if let Some(extended_start) = extended {
+        let mut ebr_start = extended_start;
+        for number in MBR_FIRST_LOGICAL..MBR_FIRST_LOGICAL + MBR_MAX_LOGICAL {
+            // Unreadable or broken EBR ends the chain, partitions found so far are kept.
+            let ebr = match read_sector(device, ebr_start) {
+                Ok(ebr) if u16_at(&ebr, 510) == MBR_SIGNATURE => ebr,
+                _ => break
+            };
+            let [(type_, start, count), (next_type, next, _), ..] = mbr_entries(&ebr);
+            if type_ != 0 && count != 0 {
+                partitions.push(PartitionEntry { number, start: ebr_start + start, count });
+            }
+            if next_type == 0 || next == 0 {
+                break;
+            }
+            ebr_start = extended_start + next;
+        }
+    }
+    Ok(partitions)
+}
+
+fn scan(device: &Arc<dyn BlockDevice>) -> Result<Vec<PartitionEntry>> {
+    let mbr = read_sector(device, 0)?;
+    if u16_at(&mbr, 510) != MBR_SIGNATURE {
+        // Whole disk holds a filesystem.
+        return Ok(Vec::new());
+    }
+    if mbr_entries(&mbr).iter().any(|(type_, _, _)| *type_ == MBR_TYPE_PROTECTIVE) {
+        parse_gpt(device)
+    } else {
+        parse_mbr(device, &mbr)
+    }
+}
+
+// Like Linux, `mmcblk0` gets `mmcblk0p1` while `vda` gets `vda1`.
+fn partition_name(disk: &str, number: usize) -> String {
+    if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("{}p{}", disk, number)
+    } else {
+        format!("{}{}", disk, number)
+    }
+}
+
+/// Read partition table of disk `name` and register its partitions to devfs, with minor numbers
+/// following the disk's. At most `max_partitions` are registered.
+pub fn register_partitions(name: &str, major: usize, disk_minor: usize, max_partitions: usize, device: Arc<dyn BlockDevice>) -> EmptyResult {
+    let sectors = device.sector_count();
+    for entry in scan(&device)? {
+        if entry.number > max_partitions {
+            warn!("Partition {} of {} is out of minor numbers, ignored.", entry.number, name);
+            continue;
+        }
+        if entry.start >= sectors || entry.count > sectors - entry.start {
+            warn!("Partition {} of {} is beyond the disk, ignored.", entry.number, name);
+            continue;
+        }
+        let partition_name = partition_name(name, entry.number);
+        let partition = Arc::new(Partition {
+            device: device.clone(),
+            start: entry.start * device.sector_size(),
+            size: entry.count * device.sector_size(),
+        });
+        info!("Found partition {}, {} sectors from sector {}.", partition_name, entry.count, entry.start);
+        register_block_device(&partition_name, major, disk_minor + entry.number, partition)?;
+    }
+    Ok(())
+}
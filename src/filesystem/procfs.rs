@@ -0,0 +1,510 @@
+//! # Procfs
+//!
+//! Filesystem exposing kernel and process state as text files, content is generated on read.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::fmt::Write;
+use core::time::Duration;
+use crate::core::Spinlock;
+use crate::cpu::CPU;
+use crate::filesystem::{cache_stats, DirEntry, DirEntryType, File, FileModes, FileOpenFlags, Filesystem, Inode, InodeStat, MountFlags, mounts, register_filesystem, SeekPosition};
+use crate::memory::{page_stats, PAGE_SIZE, PTEFlags, VirtAddr, VirtPageId};
+use crate::process::{cputime, get_process_manager, Process, ProcessData, ProcessStatus, VmaBacking};
+use crate::utils::error::{EmptyResult, Result};
+
+// Clock ticks of times in stat, USER_HZ of Linux.
+const USER_HZ: u128 = 100;
+
+fn clock_ticks(time: Duration) -> u128 {
+    time.as_millis() * USER_HZ / 1000
+}
+
+const PID_ENTRIES: [&str; 7] = ["stat", "status", "cmdline", "maps", "fd", "cwd", "mountinfo"];
+
+/// Node of procfs. Pid of None is the current process, `PidDir(None)` is `/proc/self`, a symlink
+/// to the pid directory of whoever is looking at it.
+#[derive(Clone, Copy)]
+enum ProcfsNode {
+    Root,
+    Meminfo,
+    Cpuinfo,
+    Mounts,
+    PidDir(Option<usize>),
+    Stat(Option<usize>),
+    Status(Option<usize>),
+    Cmdline(Option<usize>),
+    Maps(Option<usize>),
+    FdDir(Option<usize>),
+    Fd(Option<usize>, usize),
+    Cwd(Option<usize>),
+    Mountinfo(Option<usize>),
+}
+
+fn get_process(pid: Option<usize>) -> Option<Arc<Process>> {
+    match pid {
+        Some(pid) => get_process_manager().lock().get_process(pid),
+        None => CPU::get_current_process(),
+    }
+}
+
+impl ProcfsNode {
+    fn pid(&self) -> Option<Option<usize>> {
+        match *self {
+            ProcfsNode::PidDir(pid) | ProcfsNode::Stat(pid) | ProcfsNode::Status(pid)
+            | ProcfsNode::Cmdline(pid) | ProcfsNode::Maps(pid) | ProcfsNode::FdDir(pid)
+            | ProcfsNode::Fd(pid, _) | ProcfsNode::Cwd(pid) | ProcfsNode::Mountinfo(pid) => Some(pid),
+            _ => None
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        matches!(self, ProcfsNode::Root | ProcfsNode::PidDir(Some(_)) | ProcfsNode::FdDir(_))
+    }
+
+    fn is_link(&self) -> bool {
+        matches!(self, ProcfsNode::PidDir(None) | ProcfsNode::Fd(..) | ProcfsNode::Cwd(_))
+    }
+
+    fn dentry_type(&self) -> DirEntryType {
+        if self.is_dir() {
+            DirEntryType::Dir
+        } else if self.is_link() {
+            DirEntryType::Link
+        } else {
+            DirEntryType::File
+        }
+    }
+
+    fn ino(&self) -> usize {
+        // Each process owns 2^32 numbers, fixed entries below 0x100 and fds from 0x100 up.
+        let pid_base = |pid: Option<usize>| {
+            let pid = pid.or_else(|| CPU::get_current_process().map(|proc| proc.pid.pid())).unwrap_or(0);
+            (pid + 1) << 32
+        };
+        match *self {
+            ProcfsNode::Root => 1,
+            ProcfsNode::Meminfo => 2,
+            ProcfsNode::Cpuinfo => 3,
+            ProcfsNode::Mounts => 4,
+            ProcfsNode::PidDir(None) => 5,
+            ProcfsNode::PidDir(pid) => pid_base(pid),
+            ProcfsNode::Stat(pid) => pid_base(pid) | 1,
+            ProcfsNode::Status(pid) => pid_base(pid) | 2,
+            ProcfsNode::Cmdline(pid) => pid_base(pid) | 3,
+            ProcfsNode::Maps(pid) => pid_base(pid) | 4,
+            ProcfsNode::FdDir(pid) => pid_base(pid) | 5,
+            ProcfsNode::Cwd(pid) => pid_base(pid) | 6,
+            ProcfsNode::Mountinfo(pid) => pid_base(pid) | 7,
+            ProcfsNode::Fd(pid, fd) => pid_base(pid) + 0x100 + fd,
+        }
+    }
+
+    /// Whether the node still exists, process may have gone or fd may be closed.
+    fn exists(&self) -> bool {
+        match (self, self.pid()) {
+            (ProcfsNode::Fd(_, fd), Some(pid)) => get_process(pid)
+                .is_some_and(|proc| proc.data.lock().files.get(*fd).is_some_and(|file| file.is_some())),
+            (_, Some(pid)) => get_process(pid).is_some(),
+            (_, None) => true,
+        }
+    }
+
+    /// Entries of directory node with their names.
+    fn children(&self) -> Vec<(String, ProcfsNode)> {
+        match *self {
+            ProcfsNode::Root => {
+                let mut children = vec![
+                    ("self".to_string(), ProcfsNode::PidDir(None)),
+                    ("meminfo".to_string(), ProcfsNode::Meminfo),
+                    ("cpuinfo".to_string(), ProcfsNode::Cpuinfo),
+                    ("mounts".to_string(), ProcfsNode::Mounts),
+                ];
+                let pids = get_process_manager().lock().process_list()
+                    .map(|proc| proc.pid.pid())
+                    .collect::<Vec<_>>();
+                children.extend(pids.into_iter().map(|pid| (pid.to_string(), ProcfsNode::PidDir(Some(pid)))));
+                children
+            }
+            ProcfsNode::PidDir(pid) => PID_ENTRIES.iter()
+                .filter_map(|name| self.child(name).map(|node| (name.to_string(), node)))
+                .collect(),
+            ProcfsNode::FdDir(pid) => get_process(pid).map(|proc| {
+                proc.data.lock().files.iter().enumerate()
+                    .filter(|(_, file)| file.is_some())
+                    .map(|(fd, _)| (fd.to_string(), ProcfsNode::Fd(pid, fd)))
+                    .collect()
+            }).unwrap_or_default(),
+            _ => vec![]
+        }
+    }
+
+    fn child(&self, name: &str) -> Option<ProcfsNode> {
+        let node = match *self {
+            ProcfsNode::Root => match name {
+                "self" => ProcfsNode::PidDir(None),
+                "meminfo" => ProcfsNode::Meminfo,
+                "cpuinfo" => ProcfsNode::Cpuinfo,
+                "mounts" => ProcfsNode::Mounts,
+                _ => ProcfsNode::PidDir(Some(name.parse().ok()?)),
+            },
+            ProcfsNode::PidDir(pid) => match name {
+                "stat" => ProcfsNode::Stat(pid),
+                "status" => ProcfsNode::Status(pid),
+                "cmdline" => ProcfsNode::Cmdline(pid),
+                "maps" => ProcfsNode::Maps(pid),
+                "fd" => ProcfsNode::FdDir(pid),
+                "cwd" => ProcfsNode::Cwd(pid),
+                "mountinfo" => ProcfsNode::Mountinfo(pid),
+                _ => return None
+            },
+            ProcfsNode::FdDir(pid) => ProcfsNode::Fd(pid, name.parse().ok()?),
+            _ => return None
+        };
+        Some(node)
+    }
+
+    /// Generate content of file node.
+    fn generate(&self) -> Result<String> {
+        let mut content = String::new();
+        match *self {
+            ProcfsNode::Meminfo => {
+                let (total, free) = page_stats();
+                let kb = |pages: usize| pages * PAGE_SIZE / 1024;
+                writeln!(content, "MemTotal:       {:8} kB", kb(total)).unwrap();
+                writeln!(content, "MemFree:        {:8} kB", kb(free)).unwrap();
+                writeln!(content, "MemAvailable:   {:8} kB", kb(free)).unwrap();
+                let (buffers, cached) = cache_stats();
+                writeln!(content, "Buffers:        {:8} kB", buffers / 1024).unwrap();
+                writeln!(content, "Cached:         {:8} kB", cached / 1024).unwrap();
+            }
+            ProcfsNode::Cpuinfo => {
+                for hart in 0..CPU::get_count() {
+                    writeln!(content, "processor\t: {}", hart).unwrap();
+                    writeln!(content, "hart\t\t: {}", hart).unwrap();
+                    writeln!(content, "isa\t\t: rv64imafdc").unwrap();
+                    writeln!(content, "mmu\t\t: sv39").unwrap();
+                    writeln!(content).unwrap();
+                }
+            }
+            ProcfsNode::Mounts => {
+                for mount in mounts() {
+                    writeln!(content, "{} {} {} {} 0 0", mount.device, mount.mount_point, mount.filesystem, mount.options()).unwrap();
+                }
+            }
+            ProcfsNode::Mountinfo(_) => {
+                // Every process sees the same mounts. Device numbers are not tracked, hence 0:0.
+                for mount in mounts() {
+                    writeln!(content, "{} {} 0:0 / {} {} - {} {} {}", mount.id, mount.parent_id, mount.mount_point,
+                             mount.options(), mount.filesystem, mount.device,
+                             if mount.flags.contains(MountFlags::MS_RDONLY) { "ro" } else { "rw" }).unwrap();
+                }
+            }
+            ProcfsNode::Stat(pid) => {
+                let proc = get_process(pid).ok_or("Process gone.")?;
+                let data = proc.data.lock();
+                let ppid = data.parent.as_ref().and_then(|p| p.upgrade()).map(|p| p.pid.pid()).unwrap_or(0);
+                let threads = data.threads.iter().filter(|t| t.strong_count() > 0).count();
+                let vsize = data.memory.vmas().map(|vma| vma.pages() * PAGE_SIZE).sum::<usize>();
+                let start_time = clock_ticks(proc.start_time);
+                let time = cputime::process_time(&data);
+                // pid (comm) state ppid pgrp session tty_nr tpgid flags minflt cminflt majflt cmajflt
+                write!(content, "{} ({}) {} {} {} {} 0 -1 0 0 0 0 0 ",
+                       proc.pid.pid(), data.name, state_char(&data), ppid, proc.pid.pid(), proc.pid.pid()).unwrap();
+                let (priority, nice, rt_priority, policy, processor) = sched_fields(&data);
+                // utime stime cutime cstime priority nice num_threads itrealvalue starttime vsize rss rsslim
+                write!(content, "{} {} {} {} {} {} {} 0 {} {} {} {} ",
+                       clock_ticks(time.user), clock_ticks(time.system),
+                       clock_ticks(data.children_time.user), clock_ticks(data.children_time.system),
+                       priority, nice, threads, start_time, vsize, data.memory.resident_pages(), usize::MAX).unwrap();
+                // startcode endcode startstack kstkesp kstkeip signal blocked sigignore sigcatch wchan nswap cnswap
+                write!(content, "0 0 {} 0 0 0 0 0 0 0 0 0 ", data.memory.stack_base.addr).unwrap();
+                // exit_signal processor rt_priority policy delayacct_blkio_ticks guest_time cguest_time
+                // start_data end_data start_brk arg_start arg_end env_start env_end exit_code
+                writeln!(content, "17 {} {} {} 0 0 0 0 0 {} 0 0 0 0 0", processor, rt_priority, policy, data.memory.min_brk.addr).unwrap();
+            }
+            ProcfsNode::Status(pid) => {
+                let proc = get_process(pid).ok_or("Process gone.")?;
+                let data = proc.data.lock();
+                let ppid = data.parent.as_ref().and_then(|p| p.upgrade()).map(|p| p.pid.pid()).unwrap_or(0);
+                let threads = data.threads.iter().filter(|t| t.strong_count() > 0).count();
+                let vm_size = data.memory.vmas().map(|vma| vma.pages() * PAGE_SIZE).sum::<usize>() / 1024;
+                let vm_rss = data.memory.resident_pages() * PAGE_SIZE / 1024;
+                let state = match state_char(&data) {
+                    'R' => "R (running)",
+                    'S' => "S (sleeping)",
+                    _ => "Z (zombie)",
+                };
+                writeln!(content, "Name:\t{}", data.name).unwrap();
+                writeln!(content, "State:\t{}", state).unwrap();
+                writeln!(content, "Tgid:\t{}", proc.pid.pid()).unwrap();
+                writeln!(content, "Pid:\t{}", proc.pid.pid()).unwrap();
+                writeln!(content, "PPid:\t{}", ppid).unwrap();
+                writeln!(content, "Uid:\t0\t0\t0\t0").unwrap();
+                writeln!(content, "Gid:\t0\t0\t0\t0").unwrap();
+                writeln!(content, "FDSize:\t{}", data.files.len()).unwrap();
+                writeln!(content, "VmSize:\t{:8} kB", vm_size).unwrap();
+                writeln!(content, "VmRSS:\t{:8} kB", vm_rss).unwrap();
+                writeln!(content, "Threads:\t{}", threads).unwrap();
+            }
+            ProcfsNode::Cmdline(pid) => {
+                let proc = get_process(pid).ok_or("Process gone.")?;
+                for arg in proc.data.lock().cmdline.iter() {
+                    content.push_str(arg);
+                    content.push('\0');
+                }
+            }
+            ProcfsNode::Maps(pid) => {
+                let proc = get_process(pid).ok_or("Process gone.")?;
+                // Inodes of mapped files are asked with process unlocked, that may sleep on disk I/O.
+                let vmas = {
+                    let data = proc.data.lock();
+                    let memory = &data.memory;
+                    let heap = VirtPageId::from(memory.min_brk.round_up())..VirtPageId::from(memory.brk.round_up());
+                    let trampoline = VirtPageId::from(memory.signal_trampoline());
+                    memory.vmas().map(|vma| {
+                        let name = if vma.start == trampoline {
+                            "[sigpage]"
+                        } else if vma.end == VirtPageId::from(memory.stack_base) {
+                            "[stack]"
+                        } else if heap.contains(&vma.start) {
+                            "[heap]"
+                        } else {
+                            ""
+                        };
+                        (vma.clone(), name)
+                    }).collect::<Vec<_>>()
+                };
+                for (vma, name) in vmas {
+                    let (offset, ino, path) = match &vma.backing {
+                        VmaBacking::File { file, offset } => {
+                            let dentry = file.get_dentry().ok();
+                            let ino = dentry.as_ref().and_then(|d| d.get_inode()).map(|inode| inode.get_stat().ino).unwrap_or(0);
+                            (*offset, ino, dentry.map(|d| d.fullpath()).unwrap_or_default())
+                        }
+                        VmaBacking::Anonymous => (0, 0, name.to_string())
+                    };
+                    let perm = |flag: PTEFlags, c: char| if vma.flags.contains(flag) { c } else { '-' };
+                    writeln!(content, "{:08x}-{:08x} {}{}{}{} {:08x} 00:00 {:<10} {}",
+                             VirtAddr::from(vma.start).addr, VirtAddr::from(vma.end).addr,
+                             perm(PTEFlags::R, 'r'), perm(PTEFlags::W, 'w'), perm(PTEFlags::X, 'x'),
+                             if vma.shared { 's' } else { 'p' },
+                             offset, ino, path).unwrap();
+                }
+            }
+            _ => return Err("Not a regular procfs file.".into())
+        }
+        Ok(content)
+    }
+}
+
+// (priority, nice, rt_priority, policy, processor) of the first live thread.
+fn sched_fields(data: &ProcessData) -> (isize, isize, usize, usize, usize) {
+    data.threads.iter()
+        .find_map(|t| t.upgrade())
+        .map(|t| {
+            let thread_data = t.data.lock();
+            let sched = &thread_data.sched;
+            (sched.proc_priority(), sched.nice, sched.rt_priority, sched.policy as usize, sched.cpu)
+        })
+        .unwrap_or((20, 0, 0, 0, 0))
+}
+
+/// State of process as in stat, running if any thread is running.
+fn state_char(data: &ProcessData) -> char {
+    if data.status == ProcessStatus::Zombie {
+        return 'Z';
+    }
+    let running = data.threads.iter()
+        .filter_map(|t| t.upgrade())
+        .any(|t| matches!(t.data.lock().status, ProcessStatus::Running | ProcessStatus::Ready));
+    if running { 'R' } else { 'S' }
+}
+
+struct ProcfsInode {
+    node: ProcfsNode,
+}
+
+impl ProcfsInode {
+    fn make_dentry(name: String, node: ProcfsNode, this_dentry: Weak<DirEntry>) -> DirEntry {
+        DirEntry::new(Some(this_dentry), name, Some(Arc::new(ProcfsInode { node })), node.dentry_type())
+    }
+}
+
+impl Inode for ProcfsInode {
+    fn lookup(&self, name: &str, this_dentry: Weak<DirEntry>) -> Option<DirEntry> {
+        let node = self.node.child(name)?;
+        if !node.exists() {
+            return None;
+        }
+        Some(Self::make_dentry(name.to_string(), node, this_dentry))
+    }
+
+    fn link(&self, inode: Arc<dyn Inode>, name: &str) -> EmptyResult {
+        Err("Cannot perform link on procfs.".into())
+    }
+
+    fn unlink(&self, name: &str) -> EmptyResult {
+        Err("Cannot perform unlink on procfs.".into())
+    }
+
+    fn mkdir(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        Err("Cannot perform mkdir on procfs.".into())
+    }
+
+    fn rmdir(&self, name: &str) -> EmptyResult {
+        Err("Cannot perform rmdir on procfs.".into())
+    }
+
+    fn read_dir(&self, this_dentry: Weak<DirEntry>) -> Result<Vec<DirEntry>> {
+        if !self.node.is_dir() {
+            return Err("Cannot read dir on a file inode.".into());
+        }
+        Ok(self.node.children().into_iter()
+            .map(|(name, node)| Self::make_dentry(name, node, this_dentry.clone()))
+            .collect())
+    }
+
+    fn open(&self, dentry: Arc<DirEntry>, flags: FileOpenFlags, mode: FileModes) -> Result<Arc<dyn File>> {
+        if self.node.is_link() {
+            return Err("Cannot open symlink.".into());
+        }
+        Ok(Arc::new(ProcfsFile {
+            node: self.node,
+            dentry,
+            content: Spinlock::new(None),
+            offset: Spinlock::new(0),
+        }))
+    }
+
+    fn get_dentry_type(&self) -> DirEntryType {
+        self.node.dentry_type()
+    }
+
+    fn get_stat(&self) -> InodeStat {
+        let mode = if self.node.is_dir() {
+            FileModes::DIRECTORY | FileModes::Read | FileModes::Exec
+        } else if self.node.is_link() {
+            FileModes::LINK | FileModes::RWX
+        } else {
+            FileModes::REGULAR | FileModes::Read
+        };
+        InodeStat {
+            ino: self.node.ino(),
+            mode: mode.bits() as usize,
+            nlink: 1,
+            rdev: 0,
+            // Content is generated on read, size is unknown like Linux.
+            size: 0,
+            block_size: PAGE_SIZE,
+            atime: Duration::ZERO,
+            mtime: Duration::ZERO,
+            ctime: Duration::ZERO,
+        }
+    }
+
+    fn is_dynamic(&self) -> bool {
+        true
+    }
+
+    fn readlink(&self) -> Result<String> {
+        match self.node {
+            ProcfsNode::PidDir(None) => Ok(CPU::get_current_process().ok_or("No current process.")?.pid.pid().to_string()),
+            ProcfsNode::Cwd(pid) => {
+                let proc = get_process(pid).ok_or("Process gone.")?;
+                let cwd = proc.data.lock().cwd.clone();
+                Ok(cwd.fullpath())
+            }
+            ProcfsNode::Fd(pid, fd) => {
+                let proc = get_process(pid).ok_or("Process gone.")?;
+                let file = proc.data.lock().files.get(fd).cloned().flatten().ok_or("Bad file descriptor.")?;
+                // Pipe has no dentry.
+                Ok(file.get_dentry().map(|dentry| dentry.fullpath()).unwrap_or(format!("anon_inode:[{}]", fd)))
+            }
+            _ => Err("Not a symlink.".into())
+        }
+    }
+}
+
+struct ProcfsFile {
+    node: ProcfsNode,
+    dentry: Arc<DirEntry>,
+    // Generated on first read, and kept until closed so that reads in pieces are consistent.
+    content: Spinlock<Option<Vec<u8>>>,
+    offset: Spinlock<usize>,
+}
+
+impl ProcfsFile {
+    fn content_len(&self) -> Result<usize> {
+        let mut content = self.content.lock();
+        if content.is_none() {
+            *content = Some(self.node.generate()?.into_bytes());
+        }
+        Ok(content.as_ref().unwrap().len())
+    }
+}
+
+impl File for ProcfsFile {
+    fn seek(&self, offset: isize, whence: SeekPosition) -> Result<usize> {
+        let mut cur = self.offset.lock();
+        let base = match whence {
+            SeekPosition::Set => 0,
+            SeekPosition::Cur => *cur as isize,
+            SeekPosition::End => self.content_len()? as isize,
+        };
+        let new_offset = base + offset;
+        if new_offset < 0 {
+            return Err("Seek before start of file.".into());
+        }
+        *cur = new_offset as usize;
+        Ok(*cur)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut offset = self.offset.lock();
+        let len = self.content_len()?;
+        let content = self.content.lock();
+        let content = content.as_ref().unwrap();
+        let begin = min(*offset, len);
+        let end = min(begin + buf.len(), len);
+        buf[..end - begin].copy_from_slice(&content[begin..end]);
+        *offset = end;
+        Ok(end - begin)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        Err("Procfs is read only.".into())
+    }
+
+    fn close(&self) -> EmptyResult {
+        Ok(())
+    }
+
+    fn get_dentry(&self) -> Result<Arc<DirEntry>> {
+        Ok(self.dentry.clone())
+    }
+}
+
+struct Procfs {}
+
+impl Filesystem for Procfs {
+    fn new() -> Self {
+        Self {}
+    }
+
+    fn mount(&self, device: Option<Arc<dyn File>>, mount_point: Arc<DirEntry>, flags: MountFlags) -> Result<Arc<dyn Inode>> {
+        Ok(Arc::new(ProcfsInode { node: ProcfsNode::Root }))
+    }
+}
+
+pub fn init() {
+    register_filesystem("proc", Box::new(Procfs::new()));
+}
@@ -0,0 +1,513 @@
+//! # Tmpfs
+//!
+//! Filesystem keeping everything in memory. Contents are gone once the last reference is dropped.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use crate::config::KERNEL_HEAP_SIZE_IN_MEM;
+use crate::core::Spinlock;
+use crate::device::clock;
+use crate::filesystem::{DirEntry, DirEntryType, File, FileModes, FileOpenFlags, Filesystem, Inode, InodeStat, MountFlags, register_filesystem, RenameFlags, SeekPosition};
+use crate::memory::PAGE_SIZE;
+use crate::utils::error::{EmptyResult, KernelError, Result};
+
+// Content lives in kernel heap, shared by every tmpfs mount and the rest of kernel.
+const MAX_FILE_SIZE: usize = KERNEL_HEAP_SIZE_IN_MEM / 8;
+const MAX_TOTAL_SIZE: usize = KERNEL_HEAP_SIZE_IN_MEM / 4;
+
+// Bytes of file content in all tmpfs mounts.
+static USED_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Take `size` bytes of content from what all tmpfs mounts may use.
+fn charge(size: usize) -> EmptyResult {
+    USED_SIZE.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+        used.checked_add(size).filter(|used| *used <= MAX_TOTAL_SIZE)
+    }).map(|_| ()).map_err(|_| KernelError::NO_SPACE)
+}
+
+fn uncharge(size: usize) {
+    USED_SIZE.fetch_sub(size, Ordering::SeqCst);
+}
+
+enum TmpfsNode {
+    File(Vec<u8>),
+    Dir(BTreeMap<String, Arc<TmpfsInode>>),
+    Symlink(String),
+}
+
+struct TmpfsInodeData {
+    node: TmpfsNode,
+    nlink: usize,
+    atime: Duration,
+    mtime: Duration,
+    ctime: Duration,
+}
+
+/// Inodes of one mounted tmpfs, used to find our own inode from `Arc<dyn Inode>` when linking.
+struct TmpfsSuperblock {
+    next_ino: AtomicUsize,
+    inodes: Spinlock<BTreeMap<usize, Weak<TmpfsInode>>>,
+}
+
+impl TmpfsSuperblock {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            next_ino: AtomicUsize::new(1),
+            inodes: Spinlock::new(BTreeMap::new()),
+        })
+    }
+
+    fn alloc_inode(self: &Arc<Self>, node: TmpfsNode) -> Arc<TmpfsInode> {
+        let ino = self.next_ino.fetch_add(1, Ordering::SeqCst);
+        let now = clock::realtime();
+        let inode = Arc::new_cyclic(|this| TmpfsInode {
+            ino,
+            this: this.clone(),
+            sb: self.clone(),
+            data: Spinlock::new(TmpfsInodeData {
+                node,
+                nlink: 1,
+                atime: now,
+                mtime: now,
+                ctime: now,
+            }),
+        });
+        self.inodes.lock().insert(ino, Arc::downgrade(&inode));
+        inode
+    }
+
+    /// Find tmpfs inode behind a trait object. None if it belongs to other filesystem.
+    fn find(&self, inode: &Arc<dyn Inode>) -> Option<Arc<TmpfsInode>> {
+        let ino = inode.get_stat().ino;
+        let found = self.inodes.lock().get(&ino)?.upgrade()?;
+        if Arc::as_ptr(&found) as *const () == Arc::as_ptr(inode) as *const () {
+            Some(found)
+        } else {
+            None
+        }
+    }
+}
+
+struct TmpfsInode {
+    ino: usize,
+    this: Weak<TmpfsInode>,
+    sb: Arc<TmpfsSuperblock>,
+    data: Spinlock<TmpfsInodeData>,
+}
+
+impl Drop for TmpfsInode {
+    fn drop(&mut self) {
+        self.sb.inodes.lock().remove(&self.ino);
+        if let TmpfsNode::File(content) = &self.data.lock().node {
+            uncharge(content.len());
+        }
+    }
+}
+
+impl TmpfsInode {
+    fn dentry_type(node: &TmpfsNode) -> DirEntryType {
+        match node {
+            TmpfsNode::Dir(_) => DirEntryType::Dir,
+            TmpfsNode::Symlink(_) => DirEntryType::Link,
+            TmpfsNode::File(_) => DirEntryType::File,
+        }
+    }
+
+    /// Add a new child to this directory.
+    fn add_child(&self, name: &str, node: TmpfsNode) -> Result<Arc<TmpfsInode>> {
+        let mut data = self.data.lock();
+        let is_dir = matches!(node, TmpfsNode::Dir(_));
+        let children = match &mut data.node {
+            TmpfsNode::Dir(children) => children,
+            _ => return Err("Not a directory.".into())
+        };
+        if children.contains_key(name) {
+            return Err(KernelError::ALREADY_EXISTS);
+        }
+        let inode = self.sb.alloc_inode(node);
+        children.insert(name.to_string(), inode.clone());
+        if is_dir {
+            // ".." of child
+            data.nlink += 1;
+        }
+        let now = clock::realtime();
+        data.mtime = now;
+        data.ctime = now;
+        Ok(inode)
+    }
+
+    /// Remove a child from this directory, `is_dir` tells which kind is expected.
+    fn remove_child(&self, name: &str, is_dir: bool) -> EmptyResult {
+        let mut data = self.data.lock();
+        let children = match &mut data.node {
+            TmpfsNode::Dir(children) => children,
+            _ => return Err("Not a directory.".into())
+        };
+        let child = children.get(name).ok_or("No such file.")?.clone();
+        let mut child_data = child.data.lock();
+        match (&child_data.node, is_dir) {
+            (TmpfsNode::Dir(grandchildren), true) => {
+                if !grandchildren.is_empty() {
+                    return Err(KernelError::NOT_EMPTY);
+                }
+                // Empty directory goes away with its "." link.
+                child_data.nlink = 0;
+            }
+            (TmpfsNode::Dir(_), false) => return Err("Is a directory.".into()),
+            (_, true) => return Err("Not a directory.".into()),
+            (_, false) => child_data.nlink -= 1,
+        }
+        let now = clock::realtime();
+        child_data.ctime = now;
+        drop(child_data);
+        children.remove(name);
+        if is_dir {
+            data.nlink -= 1;
+        }
+        data.mtime = now;
+        data.ctime = now;
+        Ok(())
+    }
+
+    fn children_of(node: &mut TmpfsNode) -> Result<&mut BTreeMap<String, Arc<TmpfsInode>>> {
+        match node {
+            TmpfsNode::Dir(children) => Ok(children),
+            _ => Err("Not a directory.".into())
+        }
+    }
+
+    /// Rename with directories locked, `dst` is None when renaming inside `src`.
+    fn rename_locked(src: &mut TmpfsInodeData, mut dst: Option<&mut TmpfsInodeData>, old_name: &str, new_name: &str, flags: RenameFlags) -> EmptyResult {
+        let source = Self::children_of(&mut src.node)?.get(old_name).ok_or("No such file.")?.clone();
+        let target = match &mut dst {
+            Some(dst) => Self::children_of(&mut dst.node)?,
+            None => Self::children_of(&mut src.node)?,
+        }.get(new_name).cloned();
+        if target.as_ref().is_some_and(|target| Arc::ptr_eq(target, &source)) {
+            // Both names are links to the same inode, nothing to do.
+            return Ok(());
+        }
+        let now = clock::realtime();
+        let source_is_dir = matches!(source.data.lock().node, TmpfsNode::Dir(_));
+        // Links of directories moved out of src into dst, for their "..".
+        let mut moved_dirs: isize = if source_is_dir { 1 } else { 0 };
+
+        if flags.contains(RenameFlags::RENAME_EXCHANGE) {
+            let target = target.ok_or("No such file.")?;
+            if matches!(target.data.lock().node, TmpfsNode::Dir(_)) {
+                moved_dirs -= 1;
+            }
+            Self::children_of(&mut src.node)?.insert(old_name.to_string(), target.clone());
+            target.data.lock().ctime = now;
+        } else {
+            if let Some(target) = target {
+                if flags.contains(RenameFlags::RENAME_NOREPLACE) {
+                    return Err(KernelError::ALREADY_EXISTS);
+                }
+                let mut target_data = target.data.lock();
+                match (&target_data.node, source_is_dir) {
+                    (TmpfsNode::Dir(grandchildren), true) => {
+                        if !grandchildren.is_empty() {
+                            return Err(KernelError::NOT_EMPTY);
+                        }
+                        target_data.nlink = 0;
+                        // ".." of the replaced directory
+                        match &mut dst {
+                            Some(dst) => dst.nlink -= 1,
+                            None => src.nlink -= 1,
+                        }
+                    }
+                    (TmpfsNode::Dir(_), false) => return Err("Is a directory.".into()),
+                    (_, true) => return Err("Not a directory.".into()),
+                    (_, false) => target_data.nlink -= 1,
+                }
+                target_data.ctime = now;
+            }
+            Self::children_of(&mut src.node)?.remove(old_name);
+        }
+        match &mut dst {
+            Some(dst) => {
+                Self::children_of(&mut dst.node)?.insert(new_name.to_string(), source.clone());
+                dst.nlink = (dst.nlink as isize + moved_dirs) as usize;
+                src.nlink = (src.nlink as isize - moved_dirs) as usize;
+                dst.mtime = now;
+                dst.ctime = now;
+            }
+            None => {
+                Self::children_of(&mut src.node)?.insert(new_name.to_string(), source.clone());
+            }
+        }
+        source.data.lock().ctime = now;
+        src.mtime = now;
+        src.ctime = now;
+        Ok(())
+    }
+
+    fn make_dentry(&self, name: &str, inode: Arc<TmpfsInode>, this_dentry: Weak<DirEntry>) -> DirEntry {
+        let type_ = Self::dentry_type(&inode.data.lock().node);
+        DirEntry::new(Some(this_dentry), name.to_string(), Some(inode), type_)
+    }
+}
+
+impl Inode for TmpfsInode {
+    fn lookup(&self, name: &str, this_dentry: Weak<DirEntry>) -> Option<DirEntry> {
+        let child = match &self.data.lock().node {
+            TmpfsNode::Dir(children) => children.get(name)?.clone(),
+            _ => return None
+        };
+        Some(self.make_dentry(name, child, this_dentry))
+    }
+
+    fn link(&self, inode: Arc<dyn Inode>, name: &str) -> EmptyResult {
+        let inode = self.sb.find(&inode).ok_or("Cross-device link.")?;
+        let mut data = self.data.lock();
+        let children = match &mut data.node {
+            TmpfsNode::Dir(children) => children,
+            _ => return Err("Not a directory.".into())
+        };
+        if children.contains_key(name) {
+            return Err(KernelError::ALREADY_EXISTS);
+        }
+        {
+            let mut inode_data = inode.data.lock();
+            if let TmpfsNode::Dir(_) = inode_data.node {
+                return Err("Cannot hard link a directory.".into());
+            }
+            inode_data.nlink += 1;
+            inode_data.ctime = clock::realtime();
+        }
+        children.insert(name.to_string(), inode);
+        let now = clock::realtime();
+        data.mtime = now;
+        data.ctime = now;
+        Ok(())
+    }
+
+    fn unlink(&self, name: &str) -> EmptyResult {
+        self.remove_child(name, false)
+    }
+
+    fn mkdir(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let inode = self.add_child(name, TmpfsNode::Dir(BTreeMap::new()))?;
+        // "." and the entry in parent
+        inode.data.lock().nlink = 2;
+        Ok(inode)
+    }
+
+    fn rmdir(&self, name: &str) -> EmptyResult {
+        self.remove_child(name, true)
+    }
+
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str, flags: RenameFlags) -> EmptyResult {
+        let new_dir = self.sb.find(new_dir).ok_or("Cross-device rename.")?;
+        if new_dir.ino == self.ino {
+            return Self::rename_locked(&mut self.data.lock(), None, old_name, new_name, flags);
+        }
+        // Lock both directories in inode order.
+        let (mut src, mut dst) = if self.ino < new_dir.ino {
+            let src = self.data.lock();
+            (src, new_dir.data.lock())
+        } else {
+            let dst = new_dir.data.lock();
+            (self.data.lock(), dst)
+        };
+        Self::rename_locked(&mut src, Some(&mut dst), old_name, new_name, flags)
+    }
+
+    fn read_dir(&self, this_dentry: Weak<DirEntry>) -> Result<Vec<DirEntry>> {
+        let children = match &self.data.lock().node {
+            TmpfsNode::Dir(children) => children.iter()
+                .map(|(name, inode)| (name.clone(), inode.clone()))
+                .collect::<Vec<_>>(),
+            _ => return Err("Cannot read dir on a file inode.".into())
+        };
+        Ok(children.into_iter()
+            .map(|(name, inode)| self.make_dentry(&name, inode, this_dentry.clone()))
+            .collect())
+    }
+
+    fn open(&self, dentry: Arc<DirEntry>, flags: FileOpenFlags, mode: FileModes) -> Result<Arc<dyn File>> {
+        let mut data = self.data.lock();
+        match &mut data.node {
+            TmpfsNode::File(content) => {
+                if flags.contains(FileOpenFlags::O_TRUNC) && !content.is_empty() {
+                    uncharge(content.len());
+                    // Memory goes back to heap, not kept as capacity.
+                    *content = Vec::new();
+                    let now = clock::realtime();
+                    data.mtime = now;
+                    data.ctime = now;
+                }
+            }
+            TmpfsNode::Dir(_) => return Err("Cannot open directory as file.".into()),
+            TmpfsNode::Symlink(_) => return Err("Cannot open symlink.".into()),
+        }
+        Ok(Arc::new(TmpfsFile {
+            inode: self.this.upgrade().unwrap(),
+            dentry,
+            offset: Spinlock::new(0),
+            append: flags.contains(FileOpenFlags::O_APPEND),
+        }))
+    }
+
+    fn get_dentry_type(&self) -> DirEntryType {
+        Self::dentry_type(&self.data.lock().node)
+    }
+
+    fn get_stat(&self) -> InodeStat {
+        let data = self.data.lock();
+        let (type_bits, size) = match &data.node {
+            TmpfsNode::File(content) => (FileModes::REGULAR, content.len()),
+            TmpfsNode::Dir(children) => (FileModes::DIRECTORY, children.len()),
+            TmpfsNode::Symlink(target) => (FileModes::LINK, target.len()),
+        };
+        InodeStat {
+            ino: self.ino,
+            mode: (type_bits | FileModes::RWX).bits() as usize,
+            nlink: data.nlink,
+            rdev: 0,
+            size,
+            block_size: PAGE_SIZE,
+            atime: data.atime,
+            mtime: data.mtime,
+            ctime: data.ctime,
+        }
+    }
+
+    fn create(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        Ok(self.add_child(name, TmpfsNode::File(Vec::new()))?)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>> {
+        Ok(self.add_child(name, TmpfsNode::Symlink(target.to_string()))?)
+    }
+
+    fn readlink(&self) -> Result<String> {
+        let mut data = self.data.lock();
+        data.atime = clock::realtime();
+        match &data.node {
+            TmpfsNode::Symlink(target) => Ok(target.clone()),
+            _ => Err("Not a symlink.".into())
+        }
+    }
+}
+
+struct TmpfsFile {
+    inode: Arc<TmpfsInode>,
+    dentry: Arc<DirEntry>,
+    offset: Spinlock<usize>,
+    append: bool,
+}
+
+impl TmpfsFile {
+    /// Write at `offset`, or at end of file if None. Return offset written at.
+    fn write_from(&self, offset: Option<usize>, buf: &[u8]) -> Result<usize> {
+        let mut data = self.inode.data.lock();
+        let content = match &mut data.node {
+            TmpfsNode::File(content) => content,
+            _ => return Err("Not a regular file.".into())
+        };
+        let offset = offset.unwrap_or(content.len());
+        let end = offset.checked_add(buf.len()).filter(|end| *end <= MAX_FILE_SIZE)
+            .ok_or(KernelError::FILE_TOO_LARGE)?;
+        if content.len() < end {
+            let grow = end - content.len();
+            charge(grow)?;
+            // Exact, so capacity stays what is charged.
+            if content.try_reserve_exact(grow).is_err() {
+                uncharge(grow);
+                return Err(KernelError::NO_SPACE);
+            }
+            // Hole is filled with zero.
+            content.resize(end, 0);
+        }
+        content[offset..end].copy_from_slice(buf);
+        let now = clock::realtime();
+        data.mtime = now;
+        data.ctime = now;
+        Ok(offset)
+    }
+}
+
+impl File for TmpfsFile {
+    fn seek(&self, offset: isize, whence: SeekPosition) -> Result<usize> {
+        let mut cur = self.offset.lock();
+        let base = match whence {
+            SeekPosition::Set => 0,
+            SeekPosition::Cur => *cur as isize,
+            SeekPosition::End => self.inode.get_stat().size as isize,
+        };
+        let new_offset = base.checked_add(offset).ok_or("Seek offset overflow.")?;
+        if new_offset < 0 {
+            return Err("Seek before start of file.".into());
+        }
+        *cur = new_offset as usize;
+        Ok(*cur)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut offset = self.offset.lock();
+        let read_bytes = self.read_at(*offset, buf)?;
+        *offset += read_bytes;
+        Ok(read_bytes)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        let mut offset = self.offset.lock();
+        *offset = self.write_from(if self.append { None } else { Some(*offset) }, buf)? + buf.len();
+        Ok(buf.len())
+    }
+
+    fn close(&self) -> EmptyResult {
+        Ok(())
+    }
+
+    fn get_dentry(&self) -> Result<Arc<DirEntry>> {
+        Ok(self.dentry.clone())
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let mut data = self.inode.data.lock();
+        let content = match &data.node {
+            TmpfsNode::File(content) => content,
+            _ => return Err("Not a regular file.".into())
+        };
+        let begin = min(offset, content.len());
+        let end = min(begin + buf.len(), content.len());
+        buf[..end - begin].copy_from_slice(&content[begin..end]);
+        data.atime = clock::realtime();
+        Ok(end - begin)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        self.write_from(Some(offset), buf).map(|_| buf.len())
+    }
+}
+
+struct Tmpfs {}
+
+impl Filesystem for Tmpfs {
+    fn new() -> Self {
+        Self {}
+    }
+
+    fn mount(&self, device: Option<Arc<dyn File>>, mount_point: Arc<DirEntry>, flags: MountFlags) -> Result<Arc<dyn Inode>> {
+        // Device is ignored, every mount gets its own empty tree.
+        let root = TmpfsSuperblock::new().alloc_inode(TmpfsNode::Dir(BTreeMap::new()));
+        root.data.lock().nlink = 2;
+        Ok(root)
+    }
+}
+
+pub fn init() {
+    register_filesystem("tmpfs", Box::new(Tmpfs::new()));
+}
@@ -1,6 +1,6 @@
 use lazy_static::lazy_static;
 use log::info;
-use riscv::register::{sie, scause::Interrupt, time};
+use riscv::register::{sie, sip, scause::Interrupt, time};
 
 mod trap;
 pub mod plic;
@@ -24,6 +24,8 @@ pub fn init() {
 pub fn interrupt_handler(scause: Interrupt) {
     match scause {
         Interrupt::SupervisorTimer => device::timer::handler(),
+        // IPI from scheduler, only to wake the CPU up from wfi.
+        Interrupt::SupervisorSoft => unsafe { sip::clear_ssoft() },
         Interrupt::SupervisorExternal => {
             let irq = plic::claim();
             if irq != 0 {
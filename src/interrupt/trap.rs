@@ -15,6 +15,7 @@ use riscv::register::sstatus::{SPP, Sstatus};
 use crate::cpu::CPU;
 use crate::interrupt::interrupt_handler;
 use crate::memory::{Addr, PAGE_SIZE, PhyPage, PTEFlags, VirtAddr, VirtPageId};
+use crate::process::{cputime, signal, MemoryAccess};
 use crate::syscall::{Syscall, syscall_handler};
 
 global_asm!(include_str!("trap.S"));
@@ -87,19 +88,29 @@ pub fn disable_trap() {
 }
 
 fn exception_handler(trap_context: &TrapContext, exp: scause::Exception, sstatus: sstatus::Sstatus, sepc: usize, stval: usize, from_user: bool) -> Option<usize> {
-    // TODO: handle page fault for CoW
     match exp {
         Exception::Breakpoint => {
             warn!("Breakpoint triggered.");
             Some(2) // ebreak length
         }
-        Exception::StorePageFault | Exception::LoadPageFault => {
+        Exception::StorePageFault | Exception::LoadPageFault | Exception::InstructionPageFault => {
             // handle page fault
+            let access = match exp {
+                Exception::StorePageFault => MemoryAccess::Write,
+                Exception::LoadPageFault => MemoryAccess::Read,
+                _ => MemoryAccess::Execute,
+            };
             let proc = CPU::get_current_process().unwrap();
-            let mut proc_data = proc.data.lock();
+            if proc.handle_page_fault(stval.into(), access) {
+                return Some(0); // populated or CoW resolved
+            }
 
-            if proc_data.memory.alloc_stack_if_possible(stval.into()) {
-                return Some(0); // alloc successful
+            if from_user {
+                // Access violates the mapping, let the signal handle it.
+                warn!("Segmentation fault on TID {}: {:?} at {:#x}, sepc: {:#x}.",
+                    CPU::get_current_thread().unwrap().tid(), exp, stval, sepc);
+                signal::force_signal(&CPU::get_current_thread().unwrap(), signal::SIGSEGV);
+                return Some(0);
             }
 
             error!("Unhandled Page-Fault happened: {:?} from {}: sepc: {:#x}, stval: {:#x}", exp,
@@ -147,6 +158,7 @@ fn exception_handler(trap_context: &TrapContext, exp: scause::Exception, sstatus
 #[no_mangle]
 fn user_trap_handler(trap_context: &mut TrapContext) {
     set_interrupt_to_kernel();
+    cputime::leave_user(&mut CPU::get_current_thread().unwrap().data.lock());
     let scause = scause::read();
     let stval = stval::read();
     let sepc = sepc::read();
@@ -193,13 +205,16 @@ pub fn user_trap_returner() {
     extern "C" {
         fn trap_ret_u(trap_context: &TrapContext);
     }
+    // Deliver pending signals before going back to user space.
+    signal::do_signal();
     disable_trap();
-    let proc = CPU::get_current_process().unwrap();
+    let thread = CPU::get_current_thread().unwrap();
     let trap_context = {
-        let mut data = proc.data.lock();
+        let mut data = thread.data.lock();
+        cputime::enter_user(&mut data);
         data.get_trap_context()
     };
-    drop(proc);
+    drop(thread);
     set_interrupt_to_user();
     unsafe {
         sstatus::set_spp(SPP::User);
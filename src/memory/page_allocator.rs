@@ -8,6 +8,7 @@
 use alloc::format;
 use alloc::vec::Vec;
 use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use buddy_system_allocator::{LockedFrameAllocator, LockedHeap};
 use lazy_static::lazy_static;
 use log::{info, trace};
@@ -35,9 +36,11 @@ pub fn init() {
         let end_page_id = PhyPageId::from(startup::get_boot_memory_info().usable_end);
         info!("Add {} to {} to PageAllocator, totally {} pages.", start_page_id, end_page_id, end_page_id.id - start_page_id.id);
         PAGE_ALLOCATOR.lock().add_frame(start_page_id.id, end_page_id.id);
+        TOTAL_PAGES.store(end_page_id.id - start_page_id.id, Ordering::Relaxed);
     }
     // Allocate 8 MB kernel heap
     let pages = PAGE_ALLOCATOR.lock().alloc(KERNEL_HEAP_SIZE_IN_MEM / PAGE_SIZE).unwrap();
+    ALLOCATED_PAGES.fetch_add(KERNEL_HEAP_SIZE_IN_MEM / PAGE_SIZE, Ordering::Relaxed);
     let paddr = PhyAddr::from(PhyPageId::from(pages));
     unsafe {
         HEAP_ALLOCATOR.lock().add_to_heap(paddr.get_addr(), paddr.to_offset(KERNEL_HEAP_SIZE_IN_MEM as isize).get_addr());
@@ -79,6 +82,16 @@ lazy_static! {
     static ref PAGE_ALLOCATOR: LockedFrameAllocator<32> = LockedFrameAllocator::new();
 }
 
+// Frame allocator keeps no public statistics, so we count pages ourselves.
+static TOTAL_PAGES: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// Return total and free pages managed by page allocator.
+pub fn page_stats() -> (usize, usize) {
+    let total = TOTAL_PAGES.load(Ordering::Relaxed);
+    (total, total.saturating_sub(ALLOCATED_PAGES.load(Ordering::Relaxed)))
+}
+
 pub struct PhyPage {
     pub id: PhyPageId,
 }
@@ -101,6 +114,7 @@ impl PhyPage {
 
     pub fn alloc() -> Self {
         let id = PhyPageId::from(PAGE_ALLOCATOR.lock().alloc(1).expect("Allocate 1 page failed."));
+        ALLOCATED_PAGES.fetch_add(1, Ordering::Relaxed);
         // Clean page
         let addr = PhyAddr::from(id);
         // addr.get_slice_mut::<usize>(PAGE_SIZE / size_of::<usize>()).iter_mut().for_each(|cell| *cell = 0);
@@ -112,6 +126,7 @@ impl PhyPage {
 
     pub fn alloc_many(count: usize) -> Vec<Self> {
         let start_id = PAGE_ALLOCATOR.lock().alloc(count).expect(format!("Allocate {} page failed", count).as_str());
+        ALLOCATED_PAGES.fetch_add(count, Ordering::Relaxed);
         (start_id..start_id + count).map(|id| Self::new(id.into())).collect()
     }
 
@@ -137,13 +152,17 @@ impl Drop for PhyPage {
     fn drop(&mut self) {
         // info!("Dropping a phy page at {:x}", self.id.id * PAGE_SIZE);
         PAGE_ALLOCATOR.lock().dealloc(self.id.id, 1);
+        ALLOCATED_PAGES.fetch_sub(1, Ordering::Relaxed);
     }
 }
 
 pub unsafe fn alloc_page_without_trace(count: usize) -> usize {
-    PAGE_ALLOCATOR.lock().alloc(count).unwrap()
+    let id = PAGE_ALLOCATOR.lock().alloc(count).unwrap();
+    ALLOCATED_PAGES.fetch_add(count, Ordering::Relaxed);
+    id
 }
 
 pub unsafe fn dealloc_page_without_trace(first_page_id: usize, count: usize) {
-    PAGE_ALLOCATOR.lock().dealloc(first_page_id, count)
+    PAGE_ALLOCATOR.lock().dealloc(first_page_id, count);
+    ALLOCATED_PAGES.fetch_sub(count, Ordering::Relaxed);
 }
\ No newline at end of file
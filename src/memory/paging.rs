@@ -7,9 +7,10 @@ use lazy_static::lazy_static;
 use log::{debug, info, trace};
 use riscv::asm;
 use riscv::register::{satp, sstatus};
+use sbi::hart_mask::HartMask;
 use crate::config::HARDWARE_BASE_ADDR;
 use crate::core::Spinlock;
-use crate::cpu::{CPUID, VendorId};
+use crate::cpu::{CPU, CPUID, VendorId};
 use crate::interrupt::enable_trap;
 use crate::memory::address::{VirtAddr, VirtPageId, Addr};
 use crate::memory::PAGE_SIZE;
@@ -162,6 +163,14 @@ impl PageTable {
         Self::set_pte(pte, pa, va, flags)
     }
 
+    /// Change an existing mapping, used when flags or target page of a mapped page changes.
+    pub fn remap(&mut self, va: VirtAddr, pa: PhyAddr, flags: PTEFlags) {
+        let pte = self.find_pte(VirtPageId::from(va)).unwrap();
+        assert!(pte.valid(), "{} is not mapped.", va);
+        *pte = PageTableEntry::empty();
+        Self::set_pte(pte, pa, va, flags)
+    }
+
     pub fn unmap(&mut self, va: VirtAddr) {
         let pte = self.find_pte(VirtPageId::from(va)).unwrap();
         assert!(pte.valid(), "{} is not mapped.", va);
@@ -225,6 +234,24 @@ pub fn flush_page_table(va: Option<VirtAddr>) {
     }
 }
 
+/// Flush [va, va + size) on every CPU, after PTEs of a page table which may be in use on other CPUs
+/// are downgraded, moved or removed. Others are flushed by SBI remote fence, which returns once
+/// they are done.
+pub fn shootdown_page_table(va: VirtAddr, size: usize) {
+    if size == PAGE_SIZE {
+        flush_page_table(Some(va));
+    } else {
+        flush_page_table(None);
+    }
+    if CPU::get_count() > 1 {
+        let current = CPU::get_current_id();
+        let others = (0..CPU::get_count())
+            .filter(|cpu| *cpu != current)
+            .fold(HartMask::new(0), |mask, cpu| mask.with(cpu));
+        let _ = sbi::rfence::remote_sfence_vma(others, va.get_addr(), size);
+    }
+}
+
 pub fn init() {
     trace!("In position mapping kernel.");
     let mut kernel_pt = KERNEL_PAGE_TABLE.lock();
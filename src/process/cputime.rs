@@ -0,0 +1,84 @@
+//! # CPU time
+//!
+//! User and system time of threads and processes. Scheduler counts the total time a thread runs,
+//! user time is counted from returning to user space until trapping into kernel, the rest of it
+//! is system time.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use core::ops::{Add, AddAssign};
+use core::time::Duration;
+use crate::device::timer;
+use crate::process::{ProcessData, ThreadData};
+use crate::process::scheduler;
+
+#[derive(Copy, Clone, Default, Debug)]
+pub struct CpuTime {
+    pub user: Duration,
+    pub system: Duration,
+}
+
+impl CpuTime {
+    pub fn total(&self) -> Duration {
+        self.user + self.system
+    }
+}
+
+impl Add for CpuTime {
+    type Output = CpuTime;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            user: self.user + rhs.user,
+            system: self.system + rhs.system,
+        }
+    }
+}
+
+impl AddAssign for CpuTime {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+/// User time of a thread, lives in `ThreadData`.
+#[derive(Copy, Clone, Default)]
+pub struct UserTime {
+    total: Duration,
+    // Time since boot it went to user space, None while in kernel.
+    entered: Option<Duration>,
+}
+
+/// Called right before returning to user space.
+pub fn enter_user(data: &mut ThreadData) {
+    data.user_time.entered = Some(timer::current_time());
+}
+
+/// Called on trap from user space, before anything else.
+pub fn leave_user(data: &mut ThreadData) {
+    if let Some(entered) = data.user_time.entered.take() {
+        data.user_time.total += timer::current_time().saturating_sub(entered);
+    }
+}
+
+/// CPU time of a thread so far.
+pub fn thread_time(data: &ThreadData) -> CpuTime {
+    let mut user = data.user_time.total;
+    if let Some(entered) = data.user_time.entered {
+        user += timer::current_time().saturating_sub(entered);
+    }
+    // Sampled apart, total may fall behind user by a little.
+    let total = scheduler::runtime(data).max(user);
+    CpuTime {
+        user,
+        system: total - user,
+    }
+}
+
+/// CPU time of all threads of a process so far, exited ones included.
+pub fn process_time(proc_data: &ProcessData) -> CpuTime {
+    proc_data.threads.iter()
+        .filter_map(|t| t.upgrade())
+        .fold(proc_data.exited_time, |time, thread| time + thread_time(&thread.data.lock()))
+}
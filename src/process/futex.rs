@@ -0,0 +1,201 @@
+//! # Futex
+//!
+//! Fast userspace mutex. Waiters are queued by key of the futex word. Word in private mapping is
+//! keyed by address space and virtual address, which CoW never changes. Word in shared mapping is
+//! keyed by what backs it, so that processes mapping it anywhere meet in the same queue.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::time::Duration;
+use lazy_static::lazy_static;
+use crate::core::Spinlock;
+use crate::cpu::CPU;
+use crate::device::timer;
+use crate::memory::{Addr, PAGE_SIZE, PhyAddr, VirtAddr, VirtPageId};
+use crate::process::{do_yield, MemoryAccess, Process, ProcessStatus, scheduler, Thread, VmaBacking};
+use crate::process::process_memory::ProcessMemory;
+use crate::syscall::{SyscallError, SyscallResult};
+
+pub const FUTEX_BITSET_MATCH_ANY: u32 = 0xffff_ffff;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum FutexKey {
+    /// Private mapping, by process and virtual address.
+    Private { process: usize, vaddr: usize },
+    /// Shared file mapping, by inode and file offset.
+    File { inode: usize, offset: usize },
+    /// Shared anonymous mapping, by physical address. Its pages are shared as they are.
+    Page(usize),
+}
+
+/// Key of futex word at `vaddr` of `proc`, and where the word is now. None if not mapped.
+/// With `private` (FUTEX_PRIVATE_FLAG), word in shared mapping is keyed like private one.
+pub fn key_of(proc: &Process, memory: &mut ProcessMemory, vaddr: VirtAddr, private: bool) -> Option<(FutexKey, PhyAddr)> {
+    // Faulted in writable like Linux, so a CoW page is copied first and the word is read from the
+    // frame this process writes to, not a stale shared one. Read-only mapping is never copied.
+    let word = memory.translate(vaddr, MemoryAccess::Write)
+        .or_else(|| memory.translate(vaddr, MemoryAccess::Read))?;
+    let vpn = VirtPageId::from(vaddr);
+    let vma = memory.vmas().find(|vma| vma.contains(vpn))?;
+    let key = if private || !vma.shared {
+        FutexKey::Private { process: proc as *const Process as usize, vaddr: vaddr.get_addr() }
+    } else if let VmaBacking::File { file, .. } = &vma.backing {
+        let inode = match file.get_dentry().ok().and_then(|dentry| dentry.get_inode()) {
+            Some(inode) => Arc::as_ptr(&inode) as *const () as usize,
+            None => Arc::as_ptr(file) as *const () as usize
+        };
+        let offset = vma.file_offset(vpn).unwrap() + vaddr.get_addr() % PAGE_SIZE;
+        FutexKey::File { inode, offset }
+    } else {
+        FutexKey::Page(word.get_addr())
+    };
+    Some((key, word))
+}
+
+struct FutexWaiter {
+    thread: Weak<Thread>,
+    bitset: u32,
+    woken: AtomicBool,
+}
+
+impl FutexWaiter {
+    fn wake(&self) {
+        self.woken.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.upgrade() {
+            scheduler::wake_up(&thread, &mut thread.data.lock());
+        }
+    }
+}
+
+type FutexQueue = VecDeque<Arc<FutexWaiter>>;
+
+lazy_static! {
+    static ref FUTEX_QUEUES: Spinlock<BTreeMap<FutexKey, FutexQueue>> = Spinlock::new(BTreeMap::new());
+}
+
+fn load_word(word: PhyAddr) -> u32 {
+    word.get_ref::<AtomicU32>().load(Ordering::SeqCst)
+}
+
+fn remove_waiter(queues: &mut BTreeMap<FutexKey, FutexQueue>, key: FutexKey, waiter: &Arc<FutexWaiter>) {
+    // Waiter may have been requeued, so search every queue.
+    let key = if queues.get(&key).is_some_and(|q| q.iter().any(|w| Arc::ptr_eq(w, waiter))) {
+        key
+    } else if let Some((key, _)) = queues.iter().find(|(_, q)| q.iter().any(|w| Arc::ptr_eq(w, waiter))) {
+        *key
+    } else {
+        return;
+    };
+    let queue = queues.get_mut(&key).unwrap();
+    queue.retain(|w| !Arc::ptr_eq(w, waiter));
+    if queue.is_empty() {
+        queues.remove(&key);
+    }
+}
+
+/// Sleep on futex word at `word` if it still holds `expected`. `deadline` is time since boot.
+pub fn wait(key: FutexKey, word: PhyAddr, expected: u32, bitset: u32, deadline: Option<Duration>) -> SyscallResult {
+    let thread = CPU::get_current_thread().unwrap();
+    let waiter = Arc::new(FutexWaiter {
+        thread: Arc::downgrade(&thread),
+        bitset,
+        woken: AtomicBool::new(false),
+    });
+    {
+        // Value is checked under queue lock, so a waker changing it afterwards always finds us.
+        let mut queues = FUTEX_QUEUES.lock();
+        if load_word(word) != expected {
+            return Err(SyscallError::EAGAIN);
+        }
+        queues.entry(key).or_default().push_back(waiter.clone());
+    }
+    let timeout = deadline.map(|deadline| timer::add_timeout(deadline, &thread));
+    let result = loop {
+        let proc_data = thread.process.data.lock();
+        let mut queues = FUTEX_QUEUES.lock();
+        // Suspended before checking, a wakeup from now on makes it Ready and yield returns.
+        thread.data.lock().status = ProcessStatus::Suspend;
+        if waiter.woken.load(Ordering::SeqCst) {
+            break Ok(0);
+        }
+        if deadline.is_some_and(|deadline| timer::current_time() >= deadline) {
+            remove_waiter(&mut queues, key, &waiter);
+            break Err(SyscallError::ETIMEDOUT);
+        }
+        let interrupted = {
+            let thread_data = thread.data.lock();
+            thread_data.killed || thread_data.signal.has_deliverable(&proc_data.signal.pending)
+        };
+        if interrupted {
+            remove_waiter(&mut queues, key, &waiter);
+            break Err(SyscallError::EINTR);
+        }
+        drop(queues);
+        drop(proc_data);
+        do_yield();
+    };
+    thread.data.lock().status = ProcessStatus::Running;
+    if let Some(timeout) = timeout {
+        timer::cancel_timer(timeout);
+    }
+    result
+}
+
+/// Wake at most `count` waiters matching `bitset`. Return the number of woken waiters.
+pub fn wake(key: FutexKey, count: usize, bitset: u32) -> usize {
+    let mut queues = FUTEX_QUEUES.lock();
+    let queue = if let Some(queue) = queues.get_mut(&key) {
+        queue
+    } else {
+        return 0;
+    };
+    let mut woken = 0;
+    queue.retain(|waiter| {
+        if woken < count && waiter.bitset & bitset != 0 {
+            waiter.wake();
+            woken += 1;
+            false
+        } else {
+            true
+        }
+    });
+    if queue.is_empty() {
+        queues.remove(&key);
+    }
+    woken
+}
+
+/// Wake at most `wake_count` waiters and move at most `requeue_count` of the rest to `key2`.
+/// With `expected`, the word at `word` is compared first like FUTEX_CMP_REQUEUE.
+/// Return the number of woken and requeued waiters.
+pub fn requeue(key: FutexKey, word: PhyAddr, key2: FutexKey, wake_count: usize, requeue_count: usize, expected: Option<u32>) -> Result<(usize, usize), SyscallError> {
+    let mut queues = FUTEX_QUEUES.lock();
+    if let Some(expected) = expected && load_word(word) != expected {
+        return Err(SyscallError::EAGAIN);
+    }
+    let mut queue = if let Some(queue) = queues.remove(&key) {
+        queue
+    } else {
+        return Ok((0, 0));
+    };
+    let mut woken = 0;
+    while woken < wake_count && let Some(waiter) = queue.pop_front() {
+        waiter.wake();
+        woken += 1;
+    }
+    let mut requeued = 0;
+    if key2 != key {
+        while requeued < requeue_count && let Some(waiter) = queue.pop_front() {
+            queues.entry(key2).or_default().push_back(waiter);
+            requeued += 1;
+        }
+    }
+    if !queue.is_empty() {
+        queues.insert(key, queue);
+    }
+    Ok((woken, requeued))
+}
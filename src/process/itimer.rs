@@ -0,0 +1,133 @@
+//! # Interval timer
+//!
+//! setitimer timers of a process. ITIMER_REAL counts real time and sends SIGALRM, ITIMER_VIRTUAL
+//! counts user time and ITIMER_PROF all CPU time of the process on scheduler ticks, sending
+//! SIGVTALRM and SIGPROF.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use alloc::sync::{Arc, Weak};
+use core::time::Duration;
+use crate::device::timer::{self, TimerId};
+use crate::process::Process;
+use crate::process::signal::{self, SIGALRM, SIGPROF, SIGVTALRM};
+
+pub const ITIMER_REAL: usize = 0;
+pub const ITIMER_VIRTUAL: usize = 1;
+pub const ITIMER_PROF: usize = 2;
+
+#[derive(Copy, Clone, Default)]
+pub struct ITimer {
+    interval: Duration,
+    // Deadline since boot for ITIMER_REAL, CPU time left for others. None if disarmed.
+    value: Option<Duration>,
+    timer: Option<TimerId>,
+    // Changed on every setitimer, so a timer fired meanwhile does nothing.
+    generation: usize,
+}
+
+fn signal_of(which: usize) -> usize {
+    match which {
+        ITIMER_REAL => SIGALRM,
+        ITIMER_VIRTUAL => SIGVTALRM,
+        _ => SIGPROF,
+    }
+}
+
+// Time left of an armed timer.
+fn time_left(which: usize, itimer: &ITimer) -> Duration {
+    match itimer.value {
+        Some(deadline) if which == ITIMER_REAL => deadline.saturating_sub(timer::current_time()),
+        Some(left) => left,
+        None => Duration::ZERO,
+    }
+}
+
+fn arm_real(proc: &Arc<Process>, itimer: &mut ITimer) {
+    if let Some(deadline) = itimer.value {
+        let proc = Arc::downgrade(proc);
+        let generation = itimer.generation;
+        itimer.timer = Some(timer::add_timer(deadline, Arc::new(move || expire_real(&proc, generation))));
+    }
+}
+
+fn expire_real(proc: &Weak<Process>, generation: usize) {
+    let Some(proc) = proc.upgrade() else { return };
+    let mut proc_data = proc.data.lock();
+    let itimer = &mut proc_data.itimers[ITIMER_REAL];
+    if itimer.generation != generation {
+        return;
+    }
+    itimer.timer = None;
+    if itimer.interval.is_zero() {
+        itimer.value = None;
+    } else {
+        // Missed periods are dropped.
+        let next = itimer.value.unwrap() + itimer.interval;
+        itimer.value = Some(next.max(timer::current_time()));
+        let mut itimer = *itimer;
+        arm_real(&proc, &mut itimer);
+        proc_data.itimers[ITIMER_REAL] = itimer;
+    }
+    drop(proc_data);
+    signal::send_signal(&proc, SIGALRM);
+}
+
+/// (interval, time left) of timer `which`, zero time left if disarmed.
+pub fn get(proc: &Arc<Process>, which: usize) -> (Duration, Duration) {
+    let itimer = proc.data.lock().itimers[which];
+    (itimer.interval, time_left(which, &itimer))
+}
+
+/// Arm timer `which` to fire in `value` and then every `interval`, or disarm it if `value` is
+/// zero. Return the old (interval, time left).
+pub fn set(proc: &Arc<Process>, which: usize, interval: Duration, value: Duration) -> (Duration, Duration) {
+    let mut proc_data = proc.data.lock();
+    let mut itimer = proc_data.itimers[which];
+    let old = (itimer.interval, time_left(which, &itimer));
+    if let Some(id) = itimer.timer.take() {
+        timer::cancel_timer(id);
+    }
+    itimer.generation += 1;
+    itimer.interval = timer::clamp_period(interval);
+    itimer.value = if value.is_zero() {
+        None
+    } else if which == ITIMER_REAL {
+        Some(timer::current_time() + value)
+    } else {
+        Some(value)
+    };
+    if which == ITIMER_REAL {
+        arm_real(proc, &mut itimer);
+    }
+    proc_data.itimers[which] = itimer;
+    old
+}
+
+/// Charge CPU time `ran` on a tick to the process, called by timer interrupt handler. `user` if
+/// it interrupted user space.
+pub fn tick(proc: &Arc<Process>, ran: Duration, user: bool) {
+    // Interrupted code may hold it, skip this tick then.
+    let Some(mut proc_data) = proc.data.try_lock() else { return };
+    let mut expired = [false; 3];
+    for which in [ITIMER_VIRTUAL, ITIMER_PROF] {
+        if which == ITIMER_VIRTUAL && !user {
+            continue;
+        }
+        let itimer = &mut proc_data.itimers[which];
+        let Some(left) = itimer.value else { continue };
+        if left > ran {
+            itimer.value = Some(left - ran);
+            continue;
+        }
+        expired[which] = true;
+        itimer.value = if itimer.interval.is_zero() { None } else { Some(itimer.interval) };
+    }
+    drop(proc_data);
+    for which in [ITIMER_VIRTUAL, ITIMER_PROF] {
+        if expired[which] {
+            signal::send_signal(proc, signal_of(which));
+        }
+    }
+}
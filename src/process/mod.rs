@@ -8,12 +8,20 @@
 mod pid;
 mod process;
 // User process
+mod thread;
+// Thread of user process
 mod task;
 // kernel task
 mod process_memory;
-mod condvar;
+mod vma;
+mod wait_queue;
+mod sleeplock;
 mod aux_;
-mod signal;
+pub mod signal;
+pub mod futex;
+pub mod scheduler;
+pub mod itimer;
+pub mod cputime;
 
 
 use alloc::string::String;
@@ -28,13 +36,18 @@ use riscv::register::mcause::Trap;
 use riscv::register::medeleg::clear_supervisor_env_call;
 use crate::core::{Spinlock, SpinlockGuard};
 
-pub use process::{Process, ProcessData, ProcessStatus, ProcessManager};
+pub use process::{Process, ProcessData, ProcessStatus, ProcessManager, CloneFlags, CLONE_SIGNAL_MASK};
+pub use thread::{Thread, ThreadData};
+pub use process_memory::MemoryAccess;
+pub use vma::{Vma, VmaBacking};
 pub use task::{TaskContext};
-pub use condvar::Condvar;
+pub use wait_queue::{WaitQueue, WakeReason};
+pub use sleeplock::SleepLock;
 pub use pid::Pid;
 use crate::cpu::CPU;
+use crate::device::timer;
 use crate::init;
-use crate::interrupt::{enable_trap, TrapContext};
+use crate::interrupt::{disable_trap, enable_trap, TrapContext};
 use crate::memory::{Addr, PAGE_SIZE, PhyAddr, PhyPage, PTEFlags, VirtAddr, VirtPageId};
 pub use task::{context_switch, do_yield, try_yield};
 use crate::filesystem::{File, SeekPosition};
@@ -47,37 +60,73 @@ pub fn get_process_manager() -> &'static Spinlock<ProcessManager> {
     return &PROCESS_MANAGER
 }
 
+/// Close files and write back shared file mappings of the process if current thread is its last
+/// one. Called before exiting, as both may sleep on disk I/O, which a zombie thread or one holding
+/// process manager must not do.
+pub fn release_on_exit(thread: &Arc<Thread>) {
+    let mut proc_data = thread.process.data.lock();
+    if !proc_data.mark_exiting(thread.tid()) {
+        return;
+    }
+    let files = core::mem::take(&mut proc_data.files);
+    proc_data.memory.writeback_all();
+    let pages = proc_data.memory.take_writeback();
+    drop(proc_data);
+    for page in pages {
+        page.write();
+    }
+    for file in files.into_iter().flatten() {
+        if Arc::strong_count(&file) == 1 {
+            let _ = file.close();
+        }
+    }
+}
+
 pub fn init() {
-    let mut init_proc = PROCESS_MANAGER.lock().spawn();
-    init_proc.load_elf(init::INIT_BINARY);
+    let init_thread = PROCESS_MANAGER.lock().spawn();
+    init_thread.process.load_elf(&init_thread, init::INIT_BINARY).expect("Failed to load init.");
+    init_thread.process.data.lock().name = "init".into();
     info!("Init proc is loaded.");
 }
 
 // Worker is running under every cpu
 pub fn worker() -> ! {
     loop {
+        // Take interrupts pending since last round.
         enable_trap();
-        let proc = PROCESS_MANAGER.lock().scheduler();
-        if let Some(proc) = proc {
-            // Change current proc
+        // Nothing is queued here by an interrupt between finding none and wfi below, which would
+        // be left there with no tick to notice it.
+        disable_trap();
+        let thread = scheduler::pick_next();
+        if let Some(thread) = thread {
+            enable_trap();
+            timer::start_tick();
+            // Change current thread
             let cpu = CPU::get_current().unwrap();
-            let current_proc = cpu.get_process();
-            if let Some(current_proc) = current_proc {
-                current_proc.data.lock().status = ProcessStatus::Ready;
-            }
-            let mut proc_data = proc.data.lock();
-            proc_data.status = ProcessStatus::Running;
-            let new_ctx = &proc_data.kernel_task_context as *const TaskContext;
-            drop(proc_data);
+            let mut thread_data = thread.data.lock();
+            scheduler::switch_in(&mut thread_data);
+            let new_ctx = &thread_data.kernel_task_context as *const TaskContext;
+            drop(thread_data);
 
-            cpu.set_process(Some(proc));
+            cpu.set_thread(Some(thread.clone()));
 
-            // switch to proc task context
+            // switch to thread task context
             let cpu_task_context = cpu.get_context_mut();
-            // get proc context
 
             unsafe { context_switch(cpu_task_context, new_ctx); }
+
+            let mut thread_data = thread.data.lock();
+            scheduler::switch_out(&thread, &mut thread_data);
+            let exited = thread_data.status == ProcessStatus::Zombie;
+            drop(thread_data);
+            // Exited thread is switched out, now it is safe to free its kernel stack.
+            if exited {
+                PROCESS_MANAGER.lock().remove_thread(&thread);
+            }
         } else {
+            // Tickless while idle, woken up by timers or IPIs. wfi returns on a pending interrupt
+            // even with SIE off, it is taken once enabled again.
+            timer::stop_tick();
             wfi();
         }
     }
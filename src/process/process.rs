@@ -10,25 +10,38 @@ use alloc::string::String;
 use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
-use core::cmp::max;
+use core::cmp::{max, min};
 use core::mem::size_of;
+use core::ptr;
+use core::time::Duration;
 use fdt::standard_nodes::Memory;
 use log::{error, info, trace, warn};
 use riscv::register::mcause::Trap;
-use crate::core::{Intrlock, Spinlock};
+use xmas_elf::ElfFile;
+use xmas_elf::header::{Class, Data, Machine};
+use xmas_elf::program::{ProgramHeader, ProgramHeader64};
+use bitflags::bitflags;
+use crate::core::{Intrlock, IntrlockGuard, Spinlock};
 use crate::cpu::CPU;
-use crate::filesystem::{DirEntry, DirEntryType, File, SeekPosition};
+use crate::device::timer;
+use crate::filesystem::{DirEntry, DirEntryType, File, FileModes, FileOpenFlags, SeekPosition};
 use crate::interrupt::{enable_trap, TrapContext, user_trap_returner};
 use super::pid::Pid;
 use crate::{config, memory};
-use crate::config::CLOCK_FREQ;
+use crate::config::{CLOCK_FREQ, PROCESS_MAX_ARG_SIZE, PROCESS_MMAP_BASE};
 use crate::memory::{PAGE_SIZE, PageTable, PhyAddr, PhyPage, PhyPageId, PTEFlags, VirtAddr, Addr, VirtPageId};
-use crate::process::{do_yield, PROCESS_MANAGER, TaskContext};
+use crate::process::{do_yield, PROCESS_MANAGER, TaskContext, Thread};
 use crate::process::aux_ as aux;
 use crate::process::aux_::Aux;
-use crate::process::condvar::Condvar;
+use crate::process::wait_queue::{WaitQueue, WakeReason};
+use crate::process::cputime::{self, CpuTime};
+use crate::process::futex;
+use crate::process::itimer::ITimer;
+use crate::process::scheduler;
+use crate::process::signal::{self, SignalState};
 use crate::syscall::{SyscallError, SyscallResult};
-use super::process_memory::ProcessMemory;
+use crate::utils::error::{EmptyResult, Result};
+use super::process_memory::{MemoryAccess, ProcessMemory};
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum ProcessStatus {
@@ -38,32 +51,71 @@ pub enum ProcessStatus {
     Zombie,
 }
 
+bitflags! {
+    #[derive(Copy, Clone, Debug)]
+    pub struct CloneFlags: usize {
+        const CLONE_VM = 0x0000100;
+        const CLONE_FS = 0x0000200;
+        const CLONE_FILES = 0x0000400;
+        const CLONE_SIGHAND = 0x0000800;
+        const CLONE_PTRACE = 0x0002000;
+        const CLONE_VFORK = 0x0004000;
+        const CLONE_PARENT = 0x0008000;
+        const CLONE_THREAD = 0x0010000;
+        const CLONE_NEWNS = 0x0020000;
+        const CLONE_SYSVSEM = 0x0040000;
+        const CLONE_SETTLS = 0x0080000;
+        const CLONE_PARENT_SETTID = 0x0100000;
+        const CLONE_CHILD_CLEARTID = 0x0200000;
+        const CLONE_DETACHED = 0x0400000;
+        const CLONE_UNTRACED = 0x0800000;
+        const CLONE_CHILD_SETTID = 0x1000000;
+    }
+}
+
+/// Low byte of clone flags is the signal sent to parent when child exits.
+pub const CLONE_SIGNAL_MASK: usize = 0xff;
+
+/// Thread group, which owns resources shared by its threads.
 pub struct Process {
     pub pid: Pid,
+    // Time since boot
+    pub start_time: Duration,
     pub data: Intrlock<ProcessData>,
+    // Woken up by exiting children, waited under the lock of data.
+    pub child_exit: WaitQueue,
+    // Woken up by exiting threads, waited under the lock of data.
+    pub thread_exit: WaitQueue,
 }
 
 pub struct ProcessData {
+    // Only Zombie is meaningful for process, others are tracked per thread.
     pub status: ProcessStatus,
+    // Encoded as wait status of wait4, see `ProcessManager::exit_thread`.
     pub exit_code: usize,
+    // Set by exit_group or fatal signal, overrides exit code of the last thread.
+    pub group_exit_code: Option<usize>,
     pub parent: Option<Weak<Process>>,
     pub children: Vec<Weak<Process>>,
-    pub kernel_stack: Vec<PhyPage>,
-    // We use kernel_stack to store trap context
-    pub kernel_task_context: TaskContext,
+    // Threads not exited yet
+    pub threads: Vec<Weak<Thread>>,
+    // File name of the running image, and its argv
+    pub name: String,
+    pub cmdline: Vec<String>,
     pub memory: ProcessMemory,
     // Files
     pub cwd: Arc<DirEntry>,
     pub files: Vec<Option<Arc<dyn File>>>,
-    // Condvars
-    pub condvar_waiting_for_exit: Condvar,
+    // Signals
+    pub signal: SignalState,
+    // setitimer timers, not inherited by children
+    pub itimers: [ITimer; 3],
+    // CPU time of exited threads, and of reaped children along with their reaped children
+    pub exited_time: CpuTime,
+    pub children_time: CpuTime,
 }
 
 impl ProcessData {
-    pub fn get_trap_context(&mut self) -> &'static mut TrapContext {
-        PhyAddr::from(self.kernel_stack[0].id).get_ref_mut::<TrapContext>()
-    }
-
     pub fn allocate_fd(&mut self) -> usize {
         if let Some(fd) = (0..self.files.len()).find(|fd| self.files[*fd].is_none()) {
             fd
@@ -72,271 +124,459 @@ impl ProcessData {
             self.files.len() - 1
         }
     }
+
+    /// Make all threads except `tid` exit on their way back to user space.
+    pub fn kill_other_threads(&self, tid: usize) {
+        self.threads.iter()
+            .filter_map(|t| t.upgrade())
+            .filter(|t| t.tid() != tid)
+            .for_each(|t| {
+                let mut thread_data = t.data.lock();
+                thread_data.killed = true;
+                scheduler::wake_up_interruptible(&t, &mut thread_data);
+            });
+    }
+
+    /// Mark thread `tid` exiting, return whether others are all exiting or gone.
+    /// Of threads exiting at once, only the last one marked gets true.
+    pub fn mark_exiting(&mut self, tid: usize) -> bool {
+        let mut last = true;
+        for thread in self.threads.iter().filter_map(|t| t.upgrade()) {
+            let mut thread_data = thread.data.lock();
+            if thread.tid() == tid {
+                thread_data.exiting = true;
+            } else if thread_data.status != ProcessStatus::Zombie && !thread_data.exiting {
+                last = false;
+            }
+        }
+        last
+    }
 }
 
 impl Process {
     pub fn new() -> Self {
         let pid = Pid::new();
-        let kernel_stack = PhyPage::alloc_many(config::PROCESS_KERNEL_STACK_SIZE);
-        // let kernel_sp = PhyAddr::from(kernel_stack.id).addr + PAGE_SIZE - size_of::<TrapContext>();
-        let kernel_sp = PhyAddr::from(kernel_stack[config::PROCESS_KERNEL_STACK_SIZE - 1].id).addr + PAGE_SIZE * config::PROCESS_KERNEL_STACK_SIZE;
-
         let memory = ProcessMemory::new();
 
-        let kernel_task_context = TaskContext::new().with_sp(kernel_sp).with_ra(user_trap_returner as usize);
-        let user_satp = memory.get_satp();
-
-        let mut process_data = ProcessData {
+        let process_data = ProcessData {
             status: ProcessStatus::Ready,
             exit_code: 0,
+            group_exit_code: None,
             parent: None,
             children: vec![],
-            kernel_stack,
-            kernel_task_context,
+            threads: vec![],
+            name: String::new(),
+            cmdline: vec![],
             memory,
             cwd: DirEntry::root(),
             files: Vec::new(),
-            condvar_waiting_for_exit: Condvar::new(),
+            signal: SignalState::new(),
+            itimers: [ITimer::default(); 3],
+            exited_time: CpuTime::default(),
+            children_time: CpuTime::default(),
         };
-        let trap_context = process_data.get_trap_context();
-        trap_context.kernel_sp = kernel_sp;
-        trap_context.satp = user_satp;
-        // setup files
-        process_data.files.push(Some(Arc::new(crate::device::console::Stdin)));
-        process_data.files.push(Some(Arc::new(crate::device::console::Stdout)));
-        process_data.files.push(Some(Arc::new(crate::device::console::Stdout)));
 
         Self {
             pid,
+            start_time: timer::current_time(),
             data: Intrlock::new(process_data),
+            child_exit: WaitQueue::new(),
+            thread_exit: WaitQueue::new(),
         }
     }
 
-    pub fn load_elf(&self, elf_binary: &[u8]) -> Vec<Aux> {
-        let mut aux_table: Vec<Aux> = vec![];
-        let elf = xmas_elf::ElfFile::new(elf_binary).unwrap();
-        let header = elf.header;
-        assert_eq!(header.pt1.magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf.");
-        let mut proc_data = self.data.lock();
-        let memory = &mut proc_data.memory;
-        let mut head_va = 0;
-        // Load prog header
-        for i in 0..elf.header.pt2.ph_count() {
-            let ph = elf.program_header(i).unwrap();
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
-                let start_va = VirtAddr::from(ph.virtual_addr() as usize);
-                let end_va = start_va.clone().to_offset(ph.mem_size() as isize);
-                if head_va == 0 {
-                    head_va = start_va.addr;
-                }
-                let mut flags = PTEFlags::U;
-                if ph.flags().is_read() {
-                    flags |= PTEFlags::R;
-                }
-                if ph.flags().is_write() {
-                    flags |= PTEFlags::W;
-                }
-                if ph.flags().is_execute() {
-                    flags |= PTEFlags::X;
-                }
-                // TODO: Use other model to manage user space mapping
-                let size = ph.file_size() as usize;
-                let offset = ph.offset() as usize % PAGE_SIZE;
-                let total_size = size + offset;
-                let pg_count = (total_size + PAGE_SIZE - 1) / PAGE_SIZE;
-                let data = &elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize];
-                let mut max_vpn: VirtPageId = VirtPageId::from(0);
-
-                for pg in 0..pg_count {
-                    // FIXME: non-continuous page could cause UK transfer error.
-                    let page = PhyPage::alloc();
-                    // continuous page is not required for user program.
-                    // info!("Copy 0x{:x} to 0x{:x}", ph.virtual_addr(), page.id.id * PAGE_SIZE);
-
-                    let begin = if pg == 0 { 0 } else { PAGE_SIZE * pg - offset };
-                    let inpage_offset = if pg == 0 { offset } else { 0 };
-                    let end = if pg == pg_count - 1 {
-                        size
-                    } else {
-                        begin + PAGE_SIZE - inpage_offset
-                    };
-                    page.copy_u8(inpage_offset, &data[begin..end]);
-                    let vpn: VirtPageId = VirtAddr::from(ph.virtual_addr() as usize + PAGE_SIZE * pg).into();
-                    // info!("Mapped {}", VirtAddr::from(vpn));
-                    max_vpn = vpn;
-                    memory.map(vpn, page, flags);
-                }
-
-                let need_vpn = VirtPageId::from(VirtAddr::from(ph.virtual_addr() as usize + ph.mem_size() as usize));
-                if need_vpn > max_vpn {
-                    trace!("Allocating {} pages for virt memory.", need_vpn.id - max_vpn.id);
-                    for pg in 0..need_vpn.id - max_vpn.id {
-                        let page = PhyPage::alloc();
-                        memory.map(VirtPageId::from(max_vpn.id + 1 + pg), page, flags);
-                    }
-                }
+    /// Handle page fault on user memory. Return false if the access is not allowed.
+    /// Page of file mapping is read with process unlocked, as reading may sleep on disk I/O.
+    pub fn handle_page_fault(&self, vaddr: VirtAddr, access: MemoryAccess) -> bool {
+        loop {
+            let mut proc_data = self.data.lock();
+            let Some(read) = proc_data.memory.fault_page_to_read(vaddr, access) else {
+                return proc_data.memory.handle_page_fault(vaddr, access);
+            };
+            drop(proc_data);
+            let Some(page) = read.read() else {
+                return false;
+            };
+            // Faulting access is retried, first write to shared file page faults again for dirty.
+            if self.data.lock().memory.install_read_page(&read, page) {
+                return true;
             }
+            // Mapping is changed meanwhile, look at the new one.
         }
-        // get prog end
-        // prog_end, brk, min_brk is point to first not valid byte.
-        // eg. prog_end = 0x1fff, then 0x1ffe is last valid byte. prog_end = 0x2000, whole page is not mapped.
-        let prog_end = VirtPageId::from(memory.get_mapped_last_page().id + 1).into();
-        memory.prog_end = prog_end;
-        memory.brk = memory.prog_end;
-        memory.min_brk = memory.prog_end;
-        // Setup user stack
-        memory.increase_user_stack();
-        let sp = memory.stack_base.get_addr();
-        let ctx = proc_data.get_trap_context();
-        ctx.reg[TrapContext::sp] = sp;
-        // Setup entry point
-        ctx.sepc = elf.header.pt2.entry_point() as usize;
-        // Setup aux
-        aux_table.push(Aux::new(aux::AT_PHDR, head_va + elf.header.pt2.ph_offset() as usize));
-        aux_table.push(Aux::new(aux::AT_PHENT, elf.header.pt2.ph_entry_size() as usize));
-        aux_table.push(Aux::new(aux::AT_PHNUM, elf.header.pt2.ph_count() as usize));
-        aux_table.push(Aux::new(aux::AT_PAGESZ, PAGE_SIZE));
-        aux_table.push(Aux::new(aux::AT_BASE, 0));
-        aux_table.push(Aux::new(aux::AT_FLAGS, 0));
-        aux_table.push(Aux::new(aux::AT_ENTRY, elf.header.pt2.entry_point() as usize));
-        aux_table.push(Aux::new(aux::AT_UID, 0));
-        aux_table.push(Aux::new(aux::AT_EUID, 0));
-        aux_table.push(Aux::new(aux::AT_GID, 0));
-        aux_table.push(Aux::new(aux::AT_EGID, 0));
-        aux_table.push(Aux::new(aux::AT_HWCAP, 0x112d));
-        aux_table.push(Aux::new(aux::AT_CLKTCK, CLOCK_FREQ));
-        aux_table.push(Aux::new(aux::AT_SECURE, 0));
-        aux_table
-    }
-
-    pub fn execve(&self, file: Arc<dyn File>, argv: Vec<String>, env: Vec<String>) -> usize {
-        let file_size = file.seek(0, SeekPosition::End).unwrap();
-        file.seek(0, SeekPosition::Set).unwrap();
-        let mut binary_vec = vec![0u8; file_size];
-        let read_size = file.read(binary_vec.as_mut_slice()).unwrap();
-        assert_eq!(read_size, file_size, "Read size not equal to file size.");
-        let binary_slice = binary_vec.as_slice();
-        let binary_ptr = binary_slice.as_ptr();
-        // clear old user space
-        self.data.lock().memory.reset();
-        // load new
-        // TODO: move out stack allocation
-        let mut aux_table = self.load_elf(binary_slice);
-        // setup argv and env
+    }
+
+    /// Access user memory by `access` with process locked.
+    /// File page it misses is read with process unlocked, then it's retried, so failing must leave no effect.
+    pub fn access_user<T>(&self, mut access: impl FnMut(&mut ProcessData) -> Result<T>) -> Result<T> {
+        loop {
+            let mut proc_data = self.data.lock();
+            proc_data.memory.take_missing_page();
+            let result = access(&mut *proc_data);
+            let read = match result {
+                Err(_) => proc_data.memory.take_missing_page(),
+                Ok(_) => None
+            };
+            let Some(read) = read else {
+                return result;
+            };
+            drop(proc_data);
+            let page = read.read().ok_or("Failed to read file page of user memory.")?;
+            // Retried even if mapping is changed meanwhile, the new one is looked at.
+            self.data.lock().memory.install_read_page(&read, page);
+        }
+    }
+
+    pub fn copy_to_user(&self, vaddr: VirtAddr, data: &[u8]) -> EmptyResult {
+        self.access_user(|proc_data| proc_data.memory.copy_to_user(vaddr, data))
+    }
+
+    pub fn copy_from_user(&self, vaddr: VirtAddr, buf: &mut [u8]) -> EmptyResult {
+        self.access_user(|proc_data| proc_data.memory.copy_from_user(vaddr, buf))
+    }
+
+    pub fn copy_cstr_from_user(&self, vaddr: VirtAddr, max_len: usize) -> Result<String> {
+        self.access_user(|proc_data| proc_data.memory.copy_cstr_from_user(vaddr, max_len))
+    }
+
+    pub fn write_user<T: Copy>(&self, vaddr: VirtAddr, value: &T) -> EmptyResult {
+        self.access_user(|proc_data| proc_data.memory.write_user(vaddr, value))
+    }
+
+    pub fn read_user<T: Copy>(&self, vaddr: VirtAddr) -> Result<T> {
+        self.access_user(|proc_data| proc_data.memory.read_user(vaddr))
+    }
+
+    /// Load elf into a fresh address space of process, used for the first process.
+    pub fn load_elf(&self, thread: &Thread, elf_binary: &[u8]) -> SyscallResult {
+        let (mut memory, entry, aux_table) = load_elf_image(elf_binary)?;
+        let (sp, argc, argv, envp) = setup_user_stack(&mut memory, vec![], vec![], aux_table)?;
         let mut proc_data = self.data.lock();
-        let context = proc_data.get_trap_context();
-        let virt_sp = context.reg[TrapContext::sp];
-        let stack_bottom = VirtAddr::from(virt_sp - PAGE_SIZE)
-            .into_pa(proc_data.memory.get_pagetable()).unwrap().to_offset(PAGE_SIZE as isize);
-        let mut sp = stack_bottom.clone(); // sp always point a 'valid' data if any valid data available.
-
-        /* Push strings */
-        let pusher = |s: String, sp: PhyAddr| -> (PhyAddr, *const u8) {
-            let len = s.len();
-            let sp = sp.to_offset(-(len as isize + 1)).round_down_to(16);
-            let mut_slice = sp.get_slice_mut::<u8>(len + 1);
-            mut_slice[..len].copy_from_slice(s.as_bytes());
-            mut_slice[len] = 0u8;
-            let offset = stack_bottom.get_addr() - sp.get_addr();
-            (sp, (virt_sp - offset) as *const u8)
-        };
+        let ctx = thread.data.lock().get_trap_context();
+        ctx.sepc = entry;
+        ctx.reg[TrapContext::sp] = sp;
+        ctx.reg[TrapContext::a1] = argv;
+        ctx.reg[TrapContext::a2] = envp;
+        ctx.satp = memory.get_satp();
+        proc_data.memory = memory;
+        Ok(argc)
+    }
 
-        let mut env_table: Vec<*const u8> = vec![];
-        for a in env {
-            let (new_sp, vptr) = pusher(a, sp);
-            sp = new_sp;
-            env_table.push(vptr);
-        }
-        env_table.push(0 as *const u8);
-        sp = sp.round_down_to(size_of::<usize>());
-
-        let mut argv_table: Vec<*const u8> = vec![];
-        for a in argv {
-            let (new_sp, vptr) = pusher(a, sp);
-            sp = new_sp;
-            argv_table.push(vptr);
-        }
-        argv_table.push(0 as *const u8);
-        sp = sp.round_down_to(size_of::<usize>());
-
-        // aux
-        // aux_table.push(Aux::new(aux::AT_RANDOM, 0));
-        aux_table.push(Aux::new(aux::AT_EXECFN, argv_table[0] as usize));
-        aux_table.push(Aux::new(aux::AT_NULL, 0));
-
-        /* Push pointers tables */
-        sp = sp.to_offset(-((size_of::<Aux>() * aux_table.len()) as isize));
-        sp.get_slice_mut::<Aux>(aux_table.len()).copy_from_slice(aux_table.as_slice());
-        sp = sp.to_offset(-((size_of::<*const u8>() * env_table.len()) as isize));
-        sp.get_slice_mut::<*const u8>(env_table.len()).copy_from_slice(env_table.as_slice());
-        let envp = virt_sp - (stack_bottom.get_addr() - sp.get_addr());
-        sp = sp.to_offset(-((size_of::<*const u8>() * argv_table.len()) as isize));
-        sp.get_slice_mut::<*const u8>(argv_table.len()).copy_from_slice(argv_table.as_slice());
-        let argv = virt_sp - (stack_bottom.get_addr() - sp.get_addr());
-
-        if false {
-            // OmochaOS Type
-
-            // push envp, argv, argc
-            sp = sp.to_offset(-(size_of::<*const u8>() as isize));
-            *(sp.get_ref_mut::<*const u8>()) = envp as *const u8;
-            sp = sp.to_offset(-(size_of::<*const u8>() as isize));
-            *(sp.get_ref_mut::<*const u8>()) = argv as *const u8;
-            sp = sp.to_offset(-(size_of::<*const u8>() as isize));
-            *(sp.get_ref_mut::<*const u8>()) = (argv_table.len() - 1) as *const u8;
-
-            /* stack should be like:
-             * |  0x80000000  | <--- Stack base
-             * +--------------+
-             * | env strings  |
-             * |    padding   |
-             * | arg strings  |
-             * |    padding   |
-             * +--------------+
-             * |  TODO: auxv table  |
-             * |  envp table  |
-             * |  argv table  |
-             * +--------------+
-             * |     envp     |
-             * |     argv     |
-             * |     argc     |
-             * +--------------+
-             * |      sp      | <--- user stack top
-             */
-        } else {
-            // Linux Type
-
-            // push envp, argv, argc
-            sp = sp.to_offset(-(size_of::<usize>() as isize));
-            *(sp.get_ref_mut::<usize>()) = argv_table.len() - 1;
-
-            /* stack should be like:
-             * |  0x80000000  | <--- Stack base
-             * +--------------+
-             * | env strings  |
-             * |    padding   |
-             * | arg strings  |
-             * |    padding   |
-             * +--------------+
-             * |  auxv table  |
-             * |  envp table  |
-             * |  argv table  |
-             * |     argc     |
-             * +--------------+
-             * |      sp      | <--- user stack top
-             */
-        }
-
-        let virt_sp = virt_sp - (stack_bottom.get_addr() - sp.get_addr());
+    /// Replace image of process. On failure, the old image is untouched.
+    pub fn execve(&self, thread: &Arc<Thread>, file: Arc<dyn File>, argv: Vec<String>, env: Vec<String>) -> SyscallResult {
+        let binary = read_whole_file(&file).map_err(|_| SyscallError::EIO)?;
+        // Like comm of Linux, name is truncated to 15 characters.
+        let name: String = file.get_dentry().map(|dentry| dentry.name.chars().take(15).collect()).unwrap_or_default();
+        let cmdline = argv.clone();
+        // New image is built aside, so that nothing is lost if it fails.
+        let (mut memory, entry, aux_table) = load_elf_image(binary.as_slice())?;
+        let (sp, argc, argv, envp) = setup_user_stack(&mut memory, argv, env, aux_table)?;
 
+        let mut proc_data = self.data.lock();
+        // Killed by another thread calling execve or exit_group first, give way to it.
+        if thread.data.lock().killed {
+            return Err(SyscallError::EINTR);
+        }
+        // Other threads are gone with the old image. They may be running in user space on the old
+        // page table, so wait until they exit. Their clear_child_tid is in the old image.
+        proc_data.kill_other_threads(thread.tid());
+        loop {
+            let others: Vec<Arc<Thread>> = proc_data.threads.iter()
+                .filter_map(|t| t.upgrade())
+                .filter(|t| t.tid() != thread.tid())
+                .collect();
+            if others.is_empty() {
+                break;
+            }
+            others.iter().for_each(|t| t.data.lock().clear_child_tid = VirtAddr::from(0));
+            drop(others);
+            self.thread_exit.wait(proc_data);
+            proc_data = self.data.lock();
+        }
+        let old_memory = core::mem::replace(&mut proc_data.memory, memory);
+        proc_data.signal.reset_on_exec();
+        proc_data.name = name;
+        proc_data.cmdline = cmdline;
+        let mut thread_data = thread.data.lock();
+        thread_data.clear_child_tid = VirtAddr::from(0);
         // setup context
-        context.reg[TrapContext::sp] = virt_sp;
+        let context = thread_data.get_trap_context();
+        context.sepc = entry;
+        context.reg[TrapContext::sp] = sp;
         context.reg[TrapContext::a1] = argv;
         context.reg[TrapContext::a2] = envp;
         context.satp = proc_data.memory.get_satp();
+        drop(thread_data);
+        drop(proc_data);
+        // Like Linux, calling thread takes over pid, it is the only one left.
+        PROCESS_MANAGER.lock().take_over_pid(thread);
+        // Shared file mappings of old image are written back here.
+        drop(old_memory);
+        Ok(argc) // jump to switch with argc as a0
+    }
+}
+
+/// Build a new address space from elf. Return the address space, entry point and aux table.
+fn load_elf_image(elf_binary: &[u8]) -> core::result::Result<(ProcessMemory, usize, Vec<Aux>), SyscallError> {
+    let mut aux_table: Vec<Aux> = vec![];
+    let elf = xmas_elf::ElfFile::new(elf_binary).map_err(|_| SyscallError::ENOEXEC)?;
+    let mut memory = ProcessMemory::new();
+    let image = map_elf(&mut memory, &elf, config::PROCESS_PIE_BASE)?;
+    // Dynamic linker takes control first, and jumps to program entry read from aux.
+    let (entry, interp_base) = if let Some(interp_path) = &image.interp {
+        let interp_dentry = DirEntry::from_path(interp_path, None).ok_or(SyscallError::ENOENT)?;
+        if interp_dentry.get_inode().is_some_and(|inode| inode.get_dentry_type() == DirEntryType::Dir) {
+            return Err(SyscallError::EACCES);
+        }
+        let interp_binary = interp_dentry.open(FileOpenFlags::O_RDONLY, FileModes::from_bits(0).unwrap())
+            .and_then(|file| read_whole_file(&file))
+            .map_err(|_| SyscallError::EIO)?;
+        let interp_elf = xmas_elf::ElfFile::new(interp_binary.as_slice()).map_err(|_| SyscallError::ENOEXEC)?;
+        let interp = map_elf(&mut memory, &interp_elf, config::PROCESS_INTERP_BASE)?;
+        if interp.interp.is_some() {
+            // Interpreter requiring another interpreter.
+            return Err(SyscallError::ENOEXEC);
+        }
+        (interp.entry, interp.bias)
+    } else {
+        (image.entry, 0)
+    };
+    // prog_end, brk, min_brk is point to first not valid byte.
+    // Heap starts right after the program, never after the interpreter.
+    memory.prog_end = VirtAddr::from(image.end).round_up();
+    memory.brk = memory.prog_end;
+    memory.min_brk = memory.prog_end;
+    memory.map_signal_trampoline();
+    // Setup aux
+    aux_table.push(Aux::new(aux::AT_PHDR, image.phdr));
+    aux_table.push(Aux::new(aux::AT_PHENT, elf.header.pt2.ph_entry_size() as usize));
+    aux_table.push(Aux::new(aux::AT_PHNUM, elf.header.pt2.ph_count() as usize));
+    aux_table.push(Aux::new(aux::AT_PAGESZ, PAGE_SIZE));
+    aux_table.push(Aux::new(aux::AT_BASE, interp_base));
+    aux_table.push(Aux::new(aux::AT_FLAGS, 0));
+    aux_table.push(Aux::new(aux::AT_ENTRY, image.entry));
+    aux_table.push(Aux::new(aux::AT_UID, 0));
+    aux_table.push(Aux::new(aux::AT_EUID, 0));
+    aux_table.push(Aux::new(aux::AT_GID, 0));
+    aux_table.push(Aux::new(aux::AT_EGID, 0));
+    aux_table.push(Aux::new(aux::AT_HWCAP, 0x112d));
+    aux_table.push(Aux::new(aux::AT_CLKTCK, CLOCK_FREQ));
+    aux_table.push(Aux::new(aux::AT_SECURE, 0));
+    Ok((memory, entry, aux_table))
+}
+
+/// Push argv, env and aux onto user stack. Return sp, argc, argv and envp.
+fn setup_user_stack(memory: &mut ProcessMemory, argv: Vec<String>, env: Vec<String>, mut aux_table: Vec<Aux>) -> core::result::Result<(usize, usize, usize, usize), SyscallError> {
+    /* stack should be like:
+     * |  0x80000000  | <--- Stack base
+     * +--------------+
+     * | env strings  |
+     * |    padding   |
+     * | arg strings  |
+     * |    padding   |
+     * +--------------+
+     * |  auxv table  |
+     * |  envp table  |
+     * |  argv table  |
+     * |     argc     |
+     * +--------------+ <--- user stack top
+     */
+    let mut sp = memory.stack_base.get_addr();
+    let arg_size: usize = argv.iter().chain(env.iter()).map(|s| s.len() + 1).sum();
+    if arg_size > PROCESS_MAX_ARG_SIZE {
+        return Err(SyscallError::E2BIG);
+    }
+
+    /* Push strings */
+    let mut push_str = |s: String, sp: &mut usize| -> core::result::Result<usize, SyscallError> {
+        *sp = (*sp - (s.len() + 1)) & !0xf;
+        memory.copy_to_user(VirtAddr::from(*sp), s.as_bytes()).map_err(|_| SyscallError::ENOMEM)?;
+        memory.copy_to_user(VirtAddr::from(*sp + s.len()), &[0u8]).map_err(|_| SyscallError::ENOMEM)?;
+        Ok(*sp)
+    };
+    let mut env_table: Vec<usize> = vec![];
+    for s in env {
+        env_table.push(push_str(s, &mut sp)?);
+    }
+    env_table.push(0);
+    let mut argv_table: Vec<usize> = vec![];
+    for s in argv {
+        argv_table.push(push_str(s, &mut sp)?);
+    }
+    argv_table.push(0);
+    let argc = argv_table.len() - 1;
+
+    // aux
+    // aux_table.push(Aux::new(aux::AT_RANDOM, 0));
+    aux_table.push(Aux::new(aux::AT_EXECFN, argv_table[0]));
+    aux_table.push(Aux::new(aux::AT_NULL, 0));
+
+    /* Push pointers tables */
+    let tables_size = size_of::<Aux>() * aux_table.len()
+        + size_of::<usize>() * (env_table.len() + argv_table.len() + 1);
+    // sp is 16 bytes aligned after argc is pushed.
+    sp = (sp - tables_size) & !0xf;
+    let argv_ptr = sp + size_of::<usize>();
+    let envp_ptr = argv_ptr + size_of::<usize>() * argv_table.len();
+    let auxv_ptr = envp_ptr + size_of::<usize>() * env_table.len();
+    let write_table = |memory: &mut ProcessMemory, addr: usize, table: &[usize]| -> core::result::Result<(), SyscallError> {
+        for (i, value) in table.iter().enumerate() {
+            memory.write_user(VirtAddr::from(addr + i * size_of::<usize>()), value)
+                .map_err(|_| SyscallError::ENOMEM)?;
+        }
+        Ok(())
+    };
+    memory.write_user(VirtAddr::from(sp), &argc).map_err(|_| SyscallError::ENOMEM)?;
+    write_table(memory, argv_ptr, argv_table.as_slice())?;
+    write_table(memory, envp_ptr, env_table.as_slice())?;
+    for (i, aux) in aux_table.iter().enumerate() {
+        memory.write_user(VirtAddr::from(auxv_ptr + i * size_of::<Aux>()), aux)
+            .map_err(|_| SyscallError::ENOMEM)?;
+    }
+    Ok((sp, argc, argv_ptr, envp_ptr))
+}
+
+/// ELF image mapped into user space.
+struct ElfImage {
+    // Load bias, zero for ET_EXEC.
+    bias: usize,
+    entry: usize,
+    // Address of program headers in user space.
+    phdr: usize,
+    // First byte after the highest segment.
+    end: usize,
+    interp: Option<String>,
+}
+
+fn read_whole_file(file: &Arc<dyn File>) -> Result<Vec<u8>> {
+    let file_size = file.seek(0, SeekPosition::End)?;
+    file.seek(0, SeekPosition::Set)?;
+    let mut buf = vec![0u8; file_size];
+    if file.read(buf.as_mut_slice())? != file_size {
+        return Err("read size not equal to file size.".into());
+    }
+    Ok(buf)
+}
+
+/// Map PT_LOAD segments of elf. ET_DYN is placed at `dyn_base`, ET_EXEC at its own address.
+fn map_elf(memory: &mut ProcessMemory, elf: &ElfFile, dyn_base: usize) -> core::result::Result<ElfImage, SyscallError> {
+    let header = &elf.header;
+    if header.pt1.class() != Class::SixtyFour || header.pt1.data() != Data::LittleEndian
+        || header.pt2.machine().as_machine() != Machine::RISC_V
+        || header.pt2.ph_entry_size() as usize != size_of::<ProgramHeader64>() {
+        return Err(SyscallError::ENOEXEC);
+    }
+    let mut loads = vec![];
+    let mut interp = None;
+    let mut phdr = None;
+    for ph in elf.program_iter() {
+        match ph.get_type().map_err(|_| SyscallError::ENOEXEC)? {
+            xmas_elf::program::Type::Load => loads.push(ph),
+            xmas_elf::program::Type::Interp => {
+                let path = segment_data(elf, &ph)?;
+                let path = core::str::from_utf8(path).map_err(|_| SyscallError::ENOEXEC)?;
+                interp = Some(String::from(path.trim_end_matches('\0')));
+            }
+            xmas_elf::program::Type::Phdr => phdr = Some(ph.virtual_addr() as usize),
+            _ => {}
+        }
+    }
+    let first_va = loads.iter().map(|ph| ph.virtual_addr() as usize).min().ok_or(SyscallError::ENOEXEC)?;
+    let bias = match header.pt2.type_().as_type() {
+        xmas_elf::header::Type::Executable => 0,
+        // Image linked above where it goes would need a negative bias, which is not supported.
+        xmas_elf::header::Type::SharedObject => dyn_base.checked_sub(VirtAddr::from(first_va).round_down().get_addr())
+            .ok_or(SyscallError::ENOEXEC)?,
+        _ => return Err(SyscallError::ENOEXEC)
+    };
+
+    // Check all segments before mapping anything.
+    let mut prev_end_va = 0;
+    for ph in loads.iter() {
+        segment_data(elf, ph)?;
+        let align = ph.align() as usize;
+        if ph.file_size() > ph.mem_size() || (align > 1 && !align.is_power_of_two())
+            || ph.virtual_addr() as usize % PAGE_SIZE != ph.offset() as usize % PAGE_SIZE {
+            return Err(SyscallError::ENOEXEC);
+        }
+        let start_va = (ph.virtual_addr() as usize).checked_add(bias).ok_or(SyscallError::ENOMEM)?;
+        let end_va = start_va.checked_add(ph.mem_size() as usize).ok_or(SyscallError::ENOMEM)?;
+        // User image must not reach mmap area and stack.
+        if start_va < PAGE_SIZE || end_va > PROCESS_MMAP_BASE {
+            return Err(SyscallError::ENOMEM);
+        }
+        // Segments are in address order and do not overlap, only a page can be shared with the
+        // previous one. Nor may they overlap an image mapped before, like the program under
+        // its interpreter.
+        let start_vpn = VirtPageId::from(VirtAddr::from(start_va));
+        let end_vpn = VirtPageId::from(VirtAddr::from(end_va).round_up());
+        if start_va < prev_end_va || memory.is_range_used(start_vpn, end_vpn) {
+            return Err(SyscallError::ENOEXEC);
+        }
+        prev_end_va = end_va;
+    }
 
-        argv_table.len() - 1 // jump to switch with argc as a0
+    let mut end = 0;
+    for ph in loads.iter() {
+        let start_va = ph.virtual_addr() as usize + bias;
+        let mut flags = PTEFlags::U;
+        if ph.flags().is_read() {
+            flags |= PTEFlags::R;
+        }
+        if ph.flags().is_write() {
+            flags |= PTEFlags::W;
+        }
+        if ph.flags().is_execute() {
+            flags |= PTEFlags::X;
+        }
+        map_segment(memory, segment_data(elf, ph)?, start_va, ph.mem_size() as usize, flags);
+        end = max(end, start_va + ph.mem_size() as usize);
     }
+
+    // Without PT_PHDR, program headers are found in the segment covering them in file.
+    let phdr = phdr.or_else(|| {
+        let ph_offset = header.pt2.ph_offset();
+        loads.iter()
+            .find(|ph| ph.offset() <= ph_offset && ph_offset < ph.offset() + ph.file_size())
+            .map(|ph| (ph.virtual_addr() + ph_offset - ph.offset()) as usize)
+    }).unwrap_or(0);
+
+    let entry = (header.pt2.entry_point() as usize).checked_add(bias).ok_or(SyscallError::ENOEXEC)?;
+    let phdr = if phdr == 0 { 0 } else { phdr.checked_add(bias).ok_or(SyscallError::ENOEXEC)? };
+    Ok(ElfImage {
+        bias,
+        entry,
+        phdr,
+        end,
+        interp,
+    })
+}
+
+/// Content of segment in file.
+fn segment_data<'a>(elf: &ElfFile<'a>, ph: &ProgramHeader) -> core::result::Result<&'a [u8], SyscallError> {
+    let begin = ph.offset() as usize;
+    let end = begin.checked_add(ph.file_size() as usize).ok_or(SyscallError::ENOEXEC)?;
+    elf.input.get(begin..end).ok_or(SyscallError::ENOEXEC)
+}
+
+/// Map one segment, `data` is its content in file and rest of `mem_size` is zero filled.
+fn map_segment(memory: &mut ProcessMemory, data: &[u8], start_va: usize, mem_size: usize, flags: PTEFlags) {
+    let first_vpn = VirtPageId::from(VirtAddr::from(start_va));
+    let end_vpn = VirtPageId::from(VirtAddr::from(start_va + mem_size).round_up());
+    for id in first_vpn.id..end_vpn.id {
+        let vpn = VirtPageId::from(id);
+        // Page shared with previous segment is already mapped.
+        if !memory.is_mapped(&vpn) {
+            memory.map(vpn, PhyPage::alloc(), flags);
+        }
+        let page_va = VirtAddr::from(vpn).get_addr();
+        let begin = max(page_va, start_va);
+        let end = min(page_va + PAGE_SIZE, start_va + data.len());
+        if begin < end {
+            let pa = VirtAddr::from(begin).into_pa(memory.get_pagetable()).unwrap();
+            pa.get_u8_mut(end - begin).copy_from_slice(&data[begin - start_va..end - start_va]);
+        }
+    }
+    memory.add_segment_vma(first_vpn, end_vpn, flags);
 }
 
 impl Drop for Process {
@@ -347,7 +587,7 @@ impl Drop for Process {
 
 pub struct ProcessManager {
     process_list: BTreeMap<usize, Arc<Process>>,
-    previous_scheduled_pid: usize,
+    thread_list: BTreeMap<usize, Arc<Thread>>,
 }
 
 const WNOHANG: usize = 1;
@@ -356,89 +596,193 @@ impl ProcessManager {
     pub fn new() -> Self {
         Self {
             process_list: BTreeMap::new(),
-            previous_scheduled_pid: 0,
+            thread_list: BTreeMap::new(),
         }
     }
 
-    pub fn spawn(&mut self) -> Arc<Process> {
+    /// Create a new process with its main thread.
+    pub fn spawn(&mut self) -> Arc<Thread> {
         let proc = Arc::new(Process::new());
-        // info!("Process at {:x}", proc.as_ref() as *const Process as usize);
-        self.process_list.insert(proc.pid.pid(), proc.clone());
-        proc
-    }
-
-    pub fn scheduler(&mut self) -> Option<Arc<Process>> {
-        // 推举下一个Ready但是没Running的进程
-        let bebind = self.process_list.iter().filter(|(pid, _)| {
-            pid > &&self.previous_scheduled_pid
-        }).find_map(|(_, proc)| {
-            match proc.data.lock().status {
-                ProcessStatus::Ready => Some(proc.clone()),
-                _ => None
+        let mut proc_data = proc.data.lock();
+        // stdin, stdout and stderr on the console, children inherit them through fork.
+        let tty = DirEntry::from_path("/dev/tty", None)
+            .and_then(|dentry| dentry.open(FileOpenFlags::O_RDWR, FileModes::from_bits(0).unwrap()).ok())
+            .expect("Failed to open /dev/tty.");
+        (0..3).for_each(|_| proc_data.files.push(Some(tty.clone())));
+        let thread = Arc::new(Thread::new(proc.clone(), None, proc_data.memory.get_satp()));
+        proc_data.threads.push(Arc::downgrade(&thread));
+        drop(proc_data);
+        self.process_list.insert(proc.pid.pid(), proc);
+        self.thread_list.insert(thread.tid(), thread.clone());
+        scheduler::enqueue(&thread, &mut thread.data.lock());
+        thread
+    }
+
+    pub fn get_process(&self, pid: usize) -> Option<Arc<Process>> {
+        self.process_list.get(&pid).cloned()
+    }
+
+    pub fn get_thread(&self, tid: usize) -> Option<Arc<Thread>> {
+        self.thread_list.get(&tid).cloned()
+    }
+
+    pub fn process_list(&self) -> impl Iterator<Item=&Arc<Process>> {
+        self.process_list.values()
+    }
+
+    pub fn thread_list(&self) -> impl Iterator<Item=&Arc<Thread>> {
+        self.thread_list.values()
+    }
+
+    /// Create a thread in the same process with CLONE_THREAD, or fork a new process otherwise.
+    /// Return the tid of the new thread.
+    pub fn clone(&mut self, thread: &Arc<Thread>, flags: CloneFlags, child_stack: usize,
+                 parent_tid: VirtAddr, tls: usize, child_tid: VirtAddr) -> usize {
+        let parent = thread.process.clone();
+        let mut parent_data = parent.data.lock();
+
+        let child_thread = if flags.contains(CloneFlags::CLONE_THREAD) {
+            let child_thread = Arc::new(Thread::new(parent.clone(), Some(Pid::new()), parent_data.memory.get_satp()));
+            parent_data.threads.push(Arc::downgrade(&child_thread));
+            if flags.contains(CloneFlags::CLONE_CHILD_SETTID) {
+                let _ = parent_data.memory.write_user(child_tid, &(child_thread.tid() as u32));
             }
-        });
-        let result = if bebind.is_some() {
-            bebind
+            child_thread
         } else {
-            self.process_list.iter().filter(|(pid, _)| {
-                pid <= &&self.previous_scheduled_pid
-            }).find_map(|(_, proc)| {
-                match proc.data.lock().status {
-                    ProcessStatus::Ready => Some(proc.clone()),
-                    _ => None
-                }
-            })
+            if flags.intersects(CloneFlags::CLONE_VM | CloneFlags::CLONE_FILES | CloneFlags::CLONE_SIGHAND) {
+                warn!("Sharing {:?} between processes is not supported, child gets a copy instead.",
+                    flags & (CloneFlags::CLONE_VM | CloneFlags::CLONE_FILES | CloneFlags::CLONE_SIGHAND));
+            }
+            let child = Arc::new(Process::new());
+            let mut child_data = child.data.lock();
+            child_data.parent = Some(Arc::downgrade(&parent));
+            parent_data.children.push(Arc::downgrade(&child));
+
+            child_data.memory.copy_from(&mut parent_data.memory, true);
+            child_data.cwd = parent_data.cwd.clone();
+            child_data.name = parent_data.name.clone();
+            child_data.cmdline = parent_data.cmdline.clone();
+            child_data.signal = parent_data.signal.fork();
+            parent_data.files.iter().enumerate()
+                .filter(|(fd, file)| file.is_some())
+                .for_each(|(fd, file)| {
+                    while child_data.files.get(fd).is_none() {
+                        child_data.files.push(None)
+                    }
+                    child_data.files[fd] = if let Some(file) = file {
+                        Some(file.clone())
+                    } else {
+                        panic!("not possible.")
+                    }
+                });
+
+            let child_thread = Arc::new(Thread::new(child.clone(), None, child_data.memory.get_satp()));
+            child_data.threads.push(Arc::downgrade(&child_thread));
+            if flags.contains(CloneFlags::CLONE_CHILD_SETTID) {
+                let _ = child_data.memory.write_user(child_tid, &(child_thread.tid() as u32));
+            }
+            drop(child_data);
+            self.process_list.insert(child.pid.pid(), child);
+            child_thread
         };
-        result.map(|proc| {
-            self.previous_scheduled_pid = proc.pid.pid();
-            proc
-        })
-    }
 
-    pub fn fork(&mut self, parent: Arc<Process>, child_stack: *const u8) -> usize {
-        // TODO: child stack is unused.
-        let mut parent_data = parent.data.lock();
-        let child = self.spawn();
-        let mut child_data = child.data.lock();
-        child_data.parent = Some(Arc::downgrade(&parent));
-        parent_data.children.push(Arc::downgrade(&child));
-
-        child_data.memory.copy_from(&parent_data.memory, true);
-        child_data.status = ProcessStatus::Ready;
-        child_data.cwd = parent_data.cwd.clone();
-        child_data.get_trap_context().copy_from(parent_data.get_trap_context());
-        child_data.get_trap_context().reg[TrapContext::a0] = 0; // child fork's ret
-        parent_data.files.iter().enumerate()
-            .filter(|(fd, file)| { *fd >= 3 && file.is_some() })
-            .for_each(|(fd, file)| {
-                while child_data.files.get(fd).is_none() {
-                    child_data.files.push(None)
-                }
-                child_data.files[fd] = if let Some(file) = file {
-                    Some(file.clone())
-                } else {
-                    panic!("not possible.")
-                }
-            });
+        if flags.contains(CloneFlags::CLONE_PARENT_SETTID) {
+            let _ = parent_data.memory.write_user(parent_tid, &(child_thread.tid() as u32));
+        }
 
-        drop(child_data);
+        let mut parent_thread_data = thread.data.lock();
+        let mut child_thread_data = child_thread.data.lock();
+        child_thread_data.signal = parent_thread_data.signal.fork();
+        child_thread_data.sched = parent_thread_data.sched.fork();
+        if flags.contains(CloneFlags::CLONE_CHILD_CLEARTID) {
+            child_thread_data.clear_child_tid = child_tid;
+        }
+        let child_context = child_thread_data.get_trap_context();
+        child_context.copy_from(parent_thread_data.get_trap_context());
+        child_context.reg[TrapContext::a0] = 0; // child clone's ret
+        if child_stack != 0 {
+            child_context.reg[TrapContext::sp] = child_stack;
+        }
+        if flags.contains(CloneFlags::CLONE_SETTLS) {
+            child_context.reg[TrapContext::tp] = tls;
+        }
+        drop(child_thread_data);
+        drop(parent_thread_data);
         drop(parent_data);
 
-        // parent process
-        child.pid.pid()
+        let tid = child_thread.tid();
+        scheduler::enqueue(&child_thread, &mut child_thread.data.lock());
+        self.thread_list.insert(tid, child_thread);
+        tid
     }
 
-    pub fn exit(&mut self, proc: Arc<Process>, exit_code: usize) {
+    /// Exit a single thread. Process exits along with its last thread. `exit_code` is the wait
+    /// status, `(code & 0xff) << 8` for exit and the signal number for a kill.
+    pub fn exit_thread(&mut self, thread: &Arc<Thread>, exit_code: usize) {
+        let proc = thread.process.clone();
+        trace!("Thread {} of proc {} want to exit.", thread.tid(), proc.pid.pid());
+        let mut proc_data = proc.data.lock();
+        let mut thread_data = thread.data.lock();
+        thread_data.status = ProcessStatus::Zombie;
+        let clear_child_tid = thread_data.clear_child_tid;
+        // Time till it is switched out is not counted, which is short.
+        proc_data.exited_time += cputime::thread_time(&thread_data);
+        drop(thread_data);
+        proc_data.threads.retain(|t| t.strong_count() > 0 && !ptr::eq(t.as_ptr(), Arc::as_ptr(thread)));
+        // execve of another thread may be waiting for this one.
+        proc.thread_exit.wake_all();
+
+        if !clear_child_tid.is_null() && proc_data.memory.write_user(clear_child_tid, &0u32).is_ok() {
+            // pthread_join waits on this.
+            if let Some((key, _)) = futex::key_of(&proc, &mut proc_data.memory, clear_child_tid, false) {
+                futex::wake(key, 1, futex::FUTEX_BITSET_MATCH_ANY);
+            }
+        }
+
+        if proc_data.threads.is_empty() {
+            let exit_code = proc_data.group_exit_code.unwrap_or(exit_code);
+            self.exit(&proc, proc_data, exit_code);
+        }
+        // Thread is removed from list by worker after it is switched out.
+    }
+
+    /// Exit all threads of the process.
+    pub fn exit_group(&mut self, thread: &Arc<Thread>, exit_code: usize) {
+        let mut proc_data = thread.process.data.lock();
+        if proc_data.group_exit_code.is_none() {
+            proc_data.group_exit_code = Some(exit_code);
+        }
+        proc_data.kill_other_threads(thread.tid());
+        drop(proc_data);
+        self.exit_thread(thread, exit_code);
+    }
+
+    /// Drop an exited thread which is no longer running on any CPU.
+    pub fn remove_thread(&mut self, thread: &Arc<Thread>) {
+        // Its tid may be taken over already, see `take_over_pid`.
+        if self.thread_list.get(&thread.tid()).is_some_and(|t| Arc::ptr_eq(t, thread)) {
+            self.thread_list.remove(&thread.tid());
+        }
+    }
+
+    /// Make the only thread of process left by execve its main thread, if it is not.
+    pub fn take_over_pid(&mut self, thread: &Arc<Thread>) {
+        if thread.is_main_thread() {
+            return;
+        }
+        self.thread_list.remove(&thread.tid());
+        thread.become_main_thread();
+        self.thread_list.insert(thread.tid(), thread.clone());
+    }
+
+    fn exit(&mut self, proc: &Arc<Process>, mut proc_data: IntrlockGuard<ProcessData>, exit_code: usize) {
         trace!("Proc {} want to exit.", proc.pid.pid());
-        let strong_count = Arc::strong_count(&proc);
-        // There is 3 strong count expected: 1 in ProcessManager's BTree Map. 1 in CPU::current_process. 1 in this function.
-        assert_eq!(strong_count, 3, "There is some other holding process' strong ref.");
         assert_ne!(proc.pid.pid(), 1, "Init process want exit!");
 
         // Set process status
-        let mut proc_data = proc.data.lock();
         proc_data.status = ProcessStatus::Zombie;
         proc_data.exit_code = exit_code;
+        // Shared file mappings are clean, written back by the last thread in `release_on_exit`.
         proc_data.memory.reset(); // 尽量清理内存，但是会留下一个页表根页。
 
         // reparent child to init
@@ -461,58 +805,52 @@ impl ProcessManager {
             }
         }
 
-        // wakeup waiting list
-        proc_data.condvar_waiting_for_exit.wakeup();
+        // notify parent
+        let parent = proc_data.parent.as_ref().and_then(|p| p.upgrade());
+        drop(proc_data);
+        if let Some(parent) = parent {
+            // Parent checks its children under its lock before waiting, so no exit is missed.
+            let parent_data = parent.data.lock();
+            parent.child_exit.wake_all();
+            drop(parent_data);
+            signal::send_signal(&parent, signal::SIGCHLD);
+        }
     }
 
-    pub fn wait_for(pm: &Spinlock<ProcessManager>, parent: Arc<Process>, pid: isize, exit_code: &mut usize, option: usize) -> SyscallResult {
-        'outer: loop {
+    /// Reap a zombie child, its exit code and CPU time are stored into `exit_code` and `child_time`.
+    pub fn wait_for(pm: &Spinlock<ProcessManager>, parent: Arc<Process>, pid: isize, exit_code: &mut usize, child_time: &mut CpuTime, option: usize) -> SyscallResult {
+        loop {
             let mut proc_data = parent.data.lock();
             proc_data.children.retain(|p| p.strong_count() > 0);
-            if proc_data.children.len() == 0 {
-                break 'outer Err(SyscallError::ECHILD); // No child
+            let children: Vec<Arc<Process>> = proc_data.children.iter().filter_map(|p| p.upgrade()).collect();
+            if children.is_empty() || (pid > 0 && !children.iter().any(|c| c.pid.pid() as isize == pid)) {
+                return Err(SyscallError::ECHILD); // No child, or not ours
             }
-            for child in &proc_data.children {
-                let child = child.upgrade().unwrap();
+            let zombie = children.iter().find(|child| {
+                (pid == -1 || pid == child.pid.pid() as isize) && child.data.lock().status == ProcessStatus::Zombie
+            });
+            if let Some(child) = zombie {
+                let pid = child.pid.pid();
                 let child_data = child.data.lock();
-                let mut result = None;
-                if child_data.status == ProcessStatus::Zombie {
-                    // found one zombie
-                    if pid == -1 || pid == child.pid.pid() as isize {
-                        // is what we waited for
-                        let pid = child.pid.pid() as isize;
-                        *exit_code = child_data.exit_code;
-                        result = Some(pid);
-                    }
-                }
-                if let Some(pid) = result {
-                    drop(child_data);
-                    let _ = pm.lock().process_list.remove(&(pid as usize)); // drop process inside btree map
-                    assert_eq!(Arc::strong_count(&child), 1, "Still someone holding child...");
-                    drop(child);
-
-                    break 'outer Ok(pid as usize);
-                }
+                *exit_code = child_data.exit_code;
+                *child_time = child_data.exited_time + child_data.children_time;
+                drop(child_data);
+                proc_data.children_time += *child_time;
+                // Reaped, hidden from other waiting threads.
+                proc_data.children.retain(|p| p.as_ptr() != Arc::as_ptr(child));
+                drop(proc_data);
+                // drop process inside btree map, exited threads not yet switched out may still hold it.
+                let _ = pm.lock().process_list.remove(&pid);
+                return Ok(pid);
             }
             if option == WNOHANG {
-                break 'outer Ok(0); // no child found, no hang
-            } else {
-                // no child found, hang
-                drop(proc_data);
-                if pid > 0 {
-                    let pm_guard = pm.lock();
-                    let waited_child = pm_guard.process_list.get(&(pid as usize));
-                    if let Some(waited_child) = waited_child {
-                        let waited_child_data = waited_child.data.lock();
-                        waited_child_data.condvar_waiting_for_exit.wait();
-                    } else {
-                        // not found one
-                        break 'outer Err(SyscallError::ECHILD);
-                    }
-                    drop(pm_guard);
-                }
-                do_yield();
+                return Ok(0); // no child found, no hang
+            }
+            drop(children);
+            // no child found, hang
+            if parent.child_exit.wait_interruptible(proc_data) == WakeReason::Interrupted {
+                return Err(SyscallError::EINTR);
             }
         }
     }
-}
\ No newline at end of file
+}
@@ -1,17 +1,87 @@
 use alloc::collections::BTreeMap;
-use alloc::format;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use log::info;
-use crate::config::{KERNEL_SPACE_BASE, PROCESS_MMAP_BASE, PROCESS_USER_STACK_BASE};
-use crate::device::timer::handler;
-use crate::memory::{Addr, PAGE_SIZE, PageTable, PhyAddr, PhyPage, PTEFlags, VirtAddr, VirtPageId};
+use core::cmp::{max, min};
+use core::mem::{MaybeUninit, size_of};
+use log::warn;
+use crate::config::{KERNEL_SPACE_BASE, PROCESS_MMAP_BASE, PROCESS_SIGNAL_TRAMPOLINE, PROCESS_USER_STACK_BASE};
+use crate::filesystem::{File, SeekPosition};
+use crate::memory::{Addr, PAGE_SIZE, PageTable, PhyAddr, PhyPage, PTEFlags, shootdown_page_table, VirtAddr, VirtPageId};
 use crate::utils::error::{EmptyResult, Result};
+use super::vma::{Vma, VmaBacking};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MemoryAccess {
+    Read,
+    Write,
+    Execute,
+}
+
+impl MemoryAccess {
+    fn required_flag(&self) -> PTEFlags {
+        match self {
+            MemoryAccess::Read => PTEFlags::R,
+            MemoryAccess::Write => PTEFlags::W,
+            MemoryAccess::Execute => PTEFlags::X,
+        }
+    }
+}
+
+/// Page of a file mapping to be read for a fault, outside of process lock as reading may sleep.
+pub struct PageRead {
+    vpn: VirtPageId,
+    file: Arc<dyn File>,
+    offset: usize,
+}
+
+impl PageRead {
+    /// Read the page from file. Bytes beyond end of file are left zero.
+    pub fn read(&self) -> Option<PhyPage> {
+        let page = PhyPage::alloc();
+        let buf = PhyAddr::from(page.id).get_u8_mut(PAGE_SIZE);
+        if self.file.read_at(self.offset, buf).is_err() {
+            warn!("Failed to read file for mapping at {}.", VirtAddr::from(self.vpn));
+            return None;
+        }
+        Some(page)
+    }
+}
+
+/// Dirty page of a shared file mapping, already made clean, to be written outside of process lock.
+pub struct PageWriteback {
+    vpn: VirtPageId,
+    page: Arc<PhyPage>,
+    file: Arc<dyn File>,
+    offset: usize,
+}
+
+impl PageWriteback {
+    pub fn write(&self) {
+        // Position of the mapping's file is not used by positioned I/O, seeking only gets the size.
+        // Mapping never extends the file, bytes beyond end of file are dropped.
+        let file_size = self.file.seek(0, SeekPosition::End).unwrap_or(0);
+        if self.offset < file_size {
+            let len = min(PAGE_SIZE, file_size - self.offset);
+            if self.file.write_at(self.offset, PhyAddr::from(self.page.id).get_u8(len)).is_err() {
+                warn!("Failed to write back mapping at {}.", VirtAddr::from(self.vpn));
+            }
+        }
+    }
+}
 
 pub struct ProcessMemory {
     page_table: PageTable,
-    // TODO: to make CoW, PhyPage could be shared. So Arc may be needed.
-    maps: BTreeMap<VirtPageId, (PhyPage, PTEFlags)>,
+    // PhyPage is shared between processes after fork (CoW).
+    // PTEFlags here is the permission of mapping, PTE may be read-only before CoW is resolved.
+    maps: BTreeMap<VirtPageId, (Arc<PhyPage>, PTEFlags)>,
+    // Regions of user space keyed by first page, pages inside are populated on first touch.
+    // Every page in maps lies in one of them.
+    vmas: BTreeMap<VirtPageId, Vma>,
+    // Pages made clean by writeback, written by whoever takes them after releasing process lock.
+    pending_writeback: Vec<PageWriteback>,
+    // File page the last failed translate missed, read by `Process::access_user` after releasing process lock.
+    missing_page: Option<PageRead>,
     // program binary end. brk should never goes below this
     pub prog_end: VirtAddr,
     // brk is not page aligned. Aligned value is real_brk.
@@ -53,16 +123,27 @@ impl ProcessMemory {
             VirtAddr::from(KERNEL_SPACE_BASE), PhyAddr::from(KERNEL_SPACE_BASE),
             PTEFlags::R | PTEFlags::W | PTEFlags::X | PTEFlags::G,
         );
-        Self {
+        let mut memory = Self {
             page_table,
             maps: BTreeMap::new(),
+            vmas: BTreeMap::new(),
+            pending_writeback: Vec::new(),
+            missing_page: None,
             prog_end: VirtAddr::from(0),
             min_brk: VirtAddr::from(0),
             brk: VirtAddr::from(0),
             stack_base: VirtAddr::from(PROCESS_USER_STACK_BASE),
             stack_top: VirtAddr::from(PROCESS_USER_STACK_BASE),
             mmap_base: VirtAddr::from(PROCESS_MMAP_BASE),
-        }
+        };
+        memory.add_stack_vma();
+        memory
+    }
+
+    fn add_stack_vma(&mut self) {
+        // Whole stack area except the signal trampoline page, grows on demand.
+        let stack_bottom = VirtPageId::from(VirtAddr::from(PROCESS_SIGNAL_TRAMPOLINE)) + 1;
+        self.add_vma(Vma::anonymous(stack_bottom, VirtPageId::from(self.stack_base), PTEFlags::U | PTEFlags::R | PTEFlags::W));
     }
 
     pub fn get_satp(&self) -> usize {
@@ -77,12 +158,15 @@ impl ProcessMemory {
         // info!("[satp {:x}] Map {} to {}",self.page_table.to_satp() ,VirtAddr::from(vpn), PhyAddr::from(page.id));
         // take page
         self.page_table.map(vpn.clone().into(), page.id.into(), flags.clone());
-        self.maps.insert(vpn, (page, flags));
+        self.maps.insert(vpn, (Arc::new(page), flags));
     }
 
     pub fn unmap(&mut self, vpn: VirtPageId) -> EmptyResult {
         if let Some(_) = self.maps.remove(&vpn) {
-            self.page_table.unmap(vpn.into());
+            // PROT_NONE page is resident without a valid PTE.
+            if self.page_table.find_pte(vpn).is_some_and(|pte| pte.valid()) {
+                self.page_table.unmap(vpn.into());
+            }
             Ok(())
         } else {
             Err("page is not mapped.".into())
@@ -94,29 +178,24 @@ impl ProcessMemory {
     }
 
     pub fn set_brk(&mut self, new_brk: VirtAddr) -> usize {
-        // brk is last not valid bytes
-        let mut real_brk = self.brk.to_offset(-1)/* last valid byte */.round_up();
-
+        // brk is first not valid byte, heap pages are [min_brk, round_up(brk))
         if new_brk < self.min_brk {
             // failure return old brk
-            // do nothing
-        } else {
-            let mut offset = new_brk.addr as isize - self.brk.addr as isize;
-            if offset == 0 {
-                // do nothing
-            } else if offset > 0 {
-                // FIXME: could overlap with mmap
-                while new_brk > real_brk {
-                    let page = PhyPage::alloc();
-                    self.map(VirtPageId::from(real_brk.to_offset(1isize)), page, PTEFlags::U | PTEFlags::W | PTEFlags::R);
-                    real_brk = real_brk.to_offset(PAGE_SIZE as isize);
-                    offset -= PAGE_SIZE as isize;
-                }
-            } else {
-                todo!();
+            return self.brk.addr;
+        }
+        let old_end = VirtPageId::from(self.brk.round_up());
+        let new_end = VirtPageId::from(new_brk.round_up());
+        if new_end > old_end {
+            if self.is_range_used(old_end, new_end) {
+                // Overlaps with mmap, failure return old brk
+                return self.brk.addr;
             }
-            self.brk = new_brk;
+            self.add_vma(Vma::anonymous(old_end, new_end, PTEFlags::U | PTEFlags::R | PTEFlags::W));
+            self.try_merge(old_end);
+        } else if new_end < old_end {
+            self.unmap_range(new_end, old_end.id - new_end.id);
         }
+        self.brk = new_brk;
         self.brk.addr
     }
 
@@ -128,9 +207,10 @@ impl ProcessMemory {
     }
 
     pub fn get_mapped_last_page(&self) -> VirtPageId {
-        let first_stack_vpn = VirtPageId::from(self.stack_top);
+        // Stack and signal trampoline are all above mmap_base
+        let first_reserved_vpn = VirtPageId::from(self.mmap_base);
         let end = self.maps.iter().filter_map(|(vpn, _)| {
-            if *vpn >= first_stack_vpn {
+            if *vpn >= first_reserved_vpn {
                 None
             } else {
                 Some(vpn)
@@ -139,90 +219,435 @@ impl ProcessMemory {
         end.cloned().unwrap_or(VirtPageId::from(0))
     }
 
-    pub fn copy_from(&mut self, other: &Self, copy_stack: bool) {
+    pub fn map_signal_trampoline(&mut self) {
+        // li a7, 139 (rt_sigreturn); ecall
+        const TRAMPOLINE_CODE: [u32; 2] = [0x08b0_0893, 0x0000_0073];
+        let page = PhyPage::alloc();
+        page.get_ref_mut::<[u32; 2]>().copy_from_slice(&TRAMPOLINE_CODE);
+        let vpn = VirtPageId::from(self.signal_trampoline());
+        let flags = PTEFlags::U | PTEFlags::R | PTEFlags::X;
+        self.map(vpn, page, flags);
+        self.add_vma(Vma::anonymous(vpn, vpn + 1, flags));
+    }
+
+    pub fn signal_trampoline(&self) -> VirtAddr {
+        VirtAddr::from(PROCESS_SIGNAL_TRAMPOLINE)
+    }
+
+    pub fn copy_from(&mut self, other: &mut Self, copy_stack: bool) {
         self.stack_top = other.stack_top;
         self.stack_base = other.stack_base;
         self.brk = other.brk;
+        self.min_brk = other.min_brk;
         self.prog_end = other.prog_end;
+        self.vmas = other.vmas.clone();
 
+        // Pages are shared and write-protected on both sides, copied when written (CoW).
+        // Pages of shared mappings are just shared, keeping their dirty state.
+        let mut downgraded = false;
         for (vpn, (page, flags)) in &other.maps {
-            let child_page = PhyPage::alloc();
-            child_page.copy_u8(0, PhyAddr::from(page.id).get_u8(PAGE_SIZE));
-            self.map(vpn.clone(), child_page, flags.clone());
+            let mut pte_flags = flags.clone();
+            if !Self::is_accessible(*flags) {
+                // PROT_NONE page has no PTE.
+                self.maps.insert(vpn.clone(), (page.clone(), flags.clone()));
+                continue;
+            }
+            if self.find_vma(*vpn).is_some_and(|vma| vma.shared) {
+                if !other.page_table.find_pte(*vpn).is_some_and(|pte| pte.writable()) {
+                    pte_flags.remove(PTEFlags::W);
+                }
+            } else if flags.contains(PTEFlags::W) {
+                pte_flags.remove(PTEFlags::W);
+                other.page_table.remap(vpn.clone().into(), page.id.into(), pte_flags);
+                downgraded = true;
+            }
+            self.page_table.map(vpn.clone().into(), page.id.into(), pte_flags);
+            self.maps.insert(vpn.clone(), (page.clone(), flags.clone()));
+        }
+        // Other threads of parent must stop writing to pages shared with child now.
+        if downgraded {
+            other.flush_tlb(VirtPageId::from(0), VirtPageId::from(VirtAddr::from(KERNEL_SPACE_BASE)));
         }
     }
 
-    fn check_collapse(&self, start_vpn: VirtPageId, pages: usize, is_increasing: bool) -> bool {
-        let range = if is_increasing {
-            start_vpn.id..(start_vpn.id + pages)
+    /// Resolve a write to CoW page. Return false if vaddr is not a CoW page.
+    pub fn resolve_cow(&mut self, vaddr: VirtAddr) -> bool {
+        let vpn = VirtPageId::from(vaddr);
+        let (page, flags) = if let Some(v) = self.maps.get_mut(&vpn) {
+            v
         } else {
-            (start_vpn.id - pages + 1)..(start_vpn.id + 1)
+            return false;
         };
-        for vpn in range {
-            let vpn = VirtPageId::from(vpn);
-            if self.maps.contains_key(&vpn) {
-                return true;
+        if !flags.contains(PTEFlags::W) {
+            return false;
+        }
+        if let Some(pte) = self.page_table.find_pte(vpn) && pte.writable() {
+            return false;
+        }
+        let copied = Arc::strong_count(page) != 1;
+        if copied {
+            // Still shared with others, make our own copy.
+            let new_page = PhyPage::alloc();
+            new_page.copy_u8(0, PhyAddr::from(page.id).get_u8(PAGE_SIZE));
+            *page = Arc::new(new_page);
+        }
+        // Else we are the last one holding it, just take it back.
+        self.page_table.remap(vpn.into(), page.id.into(), flags.clone());
+        if copied {
+            // Other threads must not keep reading the old page.
+            self.flush_tlb(vpn, vpn + 1);
+        }
+        true
+    }
+
+    /// Flush translations of [start, end) after PTEs in it are downgraded, moved or removed.
+    /// Threads sharing this address space may be running on other CPUs, they are flushed as well.
+    fn flush_tlb(&self, start: VirtPageId, end: VirtPageId) {
+        shootdown_page_table(VirtAddr::from(start), (end.id - start.id) * PAGE_SIZE);
+    }
+
+    fn find_vma(&self, vpn: VirtPageId) -> Option<&Vma> {
+        self.vmas.range(..=vpn).next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| vma.contains(vpn))
+    }
+
+    /// Check if any page in [start, end) belongs to a VMA.
+    pub fn is_range_used(&self, start: VirtPageId, end: VirtPageId) -> bool {
+        // VMAs are not overlapped, so the last one starts before end is enough.
+        self.vmas.range(..end).next_back().is_some_and(|(_, vma)| vma.end > start)
+    }
+
+    /// All regions of user space in address order.
+    pub fn vmas(&self) -> impl Iterator<Item=&Vma> {
+        self.vmas.values()
+    }
+
+    /// Number of pages resident in memory.
+    pub fn resident_pages(&self) -> usize {
+        self.maps.len()
+    }
+
+    pub fn add_vma(&mut self, vma: Vma) {
+        assert!(!self.is_range_used(vma.start, vma.end), "VMA overlapped.");
+        self.vmas.insert(vma.start, vma);
+    }
+
+    /// Cover pages of a loaded ELF segment. Page shared with previous segment stays in its VMA.
+    pub fn add_segment_vma(&mut self, start: VirtPageId, end: VirtPageId, flags: PTEFlags) {
+        let start = match self.find_vma(start) {
+            Some(vma) => vma.end,
+            None => start
+        };
+        if start < end {
+            self.add_vma(Vma::anonymous(start, end, flags));
+            self.try_merge(start);
+        }
+    }
+
+    /// Make vpn a boundary of VMAs.
+    fn split_vma_at(&mut self, vpn: VirtPageId) {
+        let start = match self.vmas.range(..vpn).next_back() {
+            Some((start, vma)) if vma.end > vpn => *start,
+            _ => return
+        };
+        let upper = self.vmas.get_mut(&start).unwrap().split_off(vpn);
+        self.vmas.insert(vpn, upper);
+    }
+
+    /// Merge VMAs meeting at vpn if they are alike.
+    fn try_merge(&mut self, vpn: VirtPageId) {
+        let lower_start = match self.vmas.range(..vpn).next_back() {
+            Some((start, vma)) if vma.end == vpn => *start,
+            _ => return
+        };
+        let mergeable = match self.vmas.get(&vpn) {
+            Some(upper) => self.vmas[&lower_start].can_merge(upper),
+            None => false
+        };
+        if mergeable {
+            let upper = self.vmas.remove(&vpn).unwrap();
+            self.vmas.get_mut(&lower_start).unwrap().end = upper.end;
+        }
+    }
+
+    /// Find free pages between brk and mmap_base, from top to bottom.
+    fn find_free_area(&self, pages: usize) -> Option<VirtPageId> {
+        let bottom = VirtPageId::from(self.brk.round_up());
+        let mut top = VirtPageId::from(self.mmap_base);
+        for vma in self.vmas.values().rev().filter(|vma| vma.start < top) {
+            if vma.end <= top && top.id - vma.end.id >= pages {
+                break;
             }
+            top = vma.start;
+        }
+        if top.id >= bottom.id + pages {
+            Some(top - pages)
+        } else {
+            None
         }
-        false
     }
 
-    pub fn mmap(&mut self, addr: Option<VirtAddr>, pages: usize, flags: PTEFlags) -> Result<VirtAddr> {
-        if let Some(first_vpn) = addr.map(|addr| VirtPageId::from(addr)) {
-            for vpn in first_vpn.id..first_vpn.id + pages {
-                let vpn = VirtPageId::from(vpn);
-                if self.maps.contains_key(&vpn) {
-                    // drop old
-                    self.unmap(vpn).unwrap();
-                }
-                let page = PhyPage::alloc();
-                self.map(vpn, page, flags);
+    /// Reserve pages for mapping, they are populated on first touch.
+    /// Pages of shared anonymous mapping are allocated right away, so that children share all of them.
+    pub fn mmap(&mut self, addr: Option<VirtAddr>, pages: usize, flags: PTEFlags, backing: VmaBacking, shared: bool) -> Result<VirtAddr> {
+        let start = if let Some(addr) = addr {
+            // Overlapped mappings are dropped.
+            let start = VirtPageId::from(addr);
+            self.unmap_range(start, pages);
+            start
+        } else {
+            self.find_free_area(pages).ok_or("no enough memory for mmap")?
+        };
+        let anonymous = matches!(backing, VmaBacking::Anonymous);
+        self.add_vma(Vma::new(start, start + pages, flags, backing, shared));
+        if shared && anonymous {
+            for i in 0..pages {
+                self.populate(start + i);
+            }
+        }
+        Ok(start.into())
+    }
+
+    /// Drop mappings in [start, start + pages), VMAs are split if partially covered.
+    pub fn unmap_range(&mut self, start: VirtPageId, pages: usize) {
+        let end = start + pages;
+        self.writeback(start, end);
+        self.split_vma_at(start);
+        self.split_vma_at(end);
+        let vma_starts = self.vmas.range(start..end).map(|(vpn, _)| *vpn).collect::<Vec<_>>();
+        for vpn in vma_starts {
+            self.vmas.remove(&vpn);
+        }
+        let resident = self.maps.range(start..end).map(|(vpn, _)| *vpn).collect::<Vec<_>>();
+        if !resident.is_empty() {
+            for vpn in resident {
+                self.unmap(vpn).unwrap();
             }
-            Ok(first_vpn.into())
+            self.flush_tlb(start, end);
+        }
+    }
+
+    /// Allocate and map a zeroed page of vpn in anonymous VMA.
+    /// File page is never read here with process locked, see `Process::handle_page_fault`.
+    fn populate(&mut self, vpn: VirtPageId) -> bool {
+        if self.page_to_read(vpn).is_some() {
+            return false;
+        }
+        self.map_populated(vpn, PhyPage::alloc())
+    }
+
+    fn page_to_read(&self, vpn: VirtPageId) -> Option<PageRead> {
+        let vma = self.find_vma(vpn)?;
+        if let VmaBacking::File { file, .. } = &vma.backing {
+            Some(PageRead { vpn, file: file.clone(), offset: vma.file_offset(vpn).unwrap() })
         } else {
-            // addr is not fixed, we alloc this.
-            let first_page = {
-                let mmap_base_vpn = VirtPageId::from(self.mmap_base) - 1;
-                if (!self.maps.contains_key(&mmap_base_vpn)) &&
-                    (!self.check_collapse(mmap_base_vpn, pages, false)) {
-                    Some(mmap_base_vpn - pages + 1)
+            None
+        }
+    }
+
+    /// Map a newly filled page of vpn according to its VMA.
+    fn map_populated(&mut self, vpn: VirtPageId, page: PhyPage) -> bool {
+        let vma = if let Some(vma) = self.find_vma(vpn) {
+            vma
+        } else {
+            return false;
+        };
+        // Shared file page is mapped clean, it becomes writable when first written.
+        let pte_flags = if vma.is_shared_file() { vma.flags - PTEFlags::W } else { vma.flags };
+        let flags = vma.flags;
+        // PROT_NONE page has no PTE.
+        if Self::is_accessible(flags) {
+            self.page_table.map(vpn.into(), page.id.into(), pte_flags);
+        }
+        self.maps.insert(vpn, (Arc::new(page), flags));
+        true
+    }
+
+    /// File page to read for an allowed access at vaddr, None if page is resident or not file backed.
+    pub fn fault_page_to_read(&self, vaddr: VirtAddr, access: MemoryAccess) -> Option<PageRead> {
+        let vpn = VirtPageId::from(vaddr);
+        if self.is_mapped(&vpn) || !self.find_vma(vpn)?.flags.contains(access.required_flag()) {
+            return None;
+        }
+        self.page_to_read(vpn)
+    }
+
+    /// Map page read for a fault. Return false if the mapping has changed since, page is dropped then.
+    pub fn install_read_page(&mut self, read: &PageRead, page: PhyPage) -> bool {
+        if self.is_mapped(&read.vpn) {
+            // Populated by another thread meanwhile.
+            return true;
+        }
+        let unchanged = self.find_vma(read.vpn).is_some_and(|vma| match &vma.backing {
+            VmaBacking::File { file, .. } => Arc::ptr_eq(file, &read.file) && vma.file_offset(read.vpn) == Some(read.offset),
+            VmaBacking::Anonymous => false
+        });
+        unchanged && self.map_populated(read.vpn, page)
+    }
+
+    /// Mark resident page of shared file mapping dirty by making it writable.
+    fn mark_dirty(&mut self, vpn: VirtPageId) -> bool {
+        let (page, flags) = &self.maps[&vpn];
+        if !flags.contains(PTEFlags::W) {
+            return false;
+        }
+        self.page_table.remap(vpn.into(), page.id.into(), flags.clone());
+        true
+    }
+
+    /// Make dirty pages of shared file mappings in [start, end) clean, queue them for writing back.
+    fn writeback(&mut self, start: VirtPageId, end: VirtPageId) {
+        let dirty = self.maps.range(start..end)
+            .filter(|(vpn, _)| self.page_table.find_pte(**vpn).is_some_and(|pte| pte.valid() && pte.writable()))
+            .filter_map(|(vpn, (page, flags))| {
+                let vma = self.find_vma(*vpn).filter(|vma| vma.is_shared_file())?;
+                if let VmaBacking::File { file, .. } = &vma.backing {
+                    Some((*flags, PageWriteback {
+                        vpn: *vpn,
+                        page: page.clone(),
+                        file: file.clone(),
+                        offset: vma.file_offset(*vpn).unwrap(),
+                    }))
                 } else {
-                    let brk_page = VirtPageId::from(self.brk.to_offset(-1).round_up());
-                    self.maps.keys()
-                        .filter_map(|mapped_vpn| {
-                            if mapped_vpn >= &VirtPageId::from(self.mmap_base) || mapped_vpn <= &brk_page {
-                                None
-                            } else {
-                                let next = mapped_vpn.clone() - 1;
-                                if self.maps.contains_key(&next) {
-                                    None
-                                } else {
-                                    Some(next)
-                                }
-                            }
-                        })
-                        .find_map(|first_not_mapped_vpn| {
-                            if !self.check_collapse(first_not_mapped_vpn, pages, false) {
-                                Some(first_not_mapped_vpn - pages + 1)
-                            } else {
-                                None
-                            }
-                        })
+                    None
                 }
+            })
+            .collect::<Vec<_>>();
+        if dirty.is_empty() {
+            return;
+        }
+        for (flags, writeback) in dirty {
+            // Written again if it gets dirty before being written.
+            self.page_table.remap(writeback.vpn.into(), writeback.page.id.into(), flags - PTEFlags::W);
+            self.pending_writeback.push(writeback);
+        }
+        // Writes from now on must fault to mark the page dirty again.
+        self.flush_tlb(start, end);
+    }
+
+    /// Queue every dirty page of shared file mappings for writing back.
+    pub fn writeback_all(&mut self) {
+        self.writeback(VirtPageId::from(0), VirtPageId::from(VirtAddr::from(KERNEL_SPACE_BASE)));
+    }
+
+    /// Take pages queued by writeback, caller writes them after releasing process lock.
+    pub fn take_writeback(&mut self) -> Vec<PageWriteback> {
+        core::mem::take(&mut self.pending_writeback)
+    }
+
+    /// Take file page the last failed translate missed, caller reads it after releasing process lock.
+    pub fn take_missing_page(&mut self) -> Option<PageRead> {
+        self.missing_page.take()
+    }
+
+    fn is_accessible(flags: PTEFlags) -> bool {
+        flags.intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X)
+    }
+
+    /// Check if every page in [start, end) belongs to a VMA.
+    fn is_range_covered(&self, start: VirtPageId, end: VirtPageId) -> bool {
+        let mut cur = start;
+        while cur < end {
+            match self.find_vma(cur) {
+                Some(vma) => cur = vma.end,
+                None => return false
+            }
+        }
+        true
+    }
+
+    /// Set PTE of a resident page, PTE is cleared if page is not accessible.
+    fn set_pte_flags(&mut self, vpn: VirtPageId, pte_flags: PTEFlags) {
+        let page_id = self.maps[&vpn].0.id;
+        let valid = self.page_table.find_pte(vpn).is_some_and(|pte| pte.valid());
+        match (valid, Self::is_accessible(pte_flags)) {
+            (true, true) => self.page_table.remap(vpn.into(), page_id.into(), pte_flags),
+            (true, false) => self.page_table.unmap(vpn.into()),
+            (false, true) => self.page_table.map(vpn.into(), page_id.into(), pte_flags),
+            (false, false) => {}
+        }
+    }
+
+    /// Change permission of pages in [start, start + pages), VMAs are split or merged as needed.
+    /// Return false if some pages in range are not mapped.
+    pub fn mprotect(&mut self, start: VirtPageId, pages: usize, flags: PTEFlags) -> bool {
+        let end = start + pages;
+        if !self.is_range_covered(start, end) {
+            return false;
+        }
+        // Dirty shared pages are written back first, all of them are clean afterwards.
+        self.writeback(start, end);
+        self.split_vma_at(start);
+        self.split_vma_at(end);
+        let vma_starts = self.vmas.range(start..end).map(|(vpn, _)| *vpn).collect::<Vec<_>>();
+        for vpn in vma_starts.iter() {
+            self.vmas.get_mut(vpn).unwrap().flags = flags;
+        }
+
+        let resident = self.maps.range(start..end).map(|(vpn, _)| *vpn).collect::<Vec<_>>();
+        let changed = !resident.is_empty();
+        for vpn in resident {
+            let shared_file = self.find_vma(vpn).unwrap().is_shared_file();
+            let (page, page_flags) = self.maps.get_mut(&vpn).unwrap();
+            *page_flags = flags;
+            // Write is granted on fault for clean shared file page and CoW page.
+            let pte_flags = if shared_file || Arc::strong_count(page) != 1 {
+                flags - PTEFlags::W
+            } else {
+                flags
             };
+            self.set_pte_flags(vpn, pte_flags);
+        }
+        if changed {
+            self.flush_tlb(start, end);
+        }
 
+        for vpn in vma_starts {
+            self.try_merge(vpn);
+        }
+        self.try_merge(end);
+        true
+    }
 
-            if let Some(first_not_mapped_vpn) = first_page {
-                for vpn in first_not_mapped_vpn.id..first_not_mapped_vpn.id + pages {
-                    let vpn = VirtPageId::from(vpn);
-                    let page = PhyPage::alloc();
-                    self.map(vpn, page, flags);
-                }
-                Ok(first_not_mapped_vpn.into())
+    /// Queue shared file pages in [start, start + pages) for writing back.
+    /// Return false if some pages in range are not mapped.
+    pub fn msync(&mut self, start: VirtPageId, pages: usize) -> bool {
+        let end = start + pages;
+        if !self.is_range_covered(start, end) {
+            return false;
+        }
+        self.writeback(start, end);
+        true
+    }
+
+    /// Handle page fault from user space or kernel accessing user memory.
+    /// Return false if the access is not allowed.
+    pub fn handle_page_fault(&mut self, vaddr: VirtAddr, access: MemoryAccess) -> bool {
+        let vpn = VirtPageId::from(vaddr);
+        let (vma_flags, shared) = if let Some(vma) = self.find_vma(vpn) {
+            (vma.flags, vma.shared)
+        } else {
+            return false;
+        };
+        if !vma_flags.contains(access.required_flag()) {
+            return false;
+        }
+        if self.page_table.find_pte(vpn).is_some_and(|pte| pte.valid() && pte.flags().contains(access.required_flag())) {
+            // Handled by another thread, this CPU had a stale translation, which trap entry flushed.
+            return true;
+        }
+        if self.is_mapped(&vpn) {
+            // Resident page only faults on CoW or first write to shared file page.
+            if access != MemoryAccess::Write {
+                false
+            } else if shared {
+                self.mark_dirty(vpn)
             } else {
-                // no mem
-                Err("no enough memory for mmap".into())
+                self.resolve_cow(vaddr)
             }
+        } else {
+            self.populate(vpn)
         }
     }
 
@@ -233,41 +658,125 @@ impl ProcessMemory {
             VirtAddr::from(KERNEL_SPACE_BASE), PhyAddr::from(KERNEL_SPACE_BASE),
             PTEFlags::R | PTEFlags::W | PTEFlags::X | PTEFlags::G,
         );
+        self.writeback_all();
         self.maps.clear();
+        self.vmas.clear();
 
         self.prog_end = VirtAddr::from(0);
         self.brk = VirtAddr::from(0);
+        self.min_brk = VirtAddr::from(0);
         self.stack_base = VirtAddr::from(PROCESS_USER_STACK_BASE);
         self.stack_top = VirtAddr::from(PROCESS_USER_STACK_BASE);
         self.mmap_base = VirtAddr::from(PROCESS_MMAP_BASE);
 
         self.page_table = page_table;
+        self.add_stack_vma();
     }
 
-    pub fn alloc_stack_if_possible(&mut self, vaddr: VirtAddr) -> bool {
-        // if is stack overflow, then try to allocate new stack
-        // if user-prog requires too large stack size and new access is beyond next un-allocated page
-        // we do not consider it as a stack overflow
-        let addr_page = VirtPageId::from(vaddr);
-        if addr_page == VirtPageId::from(self.stack_top.to_offset(-1))
-            && !self.is_mapped(&addr_page) {
-            // is new unallocated stack
-            self.increase_user_stack();
-            true
+    /// Translate a user address that kernel is going to access.
+    /// Page is populated or CoW is resolved first, like user accessing it.
+    /// Non-resident file page is left for `Process::access_user` to read, None is returned for now.
+    pub fn translate(&mut self, vaddr: VirtAddr, access: MemoryAccess) -> Option<PhyAddr> {
+        if vaddr.get_addr() >= KERNEL_SPACE_BASE {
+            return None;
+        }
+        if let Some(read) = self.fault_page_to_read(vaddr, access) {
+            self.missing_page = Some(read);
+            return None;
+        }
+        if self.user_accessible(vaddr, access)
+            || (self.handle_page_fault(vaddr, access) && self.user_accessible(vaddr, access)) {
+            vaddr.into_pa(&self.page_table)
         } else {
-            false
+            None
         }
     }
 
-    pub fn translate_with_stack_alloc(&mut self, vaddr: VirtAddr) -> Option<PhyAddr> {
-        if let Some(paddr) = vaddr.into_pa(&self.page_table) {
-            return Some(paddr);
+    /// Whether the page at `vaddr` is mapped for user mode with permissions of `access`.
+    fn user_accessible(&self, vaddr: VirtAddr, access: MemoryAccess) -> bool {
+        let required = access.required_flag() | PTEFlags::U;
+        self.page_table.find_pte(VirtPageId::from(vaddr))
+            .is_some_and(|pte| pte.valid() && pte.flags().contains(required))
+    }
+
+    /// Translate a user address that kernel is going to write to.
+    pub fn translate_for_write(&mut self, vaddr: VirtAddr) -> Option<PhyAddr> {
+        self.translate(vaddr, MemoryAccess::Write)
+    }
+
+    /// Copy data to user space, could cross pages. Pages are populated if needed.
+    pub fn copy_to_user(&mut self, vaddr: VirtAddr, data: &[u8]) -> EmptyResult {
+        // Page by page, from high address to low.
+        let mut end = data.len();
+        while end > 0 {
+            let cur = vaddr.to_offset((end - 1) as isize);
+            let begin = max(cur.round_down().get_addr(), vaddr.get_addr()) - vaddr.get_addr();
+            let pa = self.translate_for_write(vaddr.to_offset(begin as isize))
+                .ok_or("user address is not writable.")?;
+            pa.get_u8_mut(end - begin).copy_from_slice(&data[begin..end]);
+            end = begin;
         }
+        Ok(())
+    }
 
-        if self.alloc_stack_if_possible(vaddr) {
-            Some(vaddr.into_pa(&self.page_table).unwrap())
-        } else {
-            None
+    /// Copy data from user space, could cross pages.
+    pub fn copy_from_user(&mut self, vaddr: VirtAddr, buf: &mut [u8]) -> EmptyResult {
+        let mut begin = 0;
+        while begin < buf.len() {
+            let cur = vaddr.to_offset(begin as isize);
+            let end = min(cur.to_offset(1).round_up().get_addr() - vaddr.get_addr(), buf.len());
+            let pa = self.translate(cur, MemoryAccess::Read).ok_or("user address is not readable.")?;
+            buf[begin..end].copy_from_slice(pa.get_u8(end - begin));
+            begin = end;
+        }
+        Ok(())
+    }
+
+    /// Copy a NUL terminated string from user space, at most `max_len` bytes without the NUL.
+    /// Pages are populated if needed.
+    pub fn copy_cstr_from_user(&mut self, vaddr: VirtAddr, max_len: usize) -> Result<String> {
+        let mut bytes = Vec::new();
+        let mut cur = vaddr;
+        loop {
+            let page_end = cur.to_offset(1).round_up();
+            let pa = self.translate(cur, MemoryAccess::Read).ok_or("user address is not readable.")?;
+            let chunk = pa.get_u8(page_end.get_addr() - cur.get_addr());
+            let nul = chunk.iter().position(|b| *b == 0);
+            bytes.extend_from_slice(&chunk[..nul.unwrap_or(chunk.len())]);
+            if bytes.len() > max_len {
+                return Err("user string is too long.".into());
+            }
+            if nul.is_some() {
+                break;
+            }
+            cur = page_end;
+        }
+        String::from_utf8(bytes).map_err(|_| "user string is not UTF-8.".into())
+    }
+
+    pub fn write_user<T: Copy>(&mut self, vaddr: VirtAddr, value: &T) -> EmptyResult {
+        let data = unsafe {
+            core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>())
+        };
+        self.copy_to_user(vaddr, data)
+    }
+
+    pub fn read_user<T: Copy>(&mut self, vaddr: VirtAddr) -> Result<T> {
+        let mut value = MaybeUninit::<T>::uninit();
+        let buf = unsafe {
+            core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>())
+        };
+        self.copy_from_user(vaddr, buf)?;
+        Ok(unsafe { value.assume_init() })
+    }
+}
+
+impl Drop for ProcessMemory {
+    fn drop(&mut self) {
+        // Shared file mappings are written back on exit.
+        self.writeback_all();
+        for page in self.take_writeback() {
+            page.write();
         }
     }
 }
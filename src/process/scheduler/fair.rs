@@ -0,0 +1,129 @@
+//! # Fair class
+//!
+//! CFS-like scheduling of normal, batch and idle threads. Running time is weighted by nice value
+//! into virtual runtime, thread with the smallest one runs next.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::time::Duration;
+use crate::process::scheduler::{MIN_NICE, SchedClass, SchedEntity, SchedPolicy};
+use crate::process::Thread;
+
+// Same as Linux sched_prio_to_weight, nice -20 to 19. Each step is about 10% of CPU time.
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291,
+    29154, 23254, 18705, 14949, 11916,
+    9548, 7620, 6100, 4904, 3906,
+    3121, 2501, 1991, 1586, 1277,
+    1024, 820, 655, 526, 423,
+    335, 272, 215, 172, 137,
+    110, 87, 70, 56, 45,
+    36, 29, 23, 18, 15,
+];
+const NICE_0_WEIGHT: u64 = 1024;
+const IDLE_WEIGHT: u64 = 3;
+
+// Period in which every runnable thread runs once.
+const SCHED_LATENCY: Duration = Duration::from_millis(24);
+const MIN_GRANULARITY: Duration = Duration::from_millis(3);
+
+fn weight(entity: &SchedEntity) -> u64 {
+    if entity.policy == SchedPolicy::Idle {
+        IDLE_WEIGHT
+    } else {
+        NICE_TO_WEIGHT[(entity.nice - MIN_NICE) as usize]
+    }
+}
+
+fn weighted_ns(time: Duration, weight: u64) -> u64 {
+    (time.as_nanos() as u64).saturating_mul(NICE_0_WEIGHT) / weight
+}
+
+pub struct FairClass {
+    // Keyed by (vruntime, tid), value with its weight.
+    queue: BTreeMap<(u64, usize), (Arc<Thread>, u64)>,
+    total_weight: u64,
+    // Never goes back, woken threads are placed around it.
+    min_vruntime: u64,
+}
+
+impl FairClass {
+    pub fn new() -> Self {
+        Self {
+            queue: BTreeMap::new(),
+            total_weight: 0,
+            min_vruntime: 0,
+        }
+    }
+
+    // Share of the latency period for thread of `weight`.
+    fn ideal_runtime(&self, weight: u64) -> Duration {
+        let share = SCHED_LATENCY.as_nanos() as u64 * weight / (self.total_weight + weight);
+        Duration::from_nanos(share).max(MIN_GRANULARITY)
+    }
+}
+
+impl SchedClass for FairClass {
+    fn enqueue(&mut self, thread: Arc<Thread>, entity: &mut SchedEntity) {
+        // Sleeper gets a little credit, not all the time it slept.
+        let floor = self.min_vruntime.saturating_sub(SCHED_LATENCY.as_nanos() as u64 / 2);
+        entity.vruntime = entity.vruntime.max(floor);
+        let weight = weight(entity);
+        self.total_weight += weight;
+        self.queue.insert((entity.vruntime, thread.tid()), (thread, weight));
+    }
+
+    fn dequeue(&mut self, tid: usize, entity: &SchedEntity) -> Option<Arc<Thread>> {
+        let (thread, weight) = self.queue.remove(&(entity.vruntime, tid))?;
+        self.total_weight -= weight;
+        Some(thread)
+    }
+
+    fn pick_next(&mut self) -> Option<Arc<Thread>> {
+        let ((vruntime, _), (thread, weight)) = self.queue.pop_first()?;
+        self.total_weight -= weight;
+        self.min_vruntime = self.min_vruntime.max(vruntime);
+        Some(thread)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    // Those which would run last.
+    fn steal_candidates(&self) -> Vec<Arc<Thread>> {
+        self.queue.values().rev().map(|(thread, _)| thread.clone()).collect()
+    }
+
+    // Queues of CPUs have their own min_vruntime, keep the lead or lag only.
+    fn migrate_out(&self, entity: &mut SchedEntity) {
+        entity.vruntime = entity.vruntime.saturating_sub(self.min_vruntime);
+    }
+
+    fn migrate_in(&self, entity: &mut SchedEntity) {
+        entity.vruntime += self.min_vruntime;
+    }
+
+    fn put_prev(&mut self, entity: &mut SchedEntity, ran: Duration) {
+        entity.vruntime += weighted_ns(ran, weight(entity));
+    }
+
+    fn check_preempt_tick(&self, entity: &mut SchedEntity, ran: Duration) -> bool {
+        let Some(((leftmost, _), _)) = self.queue.first_key_value() else { return false };
+        let ideal = self.ideal_runtime(weight(entity));
+        if ran > ideal {
+            return true;
+        }
+        // Ran a bit, and gone too far ahead of the one waiting.
+        let vruntime = entity.vruntime + weighted_ns(ran, weight(entity));
+        ran > MIN_GRANULARITY && vruntime > leftmost + ideal.as_nanos() as u64
+    }
+}
@@ -0,0 +1,415 @@
+//! # Scheduler
+//!
+//! Per-CPU run queues holding Ready threads only, split into scheduling classes. Threads of
+//! real-time class always run before those of fair class. Woken threads go to the least loaded
+//! CPU they are allowed on, idle CPUs pull waiting threads from the busiest one and every tick
+//! evens out the load.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+mod fair;
+mod rt;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+use crate::core::{Intrlock, IntrlockGuard};
+use crate::cpu::CPU;
+use crate::device::timer;
+use crate::process::{ProcessStatus, Thread, ThreadData};
+use fair::FairClass;
+use rt::RtClass;
+use sbi::hart_mask::HartMask;
+
+pub use rt::RR_TIMESLICE;
+
+pub const MIN_RT_PRIORITY: usize = 1;
+pub const MAX_RT_PRIORITY: usize = 99;
+pub const MIN_NICE: isize = -20;
+pub const MAX_NICE: isize = 19;
+// Affinity is a bit mask of CPU ids.
+pub const MAX_CPUS: usize = usize::BITS as usize;
+
+// Values are those of Linux SCHED_* constants.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SchedPolicy {
+    Normal = 0,
+    Fifo = 1,
+    RoundRobin = 2,
+    Batch = 3,
+    Idle = 5,
+}
+
+impl SchedPolicy {
+    pub fn is_realtime(self) -> bool {
+        matches!(self, SchedPolicy::Fifo | SchedPolicy::RoundRobin)
+    }
+}
+
+
+/// Scheduling state of a thread, lives in `ThreadData` and changes under its lock.
+#[derive(Clone)]
+pub struct SchedEntity {
+    pub policy: SchedPolicy,
+    pub nice: isize,
+    // 1 to 99 for real-time policies, 0 otherwise.
+    pub rt_priority: usize,
+    // Children start with normal policy.
+    pub reset_on_fork: bool,
+    // Total time spent running.
+    pub sum_exec_runtime: Duration,
+    // Weighted running time in ns, fair class runs the smallest first.
+    vruntime: u64,
+    // Used part of round robin time slice.
+    slice_used: Duration,
+    // Put back to head of its queue, given away CPU to a higher priority only.
+    preempted: bool,
+    // Running on some CPU or not switched out yet, enqueued by worker after switched out then.
+    on_cpu: bool,
+    exec_start: Duration,
+    // Bit i set if allowed to run on CPU i.
+    pub affinity: usize,
+    // CPU whose queue holds the thread, or where it ran last.
+    pub cpu: usize,
+}
+
+impl SchedEntity {
+    pub fn new() -> Self {
+        Self {
+            policy: SchedPolicy::Normal,
+            nice: 0,
+            rt_priority: 0,
+            reset_on_fork: false,
+            sum_exec_runtime: Duration::ZERO,
+            vruntime: 0,
+            slice_used: Duration::ZERO,
+            preempted: false,
+            on_cpu: false,
+            exec_start: Duration::ZERO,
+            affinity: usize::MAX,
+            cpu: 0,
+        }
+    }
+
+    /// Entity of new thread created by this one.
+    pub fn fork(&self) -> Self {
+        let mut entity = Self::new();
+        if self.reset_on_fork {
+            entity.nice = self.nice.max(0);
+        } else {
+            entity.policy = self.policy;
+            entity.nice = self.nice;
+            entity.rt_priority = self.rt_priority;
+        }
+        entity.affinity = self.affinity;
+        entity.cpu = self.cpu;
+        entity
+    }
+
+    pub fn allowed_on(&self, cpu: usize) -> bool {
+        cpu < MAX_CPUS && self.affinity & (1 << cpu) != 0
+    }
+
+    /// Priority shown in /proc/[pid]/stat, negative for real-time threads.
+    pub fn proc_priority(&self) -> isize {
+        if self.policy.is_realtime() {
+            -1 - self.rt_priority as isize
+        } else {
+            20 + self.nice
+        }
+    }
+}
+
+/// Scheduling class, owns the queue of its Ready threads.
+trait SchedClass {
+    fn enqueue(&mut self, thread: Arc<Thread>, entity: &mut SchedEntity);
+    fn dequeue(&mut self, tid: usize, entity: &SchedEntity) -> Option<Arc<Thread>>;
+    fn pick_next(&mut self) -> Option<Arc<Thread>>;
+    fn is_empty(&self) -> bool;
+    fn len(&self) -> usize;
+    /// Queued threads in the order other CPUs should take them.
+    fn steal_candidates(&self) -> Vec<Arc<Thread>>;
+    /// Make state of a thread leaving for another CPU relative to this queue.
+    fn migrate_out(&self, entity: &mut SchedEntity) {}
+    /// Make state of a thread coming from another CPU absolute to this queue.
+    fn migrate_in(&self, entity: &mut SchedEntity) {}
+    /// Charge time it ran to a thread being switched out.
+    fn put_prev(&mut self, entity: &mut SchedEntity, ran: Duration);
+    /// Whether the running thread should give CPU away, checked on every tick.
+    fn check_preempt_tick(&self, entity: &mut SchedEntity, ran: Duration) -> bool;
+}
+
+struct Classes {
+    rt: RtClass,
+    fair: FairClass,
+}
+
+impl Classes {
+    fn class(&mut self, policy: SchedPolicy) -> &mut dyn SchedClass {
+        if policy.is_realtime() {
+            &mut self.rt
+        } else {
+            &mut self.fair
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.rt.len() + self.fair.len()
+    }
+}
+
+/// Run queue of a CPU, lives in `CPU`.
+pub struct RunQueue {
+    // Interrupt handlers wake threads up. Lock order: thread data -> run queue.
+    classes: Intrlock<Classes>,
+    // Number of queued threads, read by other CPUs without taking the lock.
+    queued: AtomicUsize,
+    // Running a thread rather than looking for one.
+    busy: AtomicBool,
+}
+
+struct RunQueueGuard<'a> {
+    rq: &'a RunQueue,
+    classes: IntrlockGuard<'a, Classes>,
+}
+
+impl RunQueue {
+    pub fn new() -> Self {
+        Self {
+            classes: Intrlock::new(Classes {
+                rt: RtClass::new(),
+                fair: FairClass::new(),
+            }),
+            queued: AtomicUsize::new(0),
+            busy: AtomicBool::new(false),
+        }
+    }
+
+    fn lock(&self) -> RunQueueGuard {
+        RunQueueGuard {
+            rq: self,
+            classes: self.classes.lock(),
+        }
+    }
+
+    // Queued threads and the running one.
+    fn load(&self) -> usize {
+        self.queued.load(Ordering::Relaxed) + self.busy.load(Ordering::Relaxed) as usize
+    }
+}
+
+impl Deref for RunQueueGuard<'_> {
+    type Target = Classes;
+
+    fn deref(&self) -> &Self::Target {
+        &self.classes
+    }
+}
+
+impl DerefMut for RunQueueGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.classes
+    }
+}
+
+impl Drop for RunQueueGuard<'_> {
+    fn drop(&mut self) {
+        // Still locked, classes are unlocked after this.
+        self.rq.queued.store(self.classes.len(), Ordering::Relaxed);
+    }
+}
+
+fn run_queue(cpu: usize) -> &'static RunQueue {
+    CPU::get(cpu).unwrap().run_queue()
+}
+
+/// Affinity of all CPUs.
+pub fn all_cpus() -> usize {
+    usize::MAX >> (MAX_CPUS - CPU::get_count().min(MAX_CPUS))
+}
+
+// Least loaded CPU the thread is allowed on, the one it ran last wins a tie to keep its cache warm.
+fn select_cpu(entity: &SchedEntity) -> usize {
+    (0..CPU::get_count())
+        .filter(|cpu| entity.allowed_on(*cpu))
+        .min_by_key(|cpu| (run_queue(*cpu).load(), *cpu != entity.cpu))
+        .unwrap_or(entity.cpu)
+}
+
+// Idle CPU sleeps in wfi until an interrupt, send it one to pick up the thread just queued.
+fn kick(cpu: usize) {
+    if cpu != CPU::get_current_id() && !run_queue(cpu).busy.load(Ordering::Relaxed) {
+        let _ = sbi::ipi::send_ipi(HartMask::new(0).with(cpu));
+    }
+}
+
+/// Put a Ready thread not running anywhere into the run queue of the least loaded CPU it is
+/// allowed on, like a newly created one.
+pub fn enqueue(thread: &Arc<Thread>, data: &mut ThreadData) {
+    assert_eq!(data.status, ProcessStatus::Ready, "Enqueue thread not ready.");
+    let cpu = select_cpu(&data.sched);
+    if cpu != data.sched.cpu {
+        run_queue(data.sched.cpu).lock().class(data.sched.policy).migrate_out(&mut data.sched);
+    }
+    let mut rq = run_queue(cpu).lock();
+    let class = rq.class(data.sched.policy);
+    if cpu != data.sched.cpu {
+        class.migrate_in(&mut data.sched);
+        data.sched.cpu = cpu;
+    }
+    class.enqueue(thread.clone(), &mut data.sched);
+    drop(rq);
+    kick(cpu);
+}
+
+/// Make a suspended thread Ready. Thread not switched out yet is enqueued by its worker.
+pub fn wake_up(thread: &Arc<Thread>, data: &mut ThreadData) {
+    if data.status != ProcessStatus::Suspend {
+        return;
+    }
+    data.status = ProcessStatus::Ready;
+    if !data.sched.on_cpu {
+        enqueue(thread, data);
+    }
+}
+
+/// Like `wake_up`, but for a signal or kill, which leaves uninterruptible sleep alone.
+/// Return false if the thread is in one.
+pub fn wake_up_interruptible(thread: &Arc<Thread>, data: &mut ThreadData) -> bool {
+    if data.uninterruptible {
+        return false;
+    }
+    wake_up(thread, data);
+    true
+}
+
+// Move a queued thread allowed on `cpu` from the busiest other CPU, if its load is more than
+// `min_load`. The thread is queued to `cpu` if `queue`, otherwise returned to run right away.
+fn steal(cpu: usize, min_load: usize, queue: bool) -> Option<Arc<Thread>> {
+    let src = (0..CPU::get_count())
+        .filter(|src| *src != cpu && run_queue(*src).queued.load(Ordering::Relaxed) > 0)
+        .max_by_key(|src| run_queue(*src).load())
+        .filter(|src| run_queue(*src).load() > min_load)?;
+    let mut src_rq = run_queue(src).lock();
+    let mut candidates = src_rq.rt.steal_candidates();
+    candidates.extend(src_rq.fair.steal_candidates());
+    for thread in candidates {
+        // Run queue is held, only try to lock thread data. Skip the busy ones.
+        let Some(mut data) = thread.data.try_lock() else { continue };
+        if !data.sched.allowed_on(cpu) {
+            continue;
+        }
+        let class = src_rq.class(data.sched.policy);
+        if class.dequeue(thread.tid(), &data.sched).is_none() {
+            continue;
+        }
+        class.migrate_out(&mut data.sched);
+        drop(src_rq);
+        data.sched.cpu = cpu;
+        let mut rq = run_queue(cpu).lock();
+        let class = rq.class(data.sched.policy);
+        class.migrate_in(&mut data.sched);
+        if queue {
+            class.enqueue(thread.clone(), &mut data.sched);
+        }
+        drop(rq);
+        drop(data);
+        return Some(thread);
+    }
+    None
+}
+
+/// Next thread to run on current CPU, taken from the busiest CPU if none is queued here.
+pub fn pick_next() -> Option<Arc<Thread>> {
+    let cpu = CPU::get_current_id();
+    let mut rq = run_queue(cpu).lock();
+    let thread = rq.rt.pick_next().or_else(|| rq.fair.pick_next());
+    drop(rq);
+    // Stealing the only one queued on an idle CPU gains nothing.
+    thread.or_else(|| steal(cpu, 1, false))
+}
+
+/// Called on every tick, pull a thread from the busiest CPU if it has two more than this one.
+pub fn balance() {
+    let cpu = CPU::get_current_id();
+    steal(cpu, run_queue(cpu).load() + 1, true);
+}
+
+/// Called by worker before switching to the thread.
+pub fn switch_in(data: &mut ThreadData) {
+    let cpu = CPU::get_current_id();
+    data.status = ProcessStatus::Running;
+    data.sched.on_cpu = true;
+    data.sched.cpu = cpu;
+    data.sched.exec_start = timer::current_time();
+    run_queue(cpu).busy.store(true, Ordering::Relaxed);
+}
+
+/// Called by worker after the thread is switched out, yielded one goes back to run queue.
+pub fn switch_out(thread: &Arc<Thread>, data: &mut ThreadData) {
+    let ran = timer::current_time().saturating_sub(data.sched.exec_start);
+    data.sched.on_cpu = false;
+    data.sched.sum_exec_runtime += ran;
+    let rq = run_queue(data.sched.cpu);
+    rq.busy.store(false, Ordering::Relaxed);
+    rq.lock().class(data.sched.policy).put_prev(&mut data.sched, ran);
+    if data.status == ProcessStatus::Ready {
+        enqueue(thread, data);
+    }
+}
+
+/// Total time the thread has run, the part running right now included.
+pub fn runtime(data: &ThreadData) -> Duration {
+    if data.sched.on_cpu {
+        data.sched.sum_exec_runtime + timer::current_time().saturating_sub(data.sched.exec_start)
+    } else {
+        data.sched.sum_exec_runtime
+    }
+}
+
+/// Timer tick on running thread, return whether it should yield.
+pub fn tick(data: &mut ThreadData) -> bool {
+    let ran = timer::current_time().saturating_sub(data.sched.exec_start);
+    // Affinity changed while running, move to an allowed CPU.
+    if !data.sched.allowed_on(data.sched.cpu) {
+        return true;
+    }
+    let mut rq = run_queue(data.sched.cpu).lock();
+    if !data.sched.policy.is_realtime() && !rq.rt.is_empty() {
+        return true;
+    }
+    rq.class(data.sched.policy).check_preempt_tick(&mut data.sched, ran)
+}
+
+/// Change scheduling parameters, queued thread is moved to the queue of new policy.
+pub fn set_params(thread: &Arc<Thread>, data: &mut ThreadData, policy: SchedPolicy, rt_priority: usize, nice: isize, reset_on_fork: bool) {
+    let mut rq = run_queue(data.sched.cpu).lock();
+    // Picked by a worker but not switched in yet is not in queue either.
+    let queued = data.status == ProcessStatus::Ready && !data.sched.on_cpu
+        && rq.class(data.sched.policy).dequeue(thread.tid(), &data.sched).is_some();
+    let sched = &mut data.sched;
+    sched.policy = policy;
+    sched.rt_priority = rt_priority;
+    sched.nice = nice;
+    sched.reset_on_fork = reset_on_fork;
+    sched.slice_used = Duration::ZERO;
+    if queued {
+        rq.class(policy).enqueue(thread.clone(), sched);
+    }
+}
+
+/// Change CPUs the thread is allowed on. Queued thread moves at once if its CPU is not allowed
+/// any more, running one on its next tick.
+pub fn set_affinity(thread: &Arc<Thread>, data: &mut ThreadData, affinity: usize) {
+    data.sched.affinity = affinity;
+    if data.sched.allowed_on(data.sched.cpu) || data.status != ProcessStatus::Ready || data.sched.on_cpu {
+        return;
+    }
+    let dequeued = run_queue(data.sched.cpu).lock().class(data.sched.policy).dequeue(thread.tid(), &data.sched).is_some();
+    if dequeued {
+        enqueue(thread, data);
+    }
+}
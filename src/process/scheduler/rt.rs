@@ -0,0 +1,100 @@
+//! # Real-time class
+//!
+//! SCHED_FIFO and SCHED_RR threads in per-priority queues, the highest priority runs first.
+//! FIFO thread runs until it sleeps or yields, RR thread also gives way to its peers once its
+//! time slice is used up.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::time::Duration;
+use crate::process::scheduler::{SchedClass, SchedEntity, SchedPolicy};
+use crate::process::Thread;
+
+pub const RR_TIMESLICE: Duration = Duration::from_millis(100);
+
+pub struct RtClass {
+    // Priority to threads in the order they run.
+    queues: BTreeMap<usize, VecDeque<Arc<Thread>>>,
+}
+
+impl RtClass {
+    pub fn new() -> Self {
+        Self {
+            queues: BTreeMap::new(),
+        }
+    }
+
+    fn highest_priority(&self) -> Option<usize> {
+        self.queues.last_key_value().map(|(priority, _)| *priority)
+    }
+}
+
+impl SchedClass for RtClass {
+    fn enqueue(&mut self, thread: Arc<Thread>, entity: &mut SchedEntity) {
+        let queue = self.queues.entry(entity.rt_priority).or_default();
+        if entity.slice_used >= RR_TIMESLICE {
+            entity.slice_used = Duration::ZERO;
+            queue.push_back(thread);
+        } else if entity.preempted {
+            // Keeps its place among the same priority.
+            queue.push_front(thread);
+        } else {
+            queue.push_back(thread);
+        }
+        entity.preempted = false;
+    }
+
+    fn dequeue(&mut self, tid: usize, entity: &SchedEntity) -> Option<Arc<Thread>> {
+        let queue = self.queues.get_mut(&entity.rt_priority)?;
+        let index = queue.iter().position(|thread| thread.tid() == tid)?;
+        let thread = queue.remove(index);
+        if queue.is_empty() {
+            self.queues.remove(&entity.rt_priority);
+        }
+        thread
+    }
+
+    fn pick_next(&mut self) -> Option<Arc<Thread>> {
+        let mut entry = self.queues.last_entry()?;
+        let thread = entry.get_mut().pop_front();
+        if entry.get().is_empty() {
+            entry.remove();
+        }
+        thread
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+
+    fn len(&self) -> usize {
+        self.queues.values().map(|queue| queue.len()).sum()
+    }
+
+    // The highest priority waiting runs sooner on an idle CPU.
+    fn steal_candidates(&self) -> Vec<Arc<Thread>> {
+        self.queues.values().rev().flatten().cloned().collect()
+    }
+
+    fn put_prev(&mut self, entity: &mut SchedEntity, ran: Duration) {
+        if entity.policy == SchedPolicy::RoundRobin {
+            entity.slice_used += ran;
+        }
+    }
+
+    fn check_preempt_tick(&self, entity: &mut SchedEntity, ran: Duration) -> bool {
+        if self.highest_priority().is_some_and(|priority| priority > entity.rt_priority) {
+            entity.preempted = true;
+            return true;
+        }
+        if entity.policy != SchedPolicy::RoundRobin || entity.slice_used + ran < RR_TIMESLICE {
+            return false;
+        }
+        // Alone at its priority, keeps running until a peer shows up.
+        self.queues.contains_key(&entity.rt_priority)
+    }
+}
@@ -0,0 +1,405 @@
+//! # Signal
+//!
+//! POSIX signal state and delivery.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use alloc::sync::Arc;
+use core::mem::size_of;
+use log::{info, warn};
+use crate::cpu::CPU;
+use crate::interrupt::TrapContext;
+use crate::memory::{Addr, VirtAddr};
+use crate::process::{do_yield, get_process_manager, Process, ProcessData, ProcessStatus, release_on_exit, scheduler, Thread, ThreadData};
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGSTKFLT: usize = 16;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGURG: usize = 23;
+pub const SIGXCPU: usize = 24;
+pub const SIGXFSZ: usize = 25;
+pub const SIGVTALRM: usize = 26;
+pub const SIGPROF: usize = 27;
+pub const SIGWINCH: usize = 28;
+pub const SIGIO: usize = 29;
+pub const SIGPWR: usize = 30;
+pub const SIGSYS: usize = 31;
+/// Signals are numbered from 1 to NSIG, including real-time signals.
+pub const NSIG: usize = 64;
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+pub const SA_NOCLDSTOP: usize = 0x1;
+pub const SA_NOCLDWAIT: usize = 0x2;
+pub const SA_SIGINFO: usize = 0x4;
+pub const SA_RESTORER: usize = 0x0400_0000;
+pub const SA_ONSTACK: usize = 0x0800_0000;
+pub const SA_RESTART: usize = 0x1000_0000;
+pub const SA_NODEFER: usize = 0x4000_0000;
+pub const SA_RESETHAND: usize = 0x8000_0000;
+
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+/// `si_code` for signals sent by kill/tgkill.
+const SI_USER: i32 = 0;
+
+/// Bitmap of signals, bit `n - 1` stands for signal `n`.
+#[repr(C)]
+#[derive(Copy, Clone, Default, PartialEq)]
+pub struct SignalSet(pub u64);
+
+impl SignalSet {
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    pub fn add(&mut self, signo: usize) {
+        self.0 |= 1u64 << (signo - 1);
+    }
+
+    pub fn remove(&mut self, signo: usize) {
+        self.0 &= !(1u64 << (signo - 1));
+    }
+
+    pub fn contains(&self, signo: usize) -> bool {
+        self.0 & (1u64 << (signo - 1)) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Lowest numbered signal in set.
+    pub fn first(&self) -> Option<usize> {
+        if self.0 == 0 {
+            None
+        } else {
+            Some(self.0.trailing_zeros() as usize + 1)
+        }
+    }
+
+    /// SIGKILL and SIGSTOP could never be blocked.
+    pub fn without_unblockable(self) -> Self {
+        let mut set = self;
+        set.remove(SIGKILL);
+        set.remove(SIGSTOP);
+        set
+    }
+}
+
+/// Kernel `struct sigaction` of RISC-V, which has no `sa_restorer`.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct SigAction {
+    pub handler: usize,
+    pub flags: usize,
+    pub mask: SignalSet,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum SignalDefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+pub fn default_action(signo: usize) -> SignalDefaultAction {
+    match signo {
+        SIGCHLD | SIGURG | SIGWINCH => SignalDefaultAction::Ignore,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => SignalDefaultAction::Stop,
+        SIGCONT => SignalDefaultAction::Continue,
+        _ => SignalDefaultAction::Terminate,
+    }
+}
+
+pub fn is_valid_signal(signo: usize) -> bool {
+    signo >= 1 && signo <= NSIG
+}
+
+/// Signal handlers and process-directed pending signals, shared by all threads of a process.
+pub struct SignalState {
+    pub actions: [SigAction; NSIG],
+    pub pending: SignalSet,
+}
+
+impl SignalState {
+    pub fn new() -> Self {
+        Self {
+            actions: [SigAction::default(); NSIG],
+            pending: SignalSet::empty(),
+        }
+    }
+
+    /// Child of fork inherits handlers, but not pending signals.
+    pub fn fork(&self) -> Self {
+        Self {
+            actions: self.actions,
+            pending: SignalSet::empty(),
+        }
+    }
+
+    /// Caught signals are reset to default on exec, ignored ones stay ignored.
+    pub fn reset_on_exec(&mut self) {
+        for action in self.actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SigAction::default();
+            }
+        }
+    }
+
+    pub fn is_ignored(&self, signo: usize) -> bool {
+        let action = &self.actions[signo - 1];
+        action.handler == SIG_IGN
+            || (action.handler == SIG_DFL && default_action(signo) == SignalDefaultAction::Ignore)
+    }
+}
+
+/// Signal mask and thread-directed pending signals of a thread.
+pub struct ThreadSignal {
+    pub blocked: SignalSet,
+    pub pending: SignalSet,
+}
+
+impl ThreadSignal {
+    pub fn new() -> Self {
+        Self {
+            blocked: SignalSet::empty(),
+            pending: SignalSet::empty(),
+        }
+    }
+
+    /// New thread or forked child inherits mask, but not pending signals.
+    pub fn fork(&self) -> Self {
+        Self {
+            blocked: self.blocked,
+            pending: SignalSet::empty(),
+        }
+    }
+
+    pub fn has_deliverable(&self, shared: &SignalSet) -> bool {
+        !SignalSet((self.pending.0 | shared.0) & !self.blocked.0).is_empty()
+    }
+
+    /// Thread-directed signals go first, then the ones shared by process.
+    fn take_deliverable(&mut self, shared: &mut SignalSet) -> Option<usize> {
+        if let Some(signo) = SignalSet(self.pending.0 & !self.blocked.0).first() {
+            self.pending.remove(signo);
+            Some(signo)
+        } else {
+            let signo = SignalSet(shared.0 & !self.blocked.0).first()?;
+            shared.remove(signo);
+            Some(signo)
+        }
+    }
+}
+
+/// Linux `siginfo_t`, only leading fields are filled.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct SigInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    _pad: i32,
+    pid: i32,
+    uid: u32,
+    _rest: [u64; 13],
+}
+
+/// Linux `struct ucontext` of RISC-V. `gregs[0]` is pc, `gregs[n]` is x(n).
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct UContext {
+    flags: usize,
+    link: usize,
+    stack: [usize; 3],
+    sigmask: SignalSet,
+    _unused: [u8; 120],
+    _align: usize,
+    gregs: [usize; 32],
+    fpregs: [u64; 66],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct SignalFrame {
+    info: SigInfo,
+    ucontext: UContext,
+}
+
+impl SignalFrame {
+    fn zeroed() -> Self {
+        // Safety: plain old data.
+        unsafe { core::mem::zeroed() }
+    }
+}
+
+/// Whether the thread has a signal to handle or is killed, which ends an interruptible sleep.
+pub fn has_pending(thread: &Arc<Thread>) -> bool {
+    let proc_data = thread.process.data.lock();
+    let thread_data = thread.data.lock();
+    thread_data.killed || thread_data.signal.has_deliverable(&proc_data.signal.pending)
+}
+
+/// Send a signal to process. One thread not blocking it is woken up if sleeping in kernel.
+pub fn send_signal(proc: &Arc<Process>, signo: usize) {
+    let mut proc_data = proc.data.lock();
+    if proc_data.status == ProcessStatus::Zombie || proc_data.signal.is_ignored(signo) {
+        // Ignored signals are discarded at generation time like Linux does.
+        return;
+    }
+    proc_data.signal.pending.add(signo);
+    for thread in proc_data.threads.iter().filter_map(|t| t.upgrade()) {
+        let mut thread_data = thread.data.lock();
+        // One in uninterruptible sleep could not handle it soon, try others.
+        if !thread_data.signal.blocked.contains(signo) && scheduler::wake_up_interruptible(&thread, &mut thread_data) {
+            break;
+        }
+    }
+}
+
+/// Send a signal to one specified thread.
+pub fn send_signal_to_thread(thread: &Arc<Thread>, signo: usize) {
+    let proc_data = thread.process.data.lock();
+    if proc_data.signal.is_ignored(signo) {
+        return;
+    }
+    let mut thread_data = thread.data.lock();
+    if thread_data.status == ProcessStatus::Zombie {
+        return;
+    }
+    thread_data.signal.pending.add(signo);
+    if !thread_data.signal.blocked.contains(signo) {
+        scheduler::wake_up_interruptible(thread, &mut thread_data);
+    }
+}
+
+/// Send a synchronous signal caused by the thread itself, like a fault.
+/// It could not be ignored or blocked, otherwise the thread would fault again forever.
+pub fn force_signal(thread: &Arc<Thread>, signo: usize) {
+    let mut proc_data = thread.process.data.lock();
+    let mut thread_data = thread.data.lock();
+    let action = &mut proc_data.signal.actions[signo - 1];
+    if action.handler == SIG_IGN || thread_data.signal.blocked.contains(signo) {
+        *action = SigAction::default();
+        thread_data.signal.blocked.remove(signo);
+    }
+    thread_data.signal.pending.add(signo);
+}
+
+/// Push signal frame onto user stack and redirect user context to the handler.
+fn setup_frame(proc_data: &mut ProcessData, thread_data: &mut ThreadData, signo: usize, action: SigAction) -> bool {
+    let ctx = thread_data.get_trap_context();
+    let mut frame = SignalFrame::zeroed();
+    frame.info.signo = signo as i32;
+    frame.info.code = SI_USER;
+    frame.ucontext.sigmask = thread_data.signal.blocked;
+    frame.ucontext.gregs[0] = ctx.sepc;
+    frame.ucontext.gregs[1..].copy_from_slice(&ctx.reg[1..]);
+
+    let sp = (ctx.reg[TrapContext::sp] - size_of::<SignalFrame>()) & !0xf;
+    if proc_data.memory.write_user(VirtAddr::from(sp), &frame).is_err() {
+        return false;
+    }
+
+    ctx.reg[TrapContext::sp] = sp;
+    ctx.reg[TrapContext::ra] = proc_data.memory.signal_trampoline().get_addr();
+    ctx.reg[TrapContext::a0] = signo;
+    ctx.reg[TrapContext::a1] = sp;
+    ctx.reg[TrapContext::a2] = sp + size_of::<SigInfo>();
+    ctx.sepc = action.handler;
+
+    let blocked = &mut thread_data.signal.blocked;
+    blocked.0 |= action.mask.0;
+    if action.flags & SA_NODEFER == 0 {
+        blocked.add(signo);
+    }
+    *blocked = blocked.without_unblockable();
+    if action.flags & SA_RESETHAND != 0 {
+        proc_data.signal.actions[signo - 1] = SigAction::default();
+    }
+    true
+}
+
+/// Called on the way back to user space, handles all deliverable signals of current thread.
+pub fn do_signal() {
+    let thread = CPU::get_current_thread().unwrap();
+    if thread.data.lock().killed {
+        release_on_exit(&thread);
+        get_process_manager().lock().exit_thread(&thread, 0);
+        drop(thread);
+        do_yield();
+        unreachable!("Killed thread is scheduled again.");
+    }
+    let proc = thread.process.clone();
+    let exit_signo = loop {
+        let mut proc_data = proc.data.lock();
+        let mut thread_data = thread.data.lock();
+        let signo = if let Some(signo) = thread_data.signal.take_deliverable(&mut proc_data.signal.pending) {
+            signo
+        } else {
+            return;
+        };
+        let action = proc_data.signal.actions[signo - 1];
+        match action.handler {
+            SIG_IGN => {}
+            SIG_DFL => match default_action(signo) {
+                SignalDefaultAction::Terminate => break signo,
+                SignalDefaultAction::Stop => {
+                    warn!("Stopping process is not supported, signal {} ignored.", signo);
+                }
+                _ => {}
+            }
+            _ => {
+                if setup_frame(&mut proc_data, &mut thread_data, signo, action) {
+                    // One handler a time, others are delivered after sigreturn.
+                    return;
+                }
+                warn!("Failed to push signal frame for signal {} on TID {}.", signo, thread.tid());
+                break SIGSEGV;
+            }
+        }
+    };
+    info!("PID {} is killed by signal {}.", proc.pid.pid(), exit_signo);
+    drop(proc);
+    release_on_exit(&thread);
+    // Wait status of a killed process has its signal number in the low 7 bits, read by WTERMSIG.
+    get_process_manager().lock().exit_group(&thread, exit_signo & 0x7f);
+    drop(thread);
+    do_yield();
+    unreachable!("Killed thread is scheduled again.");
+}
+
+/// Restore user context saved by `setup_frame`. Return value is the restored a0.
+pub fn sigreturn(proc_data: &mut ProcessData, thread_data: &mut ThreadData) -> Option<usize> {
+    let ctx = thread_data.get_trap_context();
+    let frame = proc_data.memory.read_user::<SignalFrame>(VirtAddr::from(ctx.reg[TrapContext::sp])).ok()?;
+    ctx.sepc = frame.ucontext.gregs[0];
+    ctx.reg[1..].copy_from_slice(&frame.ucontext.gregs[1..]);
+    thread_data.signal.blocked = frame.ucontext.sigmask.without_unblockable();
+    Some(ctx.reg[TrapContext::a0])
+}
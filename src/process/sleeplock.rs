@@ -0,0 +1,79 @@
+//! # Sleep lock
+//!
+//! Lock for data held across sleeping, like waiting for disk I/O. Contended lockers give the
+//! CPU away instead of spinning, so the holder gets a chance to run and release it.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use core::cell::UnsafeCell;
+use core::hint;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::cpu::CPU;
+use crate::process::do_yield;
+
+pub struct SleepLock<T> {
+    lock: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+pub struct SleepLockGuard<'a, T: 'a> {
+    lock: &'a SleepLock<T>,
+}
+
+impl<T> SleepLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            lock: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> SleepLockGuard<T> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            // Nothing to switch to before scheduler runs, spin then.
+            if CPU::get_current_thread().is_some() {
+                do_yield();
+            } else {
+                hint::spin_loop();
+            }
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<SleepLockGuard<T>> {
+        if self.lock.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            None
+        } else {
+            Some(SleepLockGuard { lock: self })
+        }
+    }
+}
+
+// Like a mutex, only one thread at a time gets the data, which is all it takes to share it.
+unsafe impl<T: Send> Sync for SleepLock<T> {}
+
+unsafe impl<T: Send> Send for SleepLock<T> {}
+
+impl<'a, T> Deref for SleepLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for SleepLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for SleepLockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.lock.store(false, Ordering::Release);
+    }
+}
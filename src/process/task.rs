@@ -11,7 +11,7 @@ use log::info;
 use crate::core::{IntrlockGuard, SpinlockGuard};
 use crate::cpu::CPU;
 use crate::interrupt::TrapContext;
-use crate::process::{Process, ProcessData, ProcessStatus};
+use crate::process::{ProcessStatus, ThreadData};
 global_asm!(include_str!("switch.S"));
 
 #[repr(C)]
@@ -50,21 +50,21 @@ extern "C" {
     pub fn context_switch(old: *mut TaskContext, new: *const TaskContext);
 }
 
-fn yield_process(mut proc_data: IntrlockGuard<ProcessData>) -> *mut TaskContext {
-    match proc_data.status {
+fn yield_thread(mut thread_data: IntrlockGuard<ThreadData>) -> *mut TaskContext {
+    match thread_data.status {
         ProcessStatus::Running => {
-            proc_data.status = ProcessStatus::Ready;
+            thread_data.status = ProcessStatus::Ready;
         }
         _ => {}
     }
-    &mut proc_data.kernel_task_context as *mut TaskContext
+    &mut thread_data.kernel_task_context as *mut TaskContext
 }
 
 fn _do_yield(old_ctx: *mut TaskContext) {
     let cpu = CPU::get_current().unwrap();
     let trap_enabled = cpu.get_trap_enabled();
     let new_ctx = cpu.get_context();
-    cpu.set_process(None);
+    cpu.set_thread(None);
     drop(cpu);
     // info!("Do Yield for process {} at {:x}", proc.pid.pid(), proc.as_ref() as *const crate::process::Process as usize);
 
@@ -74,17 +74,17 @@ fn _do_yield(old_ctx: *mut TaskContext) {
 }
 
 pub fn do_yield() {
-    let proc = CPU::get_current_process().unwrap();
-    let proc_data = proc.data.lock();
-    let old_ctx = yield_process(proc_data);
-    drop(proc);
+    let thread = CPU::get_current_thread().unwrap();
+    let thread_data = thread.data.lock();
+    let old_ctx = yield_thread(thread_data);
+    drop(thread);
     _do_yield(old_ctx)
 }
 
 pub fn try_yield() {
-    let proc = CPU::get_current_process().unwrap();
-    if let Some(proc_data) = proc.data.try_lock() {
-        let old_ctx = yield_process(proc_data);
+    let thread = CPU::get_current_thread().unwrap();
+    if let Some(thread_data) = thread.data.try_lock() {
+        let old_ctx = yield_thread(thread_data);
         _do_yield(old_ctx);
     } else {
         // do nothing
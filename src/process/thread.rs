@@ -0,0 +1,106 @@
+//! # Thread
+//!
+//! Schedulable task inside a thread group. Address space, files and signal handlers are owned by
+//! the group (`Process`), kernel stack and user context are owned by each thread.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use log::trace;
+use crate::config;
+use crate::core::{Intrlock, Spinlock};
+use crate::interrupt::{TrapContext, user_trap_returner};
+use crate::memory::{PAGE_SIZE, PhyAddr, PhyPage, VirtAddr};
+use crate::process::{Process, ProcessStatus, TaskContext};
+use crate::process::cputime::UserTime;
+use crate::process::scheduler::SchedEntity;
+use crate::process::signal::ThreadSignal;
+use super::pid::Pid;
+
+pub struct Thread {
+    tid: AtomicUsize,
+    // Main thread uses pid as tid, which is owned by process.
+    tid_handle: Spinlock<Option<Pid>>,
+    pub process: Arc<Process>,
+    pub data: Intrlock<ThreadData>,
+}
+
+pub struct ThreadData {
+    pub status: ProcessStatus,
+    pub kernel_stack: Vec<PhyPage>,
+    // We use kernel_stack to store trap context
+    pub kernel_task_context: TaskContext,
+    // Zero is written here when thread exits, set by CLONE_CHILD_CLEARTID or set_tid_address.
+    pub clear_child_tid: VirtAddr,
+    pub signal: ThreadSignal,
+    // Set by exit_group or execve of other thread, thread exits before going back to user space.
+    pub killed: bool,
+    // Set when thread starts exiting, see `release_on_exit`.
+    pub exiting: bool,
+    // Set while sleeping uninterruptibly, signals and kills do not wake it up then.
+    pub uninterruptible: bool,
+    pub sched: SchedEntity,
+    pub user_time: UserTime,
+}
+
+impl ThreadData {
+    pub fn get_trap_context(&mut self) -> &'static mut TrapContext {
+        PhyAddr::from(self.kernel_stack[0].id).get_ref_mut::<TrapContext>()
+    }
+}
+
+impl Thread {
+    /// Create a thread of process. `tid` is None for main thread.
+    pub fn new(process: Arc<Process>, tid: Option<Pid>, user_satp: usize) -> Self {
+        let kernel_stack = PhyPage::alloc_many(config::PROCESS_KERNEL_STACK_SIZE);
+        let kernel_sp = PhyAddr::from(kernel_stack[config::PROCESS_KERNEL_STACK_SIZE - 1].id).addr + PAGE_SIZE * config::PROCESS_KERNEL_STACK_SIZE;
+        let kernel_task_context = TaskContext::new().with_sp(kernel_sp).with_ra(user_trap_returner as usize);
+
+        let mut thread_data = ThreadData {
+            status: ProcessStatus::Ready,
+            kernel_stack,
+            kernel_task_context,
+            clear_child_tid: VirtAddr::from(0),
+            signal: ThreadSignal::new(),
+            killed: false,
+            exiting: false,
+            uninterruptible: false,
+            sched: SchedEntity::new(),
+            user_time: UserTime::default(),
+        };
+        let trap_context = thread_data.get_trap_context();
+        trap_context.kernel_sp = kernel_sp;
+        trap_context.satp = user_satp;
+
+        Self {
+            tid: AtomicUsize::new(tid.as_ref().map_or(process.pid.pid(), |tid| tid.pid())),
+            tid_handle: Spinlock::new(tid),
+            process,
+            data: Intrlock::new(thread_data),
+        }
+    }
+
+    pub fn tid(&self) -> usize {
+        self.tid.load(Ordering::Relaxed)
+    }
+
+    pub fn is_main_thread(&self) -> bool {
+        self.tid_handle.lock().is_none()
+    }
+
+    /// Take over pid as tid and free the old tid, for execve of a non-main thread.
+    /// Thread must be the only one of its process and not in any run queue.
+    pub fn become_main_thread(&self) {
+        self.tid.store(self.process.pid.pid(), Ordering::Relaxed);
+        self.tid_handle.lock().take();
+    }
+}
+
+impl Drop for Thread {
+    fn drop(&mut self) {
+        trace!("Dropping thread {} of process {}", self.tid(), self.process.pid.pid());
+    }
+}
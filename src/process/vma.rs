@@ -0,0 +1,94 @@
+//! # VMA
+//!
+//! Virtual memory area, a continuous range of user pages sharing the same protection and backing.
+//! Pages inside are populated on first touch.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use alloc::sync::Arc;
+use crate::filesystem::File;
+use crate::memory::{PAGE_SIZE, PTEFlags, VirtPageId};
+
+#[derive(Clone)]
+pub enum VmaBacking {
+    /// Zero filled on first touch.
+    Anonymous,
+    /// Filled from file on first touch. `offset` is the file offset of first page.
+    File {
+        file: Arc<dyn File>,
+        offset: usize,
+    },
+}
+
+#[derive(Clone)]
+pub struct Vma {
+    pub start: VirtPageId,
+    // end is not included
+    pub end: VirtPageId,
+    pub flags: PTEFlags,
+    pub backing: VmaBacking,
+    // MAP_SHARED, pages are shared after fork and file pages are written back.
+    pub shared: bool,
+}
+
+impl Vma {
+    pub fn new(start: VirtPageId, end: VirtPageId, flags: PTEFlags, backing: VmaBacking, shared: bool) -> Self {
+        assert!(start < end, "Empty VMA.");
+        Self { start, end, flags, backing, shared }
+    }
+
+    pub fn anonymous(start: VirtPageId, end: VirtPageId, flags: PTEFlags) -> Self {
+        Self::new(start, end, flags, VmaBacking::Anonymous, false)
+    }
+
+    pub fn contains(&self, vpn: VirtPageId) -> bool {
+        self.start <= vpn && vpn < self.end
+    }
+
+    pub fn pages(&self) -> usize {
+        self.end.id - self.start.id
+    }
+
+    /// File offset of the page, None for anonymous memory.
+    pub fn file_offset(&self, vpn: VirtPageId) -> Option<usize> {
+        match &self.backing {
+            VmaBacking::Anonymous => None,
+            VmaBacking::File { offset, .. } => Some(offset + (vpn.id - self.start.id) * PAGE_SIZE),
+        }
+    }
+
+    /// Shared file mapping, whose dirty pages must be written back to file.
+    pub fn is_shared_file(&self) -> bool {
+        self.shared && matches!(self.backing, VmaBacking::File { .. })
+    }
+
+    /// Whether next VMA could be merged into self.
+    pub fn can_merge(&self, next: &Vma) -> bool {
+        if self.end != next.start || self.flags.bits() != next.flags.bits() || self.shared != next.shared {
+            return false;
+        }
+        match (&self.backing, &next.backing) {
+            (VmaBacking::Anonymous, VmaBacking::Anonymous) => true,
+            (VmaBacking::File { file, .. }, VmaBacking::File { file: next_file, .. }) => {
+                Arc::ptr_eq(file, next_file) && self.file_offset(self.end) == next.file_offset(next.start)
+            }
+            _ => false
+        }
+    }
+
+    /// Split at vpn. Self keeps the lower part and the upper part is returned.
+    pub fn split_off(&mut self, vpn: VirtPageId) -> Vma {
+        assert!(self.start < vpn && vpn < self.end, "Split VMA out of range.");
+        let backing = match &self.backing {
+            VmaBacking::Anonymous => VmaBacking::Anonymous,
+            VmaBacking::File { file, .. } => VmaBacking::File {
+                file: file.clone(),
+                offset: self.file_offset(vpn).unwrap(),
+            },
+        };
+        let upper = Vma::new(vpn, self.end, self.flags, backing, self.shared);
+        self.end = vpn;
+        upper
+    }
+}
@@ -0,0 +1,136 @@
+//! # Wait queue
+//!
+//! Threads sleeping until a condition holds. Waiter joins the queue before releasing the lock it
+//! checked the condition under, so a waker changing the condition under the same lock never
+//! misses it.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use crate::core::Intrlock;
+use crate::cpu::CPU;
+use crate::device::timer;
+use crate::process::{do_yield, ProcessStatus, scheduler, signal, Thread};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum WakeReason {
+    Woken,
+    TimedOut,
+    // By a signal not blocked, or the thread is killed.
+    Interrupted,
+}
+
+struct Waiter {
+    thread: Weak<Thread>,
+    // Set by waker when it takes the waiter off the queue.
+    woken: AtomicBool,
+}
+
+impl Waiter {
+    // Return false if the thread is gone.
+    fn wake(&self) -> bool {
+        self.woken.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.upgrade() {
+            scheduler::wake_up(&thread, &mut thread.data.lock());
+            true
+        } else {
+            false
+        }
+    }
+}
+
+pub struct WaitQueue {
+    // Interrupt handlers wake threads up. Lock order: wait queue -> thread data.
+    waiters: Intrlock<VecDeque<Arc<Waiter>>>,
+}
+
+impl WaitQueue {
+    pub fn new() -> Self {
+        Self {
+            waiters: Intrlock::new(VecDeque::new()),
+        }
+    }
+
+    /// Release `guard` of the lock protecting the condition and sleep until woken up.
+    pub fn wait<G>(&self, guard: G) {
+        self.sleep(guard, None, false);
+    }
+
+    /// Like `wait`, but also returns on a signal.
+    pub fn wait_interruptible<G>(&self, guard: G) -> WakeReason {
+        self.sleep(guard, None, true)
+    }
+
+    /// Like `wait`, but returns once `deadline` since boot passes, and on a signal if
+    /// `interruptible`.
+    pub fn wait_until<G>(&self, guard: G, deadline: Duration, interruptible: bool) -> WakeReason {
+        self.sleep(guard, Some(deadline), interruptible)
+    }
+
+    /// Wake the one waiting longest, return whether there is one.
+    pub fn wake_one(&self) -> bool {
+        let mut waiters = self.waiters.lock();
+        while let Some(waiter) = waiters.pop_front() {
+            if waiter.wake() {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Wake all waiting, return how many.
+    pub fn wake_all(&self) -> usize {
+        let mut waiters = self.waiters.lock();
+        waiters.drain(..).filter(|waiter| waiter.wake()).count()
+    }
+
+    fn sleep<G>(&self, guard: G, deadline: Option<Duration>, interruptible: bool) -> WakeReason {
+        let thread = CPU::get_current_thread().unwrap();
+        let waiter = Arc::new(Waiter {
+            thread: Arc::downgrade(&thread),
+            woken: AtomicBool::new(false),
+        });
+        self.waiters.lock().push_back(waiter.clone());
+        drop(guard);
+        let timeout = deadline.map(|deadline| timer::add_timeout(deadline, &thread));
+        let reason = loop {
+            // Suspended before checking, a wakeup from now on makes it Ready and yield returns.
+            let mut thread_data = thread.data.lock();
+            thread_data.status = ProcessStatus::Suspend;
+            thread_data.uninterruptible = !interruptible;
+            drop(thread_data);
+            if waiter.woken.load(Ordering::SeqCst) {
+                break WakeReason::Woken;
+            }
+            if deadline.is_some_and(|deadline| timer::current_time() >= deadline) {
+                break WakeReason::TimedOut;
+            }
+            if interruptible && signal::has_pending(&thread) {
+                break WakeReason::Interrupted;
+            }
+            do_yield();
+        };
+        let mut thread_data = thread.data.lock();
+        thread_data.status = ProcessStatus::Running;
+        thread_data.uninterruptible = false;
+        drop(thread_data);
+        if let Some(timeout) = timeout {
+            timer::cancel_timer(timeout);
+        }
+        if reason == WakeReason::Woken {
+            return reason;
+        }
+        let mut waiters = self.waiters.lock();
+        if let Some(index) = waiters.iter().position(|w| Arc::ptr_eq(w, &waiter)) {
+            waiters.remove(index);
+            reason
+        } else {
+            // Taken by a waker meanwhile, do not waste its wakeup.
+            WakeReason::Woken
+        }
+    }
+}
@@ -5,14 +5,28 @@ Libc 兼容层，不应该流出syscall的scope。
 use alloc::string::String;
 use alloc::vec::Vec;
 use core::mem::size_of;
+use core::time::Duration;
 use bitflags::Flags;
 use crate::config::{SYS_MACHINE, SYS_NAME};
 use crate::filesystem::{DirEntry, FileModes, InodeStat};
 use crate::memory::{Addr, VirtAddr};
+use crate::process::cputime::CpuTime;
 
 pub const AT_FDCWD: usize = (-100isize) as usize;
+pub const AT_SYMLINK_NOFOLLOW: usize = 0x100;
+pub const AT_REMOVEDIR: usize = 0x200;
+pub const AT_SYMLINK_FOLLOW: usize = 0x400;
+
+// Longest path string accepted from user space, NUL included.
+pub const PATH_MAX: usize = 4096;
+
+pub const MNT_FORCE: usize = 1;
+pub const MNT_DETACH: usize = 2;
+pub const MNT_EXPIRE: usize = 4;
+pub const UMOUNT_NOFOLLOW: usize = 8;
 
 #[repr(C)]
+#[derive(Copy, Clone)]
 pub struct KernelStat {
     pub st_dev: u64,
     pub st_ino: u64,
@@ -33,12 +47,100 @@ pub struct KernelStat {
 }
 
 #[repr(C)]
+#[derive(Copy, Clone)]
 pub struct Timespec {
     pub tv_sec: i64,
     // seconds
     pub tv_nsec: i64, // nanoseconds
 }
 
+impl From<Duration> for Timespec {
+    fn from(value: Duration) -> Self {
+        Self {
+            tv_sec: value.as_secs() as i64,
+            tv_nsec: value.subsec_nanos() as i64,
+        }
+    }
+}
+
+impl Timespec {
+    /// None if negative or nanoseconds out of range.
+    pub fn to_duration(&self) -> Option<Duration> {
+        if self.tv_sec < 0 || !(0..1_000_000_000).contains(&self.tv_nsec) {
+            return None;
+        }
+        Some(Duration::new(self.tv_sec as u64, self.tv_nsec as u32))
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Timeval {
+    pub tv_sec: i64,
+    pub tv_usec: i64,
+}
+
+impl From<Duration> for Timeval {
+    fn from(value: Duration) -> Self {
+        Self {
+            tv_sec: value.as_secs() as i64,
+            tv_usec: value.subsec_micros() as i64,
+        }
+    }
+}
+
+impl Timeval {
+    /// None if negative or microseconds out of range.
+    pub fn to_duration(&self) -> Option<Duration> {
+        if self.tv_sec < 0 || !(0..1_000_000).contains(&self.tv_usec) {
+            return None;
+        }
+        Some(Duration::new(self.tv_sec as u64, self.tv_usec as u32 * 1000))
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Itimerval {
+    pub it_interval: Timeval,
+    pub it_value: Timeval,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Itimerspec {
+    pub it_interval: Timespec,
+    pub it_value: Timespec,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Tms {
+    pub tms_utime: i64,
+    pub tms_stime: i64,
+    pub tms_cutime: i64,
+    pub tms_cstime: i64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Rusage {
+    pub ru_utime: Timeval,
+    pub ru_stime: Timeval,
+    // ru_maxrss to ru_nivcsw, not accounted
+    pub __reserved: [i64; 14],
+}
+
+impl From<CpuTime> for Rusage {
+    fn from(value: CpuTime) -> Self {
+        Self {
+            ru_utime: value.user.into(),
+            ru_stime: value.system.into(),
+            __reserved: [0; 14],
+        }
+    }
+}
+
 #[repr(packed)] // size = 19
 pub struct DirEnt64 {
     pub d_ino: u64,
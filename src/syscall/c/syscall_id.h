@@ -8,12 +8,20 @@
 #define SYS_close 57
 #define SYS_mkdirat 34
 #define SYS_mount 40
+#define SYS_umount2 39
+#define SYS_sync 81
+#define SYS_fsync 82
+#define SYS_fdatasync 83
 #define SYS_fstat 80
 #define SYS_readv 65
 #define SYS_writev 66
 #define SYS_newfstatat 79
 #define SYS_getdents64 61
 #define SYS_linkat 37
+#define SYS_symlinkat 36
+#define SYS_readlinkat 78
+#define SYS_unlinkat 35
+#define SYS_renameat2 276
 #define SYS_pipe2 59
 
 /* Process */
@@ -23,12 +31,50 @@
 #define SYS_wait4 260
 #define SYS_getpid 172
 #define SYS_getppid 173
+#define SYS_gettid 178
+#define SYS_exit_group 94
+#define SYS_set_tid_address 96
+#define SYS_futex 98
 #define SYS_sched_yield 124
+#define SYS_sched_setparam 118
+#define SYS_sched_setscheduler 119
+#define SYS_sched_getscheduler 120
+#define SYS_sched_getparam 121
+#define SYS_sched_get_priority_max 125
+#define SYS_sched_get_priority_min 126
+#define SYS_sched_rr_get_interval 127
+#define SYS_sched_setaffinity 122
+#define SYS_sched_getaffinity 123
+#define SYS_setpriority 140
+#define SYS_getpriority 141
+
+/* Signal */
+#define SYS_rt_sigaction 134
+#define SYS_rt_sigprocmask 135
+#define SYS_rt_sigreturn 139
+#define SYS_kill 129
+#define SYS_tgkill 131
 
 /* Memory */
 #define SYS_brk 214
 #define SYS_mmap 222
 #define SYS_munmap 215
+#define SYS_msync 227
+#define SYS_mprotect 226
+
+/* Time */
+#define SYS_clock_gettime 113
+#define SYS_clock_getres 114
+#define SYS_gettimeofday 169
+#define SYS_times 153
+#define SYS_getrusage 165
+#define SYS_nanosleep 101
+#define SYS_clock_nanosleep 115
+#define SYS_getitimer 102
+#define SYS_setitimer 103
+#define SYS_timerfd_create 85
+#define SYS_timerfd_settime 86
+#define SYS_timerfd_gettime 87
 
 /* ARK Custom Syscall */
 #define SYS_ark_sleep_ticks 1002
@@ -44,25 +90,14 @@
 #define SYS_geteuid 175
 #define SYS_getgid 176
 #define SYS_getegid 177
-#define SYS_gettid 178
 #define SYS_setuid 146
 #define SYS_setgid 144
-#define SYS_exit_group 94
-#define SYS_set_tid_address 96
 #define SYS_ioctl 29
 #define SYS_fcntl64 25
-#define SYS_clock_gettime 113
 
 /* Going to be Implemented */
 #define SYS_dup 23
-#define SYS_rt_sigaction 134
-#define SYS_rt_sigprocmask 135
 
 /* Not too urgent to be Implemented */
 #define SYS_dup3 24
-#define SYS_unlinkat 35
-#define SYS_umount2 39
-#define SYS_times 153
-#define SYS_gettimeofday 169
-#define SYS_nanosleep 101
 #define SYS_ppoll 73
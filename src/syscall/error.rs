@@ -1,3 +1,5 @@
+use crate::filesystem::{LookupError, UnmountError};
+
 #[repr(isize)]
 #[derive(Debug, Clone, Copy)]
 pub enum SyscallError {
@@ -69,7 +71,35 @@ pub enum SyscallError {
     EDOM = 33,
     /// Math result not representable
     ERANGE = 34,
+    /// Function not implemented
+    ENOSYS = 38,
+    /// Directory not empty
+    ENOTEMPTY = 39,
+    /// Too many symbolic links encountered
+    ELOOP = 40,
     ETIMEDOUT = 110,
+    /// Structure needs cleaning
+    EUCLEAN = 117,
 }
 
 pub type SyscallResult = core::result::Result<usize, SyscallError>;
+
+impl From<UnmountError> for SyscallError {
+    fn from(value: UnmountError) -> Self {
+        match value {
+            UnmountError::NotMounted => SyscallError::EINVAL,
+            UnmountError::Busy => SyscallError::EBUSY,
+            UnmountError::Sync => SyscallError::EIO,
+        }
+    }
+}
+
+impl From<LookupError> for SyscallError {
+    fn from(value: LookupError) -> Self {
+        match value {
+            LookupError::NotFound => SyscallError::ENOENT,
+            LookupError::NotDir => SyscallError::ENOTDIR,
+            LookupError::Loop => SyscallError::ELOOP,
+        }
+    }
+}
@@ -1,15 +1,18 @@
 use alloc::sync::Arc;
 use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::min;
 use core::fmt::Write;
+use core::mem::size_of;
 use core::ops::DerefMut;
 use log::info;
 use crate::cpu::CPU;
 use crate::device::pipe::PipeFile;
-use crate::memory::{VirtAddr, Addr, PageTable, PhyPageId};
+use crate::memory::{VirtAddr, Addr, PAGE_SIZE, PageTable, PhyPageId};
 use crate::filesystem as fs;
-use crate::filesystem::{DirEntry, File, FileModes, FileOpenFlags, InodeStat, SeekPosition};
-use crate::process::ProcessData;
-use crate::utils::error::EmptyResult;
+use crate::filesystem::{DirEntry, DirEntryType, File, FileModes, FileOpenFlags, InodeStat, LookupError, MountFlags, RenameFlags, SeekPosition};
+use crate::process::{Process, ProcessData, signal};
+use crate::utils::error::{EmptyResult, KernelError};
 use crate::syscall::c::*;
 use crate::syscall::error::{SyscallError, SyscallResult};
 
@@ -25,6 +28,20 @@ fn get_file_from_fd(proc_data: &ProcessData, fd: usize) -> core::result::Result<
     }
 }
 
+// Errno of a filesystem error, `default` for those without a specific one.
+fn fs_error(e: KernelError, default: SyscallError) -> SyscallError {
+    match e {
+        KernelError::ALREADY_EXISTS => SyscallError::EEXIST,
+        KernelError::NO_SPACE => SyscallError::ENOSPC,
+        KernelError::FILE_TOO_LARGE => SyscallError::EFBIG,
+        KernelError::NOT_EMPTY => SyscallError::ENOTEMPTY,
+        KernelError::CORRUPTED => SyscallError::EUCLEAN,
+        KernelError::TOO_MANY_LINKS => SyscallError::EMLINK,
+        KernelError::READ_ONLY => SyscallError::EROFS,
+        _ => default
+    }
+}
+
 fn get_dentry_from_fd(proc_data: &ProcessData, fd: usize) -> core::result::Result<Arc<DirEntry>, SyscallError> {
     if fd == AT_FDCWD {
         Ok(proc_data.cwd.clone())
@@ -37,49 +54,75 @@ fn get_dentry_from_fd(proc_data: &ProcessData, fd: usize) -> core::result::Resul
 
 /* For Single File */
 
+// User data is copied through a kernel buffer of this size at most, however long user asks.
+const IO_CHUNK_SIZE: usize = PAGE_SIZE;
+
 pub fn open(parent_fd: usize, filename_buf: VirtAddr, flags: FileOpenFlags, mode: FileModes) -> SyscallResult {
     let proc = CPU::get_current_process().unwrap();
-    let mut proc_data = proc.data.lock();
-    let filename = filename_buf.into_pa(&proc_data.memory.get_pagetable()).unwrap().get_cstr();
+    let filename = proc.copy_cstr_from_user(filename_buf, PATH_MAX).map_err(|_| SyscallError::EFAULT)?;
+    let filename = filename.as_str();
+    let proc_data = proc.data.lock();
     let cwd = get_dentry_from_fd(&proc_data, parent_fd)?;
-    if flags.is_create() {
-        todo!("Create todo")
-    } else {
-        let dentry = DirEntry::from_path(filename, Some(cwd));
-        if let Some(dentry) = dentry {
-            let file = dentry.open(flags, mode);
-            if let Ok(file) = file {
-                // find fd
-                let fd = proc_data.allocate_fd();
-                proc_data.files[fd] = Some(file);
-                Ok(fd)
-            } else {
-                Err(SyscallError::EIO)
+    // Path walk may look into process state (e.g. procfs), never hold our lock there.
+    drop(proc_data);
+    let dentry = if flags.is_create() {
+        let (parent, name) = DirEntry::resolve_parent(filename, Some(cwd))?;
+        // O_EXCL never follows the last symlink, an existing link counts as existed.
+        let follow = !flags.is_nofollow() && !flags.must_create();
+        match DirEntry::resolve(name, Some(parent.clone()), follow) {
+            Ok(dentry) => {
+                if flags.must_create() {
+                    return Err(SyscallError::EEXIST);
+                }
+                dentry
             }
-        } else {
-            Err(SyscallError::EIO)
+            Err(LookupError::NotFound) => {
+                if parent.is_readonly() {
+                    return Err(SyscallError::EROFS);
+                }
+                parent.create(name).map_err(|_| SyscallError::EACCES)?
+            }
+            Err(err) => return Err(err.into())
         }
+    } else {
+        DirEntry::resolve(filename, Some(cwd), !flags.is_nofollow())?
+    };
+    if dentry.get_type() == DirEntryType::Link {
+        // Only with O_NOFOLLOW
+        return Err(SyscallError::ELOOP);
+    }
+    if (flags.is_write() || flags.contains(FileOpenFlags::O_TRUNC)) && dentry.is_readonly() {
+        return Err(SyscallError::EROFS);
     }
+    if dentry.mount_flags().contains(MountFlags::MS_NODEV) && dentry.get_inode().is_some_and(|inode| {
+        let file_type = FileModes::from_bits_truncate(inode.get_stat().mode as u32).mask_file_type();
+        file_type == FileModes::CHAR || file_type == FileModes::BLK
+    }) {
+        return Err(SyscallError::EACCES);
+    }
+    let file = dentry.open(flags, mode).map_err(|_| SyscallError::EIO)?;
+    // find fd
+    let mut proc_data = proc.data.lock();
+    let fd = proc_data.allocate_fd();
+    proc_data.files[fd] = Some(file);
+    Ok(fd)
 }
 
 pub fn close(fd: usize) -> SyscallResult {
     let proc = CPU::get_current_process().unwrap();
     let mut proc_data = proc.data.lock();
+    let file = proc_data.files.get_mut(fd).and_then(|file| file.take()).ok_or(SyscallError::EBADF)?;
+    // Closing may sleep on disk I/O, not with process locked.
+    drop(proc_data);
 
-    if let Some(Some(file)) = &proc_data.files.get(fd) {
-        if Arc::strong_count(file) > 1 {
-            // File is dupped.
-            proc_data.files[fd] = None;
-            return Ok(0);
-        }
-        if let Ok(_) = file.close() {
-            proc_data.files[fd] = None;
-            Ok(0)
-        } else {
-            Err(SyscallError::EIO)
-        }
+    if Arc::strong_count(&file) > 1 {
+        // File is dupped.
+        return Ok(0);
+    }
+    if let Ok(_) = file.close() {
+        Ok(0)
     } else {
-        Err(SyscallError::EBADF)
+        Err(SyscallError::EIO)
     }
 }
 
@@ -91,17 +134,16 @@ pub fn read(fd: usize, user_buf: VirtAddr, len: usize) -> SyscallResult {
     drop(proc_data);
 
     let mut data = vec![0u8; len];
-    if let Ok(read_size) = file.read(data.as_mut_slice()) {
-        let data_slice = data.as_slice();
-        let proc_data = proc.data.lock();
-        // TODO: read more than a page will cause problem...
-        // TODO: unwrap is not safe
-        let phy_buf = user_buf.into_pa(&proc_data.memory.get_pagetable()).unwrap().get_u8_mut(read_size);
-        phy_buf.copy_from_slice(&data_slice[..read_size]);
-        Ok(read_size)
-    } else {
+    match file.read(data.as_mut_slice()) {
+        Ok(read_size) => {
+            proc.copy_to_user(user_buf, &data[..read_size]).map_err(|_| SyscallError::EFAULT)?;
+            Ok(read_size)
+        }
+        Err(e) if e == KernelError::WOULD_BLOCK => Err(SyscallError::EAGAIN),
+        // Blocked read interrupted before anything is read.
+        Err(_) if signal::has_pending(&CPU::get_current_thread().unwrap()) => Err(SyscallError::EINTR),
         // Err(SyscallError::EIO)
-        Ok(0)
+        Err(_) => Ok(0)
     }
 }
 
@@ -110,72 +152,113 @@ pub fn write(fd: usize, user_buf: VirtAddr, len: usize) -> SyscallResult {
     let proc_data = proc.data.lock();
 
     let file = get_file_from_fd(&proc_data, fd)?;
-    let phy_buf = user_buf.into_pa(&proc_data.memory.get_pagetable()).unwrap().get_u8(len);
     drop(proc_data);
-
-    if let Ok(write_size) = file.write(phy_buf) {
-        Ok(write_size)
-    } else {
-        // Err(SyscallError::EIO)
-        Ok(0)
+    // Copied to kernel buffer, user pages may be not populated yet or cross into unrelated frames.
+    let mut buf = vec![0u8; min(len, IO_CHUNK_SIZE)];
+    let mut written = 0;
+    while written < len {
+        let chunk = min(len - written, IO_CHUNK_SIZE);
+        if proc.copy_from_user(user_buf.to_offset(written as isize), &mut buf[..chunk]).is_err() {
+            return if written != 0 { Ok(written) } else { Err(SyscallError::EFAULT) };
+        }
+        match file.write(&buf[..chunk]) {
+            Ok(write_size) => {
+                written += write_size;
+                if write_size < chunk {
+                    break;
+                }
+            }
+            // Part written is not lost.
+            Err(_) if written != 0 => break,
+            Err(e @ (KernelError::NO_SPACE | KernelError::FILE_TOO_LARGE | KernelError::READ_ONLY)) => return Err(fs_error(e, SyscallError::EIO)),
+            Err(_) if signal::has_pending(&CPU::get_current_thread().unwrap()) => return Err(SyscallError::EINTR),
+            // Err(SyscallError::EIO)
+            Err(_) => return Ok(0)
+        }
     }
+    Ok(written)
 }
 
 #[repr(C)]
+#[derive(Copy, Clone)]
 pub struct IOVec {
     pub iov_base: u64,
     pub iov_len: u64,
 }
 
+fn copy_iovecs_from_user(proc: &Process, io_vecs: VirtAddr, len: usize) -> core::result::Result<Vec<IOVec>, SyscallError> {
+    (0..len).map(|i| {
+        proc.read_user::<IOVec>(io_vecs.to_offset((i * size_of::<IOVec>()) as isize))
+            .map_err(|_| SyscallError::EFAULT)
+    }).collect()
+}
+
 pub fn readv(fd: usize, io_vecs: VirtAddr, len: usize) -> SyscallResult {
     let proc = CPU::get_current_process().unwrap();
-    let proc_data = proc.data.lock();
-    // Safety: PageTable won't drop since we not return from syscall.
-    let page_table = unsafe {
-        (proc_data.memory.get_pagetable() as *const PageTable).as_ref().unwrap()
-    };
-    let file = get_file_from_fd(&proc_data, fd)?;
-    drop(proc_data);
+    let file = get_file_from_fd(&proc.data.lock(), fd)?;
+    let io_vecs = copy_iovecs_from_user(&proc, io_vecs, len)?;
 
-    let io_vecs = io_vecs.into_pa(page_table).unwrap().get_slice::<IOVec>(len);
+    // Read to kernel buffer first, user pages could be shared (CoW).
+    let mut buf = vec![0u8; IO_CHUNK_SIZE];
     let mut size = 0;
     for io_vec in io_vecs {
         if io_vec.iov_base == 0 || io_vec.iov_len == 0 {
             continue;
         }
-        // TODO: unwrap is not safe
-        let buf = VirtAddr::from(io_vec.iov_base as usize).into_pa(page_table).unwrap().get_u8_mut(io_vec.iov_len as usize);
-        size += if let Ok(v) = file.read(buf) {
-            v
-        } else {
-            return Err(SyscallError::EIO);
-        };
+        let (base, len) = (io_vec.iov_base as usize, io_vec.iov_len as usize);
+        let mut done = 0;
+        while done < len {
+            let chunk = min(len - done, IO_CHUNK_SIZE);
+            let read_size = match file.read(&mut buf[..chunk]) {
+                Ok(read_size) => read_size,
+                // Data read is not lost.
+                Err(_) if size != 0 => return Ok(size),
+                Err(_) => return Err(SyscallError::EIO)
+            };
+            if proc.copy_to_user(VirtAddr::from(base + done), &buf[..read_size]).is_err() {
+                return if size != 0 { Ok(size) } else { Err(SyscallError::EFAULT) };
+            }
+            done += read_size;
+            size += read_size;
+            // Short read, like end of file or a pipe drained, ends readv.
+            if read_size < chunk {
+                return Ok(size);
+            }
+        }
     }
     Ok(size)
 }
 
 pub fn writev(fd: usize, io_vecs: VirtAddr, len: usize) -> SyscallResult {
     let proc = CPU::get_current_process().unwrap();
-    let proc_data = proc.data.lock();
-    // Safety: PageTable won't drop since we not return from syscall.
-    let page_table = unsafe {
-        (proc_data.memory.get_pagetable() as *const PageTable).as_ref().unwrap()
-    };
-    let file = get_file_from_fd(&proc_data, fd)?;
-    drop(proc_data);
+    let file = get_file_from_fd(&proc.data.lock(), fd)?;
+    let io_vecs = copy_iovecs_from_user(&proc, io_vecs, len)?;
 
-    let io_vecs = io_vecs.into_pa(page_table).unwrap().get_slice::<IOVec>(len);
+    let mut buf = vec![0u8; IO_CHUNK_SIZE];
     let mut size = 0;
     for io_vec in io_vecs {
         if io_vec.iov_base == 0 || io_vec.iov_len == 0 {
             continue;
         }
-        let buf = VirtAddr::from(io_vec.iov_base as usize).into_pa(page_table).unwrap().get_u8(io_vec.iov_len as usize);
-        size += if let Ok(v) = file.write(buf) {
-            v
-        } else {
-            return Err(SyscallError::EIO);
-        };
+        let (base, len) = (io_vec.iov_base as usize, io_vec.iov_len as usize);
+        let mut done = 0;
+        while done < len {
+            let chunk = min(len - done, IO_CHUNK_SIZE);
+            if proc.copy_from_user(VirtAddr::from(base + done), &mut buf[..chunk]).is_err() {
+                return if size != 0 { Ok(size) } else { Err(SyscallError::EFAULT) };
+            }
+            let write_size = match file.write(&buf[..chunk]) {
+                Ok(write_size) => write_size,
+                // Part written is not lost.
+                Err(_) if size != 0 => return Ok(size),
+                Err(_) => return Err(SyscallError::EIO)
+            };
+            done += write_size;
+            size += write_size;
+            if write_size < chunk {
+                return Ok(size);
+            }
+        }
     }
     Ok(size)
 }
@@ -202,17 +285,19 @@ pub fn linkat(old_dirfd: usize, old_path: VirtAddr, new_dirfd: usize, new_path:
     let proc_data = proc.data.lock();
     let old_dir_dentry = get_dentry_from_fd(&proc_data, old_dirfd)?;
     let new_dir_dentry = get_dentry_from_fd(&proc_data, new_dirfd)?;
-    let old_file = DirEntry::from_path(
-        old_path.into_pa(proc_data.memory.get_pagetable()).unwrap().get_cstr(),
-        Some(old_dir_dentry),
-    ).ok_or(SyscallError::ENOENT)?;
-    let (new_parent, new_filename) = DirEntry::get_parent(
-        new_path.into_pa(proc_data.memory.get_pagetable()).unwrap().get_cstr(),
-        Some(new_dir_dentry),
-    ).ok_or(SyscallError::ENOENT)?;
+    drop(proc_data);
+    let old_path = proc.copy_cstr_from_user(old_path, PATH_MAX).map_err(|_| SyscallError::EFAULT)?;
+    let new_path = proc.copy_cstr_from_user(new_path, PATH_MAX).map_err(|_| SyscallError::EFAULT)?;
+    let (old_path, new_path) = (old_path.as_str(), new_path.as_str());
+    // Like Linux, old path is not dereferenced unless asked.
+    let old_file = DirEntry::resolve(old_path, Some(old_dir_dentry), flags & AT_SYMLINK_FOLLOW != 0)?;
+    let (new_parent, new_filename) = DirEntry::resolve_parent(new_path, Some(new_dir_dentry))?;
+    if new_parent.is_readonly() {
+        return Err(SyscallError::EROFS);
+    }
 
     if let Some(inode) = old_file.get_inode() {
-        let _ = new_parent.link(inode, new_filename).map_err(|_| SyscallError::EPERM)?;
+        let _ = new_parent.link(inode, new_filename).map_err(|e| fs_error(e, SyscallError::EPERM))?;
         Ok(0)
     } else {
         // Cannot link vfs entry
@@ -220,99 +305,266 @@ pub fn linkat(old_dirfd: usize, old_path: VirtAddr, new_dirfd: usize, new_path:
     }
 }
 
+pub fn symlinkat(target: VirtAddr, new_dirfd: usize, link_path: VirtAddr) -> SyscallResult {
+    let proc = CPU::get_current_process().unwrap();
+    let dir_dentry = get_dentry_from_fd(&proc.data.lock(), new_dirfd)?;
+    let target = proc.copy_cstr_from_user(target, PATH_MAX).map_err(|_| SyscallError::EFAULT)?;
+    let link_path = proc.copy_cstr_from_user(link_path, PATH_MAX).map_err(|_| SyscallError::EFAULT)?;
+    if target.is_empty() {
+        return Err(SyscallError::ENOENT);
+    }
+    let (parent, name) = DirEntry::resolve_parent(&link_path, Some(dir_dentry))?;
+    if DirEntry::resolve(name, Some(parent.clone()), false).is_ok() {
+        return Err(SyscallError::EEXIST);
+    }
+    if parent.is_readonly() {
+        return Err(SyscallError::EROFS);
+    }
+    parent.symlink(name, &target).map_err(|_| SyscallError::EPERM)?;
+    Ok(0)
+}
+
+pub fn readlinkat(dir_fd: usize, path: VirtAddr, buf: VirtAddr, buf_size: usize) -> SyscallResult {
+    let proc = CPU::get_current_process().unwrap();
+    let dir_dentry = get_dentry_from_fd(&proc.data.lock(), dir_fd)?;
+    let path = proc.copy_cstr_from_user(path, PATH_MAX).map_err(|_| SyscallError::EFAULT)?;
+    if buf_size == 0 {
+        return Err(SyscallError::EINVAL);
+    }
+    let dentry = DirEntry::resolve(&path, Some(dir_dentry), false)?;
+    if dentry.get_type() != DirEntryType::Link {
+        return Err(SyscallError::EINVAL);
+    }
+    let target = dentry.readlink().map_err(|_| SyscallError::EIO)?;
+    // Truncated silently and without NUL, as readlink(2) does.
+    let len = min(target.len(), buf_size);
+    proc.copy_to_user(buf, &target.as_bytes()[..len]).map_err(|_| SyscallError::EFAULT)?;
+    Ok(len)
+}
+
+pub fn unlinkat(dir_fd: usize, path: VirtAddr, flags: usize) -> SyscallResult {
+    let proc = CPU::get_current_process().unwrap();
+    let dir_dentry = get_dentry_from_fd(&proc.data.lock(), dir_fd)?;
+    let path = proc.copy_cstr_from_user(path, PATH_MAX).map_err(|_| SyscallError::EFAULT)?;
+    let path = path.as_str();
+    let remove_dir = flags & AT_REMOVEDIR != 0;
+    // Trailing slash only makes sense for directory.
+    let trimmed = path.trim_end_matches('/');
+    let must_be_dir = trimmed.len() != path.len();
+    let (parent, name) = DirEntry::resolve_parent(if trimmed.is_empty() { "/" } else { trimmed }, Some(dir_dentry))?;
+    if name == "." || name == ".." || name.is_empty() {
+        return Err(if remove_dir { SyscallError::EINVAL } else { SyscallError::EISDIR });
+    }
+    let dentry = DirEntry::resolve(name, Some(parent.clone()), false)?;
+    if parent.is_readonly() {
+        return Err(SyscallError::EROFS);
+    }
+    let is_dir = dentry.get_type() == DirEntryType::Dir;
+    if remove_dir {
+        if !is_dir {
+            return Err(SyscallError::ENOTDIR);
+        }
+        if dentry.is_mount_point() {
+            return Err(SyscallError::EBUSY);
+        }
+        if !dentry.is_empty_dir().map_err(|e| fs_error(e, SyscallError::EIO))? {
+            return Err(SyscallError::ENOTEMPTY);
+        }
+        parent.rmdir(name).map_err(|e| fs_error(e, SyscallError::EIO))?;
+    } else {
+        if is_dir {
+            return Err(SyscallError::EISDIR);
+        }
+        if must_be_dir {
+            return Err(SyscallError::ENOTDIR);
+        }
+        parent.unlink(name).map_err(|e| fs_error(e, SyscallError::EIO))?;
+    }
+    Ok(0)
+}
+
+pub fn renameat2(old_dirfd: usize, old_path: VirtAddr, new_dirfd: usize, new_path: VirtAddr, flags: usize) -> SyscallResult {
+    let flags = RenameFlags::from_bits(flags as u32).ok_or(SyscallError::EINVAL)?;
+    if flags.contains(RenameFlags::RENAME_NOREPLACE | RenameFlags::RENAME_EXCHANGE)
+        || flags.contains(RenameFlags::RENAME_WHITEOUT) {
+        return Err(SyscallError::EINVAL);
+    }
+    let exchange = flags.contains(RenameFlags::RENAME_EXCHANGE);
+    let proc = CPU::get_current_process().unwrap();
+    let proc_data = proc.data.lock();
+    let old_dir_dentry = get_dentry_from_fd(&proc_data, old_dirfd)?;
+    let new_dir_dentry = get_dentry_from_fd(&proc_data, new_dirfd)?;
+    drop(proc_data);
+    let old_path = proc.copy_cstr_from_user(old_path, PATH_MAX).map_err(|_| SyscallError::EFAULT)?;
+    let new_path = proc.copy_cstr_from_user(new_path, PATH_MAX).map_err(|_| SyscallError::EFAULT)?;
+    let (old_parent, old_name) = DirEntry::resolve_parent(old_path.trim_end_matches('/'), Some(old_dir_dentry))?;
+    let (new_parent, new_name) = DirEntry::resolve_parent(new_path.trim_end_matches('/'), Some(new_dir_dentry))?;
+    for name in [old_name, new_name] {
+        if name == "." || name == ".." || name.is_empty() {
+            return Err(SyscallError::EBUSY);
+        }
+    }
+    let source = DirEntry::resolve(old_name, Some(old_parent.clone()), false)?;
+    let target = match DirEntry::resolve(new_name, Some(new_parent.clone()), false) {
+        Ok(target) => Some(target),
+        Err(LookupError::NotFound) if !exchange => None,
+        Err(err) => return Err(err.into())
+    };
+    if let Some(target) = &target {
+        if flags.contains(RenameFlags::RENAME_NOREPLACE) {
+            return Err(SyscallError::EEXIST);
+        }
+        if Arc::ptr_eq(target, &source) {
+            // Same entry, nothing to do.
+            return Ok(0);
+        }
+    }
+    if !old_parent.same_filesystem(&new_parent) {
+        return Err(SyscallError::EXDEV);
+    }
+    if old_parent.is_readonly() {
+        return Err(SyscallError::EROFS);
+    }
+    if source.is_mount_point() || target.as_ref().is_some_and(|target| target.is_mount_point()) {
+        return Err(SyscallError::EBUSY);
+    }
+    let source_is_dir = source.get_type() == DirEntryType::Dir;
+    // Directory can not be moved into itself.
+    if source_is_dir && source.is_ancestor_of(&new_parent) {
+        return Err(SyscallError::EINVAL);
+    }
+    if let Some(target) = &target {
+        let target_is_dir = target.get_type() == DirEntryType::Dir;
+        if exchange {
+            if target_is_dir && target.is_ancestor_of(&old_parent) {
+                return Err(SyscallError::EINVAL);
+            }
+        } else if source_is_dir && !target_is_dir {
+            return Err(SyscallError::ENOTDIR);
+        } else if !source_is_dir && target_is_dir {
+            return Err(SyscallError::EISDIR);
+        } else if target_is_dir && !target.is_empty_dir().map_err(|e| fs_error(e, SyscallError::EIO))? {
+            return Err(SyscallError::ENOTEMPTY);
+        }
+    }
+    old_parent.rename(old_name, new_parent, new_name, flags).map_err(|e| fs_error(e, SyscallError::EIO))?;
+    Ok(0)
+}
+
 /* For Directory */
 
 pub fn mkdirat(dir_fd: usize, path_buf: VirtAddr, mode: usize) -> SyscallResult {
     let proc = CPU::get_current_process().unwrap();
-    let mut proc_data = proc.data.lock();
-    let path = path_buf.into_pa(&proc_data.memory.get_pagetable()).unwrap().get_cstr();
-    let dentry = get_dentry_from_fd(&proc_data, dir_fd)?;
-    let (parent, dir_name) = DirEntry::get_parent(path, Some(dentry)).ok_or(SyscallError::ENOENT)?;
-    if let Ok(_) = parent.mkdir(dir_name) {
-        Ok(0)
-    } else {
-        return Err(SyscallError::EIO);
+    let path = proc.copy_cstr_from_user(path_buf, PATH_MAX).map_err(|_| SyscallError::EFAULT)?;
+    let dentry = get_dentry_from_fd(&proc.data.lock(), dir_fd)?;
+    let (parent, dir_name) = DirEntry::resolve_parent(&path, Some(dentry))?;
+    if parent.is_readonly() {
+        return Err(SyscallError::EROFS);
     }
+    parent.mkdir(dir_name).map_err(|e| fs_error(e, SyscallError::EIO))?;
+    Ok(0)
 }
 
 /* For Filesystem */
 
 pub fn mount(dev_buf: VirtAddr, mount_point_buf: VirtAddr, filesystem_buf: VirtAddr, flags: usize, data_ptr: VirtAddr) -> SyscallResult {
     let proc = CPU::get_current_process().unwrap();
-    let mut proc_data = proc.data.lock();
-    let dev = dev_buf.into_pa(&proc_data.memory.get_pagetable()).unwrap().get_cstr();
-    let mount_point = mount_point_buf.into_pa(&proc_data.memory.get_pagetable()).unwrap().get_cstr();
-    let filesystem = filesystem_buf.into_pa(&proc_data.memory.get_pagetable()).unwrap().get_cstr();
+    let dev = proc.copy_cstr_from_user(dev_buf, PATH_MAX).map_err(|_| SyscallError::EFAULT)?;
+    let mount_point = proc.copy_cstr_from_user(mount_point_buf, PATH_MAX).map_err(|_| SyscallError::EFAULT)?;
+    let filesystem = proc.copy_cstr_from_user(filesystem_buf, PATH_MAX).map_err(|_| SyscallError::EFAULT)?;
 
-    // flags and data is not yet impl.
-    let cwd = proc_data.cwd.clone();
-    drop(proc_data);
+    // data is not yet impl.
+    let flags = MountFlags::from_bits_truncate(flags as u32);
+    let cwd = proc.data.lock().cwd.clone();
 
-    match fs::mount(Some(cwd), dev, mount_point, filesystem) {
+    match fs::mount(Some(cwd), &dev, &mount_point, &filesystem, flags) {
         Ok(_) => { Ok(0) }
         Err(err) => {
             info!("Mounting {} to {} with {} failed: {}", dev, mount_point, filesystem, err);
-            Err(SyscallError::EIO)
+            Err(fs_error(err, SyscallError::EIO))
         }
     }
 }
 
-pub fn fstat(fd: usize, kstat_buf: VirtAddr) -> SyscallResult {
+pub fn umount2(target: VirtAddr, flags: usize) -> SyscallResult {
+    if flags & !(MNT_FORCE | MNT_DETACH | MNT_EXPIRE | UMOUNT_NOFOLLOW) != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+    // Expiry marks are not kept. MNT_FORCE only matters to network filesystems, ignored.
+    if flags & MNT_EXPIRE != 0 {
+        return Err(SyscallError::EINVAL);
+    }
     let proc = CPU::get_current_process().unwrap();
-    let mut proc_data = proc.data.lock();
+    let target = proc.copy_cstr_from_user(target, PATH_MAX).map_err(|_| SyscallError::EFAULT)?;
+    let cwd = proc.data.lock().cwd.clone();
+    let mount_point = DirEntry::resolve(&target, Some(cwd), flags & UMOUNT_NOFOLLOW == 0)?;
+    fs::unmount(mount_point, flags & MNT_DETACH != 0)?;
+    Ok(0)
+}
+
+pub fn sync() -> SyscallResult {
+    // sync(2) never fails.
+    if let Err(err) = fs::sync() {
+        info!("Sync failed: {}", err);
+    }
+    Ok(0)
+}
+
+// fdatasync comes here too, metadata is always written along.
+pub fn fsync(fd: usize) -> SyscallResult {
+    let proc = CPU::get_current_process().unwrap();
+    let proc_data = proc.data.lock();
+    let file = get_file_from_fd(&proc_data, fd)?;
+    drop(proc_data);
+    file.sync().map_err(|_| SyscallError::EIO)?;
+    Ok(0)
+}
 
-    let dentry = get_dentry_from_fd(&proc_data, fd)?;
+pub fn fstat(fd: usize, kstat_buf: VirtAddr) -> SyscallResult {
+    let proc = CPU::get_current_process().unwrap();
+    let dentry = get_dentry_from_fd(&proc.data.lock(), fd)?;
     let inode = dentry.get_inode();
     let stat = inode.map(|inode| inode.get_stat()).unwrap_or(InodeStat::vfs_inode_stat());
 
-    // TODO: unwrap is not safe.
-    let kstat = kstat_buf.into_pa(proc_data.memory.get_pagetable()).unwrap().get_ref_mut::<KernelStat>();
-    *kstat = KernelStat {
+    let kstat = KernelStat {
         st_dev: 0,
         st_ino: stat.ino as u64,
         st_mode: stat.mode as u32,
         st_nlink: stat.nlink as u32,
         st_uid: 0,
         st_gid: 0,
-        st_rdev: 0,
+        st_rdev: stat.rdev as u64,
         __pad1: 0,
         st_size: stat.size as i64,
         st_blksize: stat.block_size as i32,
         __pad2: 0,
         st_blocks: ((stat.size + stat.block_size - 1) / stat.block_size) as i64,
-        st_atim: Timespec { tv_sec: 0, tv_nsec: 0 },
-        st_mtim: Timespec { tv_sec: 0, tv_nsec: 0 },
-        st_ctim: Timespec { tv_sec: 0, tv_nsec: 0 },
+        st_atim: stat.atime.into(),
+        st_mtim: stat.mtime.into(),
+        st_ctim: stat.ctime.into(),
         __glibc_reserved: [0, 0],
     };
+    proc.write_user(kstat_buf, &kstat).map_err(|_| SyscallError::EFAULT)?;
 
     Ok(0)
 }
 
-pub fn newfstatat(dir_fd: usize, path: VirtAddr, kstat_buf: VirtAddr) -> SyscallResult {
+pub fn newfstatat(dir_fd: usize, path: VirtAddr, kstat_buf: VirtAddr, flags: usize) -> SyscallResult {
     let proc = CPU::get_current_process().unwrap();
-    let mut proc_data = proc.data.lock();
-
-    let dentry = get_dentry_from_fd(&proc_data, dir_fd)?;
-    let path = path.into_pa(proc_data.memory.get_pagetable()).unwrap().get_cstr();
-    let dentry = if let Some(v) = DirEntry::from_path(path, Some(dentry)) {
-        v
-    } else {
-        return Err(SyscallError::ENOENT);
-    };
+    let dentry = get_dentry_from_fd(&proc.data.lock(), dir_fd)?;
+    let path = proc.copy_cstr_from_user(path, PATH_MAX).map_err(|_| SyscallError::EFAULT)?;
+    let dentry = DirEntry::resolve(&path, Some(dentry), flags & AT_SYMLINK_NOFOLLOW == 0)?;
     let inode = dentry.get_inode();
     let stat = inode.map(|inode| inode.get_stat()).unwrap_or(InodeStat::vfs_inode_stat());
 
-    // TODO: unwrap is not safe.
-    let kstat = kstat_buf.into_pa(proc_data.memory.get_pagetable()).unwrap().get_ref_mut::<KernelStat>();
-    *kstat = KernelStat {
+    let kstat = KernelStat {
         st_dev: 0,
         st_ino: stat.ino as u64,
         st_mode: stat.mode as u32,
         st_nlink: stat.nlink as u32,
         st_uid: 0,
         st_gid: 0,
-        st_rdev: 0,
+        st_rdev: stat.rdev as u64,
         __pad1: 0,
         st_size: stat.size as i64,
         st_blksize: stat.block_size as i32,
@@ -322,25 +574,24 @@ pub fn newfstatat(dir_fd: usize, path: VirtAddr, kstat_buf: VirtAddr) -> Syscall
         } else {
             ((stat.size + stat.block_size - 1) / stat.block_size) as i64
         },
-        st_atim: Timespec { tv_sec: 0, tv_nsec: 0 },
-        st_mtim: Timespec { tv_sec: 0, tv_nsec: 0 },
-        st_ctim: Timespec { tv_sec: 0, tv_nsec: 0 },
+        st_atim: stat.atime.into(),
+        st_mtim: stat.mtime.into(),
+        st_ctim: stat.ctime.into(),
         __glibc_reserved: [0, 0],
     };
+    proc.write_user(kstat_buf, &kstat).map_err(|_| SyscallError::EFAULT)?;
 
     Ok(0)
 }
 
 pub fn getdents64(fd: usize, buf: VirtAddr, len: usize) -> SyscallResult {
     let proc = CPU::get_current_process().unwrap();
-    let mut proc_data = proc.data.lock();
-
-    let file = get_file_from_fd(&proc_data, fd)?;
+    let file = get_file_from_fd(&proc.data.lock(), fd)?;
     let dentry = file.get_dentry().map_err(|_| SyscallError::ENOENT)?;
     let mut i = file.seek(0, SeekPosition::Cur).unwrap(); // get current offset
+    // Entries are collected without our lock, directory may be built from process state.
+    let mut dirents = vec![];
     let mut total_read = 0;
-    let mut cur = buf;
-    let pg_table = proc_data.memory.get_pagetable();
     loop {
         if let Ok(dentry) = dentry.get_child(i) {
             if let Some(dentry) = dentry {
@@ -348,17 +599,8 @@ pub fn getdents64(fd: usize, buf: VirtAddr, len: usize) -> SyscallResult {
                 if total_read + dirent64.len() > len {
                     break;
                 }
-                // TODO: unwrap is not safe.
-                let pa = cur.clone().into_pa(pg_table).unwrap();
-                if PhyPageId::from(pa.to_offset(dirent64.len() as isize)) != PhyPageId::from(pa) {
-                    cur.access_continuously(pg_table, dirent64.len(), |pa| {
-                        pa.get_u8_mut(dirent64.len()).copy_from_slice(dirent64.as_slice());
-                    });
-                } else {
-                    pa.get_u8_mut(dirent64.len()).copy_from_slice(dirent64.as_slice());
-                }
-                cur = cur.to_offset(dirent64.len() as isize);
                 total_read += dirent64.len();
+                dirents.extend_from_slice(dirent64.as_slice());
                 i += 1;
             } else {
                 break;
@@ -367,6 +609,7 @@ pub fn getdents64(fd: usize, buf: VirtAddr, len: usize) -> SyscallResult {
             return Err(SyscallError::EIO);
         }
     }
+    proc.copy_to_user(buf, dirents.as_slice()).map_err(|_| SyscallError::EFAULT)?;
 
     file.seek(i as isize, SeekPosition::Set).unwrap();
     Ok(total_read)
@@ -382,10 +625,10 @@ pub fn pipe2(fds: VirtAddr, options: usize) -> SyscallResult {
     proc_data.files[fd_read] = Some(Arc::new(file_read));
     let fd_write = proc_data.allocate_fd();
     proc_data.files[fd_write] = Some(Arc::new(file_write));
+    drop(proc_data);
 
-    let ufds = fds.into_pa(proc_data.memory.get_pagetable()).unwrap().get_slice_mut::<u32>(2);
-    ufds[0] = fd_read as u32;
-    ufds[1] = fd_write as u32;
+    let ufds = [fd_read as u32, fd_write as u32];
+    proc.write_user(fds, &ufds).map_err(|_| SyscallError::EFAULT)?;
 
     Ok(0)
 }
@@ -0,0 +1,77 @@
+use crate::cpu::CPU;
+use crate::device::{clock, timer};
+use crate::memory::{Addr, PhyAddr, VirtAddr};
+use crate::process::Process;
+use crate::process::futex::{self, FutexKey, FUTEX_BITSET_MATCH_ANY};
+use crate::syscall::c::Timespec;
+use crate::syscall::error::{SyscallError, SyscallResult};
+
+const FUTEX_WAIT: usize = 0;
+const FUTEX_WAKE: usize = 1;
+const FUTEX_REQUEUE: usize = 3;
+const FUTEX_CMP_REQUEUE: usize = 4;
+const FUTEX_WAIT_BITSET: usize = 9;
+const FUTEX_WAKE_BITSET: usize = 10;
+const FUTEX_PRIVATE_FLAG: usize = 128;
+const FUTEX_CLOCK_REALTIME: usize = 256;
+
+// Word of a file page not read yet is faulted in with process unlocked.
+fn key_of(proc: &Process, uaddr: VirtAddr, private: bool) -> Result<(FutexKey, PhyAddr), SyscallError> {
+    proc.access_user(|proc_data| {
+        futex::key_of(proc, &mut proc_data.memory, uaddr, private).ok_or("Futex word is not mapped.".into())
+    }).map_err(|_| SyscallError::EFAULT)
+}
+
+pub fn futex(uaddr: VirtAddr, op: usize, val: usize, timeout: usize, uaddr2: VirtAddr, val3: usize) -> SyscallResult {
+    if uaddr.get_addr() % 4 != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+    let cmd = op & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME);
+    let private = op & FUTEX_PRIVATE_FLAG != 0;
+    let proc = CPU::get_current_process().unwrap();
+    let (key, word) = key_of(&proc, uaddr, private)?;
+
+    match cmd {
+        FUTEX_WAIT | FUTEX_WAIT_BITSET => {
+            let bitset = if cmd == FUTEX_WAIT { FUTEX_BITSET_MATCH_ANY } else { val3 as u32 };
+            if bitset == 0 {
+                return Err(SyscallError::EINVAL);
+            }
+            let deadline = if timeout == 0 {
+                None
+            } else {
+                let ts = proc.read_user::<Timespec>(VirtAddr::from(timeout))
+                    .map_err(|_| SyscallError::EFAULT)?;
+                let time = ts.to_duration().ok_or(SyscallError::EINVAL)?;
+                // FUTEX_WAIT takes relative timeout, FUTEX_WAIT_BITSET takes absolute one on
+                // monotonic clock, or on realtime clock with FUTEX_CLOCK_REALTIME.
+                Some(if cmd == FUTEX_WAIT {
+                    timer::current_time() + time
+                } else if op & FUTEX_CLOCK_REALTIME != 0 {
+                    time.saturating_sub(clock::boot_epoch())
+                } else {
+                    time
+                })
+            };
+            futex::wait(key, word, val as u32, bitset, deadline)
+        }
+        FUTEX_WAKE | FUTEX_WAKE_BITSET => {
+            let bitset = if cmd == FUTEX_WAKE { FUTEX_BITSET_MATCH_ANY } else { val3 as u32 };
+            if bitset == 0 {
+                return Err(SyscallError::EINVAL);
+            }
+            Ok(futex::wake(key, val, bitset))
+        }
+        FUTEX_REQUEUE | FUTEX_CMP_REQUEUE => {
+            if uaddr2.get_addr() % 4 != 0 {
+                return Err(SyscallError::EINVAL);
+            }
+            let (key2, _) = key_of(&proc, uaddr2, private)?;
+            // Timeout argument is the requeue count for requeue operations.
+            let expected = if cmd == FUTEX_CMP_REQUEUE { Some(val3 as u32) } else { None };
+            let (woken, requeued) = futex::requeue(key, word, key2, val, timeout, expected)?;
+            Ok(if cmd == FUTEX_CMP_REQUEUE { woken + requeued } else { woken })
+        }
+        _ => Err(SyscallError::ENOSYS)
+    }
+}
@@ -1,13 +1,37 @@
 use bitflags::{bitflags, Flags};
+use crate::config::KERNEL_SPACE_BASE;
+use crate::core::IntrlockGuard;
 use crate::cpu::CPU;
-use crate::filesystem::SeekPosition;
+use crate::filesystem::{FileModes, FileOpenFlags};
 use crate::memory::{Addr, PAGE_SIZE, PTEFlags, VirtAddr, VirtPageId};
+use crate::process::{ProcessData, VmaBacking};
 use crate::syscall::error::{SyscallError, SyscallResult};
 
 pub fn brk(addr: usize) -> SyscallResult {
     let proc = CPU::get_current_process().unwrap();
     let mut proc_data = proc.data.lock();
-    Ok(proc_data.memory.set_brk(addr.into()))
+    let brk = proc_data.memory.set_brk(addr.into());
+    unlock_and_write_back(proc_data);
+    Ok(brk)
+}
+
+/// Release process lock, then write back dirty pages queued while changing mappings.
+/// Writing may sleep on disk I/O, which must not happen with process locked.
+fn unlock_and_write_back(mut proc_data: IntrlockGuard<ProcessData>) {
+    let pages = proc_data.memory.take_writeback();
+    drop(proc_data);
+    for page in pages {
+        page.write();
+    }
+}
+
+// Pages of [addr, addr + len) rounded up, None if it wraps or reaches kernel space.
+fn user_pages(addr: VirtAddr, len: usize) -> Option<usize> {
+    let end = addr.addr.checked_add(len)?.checked_add(PAGE_SIZE - 1)? / PAGE_SIZE * PAGE_SIZE;
+    if end > KERNEL_SPACE_BASE {
+        return None;
+    }
+    Some((end - addr.addr) / PAGE_SIZE)
 }
 
 
@@ -29,74 +53,144 @@ bitflags! {
     }
 }
 
+impl ProtFlags {
+    /// PROT_NONE gives no R/W/X, such pages are never mapped in page table.
+    fn to_pte_flags(&self) -> PTEFlags {
+        let mut pte_flags = PTEFlags::U;
+        // No write-only page in Sv39.
+        if self.intersects(ProtFlags::PROT_READ | ProtFlags::PROT_WRITE) {
+            pte_flags |= PTEFlags::R;
+        }
+        if self.contains(ProtFlags::PROT_WRITE) {
+            pte_flags |= PTEFlags::W;
+        }
+        if self.contains(ProtFlags::PROT_EXEC) {
+            pte_flags |= PTEFlags::X;
+        }
+        pte_flags
+    }
+}
+
 pub fn mmap(addr: VirtAddr, len: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> SyscallResult {
+    if len > KERNEL_SPACE_BASE {
+        return Err(SyscallError::ENOMEM);
+    }
     let len = VirtAddr::from(len).round_up().addr;
-    if len == 0 {
-        return Ok(-1isize as usize);
+    if len == 0 || offset % PAGE_SIZE != 0 {
+        return Err(SyscallError::EINVAL);
     }
-    let offset = offset as isize;
     let proc = CPU::get_current_process().unwrap();
-    let mut proc_data = proc.data.lock();
 
-    let prot = ProtFlags::from_bits(prot).unwrap();
-    let flags = MapFlags::from_bits(flags).unwrap();
+    let prot = ProtFlags::from_bits(prot).ok_or(SyscallError::EINVAL)?;
+    let flags = MapFlags::from_bits_truncate(flags);
+    let shared = match (flags.contains(MapFlags::MAP_SHARED), flags.contains(MapFlags::MAP_PRIVATE)) {
+        (true, false) => true,
+        (false, true) => false,
+        _ => return Err(SyscallError::EINVAL)
+    };
 
-    let mut pte_flags = PTEFlags::U;
-    if prot.contains(ProtFlags::PROT_READ) {
-        pte_flags |= PTEFlags::R;
-    }
-    if prot.contains(ProtFlags::PROT_WRITE) {
-        pte_flags |= PTEFlags::W;
-    }
-    if prot.contains(ProtFlags::PROT_EXEC) {
-        pte_flags |= PTEFlags::X;
-    }
+    let pte_flags = prot.to_pte_flags();
 
-    let pages_count = (len + addr.addr % PAGE_SIZE) / PAGE_SIZE;
-    let virt_addr = if flags.contains(MapFlags::MAP_FIXED) {
-        // 如果是FIXED，重叠区域会被释放然后重新映射
-        Some(proc_data.memory.mmap(Some(addr), pages_count, pte_flags).unwrap())
+    let backing = if flags.contains(MapFlags::MAP_ANONYMOUS) {
+        VmaBacking::Anonymous
     } else {
-        proc_data.memory.mmap(None, pages_count, pte_flags).ok()
+        let file = proc.data.lock().files.get(fd).cloned().flatten().ok_or(SyscallError::EBADF)?;
+        // Mapping never gives more access than fd has.
+        let fd_flags = file.open_flags().ok_or(SyscallError::ENODEV)?;
+        if !fd_flags.is_read() || shared && prot.contains(ProtFlags::PROT_WRITE) && !fd_flags.is_write() {
+            return Err(SyscallError::EACCES);
+        }
+        // Mapping owns its own file position, so page-in never moves the one of fd.
+        // Only writable shared mapping writes back to file. Opening may sleep, process is not locked.
+        let open_flags = if shared && prot.contains(ProtFlags::PROT_WRITE) {
+            FileOpenFlags::O_RDWR
+        } else {
+            FileOpenFlags::O_RDONLY
+        };
+        let file = file.get_dentry()
+            .and_then(|dentry| dentry.open(open_flags, FileModes::from_bits(0).unwrap()))
+            .map_err(|_| SyscallError::ENODEV)?;
+        VmaBacking::File { file, offset }
     };
 
-    if let Some(start_addr) = virt_addr {
-        if addr.addr % PAGE_SIZE != 0 || offset != 0 {
-            todo!("Unaligned mmap read file is not impl yet.");
+    let pages_count = len / PAGE_SIZE;
+    let addr = if flags.contains(MapFlags::MAP_FIXED) {
+        if addr.addr % PAGE_SIZE != 0 {
+            return Err(SyscallError::EINVAL);
         }
-        if !flags.contains(MapFlags::MAP_ANONYMOUS) {
-            let vpn = VirtPageId::from(start_addr);
-            if let Some(Some(file)) = proc_data.files.get(fd) {
-                file.seek(offset, SeekPosition::Set).expect("Seek failed");
-                for pg in vpn.id..vpn.id + pages_count {
-                    let this_vpn = VirtPageId::from(pg);
-                    file.read(
-                        VirtAddr::from(this_vpn).into_pa(proc_data.memory.get_pagetable()).unwrap()
-                            .get_slice_mut::<u8>(PAGE_SIZE)
-                    ).expect("Read failed");
-                }
-            } else {
-                // Failed to open file
-                for pg in vpn.id..vpn.id + pages_count {
-                    proc_data.memory.unmap(VirtPageId::from(pg)).expect("Failed to unmap mmap failed pages");
-                }
-                return Err(SyscallError::EIO);
-            }
+        // Kernel is mapped from KERNEL_SPACE_BASE in every page table.
+        if addr.addr.checked_add(len).map_or(true, |end| end > KERNEL_SPACE_BASE) {
+            return Err(SyscallError::ENOMEM);
         }
-        Ok(start_addr.get_addr())
+        // 如果是FIXED，重叠区域会被释放然后重新映射
+        Some(addr)
     } else {
-        Err(SyscallError::ENOMEM)
-    }
+        None
+    };
+    // Pages are populated on first touch, except shared anonymous ones.
+    let mut proc_data = proc.data.lock();
+    let result = proc_data.memory.mmap(addr, pages_count, pte_flags, backing, shared)
+        .map(|start_addr| start_addr.get_addr())
+        .map_err(|_| SyscallError::ENOMEM);
+    // Mappings replaced by MAP_FIXED are written back.
+    unlock_and_write_back(proc_data);
+    result
 }
 
 pub fn munmap(addr: VirtAddr, len: usize) -> SyscallResult {
-    let pages_count = (len + addr.addr % PAGE_SIZE) / PAGE_SIZE;
+    if addr.addr % PAGE_SIZE != 0 || len == 0 {
+        return Err(SyscallError::EINVAL);
+    }
+    let pages_count = user_pages(addr, len).ok_or(SyscallError::EINVAL)?;
+    let proc = CPU::get_current_process().unwrap();
+    let mut proc_data = proc.data.lock();
+    proc_data.memory.unmap_range(VirtPageId::from(addr), pages_count);
+    unlock_and_write_back(proc_data);
+    Ok(0)
+}
+
+pub fn mprotect(addr: VirtAddr, len: usize, prot: usize) -> SyscallResult {
+    if addr.addr % PAGE_SIZE != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+    let prot = ProtFlags::from_bits(prot).ok_or(SyscallError::EINVAL)?;
+    let pages_count = user_pages(addr, len).ok_or(SyscallError::ENOMEM)?;
+    if pages_count == 0 {
+        return Ok(0);
+    }
     let proc = CPU::get_current_process().unwrap();
     let mut proc_data = proc.data.lock();
-    let first_vpn = VirtPageId::from(addr);
-    for pg in first_vpn.id .. first_vpn.id + pages_count {
-        proc_data.memory.unmap(VirtPageId::from(pg)).expect("Failed to unmap pages in munmap.");
+    let changed = proc_data.memory.mprotect(VirtPageId::from(addr), pages_count, prot.to_pte_flags());
+    unlock_and_write_back(proc_data);
+    if changed {
+        Ok(0)
+    } else {
+        Err(SyscallError::ENOMEM)
+    }
+}
+
+bitflags! {
+    pub struct MsyncFlags: usize {
+        const MS_ASYNC = 0x1;
+        const MS_INVALIDATE = 0x2;
+        const MS_SYNC = 0x4;
     }
+}
 
-    Ok(0)
-}
\ No newline at end of file
+pub fn msync(addr: VirtAddr, len: usize, flags: usize) -> SyscallResult {
+    let flags = MsyncFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+    if addr.addr % PAGE_SIZE != 0 || flags.contains(MsyncFlags::MS_ASYNC | MsyncFlags::MS_SYNC) {
+        return Err(SyscallError::EINVAL);
+    }
+    let pages_count = user_pages(addr, len).ok_or(SyscallError::ENOMEM)?;
+    let proc = CPU::get_current_process().unwrap();
+    let mut proc_data = proc.data.lock();
+    let covered = proc_data.memory.msync(VirtPageId::from(addr), pages_count);
+    // Writeback is always synchronous.
+    unlock_and_write_back(proc_data);
+    if covered {
+        Ok(0)
+    } else {
+        Err(SyscallError::ENOMEM)
+    }
+}
@@ -5,6 +5,10 @@ mod process;
 mod custom;
 mod memory;
 mod dummy;
+mod signal;
+mod futex;
+mod sched;
+mod time;
 mod c;
 mod error;
 
@@ -51,25 +55,69 @@ pub fn syscall_handler(syscall: Syscall, args: &[usize; 6]) -> usize {
         Syscall::close => do_syscall!(file::close, args, 1),
         Syscall::mkdirat => do_syscall!(file::mkdirat, args, 3),
         Syscall::mount => do_syscall!(file::mount, args, 5),
+        Syscall::umount2 => do_syscall!(file::umount2, args, 2),
+        Syscall::sync => do_syscall!(file::sync, args, 0),
+        Syscall::fsync => do_syscall!(file::fsync, args, 1),
+        Syscall::fdatasync => do_syscall!(file::fsync, args, 1),
         Syscall::fstat => do_syscall!(file::fstat, args, 2),
-        Syscall::newfstatat => do_syscall!(file::newfstatat, args, 3),
+        Syscall::newfstatat => do_syscall!(file::newfstatat, args, 4),
         Syscall::getdents64 => do_syscall!(file::getdents64, args, 3),
         Syscall::linkat => do_syscall!(file::linkat, args, 5),
+        Syscall::symlinkat => do_syscall!(file::symlinkat, args, 3),
+        Syscall::readlinkat => do_syscall!(file::readlinkat, args, 4),
+        Syscall::unlinkat => do_syscall!(file::unlinkat, args, 3),
+        Syscall::renameat2 => do_syscall!(file::renameat2, args, 5),
         Syscall::pipe2 => do_syscall!(file::pipe2, args, 2),
         Syscall::dup => do_syscall!(file::dup, args, 1),
         Syscall::dup3 => do_syscall!(file::dup3, args, 2),
         /* Process */
         Syscall::exit => do_syscall!(process::exit, args, 1),
-        Syscall::clone => do_syscall!(process::clone, args, 2),
+        Syscall::exit_group => do_syscall!(process::exit_group, args, 1),
+        Syscall::clone => do_syscall!(process::clone, args, 5),
         Syscall::execve => do_syscall!(process::execve, args, 3),
-        Syscall::wait4 => do_syscall!(process::wait_for, args, 3),
+        Syscall::wait4 => do_syscall!(process::wait_for, args, 4),
         Syscall::getpid => do_syscall!(process::getpid, args, 0),
         Syscall::getppid => do_syscall!(process::getppid, args, 0),
+        Syscall::gettid => do_syscall!(process::gettid, args, 0),
+        Syscall::set_tid_address => do_syscall!(process::set_tid_address, args, 1),
+        Syscall::futex => do_syscall!(futex::futex, args, 6),
         Syscall::sched_yield => do_syscall!(process::yield_, args, 0),
+        Syscall::sched_setparam => do_syscall!(sched::sched_setparam, args, 2),
+        Syscall::sched_setscheduler => do_syscall!(sched::sched_setscheduler, args, 3),
+        Syscall::sched_getscheduler => do_syscall!(sched::sched_getscheduler, args, 1),
+        Syscall::sched_getparam => do_syscall!(sched::sched_getparam, args, 2),
+        Syscall::sched_get_priority_max => do_syscall!(sched::sched_get_priority_max, args, 1),
+        Syscall::sched_get_priority_min => do_syscall!(sched::sched_get_priority_min, args, 1),
+        Syscall::sched_rr_get_interval => do_syscall!(sched::sched_rr_get_interval, args, 2),
+        Syscall::sched_setaffinity => do_syscall!(sched::sched_setaffinity, args, 3),
+        Syscall::sched_getaffinity => do_syscall!(sched::sched_getaffinity, args, 3),
+        Syscall::setpriority => do_syscall!(sched::setpriority, args, 3),
+        Syscall::getpriority => do_syscall!(sched::getpriority, args, 2),
+        /* Signal */
+        Syscall::rt_sigaction => do_syscall!(signal::sigaction, args, 3),
+        Syscall::rt_sigprocmask => do_syscall!(signal::sigprocmask, args, 3),
+        Syscall::rt_sigreturn => do_syscall!(signal::sigreturn, args, 0),
+        Syscall::kill => do_syscall!(signal::kill, args, 2),
+        Syscall::tgkill => do_syscall!(signal::tgkill, args, 3),
         /* Memory */
         Syscall::brk => do_syscall!(memory::brk, args, 1),
         Syscall::mmap => do_syscall!(memory::mmap, args, 6),
         Syscall::munmap => do_syscall!(memory::munmap, args, 2),
+        Syscall::msync => do_syscall!(memory::msync, args, 3),
+        Syscall::mprotect => do_syscall!(memory::mprotect, args, 3),
+        /* Time */
+        Syscall::clock_gettime => do_syscall!(time::clock_gettime, args, 2),
+        Syscall::clock_getres => do_syscall!(time::clock_getres, args, 2),
+        Syscall::gettimeofday => do_syscall!(time::gettimeofday, args, 2),
+        Syscall::times => do_syscall!(time::times, args, 1),
+        Syscall::getrusage => do_syscall!(time::getrusage, args, 2),
+        Syscall::nanosleep => do_syscall!(time::nanosleep, args, 2),
+        Syscall::clock_nanosleep => do_syscall!(time::clock_nanosleep, args, 4),
+        Syscall::getitimer => do_syscall!(time::getitimer, args, 2),
+        Syscall::setitimer => do_syscall!(time::setitimer, args, 3),
+        Syscall::timerfd_create => do_syscall!(time::timerfd_create, args, 2),
+        Syscall::timerfd_settime => do_syscall!(time::timerfd_settime, args, 4),
+        Syscall::timerfd_gettime => do_syscall!(time::timerfd_gettime, args, 2),
         /* ARK Custom Syscall */
         Syscall::ark_sleep_ticks => do_syscall!(custom::sleep_ticks, args, 1),
         Syscall::ark_breakpoint => do_syscall!(custom::breakpoint, args, 3),
@@ -82,23 +130,11 @@ pub fn syscall_handler(syscall: Syscall, args: &[usize; 6]) -> usize {
         Syscall::geteuid => dummy::ret_zero(syscall),
         Syscall::getgid => dummy::ret_zero(syscall),
         Syscall::getegid => dummy::ret_zero(syscall),
-        Syscall::gettid => dummy::ret_zero(syscall),
         Syscall::setuid => dummy::ret_zero(syscall),
         Syscall::setgid => dummy::ret_zero(syscall),
-        Syscall::exit_group => dummy::ret_eperm(syscall),
-        Syscall::set_tid_address => dummy::ret_eperm(syscall),
         Syscall::ioctl => dummy::ret_zero(syscall),
         Syscall::fcntl64 => dummy::ret_eperm(syscall),
-        Syscall::clock_gettime => dummy::ret_eperm(syscall),
-        /* Going to be Implemented */
-        Syscall::rt_sigaction => dummy::unimp(syscall),
-        Syscall::rt_sigprocmask => dummy::unimp(syscall),
         /* Not too urgent to be Implemented */
-        Syscall::unlinkat => dummy::unimp(syscall),
-        Syscall::umount2 => dummy::unimp(syscall),
-        Syscall::times => dummy::unimp(syscall),
-        Syscall::gettimeofday => dummy::unimp(syscall),
-        Syscall::nanosleep => dummy::unimp(syscall),
         Syscall::ppoll => dummy::unimp(syscall)
     };
 
@@ -1,80 +1,159 @@
 use alloc::string::{String, ToString};
+use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
 use core::mem::size_of;
 use bitflags::Flags;
 use log::warn;
 use crate::cpu::CPU;
-use crate::filesystem::{DirEntry, FileModes, FileOpenFlags};
-use crate::memory::{Addr, PageTable, PhyAddr, VirtAddr};
+use crate::filesystem::{DirEntry, DirEntryType, File, FileModes, FileOpenFlags, MountFlags, SeekPosition};
+use crate::memory::{Addr, PAGE_SIZE, PageTable, PhyAddr, VirtAddr};
 use crate::process;
-use crate::process::{do_yield, get_process_manager, ProcessManager};
+use crate::process::{CLONE_SIGNAL_MASK, CloneFlags, do_yield, get_process_manager, Process, ProcessManager};
+use crate::process::cputime::CpuTime;
+use crate::process::signal::SIGCHLD;
+use crate::syscall::c::{PATH_MAX, Rusage};
 use crate::syscall::error::{SyscallError, SyscallResult};
 
-const SIGCHLD: usize = 17;
-
-pub fn clone(flags: usize, child_stack: usize) -> SyscallResult {
-    if flags != SIGCHLD { warn!("syscall clone with flags is not SIGCHLD."); }
-    let child_pid = get_process_manager().lock().fork(
-        CPU::get_current_process().unwrap(),
-        child_stack as *const u8);
+pub fn clone(flags: usize, child_stack: usize, parent_tid: VirtAddr, tls: usize, child_tid: VirtAddr) -> SyscallResult {
+    let exit_signal = flags & CLONE_SIGNAL_MASK;
+    let flags = CloneFlags::from_bits_truncate(flags & !CLONE_SIGNAL_MASK);
+    if flags.contains(CloneFlags::CLONE_THREAD) && !flags.contains(CloneFlags::CLONE_VM | CloneFlags::CLONE_SIGHAND) {
+        return Err(SyscallError::EINVAL);
+    }
+    if !flags.contains(CloneFlags::CLONE_THREAD) && exit_signal != SIGCHLD {
+        warn!("syscall clone with exit signal {} is not SIGCHLD.", exit_signal);
+    }
+    let child_tid = get_process_manager().lock().clone(
+        &CPU::get_current_thread().unwrap(),
+        flags, child_stack, parent_tid, tls, child_tid);
     do_yield(); // yield parent
-    Ok(child_pid)
+    Ok(child_tid)
 }
 
 pub fn execve(path: VirtAddr, argv: VirtAddr, envp: VirtAddr) -> SyscallResult {
     let proc = CPU::get_current_process().unwrap();
-    let mut proc_data = proc.data.lock();
-    let path = path.into_pa(&proc_data.memory.get_pagetable()).unwrap().get_cstr();
-    let page_table = &proc_data.memory.get_pagetable();
-    fn get_str_vec(vaddr: VirtAddr, page_table: &PageTable) -> Vec<String> {
+    let path = proc.copy_cstr_from_user(path, PATH_MAX).map_err(|_| SyscallError::EFAULT)?;
+    fn get_str_vec(vaddr: VirtAddr, proc: &Process) -> Option<Vec<String>> {
         let mut v: Vec<String> = Vec::new();
         if !vaddr.is_null() {
-            let mut pa = vaddr.into_pa(page_table).unwrap();
+            let mut ptr = vaddr;
             loop {
-                let str_ptr = VirtAddr::from(*pa.get_ref::<usize>());
+                let str_ptr = VirtAddr::from(proc.read_user::<usize>(ptr).ok()?);
                 if str_ptr.is_null() { break; }
-                let str_ptr = str_ptr.into_pa(page_table).unwrap();
-                v.push(str_ptr.get_cstr().to_string());
-                pa = pa.to_offset(size_of::<*const u8>() as isize);
+                v.push(proc.copy_cstr_from_user(str_ptr, MAX_ARG_STRLEN).ok()?);
+                ptr = ptr.to_offset(size_of::<*const u8>() as isize);
             }
         }
-        v
+        Some(v)
     }
 
-    let mut argv = get_str_vec(argv, page_table);
-    let mut env = get_str_vec(envp, page_table);
-    let dentry = if let Some(d) = DirEntry::from_path(path, Some(proc_data.cwd.clone())) {
-        d
-    } else {
-        return Err(SyscallError::ENOENT);
-    };
-    let fullpath = dentry.fullpath();
-    let file = if let Ok(f) = dentry.open(FileOpenFlags::O_RDONLY, FileModes::from_bits(0).unwrap()) {
-        f
-    } else {
-        return Err(SyscallError::EIO);
+    let mut argv = get_str_vec(argv, &proc).ok_or(SyscallError::EFAULT)?;
+    let mut env = get_str_vec(envp, &proc).ok_or(SyscallError::EFAULT)?;
+    let cwd = proc.data.lock().cwd.clone();
+
+    let open_exec = |path: &str| -> Result<Arc<dyn File>, SyscallError> {
+        let dentry = DirEntry::from_path(path, Some(cwd.clone())).ok_or(SyscallError::ENOENT)?;
+        if dentry.get_inode().is_some_and(|inode| inode.get_dentry_type() == DirEntryType::Dir) {
+            return Err(SyscallError::EACCES);
+        }
+        if dentry.mount_flags().contains(MountFlags::MS_NOEXEC) {
+            return Err(SyscallError::EACCES);
+        }
+        dentry.open(FileOpenFlags::O_RDONLY, FileModes::from_bits(0).unwrap()).map_err(|_| SyscallError::EIO)
     };
+    let mut path = path;
+    let mut file = open_exec(&path)?;
+    // Scripts run by the interpreter of `#!` line, which could be a script again.
+    let mut depth = 0;
+    while let Some((interp, interp_arg)) = parse_shebang(&file)? {
+        depth += 1;
+        if depth > MAX_INTERP_DEPTH {
+            return Err(SyscallError::ELOOP);
+        }
+        file = open_exec(&interp)?;
+        // argv[0] is replaced by interpreter, its argument and path of script, like Linux does.
+        let mut new_argv = vec![interp.clone()];
+        new_argv.extend(interp_arg);
+        new_argv.push(path);
+        new_argv.extend(argv.into_iter().skip(1));
+        argv = new_argv;
+        path = interp;
+    }
 
     // argv.insert(0, fullpath);
     // env.insert(0, "PATH=/:/mnt".into());
 
-    drop(proc_data);
-    Ok(proc.execve(file, argv, env))
+    proc.execve(&CPU::get_current_thread().unwrap(), file, argv, env)
+}
+
+/// Max nested interpreters of scripts, same as Linux.
+const MAX_INTERP_DEPTH: usize = 4;
+/// Max length of a single argument or environment string, same as Linux.
+const MAX_ARG_STRLEN: usize = 32 * PAGE_SIZE;
+/// Max length of `#!` line, same as Linux.
+const SHEBANG_LINE_MAX: usize = 256;
+
+/// Parse `#!` line of a script. Return interpreter path and its optional argument.
+fn parse_shebang(file: &Arc<dyn File>) -> Result<Option<(String, Option<String>)>, SyscallError> {
+    let mut buf = [0u8; SHEBANG_LINE_MAX];
+    file.seek(0, SeekPosition::Set).map_err(|_| SyscallError::EIO)?;
+    let len = file.read(&mut buf).map_err(|_| SyscallError::EIO)?;
+    if len < 2 || &buf[..2] != b"#!" {
+        return Ok(None);
+    }
+    let line = &buf[2..len];
+    let line = &line[..line.iter().position(|c| *c == b'\n').unwrap_or(line.len())];
+    let line = core::str::from_utf8(line).map_err(|_| SyscallError::ENOEXEC)?.trim();
+    // Everything after interpreter is passed as one argument.
+    let (interp, arg) = match line.find(|c: char| c == ' ' || c == '\t') {
+        Some(i) => (&line[..i], Some(line[i..].trim())),
+        None => (line, None)
+    };
+    if interp.is_empty() {
+        return Err(SyscallError::ENOEXEC);
+    }
+    Ok(Some((interp.to_string(), arg.filter(|arg| !arg.is_empty()).map(|arg| arg.to_string()))))
 }
 
 pub fn exit(code: usize) -> SyscallResult {
-    get_process_manager().lock().exit(CPU::get_current_process().unwrap(), code);
+    let thread = CPU::get_current_thread().unwrap();
+    process::release_on_exit(&thread);
+    get_process_manager().lock().exit_thread(&thread, (code & 0xff) << 8);
+    drop(thread);
+    do_yield();
+    Ok(0) // never used
+}
+
+pub fn exit_group(code: usize) -> SyscallResult {
+    let thread = CPU::get_current_thread().unwrap();
+    process::release_on_exit(&thread);
+    get_process_manager().lock().exit_group(&thread, (code & 0xff) << 8);
+    drop(thread);
     do_yield();
     Ok(0) // never used
 }
 
-pub fn wait_for(pid: usize, exit_code_buf: VirtAddr, option: usize) -> SyscallResult {
+pub fn set_tid_address(tid_ptr: VirtAddr) -> SyscallResult {
+    let thread = CPU::get_current_thread().unwrap();
+    thread.data.lock().clear_child_tid = tid_ptr;
+    Ok(thread.tid())
+}
+
+pub fn wait_for(pid: usize, exit_code_buf: VirtAddr, option: usize, rusage: VirtAddr) -> SyscallResult {
     let pid: isize = pid as isize;
     let proc = CPU::get_current_process().unwrap();
-    // TODO: unwrap is not safe
-    let exit_code = exit_code_buf.into_pa(proc.data.lock().memory.get_pagetable()).unwrap().get_ref_mut::<usize>();
-    ProcessManager::wait_for(get_process_manager(), proc, pid, exit_code, option)
+    let mut exit_code = 0;
+    let mut child_time = CpuTime::default();
+    let child_pid = ProcessManager::wait_for(get_process_manager(), proc.clone(), pid, &mut exit_code, &mut child_time, option)?;
+    if child_pid != 0 && !exit_code_buf.is_null() {
+        // wstatus is an int.
+        proc.write_user(exit_code_buf, &(exit_code as i32)).map_err(|_| SyscallError::EFAULT)?;
+    }
+    if child_pid != 0 && !rusage.is_null() {
+        proc.write_user(rusage, &Rusage::from(child_time)).map_err(|_| SyscallError::EFAULT)?;
+    }
+    Ok(child_pid)
 }
 
 pub fn getppid() -> SyscallResult {
@@ -96,6 +175,11 @@ pub fn getpid() -> SyscallResult {
     Ok(proc.pid.pid())
 }
 
+pub fn gettid() -> SyscallResult {
+    let thread = CPU::get_current_thread().unwrap();
+    Ok(thread.tid())
+}
+
 pub fn yield_() -> SyscallResult {
     do_yield();
     Ok(0)
@@ -0,0 +1,211 @@
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::time::Duration;
+use crate::cpu::CPU;
+use crate::memory::VirtAddr;
+use crate::process::{do_yield, get_process_manager, Thread};
+use crate::process::scheduler::{self, MAX_NICE, MAX_RT_PRIORITY, MIN_NICE, MIN_RT_PRIORITY, SchedPolicy};
+use crate::syscall::c::Timespec;
+use crate::syscall::error::{SyscallError, SyscallResult};
+
+const SCHED_OTHER: usize = 0;
+const SCHED_FIFO: usize = 1;
+const SCHED_RR: usize = 2;
+const SCHED_BATCH: usize = 3;
+const SCHED_IDLE: usize = 5;
+const SCHED_RESET_ON_FORK: usize = 0x4000_0000;
+
+const PRIO_PROCESS: usize = 0;
+const PRIO_PGRP: usize = 1;
+const PRIO_USER: usize = 2;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct SchedParam {
+    sched_priority: i32,
+}
+
+fn policy_from_raw(policy: usize) -> Option<SchedPolicy> {
+    match policy {
+        SCHED_OTHER => Some(SchedPolicy::Normal),
+        SCHED_FIFO => Some(SchedPolicy::Fifo),
+        SCHED_RR => Some(SchedPolicy::RoundRobin),
+        SCHED_BATCH => Some(SchedPolicy::Batch),
+        SCHED_IDLE => Some(SchedPolicy::Idle),
+        _ => None
+    }
+}
+
+// Thread of `tid`, 0 for the calling one.
+fn find_thread(tid: usize) -> Result<Arc<Thread>, SyscallError> {
+    if tid as isize < 0 {
+        return Err(SyscallError::EINVAL);
+    }
+    if tid == 0 {
+        return Ok(CPU::get_current_thread().unwrap());
+    }
+    get_process_manager().lock().get_thread(tid).ok_or(SyscallError::ESRCH)
+}
+
+fn read_param(param: VirtAddr) -> Result<SchedParam, SyscallError> {
+    if param.is_null() {
+        return Err(SyscallError::EINVAL);
+    }
+    let proc = CPU::get_current_process().unwrap();
+    let param = proc.read_user::<SchedParam>(param).map_err(|_| SyscallError::EFAULT)?;
+    Ok(param)
+}
+
+fn set_scheduler(thread: &Arc<Thread>, policy: Option<SchedPolicy>, reset_on_fork: Option<bool>, param: SchedParam) -> SyscallResult {
+    let mut data = thread.data.lock();
+    let policy = policy.unwrap_or(data.sched.policy);
+    let priority = param.sched_priority as isize;
+    // Real-time policies take 1 to 99, others only 0.
+    let valid = if policy.is_realtime() {
+        (MIN_RT_PRIORITY as isize..=MAX_RT_PRIORITY as isize).contains(&priority)
+    } else {
+        priority == 0
+    };
+    if !valid {
+        return Err(SyscallError::EINVAL);
+    }
+    let nice = data.sched.nice;
+    let reset_on_fork = reset_on_fork.unwrap_or(data.sched.reset_on_fork);
+    scheduler::set_params(thread, &mut data, policy, priority as usize, nice, reset_on_fork);
+    Ok(0)
+}
+
+pub fn sched_setscheduler(tid: usize, policy: usize, param: VirtAddr) -> SyscallResult {
+    let reset_on_fork = policy & SCHED_RESET_ON_FORK != 0;
+    let policy = policy_from_raw(policy & !SCHED_RESET_ON_FORK).ok_or(SyscallError::EINVAL)?;
+    let param = read_param(param)?;
+    let thread = find_thread(tid)?;
+    set_scheduler(&thread, Some(policy), Some(reset_on_fork), param)
+}
+
+pub fn sched_getscheduler(tid: usize) -> SyscallResult {
+    let thread = find_thread(tid)?;
+    let data = thread.data.lock();
+    Ok(data.sched.policy as usize | if data.sched.reset_on_fork { SCHED_RESET_ON_FORK } else { 0 })
+}
+
+pub fn sched_setparam(tid: usize, param: VirtAddr) -> SyscallResult {
+    let param = read_param(param)?;
+    let thread = find_thread(tid)?;
+    set_scheduler(&thread, None, None, param)
+}
+
+pub fn sched_getparam(tid: usize, param: VirtAddr) -> SyscallResult {
+    if param.is_null() {
+        return Err(SyscallError::EINVAL);
+    }
+    let thread = find_thread(tid)?;
+    let value = SchedParam {
+        sched_priority: thread.data.lock().sched.rt_priority as i32,
+    };
+    let proc = CPU::get_current_process().unwrap();
+    proc.write_user(param, &value).map_err(|_| SyscallError::EFAULT)?;
+    Ok(0)
+}
+
+pub fn sched_get_priority_max(policy: usize) -> SyscallResult {
+    let policy = policy_from_raw(policy).ok_or(SyscallError::EINVAL)?;
+    Ok(if policy.is_realtime() { MAX_RT_PRIORITY } else { 0 })
+}
+
+pub fn sched_get_priority_min(policy: usize) -> SyscallResult {
+    let policy = policy_from_raw(policy).ok_or(SyscallError::EINVAL)?;
+    Ok(if policy.is_realtime() { MIN_RT_PRIORITY } else { 0 })
+}
+
+pub fn sched_rr_get_interval(tid: usize, interval: VirtAddr) -> SyscallResult {
+    let thread = find_thread(tid)?;
+    let slice = if thread.data.lock().sched.policy == SchedPolicy::RoundRobin {
+        scheduler::RR_TIMESLICE
+    } else {
+        // Fair class has no fixed slice.
+        Duration::ZERO
+    };
+    let proc = CPU::get_current_process().unwrap();
+    proc.write_user(interval, &Timespec::from(slice)).map_err(|_| SyscallError::EFAULT)?;
+    Ok(0)
+}
+
+// Bits beyond `usize` are CPUs not supported, ignored like offline ones.
+pub fn sched_setaffinity(tid: usize, cpusetsize: usize, mask: VirtAddr) -> SyscallResult {
+    let proc = CPU::get_current_process().unwrap();
+    let mut affinity = 0usize;
+    for i in 0..cpusetsize.min(size_of::<usize>()) {
+        let byte = proc.read_user::<u8>(VirtAddr::from(mask.addr + i)).map_err(|_| SyscallError::EFAULT)?;
+        affinity |= (byte as usize) << (i * 8);
+    }
+    let affinity = affinity & scheduler::all_cpus();
+    if affinity == 0 {
+        return Err(SyscallError::EINVAL);
+    }
+    let thread = find_thread(tid)?;
+    let mut data = thread.data.lock();
+    scheduler::set_affinity(&thread, &mut data, affinity);
+    let disallowed = !data.sched.allowed_on(data.sched.cpu);
+    drop(data);
+    // Running on a CPU not allowed any more, move at once.
+    if disallowed && Arc::ptr_eq(&thread, &CPU::get_current_thread().unwrap()) {
+        drop(thread);
+        do_yield();
+    }
+    Ok(0)
+}
+
+/// Return size of the mask written, like Linux.
+pub fn sched_getaffinity(tid: usize, cpusetsize: usize, mask: VirtAddr) -> SyscallResult {
+    if cpusetsize < size_of::<usize>() || cpusetsize % size_of::<usize>() != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+    let thread = find_thread(tid)?;
+    let affinity = thread.data.lock().sched.affinity & scheduler::all_cpus();
+    let proc = CPU::get_current_process().unwrap();
+    proc.write_user(mask, &affinity).map_err(|_| SyscallError::EFAULT)?;
+    Ok(size_of::<usize>())
+}
+
+// Threads `which` and `who` of setpriority and getpriority refer to.
+fn priority_targets(which: usize, who: usize) -> Result<Vec<Arc<Thread>>, SyscallError> {
+    match which {
+        // Like Linux, a single thread.
+        PRIO_PROCESS => Ok(vec![find_thread(who)?]),
+        // No process group yet, self is the only member.
+        PRIO_PGRP if who == 0 || who == CPU::get_current_process().unwrap().pid.pid() => {
+            let proc = CPU::get_current_process().unwrap();
+            let threads = proc.data.lock().threads.iter().filter_map(|t| t.upgrade()).collect();
+            Ok(threads)
+        }
+        PRIO_PGRP => Err(SyscallError::ESRCH),
+        // Everything runs as root.
+        PRIO_USER if who == 0 => Ok(get_process_manager().lock().thread_list().cloned().collect()),
+        PRIO_USER => Err(SyscallError::ESRCH),
+        _ => Err(SyscallError::EINVAL)
+    }
+}
+
+pub fn setpriority(which: usize, who: usize, prio: usize) -> SyscallResult {
+    let nice = (prio as isize).clamp(MIN_NICE, MAX_NICE);
+    let threads = priority_targets(which, who)?;
+    if threads.is_empty() {
+        return Err(SyscallError::ESRCH);
+    }
+    for thread in threads {
+        let mut data = thread.data.lock();
+        let sched = data.sched.clone();
+        scheduler::set_params(&thread, &mut data, sched.policy, sched.rt_priority, nice, sched.reset_on_fork);
+    }
+    Ok(0)
+}
+
+/// Return 20 - nice of the highest priority target, like Linux does to avoid negative values.
+pub fn getpriority(which: usize, who: usize) -> SyscallResult {
+    let threads = priority_targets(which, who)?;
+    let nice = threads.iter().map(|thread| thread.data.lock().sched.nice).min().ok_or(SyscallError::ESRCH)?;
+    Ok((20 - nice) as usize)
+}
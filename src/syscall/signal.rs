@@ -0,0 +1,135 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use log::warn;
+use crate::cpu::CPU;
+use crate::memory::{Addr, VirtAddr};
+use crate::process::get_process_manager;
+use crate::process::signal::{self, SigAction, SignalSet, SIG_BLOCK, SIG_SETMASK, SIG_UNBLOCK, SIGKILL, SIGSEGV, SIGSTOP};
+use crate::syscall::error::{SyscallError, SyscallResult};
+
+pub fn sigaction(signum: usize, act: VirtAddr, old_act: VirtAddr) -> SyscallResult {
+    if !signal::is_valid_signal(signum) {
+        return Err(SyscallError::EINVAL);
+    }
+    let proc = CPU::get_current_process().unwrap();
+
+    let new_action = if act.is_null() {
+        None
+    } else {
+        if signum == SIGKILL || signum == SIGSTOP {
+            return Err(SyscallError::EINVAL);
+        }
+        Some(proc.read_user::<SigAction>(act).map_err(|_| SyscallError::EFAULT)?)
+    };
+
+    let mut proc_data = proc.data.lock();
+    let old_action = proc_data.signal.actions[signum - 1];
+    if let Some(mut new_action) = new_action {
+        new_action.mask = new_action.mask.without_unblockable();
+        proc_data.signal.actions[signum - 1] = new_action;
+        // Pending signals which is now ignored are discarded.
+        if new_action.handler == signal::SIG_IGN {
+            proc_data.signal.pending.remove(signum);
+            proc_data.threads.iter()
+                .filter_map(|t| t.upgrade())
+                .for_each(|t| t.data.lock().signal.pending.remove(signum));
+        }
+    }
+    drop(proc_data);
+
+    if !old_act.is_null() {
+        proc.write_user(old_act, &old_action).map_err(|_| SyscallError::EFAULT)?;
+    }
+    Ok(0)
+}
+
+pub fn sigprocmask(how: usize, set: VirtAddr, old_set: VirtAddr) -> SyscallResult {
+    let thread = CPU::get_current_thread().unwrap();
+
+    let new_set = if set.is_null() {
+        None
+    } else {
+        Some(thread.process.read_user::<SignalSet>(set).map_err(|_| SyscallError::EFAULT)?)
+    };
+
+    let mut thread_data = thread.data.lock();
+    let old_blocked = thread_data.signal.blocked;
+    if let Some(new_set) = new_set {
+        let blocked = &mut thread_data.signal.blocked;
+        match how {
+            SIG_BLOCK => blocked.0 |= new_set.0,
+            SIG_UNBLOCK => blocked.0 &= !new_set.0,
+            SIG_SETMASK => *blocked = new_set,
+            _ => return Err(SyscallError::EINVAL),
+        }
+        *blocked = blocked.without_unblockable();
+    }
+    drop(thread_data);
+
+    if !old_set.is_null() {
+        thread.process.write_user(old_set, &old_blocked).map_err(|_| SyscallError::EFAULT)?;
+    }
+    Ok(0)
+}
+
+pub fn sigreturn() -> SyscallResult {
+    let thread = CPU::get_current_thread().unwrap();
+    let mut proc_data = thread.process.data.lock();
+    let mut thread_data = thread.data.lock();
+    if let Some(a0) = signal::sigreturn(&mut proc_data, &mut thread_data) {
+        // a0 will be overwritten by syscall return value, so restore it by this way.
+        Ok(a0)
+    } else {
+        warn!("Bad signal frame on TID {}.", thread.tid());
+        proc_data.signal.actions[SIGSEGV - 1] = SigAction::default();
+        drop(thread_data);
+        drop(proc_data);
+        signal::send_signal_to_thread(&thread, SIGSEGV);
+        Err(SyscallError::EFAULT)
+    }
+}
+
+pub fn kill(pid: usize, signum: usize) -> SyscallResult {
+    let pid = pid as isize;
+    if signum != 0 && !signal::is_valid_signal(signum) {
+        return Err(SyscallError::EINVAL);
+    }
+    let current = CPU::get_current_process().unwrap();
+    let targets = {
+        let pm = get_process_manager().lock();
+        if pid > 0 {
+            pm.get_process(pid as usize).into_iter().collect::<Vec<_>>()
+        } else if pid == 0 {
+            // No process group yet, self is the only member.
+            vec![current.clone()]
+        } else if pid == -1 {
+            pm.process_list()
+                .filter(|p| p.pid.pid() != 1 && p.pid.pid() != current.pid.pid())
+                .cloned()
+                .collect()
+        } else {
+            // Process group is not supported.
+            vec![]
+        }
+    };
+    if targets.is_empty() {
+        return Err(SyscallError::ESRCH);
+    }
+    if signum != 0 {
+        targets.iter().for_each(|proc| signal::send_signal(proc, signum));
+    }
+    Ok(0)
+}
+
+pub fn tgkill(tgid: usize, tid: usize, signum: usize) -> SyscallResult {
+    if signum != 0 && !signal::is_valid_signal(signum) {
+        return Err(SyscallError::EINVAL);
+    }
+    let thread = get_process_manager().lock().get_thread(tid)
+        .filter(|t| t.process.pid.pid() == tgid)
+        .ok_or(SyscallError::ESRCH)?;
+    if signum != 0 {
+        signal::send_signal_to_thread(&thread, signum);
+    }
+    Ok(0)
+}
@@ -0,0 +1,260 @@
+use alloc::sync::Arc;
+use core::time::Duration;
+use crate::cpu::CPU;
+use crate::device::{clock, timer};
+use crate::device::timerfd::TimerFd;
+use crate::memory::VirtAddr;
+use crate::process::{cputime, itimer};
+use crate::process::WakeReason;
+use crate::syscall::c::{Itimerspec, Itimerval, Rusage, Timespec, Timeval, Tms};
+use crate::syscall::error::{SyscallError, SyscallResult};
+
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+const CLOCK_PROCESS_CPUTIME_ID: usize = 2;
+const CLOCK_THREAD_CPUTIME_ID: usize = 3;
+const CLOCK_MONOTONIC_RAW: usize = 4;
+const CLOCK_REALTIME_COARSE: usize = 5;
+const CLOCK_MONOTONIC_COARSE: usize = 6;
+const CLOCK_BOOTTIME: usize = 7;
+
+const RUSAGE_SELF: usize = 0;
+const RUSAGE_CHILDREN: usize = (-1isize) as usize;
+const RUSAGE_THREAD: usize = 1;
+
+// Clock ticks of times, USER_HZ of Linux.
+const USER_HZ: u128 = 100;
+
+const TIMER_ABSTIME: usize = 1;
+
+const TFD_TIMER_ABSTIME: usize = 1;
+const TFD_NONBLOCK: usize = 0o4000;
+const TFD_CLOEXEC: usize = 0o2000000;
+
+// Time now on clock `clockid` timers and sleeps can use.
+fn clock_now(clockid: usize) -> Result<Duration, SyscallError> {
+    match clockid {
+        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => Ok(clock::realtime()),
+        CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE => Ok(clock::monotonic()),
+        CLOCK_BOOTTIME => Ok(clock::boottime()),
+        _ => Err(SyscallError::EINVAL)
+    }
+}
+
+// Like `clock_now`, CPU time clocks included.
+fn clock_or_cputime_now(clockid: usize) -> Result<Duration, SyscallError> {
+    match clockid {
+        CLOCK_PROCESS_CPUTIME_ID => {
+            let proc = CPU::get_current_process().unwrap();
+            let time = cputime::process_time(&proc.data.lock());
+            Ok(time.total())
+        }
+        CLOCK_THREAD_CPUTIME_ID => {
+            let thread = CPU::get_current_thread().unwrap();
+            let time = cputime::thread_time(&thread.data.lock());
+            Ok(time.total())
+        }
+        _ => clock_now(clockid)
+    }
+}
+
+// Deadline since boot of `time` on clock `clockid`, absolute or relative to now.
+fn to_deadline(clockid: usize, time: Duration, absolute: bool) -> Result<Duration, SyscallError> {
+    let now = timer::current_time();
+    if absolute {
+        Ok(now + time.saturating_sub(clock_now(clockid)?))
+    } else {
+        Ok(now + time)
+    }
+}
+
+fn read_timespec(addr: VirtAddr) -> Result<Duration, SyscallError> {
+    let proc = CPU::get_current_process().unwrap();
+    let ts = proc.read_user::<Timespec>(addr).map_err(|_| SyscallError::EFAULT)?;
+    ts.to_duration().ok_or(SyscallError::EINVAL)
+}
+
+fn write_user<T: Copy>(addr: VirtAddr, value: &T) -> Result<(), SyscallError> {
+    if addr.is_null() {
+        return Ok(());
+    }
+    let proc = CPU::get_current_process().unwrap();
+    proc.write_user(addr, value).map_err(|_| SyscallError::EFAULT)?;
+    Ok(())
+}
+
+pub fn clock_gettime(clockid: usize, tp: VirtAddr) -> SyscallResult {
+    let now = clock_or_cputime_now(clockid)?;
+    if tp.is_null() {
+        return Err(SyscallError::EFAULT);
+    }
+    write_user(tp, &Timespec::from(now))?;
+    Ok(0)
+}
+
+pub fn clock_getres(clockid: usize, res: VirtAddr) -> SyscallResult {
+    clock_or_cputime_now(clockid)?;
+    write_user(res, &Timespec::from(clock::resolution()))?;
+    Ok(0)
+}
+
+/// Timezone is always UTC.
+pub fn gettimeofday(tv: VirtAddr, tz: VirtAddr) -> SyscallResult {
+    write_user(tv, &Timeval::from(clock::realtime()))?;
+    // struct timezone of minuteswest and dsttime
+    write_user(tz, &[0i32; 2])?;
+    Ok(0)
+}
+
+fn clock_ticks(time: Duration) -> i64 {
+    (time.as_millis() * USER_HZ / 1000) as i64
+}
+
+/// Return clock ticks since boot.
+pub fn times(buf: VirtAddr) -> SyscallResult {
+    let proc = CPU::get_current_process().unwrap();
+    let proc_data = proc.data.lock();
+    let time = cputime::process_time(&proc_data);
+    let children_time = proc_data.children_time;
+    drop(proc_data);
+    let tms = Tms {
+        tms_utime: clock_ticks(time.user),
+        tms_stime: clock_ticks(time.system),
+        tms_cutime: clock_ticks(children_time.user),
+        tms_cstime: clock_ticks(children_time.system),
+    };
+    write_user(buf, &tms)?;
+    Ok(clock_ticks(clock::monotonic()) as usize)
+}
+
+pub fn getrusage(who: usize, usage: VirtAddr) -> SyscallResult {
+    let time = match who {
+        RUSAGE_SELF => cputime::process_time(&CPU::get_current_process().unwrap().data.lock()),
+        RUSAGE_CHILDREN => CPU::get_current_process().unwrap().data.lock().children_time,
+        RUSAGE_THREAD => cputime::thread_time(&CPU::get_current_thread().unwrap().data.lock()),
+        _ => return Err(SyscallError::EINVAL)
+    };
+    if usage.is_null() {
+        return Err(SyscallError::EFAULT);
+    }
+    write_user(usage, &Rusage::from(time))?;
+    Ok(0)
+}
+
+// Sleep until `deadline`, time left is written to `rem` if interrupted.
+fn sleep(deadline: Duration, rem: VirtAddr) -> SyscallResult {
+    match timer::sleep_until(deadline, true) {
+        WakeReason::Interrupted => {
+            let left = deadline.saturating_sub(timer::current_time());
+            write_user(rem, &Timespec::from(left))?;
+            Err(SyscallError::EINTR)
+        }
+        _ => Ok(0)
+    }
+}
+
+pub fn nanosleep(req: VirtAddr, rem: VirtAddr) -> SyscallResult {
+    let time = read_timespec(req)?;
+    sleep(timer::current_time() + time, rem)
+}
+
+pub fn clock_nanosleep(clockid: usize, flags: usize, req: VirtAddr, rem: VirtAddr) -> SyscallResult {
+    let time = read_timespec(req)?;
+    let absolute = flags & TIMER_ABSTIME != 0;
+    let deadline = to_deadline(clockid, time, absolute)?;
+    // Absolute sleep is simply restarted with the same request, nothing left to tell.
+    sleep(deadline, if absolute { VirtAddr::from(0) } else { rem })
+}
+
+fn itimerval_of((interval, left): (Duration, Duration)) -> Itimerval {
+    Itimerval {
+        it_interval: Timeval::from(interval),
+        it_value: Timeval::from(left),
+    }
+}
+
+pub fn setitimer(which: usize, new: VirtAddr, old: VirtAddr) -> SyscallResult {
+    if which > itimer::ITIMER_PROF {
+        return Err(SyscallError::EINVAL);
+    }
+    if new.is_null() {
+        return Err(SyscallError::EFAULT);
+    }
+    let proc = CPU::get_current_process().unwrap();
+    let new = proc.read_user::<Itimerval>(new).map_err(|_| SyscallError::EFAULT)?;
+    let interval = new.it_interval.to_duration().ok_or(SyscallError::EINVAL)?;
+    let value = new.it_value.to_duration().ok_or(SyscallError::EINVAL)?;
+    let old_value = itimer::set(&proc, which, interval, value);
+    write_user(old, &itimerval_of(old_value))?;
+    Ok(0)
+}
+
+pub fn getitimer(which: usize, cur: VirtAddr) -> SyscallResult {
+    if which > itimer::ITIMER_PROF {
+        return Err(SyscallError::EINVAL);
+    }
+    let proc = CPU::get_current_process().unwrap();
+    let value = itimer::get(&proc, which);
+    if cur.is_null() {
+        return Err(SyscallError::EFAULT);
+    }
+    write_user(cur, &itimerval_of(value))?;
+    Ok(0)
+}
+
+pub fn timerfd_create(clockid: usize, flags: usize) -> SyscallResult {
+    clock_now(clockid)?;
+    if flags & !(TFD_NONBLOCK | TFD_CLOEXEC) != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+    let proc = CPU::get_current_process().unwrap();
+    let mut proc_data = proc.data.lock();
+    let fd = proc_data.allocate_fd();
+    proc_data.files[fd] = Some(Arc::new(TimerFd::new(clockid, flags & TFD_NONBLOCK != 0)));
+    Ok(fd)
+}
+
+// Run `f` on timerfd `fd`.
+fn with_timerfd<R>(fd: usize, f: impl FnOnce(&TimerFd) -> Result<R, SyscallError>) -> Result<R, SyscallError> {
+    let proc = CPU::get_current_process().unwrap();
+    let file = match proc.data.lock().files.get(fd) {
+        Some(Some(file)) => file.clone(),
+        _ => return Err(SyscallError::EBADF)
+    };
+    let timerfd = file.as_any().and_then(|file| file.downcast_ref::<TimerFd>()).ok_or(SyscallError::EINVAL)?;
+    f(timerfd)
+}
+
+fn itimerspec_of((interval, left): (Duration, Duration)) -> Itimerspec {
+    Itimerspec {
+        it_interval: Timespec::from(interval),
+        it_value: Timespec::from(left),
+    }
+}
+
+pub fn timerfd_settime(fd: usize, flags: usize, new: VirtAddr, old: VirtAddr) -> SyscallResult {
+    let proc = CPU::get_current_process().unwrap();
+    let new = proc.read_user::<Itimerspec>(new).map_err(|_| SyscallError::EFAULT)?;
+    let interval = new.it_interval.to_duration().ok_or(SyscallError::EINVAL)?;
+    let value = new.it_value.to_duration().ok_or(SyscallError::EINVAL)?;
+    let old_value = with_timerfd(fd, |timerfd| {
+        // Zero value disarms.
+        let deadline = if value.is_zero() {
+            None
+        } else {
+            Some(to_deadline(timerfd.clock(), value, flags & TFD_TIMER_ABSTIME != 0)?)
+        };
+        Ok(timerfd.set(deadline, interval))
+    })?;
+    write_user(old, &itimerspec_of(old_value))?;
+    Ok(0)
+}
+
+pub fn timerfd_gettime(fd: usize, cur: VirtAddr) -> SyscallResult {
+    let value = with_timerfd(fd, |timerfd| Ok(timerfd.get()))?;
+    if cur.is_null() {
+        return Err(SyscallError::EFAULT);
+    }
+    write_user(cur, &itimerspec_of(value))?;
+    Ok(0)
+}
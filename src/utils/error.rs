@@ -2,8 +2,28 @@ use core::any::Any;
 use core::error::Error;
 use core::fmt::{Debug, Display, Formatter};
 
+#[derive(PartialEq)]
 pub struct KernelError(&'static str);
 
+impl KernelError {
+    /// Non-blocking file operation could not be done at once, EAGAIN to user.
+    pub const WOULD_BLOCK: KernelError = KernelError("Operation would block.");
+    /// Name to create is taken, EEXIST to user.
+    pub const ALREADY_EXISTS: KernelError = KernelError("Already existed.");
+    /// Filesystem has no free block or inode, ENOSPC to user.
+    pub const NO_SPACE: KernelError = KernelError("No space left on device.");
+    /// Write beyond the largest file size, EFBIG to user.
+    pub const FILE_TOO_LARGE: KernelError = KernelError("File too large.");
+    /// Directory to remove or replace has entries, ENOTEMPTY to user.
+    pub const NOT_EMPTY: KernelError = KernelError("Directory not empty.");
+    /// On-disk structure of filesystem is inconsistent, EUCLEAN to user.
+    pub const CORRUPTED: KernelError = KernelError("Filesystem corrupted.");
+    /// Change to a read-only mount or device, EROFS to user.
+    pub const READ_ONLY: KernelError = KernelError("Read-only filesystem.");
+    /// Inode has the most links it can have, EMLINK to user.
+    pub const TOO_MANY_LINKS: KernelError = KernelError("Too many links.");
+}
+
 impl Debug for KernelError {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "Kernel Error: {}", self.0)
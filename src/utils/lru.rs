@@ -0,0 +1,86 @@
+//! # LRU
+//!
+//! Map remembering the order of use, for caches to evict the least recently used entry.
+//! ---
+//! Change log:
+//!   - 2026/10/16: File created.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::ops::RangeBounds;
+
+pub struct LruCache<K: Ord + Clone, V> {
+    // Value with the tick of last use.
+    entries: BTreeMap<K, (V, usize)>,
+    // Tick of last use to key, oldest first.
+    order: BTreeMap<usize, K>,
+    tick: usize,
+}
+
+impl<K: Ord + Clone, V> LruCache<K, V> {
+    pub fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Get and mark as most recently used.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let (_, used) = self.entries.get_mut(key)?;
+        self.order.remove(used);
+        self.tick += 1;
+        *used = self.tick;
+        self.order.insert(self.tick, key.clone());
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let old = self.remove(&key);
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+        old
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (value, used) = self.entries.remove(key)?;
+        self.order.remove(&used);
+        Some(value)
+    }
+
+    /// Remove the least recently used entry among those `can_evict` agrees on.
+    pub fn evict(&mut self, can_evict: impl Fn(&V) -> bool) -> Option<(K, V)> {
+        let key = self.order.values()
+            .find(|key| can_evict(&self.entries.get(*key).unwrap().0))?
+            .clone();
+        let value = self.remove(&key).unwrap();
+        Some((key, value))
+    }
+
+    /// Least recently used value among those `pred` agrees on, order of use is untouched.
+    pub fn find_lru(&self, pred: impl Fn(&V) -> bool) -> Option<&V> {
+        self.order.values()
+            .map(|key| &self.entries.get(key).unwrap().0)
+            .find(|value| pred(value))
+    }
+
+    /// Entries in key range, order of use is untouched.
+    pub fn range(&self, range: impl RangeBounds<K>) -> impl Iterator<Item=(&K, &V)> {
+        self.entries.range(range).map(|(key, (value, _))| (key, value))
+    }
+
+    pub fn remove_range(&mut self, range: impl RangeBounds<K>) -> Vec<V> {
+        let keys = self.entries.range(range).map(|(key, _)| key.clone()).collect::<Vec<_>>();
+        keys.iter().filter_map(|key| self.remove(key)).collect()
+    }
+
+    pub fn values(&self) -> impl Iterator<Item=&V> {
+        self.entries.values().map(|(value, _)| value)
+    }
+}
@@ -11,6 +11,7 @@ pub mod logger;
 pub mod error;
 mod panic;
 mod fixed_bitset;
+pub mod lru;
 
 #[macro_export]
 macro_rules! do_init {